@@ -0,0 +1,245 @@
+//! A load generator for walcraft, to size the hardware and catch performance regressions
+//!
+//! Writes records of a given size from several threads into a fresh WAL, then reads them
+//! back, reporting the throughput and the latency percentiles of the writes. The WAL is
+//! created in a new directory under `--dir`, which is deleted once done.
+//!
+//! ```text
+//! walcraft-bench [--dir /tmp] [--records 100000] [--record-size 128] [--threads 4]
+//!                [--sync buffered|unbuffered|fsync|per-write] [--buffer-kb 4]
+//! ```
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use walcraft::{Size, Wal, WalBuilder, WriteOptions};
+
+const USAGE: &str = "Usage: walcraft-bench [--dir path] [--records n] [--record-size bytes] \
+    [--threads n] [--sync buffered|unbuffered|fsync|per-write] [--buffer-kb n]";
+
+/// How the writes reach the disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SyncPolicy {
+    /// Through the in-memory buffer, without syncing
+    Buffered,
+    /// Straight to the file, without syncing
+    Unbuffered,
+    /// Through the in-memory buffer, syncing every filled buffer
+    Fsync,
+    /// Every write synced to disk before returning
+    PerWrite,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    dir: PathBuf,
+    records: usize,
+    record_size: usize,
+    threads: usize,
+    sync: SyncPolicy,
+    buffer_kb: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir(),
+            records: 100_000,
+            record_size: 128,
+            threads: 4,
+            sync: SyncPolicy::Buffered,
+            buffer_kb: None,
+        }
+    }
+}
+
+fn args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        let number = |value: String| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or(format!("Invalid {}", arg))
+        };
+        match arg.as_str() {
+            "--dir" => options.dir = PathBuf::from(value()?),
+            "--records" => options.records = number(value()?)?,
+            "--record-size" => options.record_size = number(value()?)?,
+            "--threads" => options.threads = number(value()?)?,
+            "--buffer-kb" => options.buffer_kb = Some(number(value()?)?),
+            "--sync" => {
+                options.sync = match value()?.as_str() {
+                    "buffered" => SyncPolicy::Buffered,
+                    "unbuffered" => SyncPolicy::Unbuffered,
+                    "fsync" => SyncPolicy::Fsync,
+                    "per-write" => SyncPolicy::PerWrite,
+                    _ => return Err("Invalid --sync".to_string()),
+                }
+            }
+            _ => return Err(format!("Unexpected argument: {}\n{}", arg, USAGE)),
+        }
+    }
+    if options.buffer_kb.is_some() && options.sync == SyncPolicy::Unbuffered {
+        return Err("--buffer-kb can't be set along with --sync unbuffered".to_string());
+    }
+    Ok(options)
+}
+
+/// The duration below which the given share of the sorted durations fall
+fn percentile(sorted: &[Duration], share: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (sorted.len() as f64 * share).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Bytes per second, in MB/s
+fn mb_per_sec(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+}
+
+fn run(options: &Options, location: &str) -> Result<(), String> {
+    let mut builder = WalBuilder::new().location(location);
+    match options.sync {
+        SyncPolicy::Unbuffered => builder = builder.disable_buffer(),
+        SyncPolicy::Fsync => builder = builder.enable_fsync(),
+        SyncPolicy::Buffered | SyncPolicy::PerWrite => {}
+    }
+    if let Some(kb) = options.buffer_kb {
+        builder = builder.buffer_size(Size::Kb(kb));
+    }
+    let wal: Wal<Vec<u8>> = builder.build().map_err(|e| e.to_string())?;
+    println!(
+        "walcraft-bench: {} records of {} bytes, {} threads, {:?} writes",
+        options.records, options.record_size, options.threads, options.sync
+    );
+
+    // write phase, every thread timing each of its writes
+    let write = WriteOptions {
+        fsync: options.sync == SyncPolicy::PerWrite,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let mut latencies = std::thread::scope(|scope| {
+        let handles = (0..options.threads)
+            .map(|thread| {
+                let wal = wal.clone();
+                // the records are spread as evenly as possible between the threads
+                let count = options.records / options.threads
+                    + usize::from(thread < options.records % options.threads);
+                scope.spawn(move || {
+                    let mut latencies = Vec::with_capacity(count);
+                    for i in 0..count {
+                        let record = vec![(thread + i) as u8; options.record_size];
+                        let start = Instant::now();
+                        wal.write_with(record, write);
+                        latencies.push(start.elapsed());
+                    }
+                    latencies
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect::<Vec<_>>()
+    });
+    let flush = Instant::now();
+    wal.flush().wait().map_err(|e| e.to_string())?;
+    let flush = flush.elapsed();
+    let elapsed = start.elapsed();
+    let bytes = options.records * options.record_size;
+    latencies.sort_unstable();
+    println!(
+        "write: {:.0} records/s, {:.1} MB/s, {} records in {:?}",
+        options.records as f64 / elapsed.as_secs_f64(),
+        mb_per_sec(bytes, elapsed),
+        options.records,
+        elapsed
+    );
+    println!(
+        "write latency: p50 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 0.999),
+        latencies.last().copied().unwrap_or_default()
+    );
+    println!("final flush: {:?}", flush);
+
+    // read phase
+    let start = Instant::now();
+    let read = wal.read()?.count();
+    let elapsed = start.elapsed();
+    println!(
+        "read: {:.0} records/s, {:.1} MB/s, {} records in {:?}",
+        read as f64 / elapsed.as_secs_f64(),
+        mb_per_sec(read * options.record_size, elapsed),
+        read,
+        elapsed
+    );
+    if read != options.records {
+        return Err(format!(
+            "Read {} records out of the {} written",
+            read, options.records
+        ));
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), String> {
+    let options = args(std::env::args().skip(1))?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let location = options
+        .dir
+        .join(format!("walcraft-bench-{}-{}", std::process::id(), nanos));
+    let location = location.to_str().ok_or("Invalid --dir")?.to_string();
+    let result = run(&options, &location);
+    let _ = std::fs::remove_dir_all(&location);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_args() {
+        let parse = |line: &str| args(line.split_whitespace().map(String::from));
+        assert_eq!(parse("").unwrap(), Options::default());
+        let options = parse("--records 10 --threads 2 --sync per-write --buffer-kb 8").unwrap();
+        assert_eq!(options.records, 10);
+        assert_eq!(options.threads, 2);
+        assert_eq!(options.sync, SyncPolicy::PerWrite);
+        assert_eq!(options.buffer_kb, Some(8));
+        assert!(parse("--threads 0").is_err());
+        assert!(parse("--sync never").is_err());
+        assert!(parse("--records").is_err());
+        assert!(parse("--sync unbuffered --buffer-kb 8").is_err());
+    }
+
+    #[test]
+    fn percentiles() {
+        let durations = (1..=1000).map(Duration::from_micros).collect::<Vec<_>>();
+        assert_eq!(percentile(&durations, 0.5), Duration::from_micros(500));
+        assert_eq!(percentile(&durations, 0.999), Duration::from_micros(999));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+
+    #[test]
+    fn bench() {
+        let options = Options {
+            records: 1000,
+            threads: 3,
+            ..Default::default()
+        };
+        let location = "./tmp/walcraft_bench";
+        let _ = std::fs::remove_dir_all(location);
+        run(&options, location).unwrap();
+    }
+}
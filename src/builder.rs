@@ -1,10 +1,20 @@
-use crate::{Size, Wal, WalConfig};
+use crate::codec::Codec;
+use crate::writer::manager::min_storage_size;
+use crate::writer::page::{MAX_PAGE_SIZE, PAGE_SIZE};
+use crate::{
+    BufferOverflow, Checksum, FormatPolicy, IntEncoding, LowDiskSpace, Size, Wal, WalConfig,
+    WalListener, WriteLimit, DEFAULT_BUFFER_SIZE,
+};
 use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Build [Wal] with custom configuration
 ///
-/// It uses a builder pattern and methods can be chained.
+/// It uses a builder pattern and methods can be chained. The builder is typed with the logs
+/// to store, so that the options depending on them are validated by [WalBuilder::build].
 ///
 /// By default, [Wal] uses a buffer of 4 KB, unlimited storage size and fsync is disabled.
 ///
@@ -12,27 +22,98 @@ use std::path::PathBuf;
 /// ```no_run
 /// use walcraft::{Size, WalBuilder, Wal};
 /// // create a wal with 4 KB buffer and 10 GB storage
-/// let wal: Wal<String> = WalBuilder::new().buffer_size(Size::Kb(4)).storage_size(Size::Gb(10)).build().unwrap();
+/// let wal = WalBuilder::<String>::new().buffer_size(Size::Kb(4)).storage_size(Size::Gb(10)).build().unwrap();
 /// // create a wal with no buffer, enable fsync and use 250 MB of storage
 /// let wal: Wal<String> = WalBuilder::new().storage_size(Size::Mb(250)).disable_buffer().enable_fsync().build().unwrap();
 /// ```
-pub struct WalBuilder {
+pub struct WalBuilder<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
     location: Option<String>,
     buffer_enabled: bool,
     buffer_size: Option<Size>,
+    buffer_overflow: Option<BufferOverflow>,
     storage_size: Option<Size>,
     fsync: bool,
+    multi_process: bool,
+    cold_location: Option<String>,
+    compress_segments: bool,
+    compress_records: Option<usize>,
+    listener: Option<Arc<dyn WalListener>>,
+    scrub_interval: Option<Duration>,
+    mirror_location: Option<String>,
+    fallback_location: Option<String>,
+    fencing: bool,
+    slow_threshold: Option<Duration>,
+    write_limit: Option<WriteLimit>,
+    int_encoding: IntEncoding,
+    max_record_size: Option<usize>,
+    power_loss_safe: bool,
+    hash_chain: bool,
+    checksum: Checksum,
+    #[cfg(feature = "signing")]
+    signing_key: Option<[u8; 32]>,
+    lazy_init: bool,
+    format_policy: FormatPolicy,
+    sortable_names: bool,
+    page_size: Option<Size>,
+    preallocate: bool,
+    disk_reserve: Option<(Size, LowDiskSpace)>,
+    max_unflushed: Option<Size>,
+    sync_window: Option<Duration>,
+    _phantom: PhantomData<fn() -> T>,
 }
 
-impl WalBuilder {
+impl<T> Default for WalBuilder<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> WalBuilder<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
     /// Initiate a default instance of [WalBuilder]
     pub fn new() -> Self {
         Self {
             location: None,
             buffer_enabled: true,
-            buffer_size: Some(Size::Kb(4)),
+            buffer_size: None,
+            buffer_overflow: None,
             storage_size: None,
             fsync: false,
+            multi_process: false,
+            cold_location: None,
+            compress_segments: false,
+            compress_records: None,
+            listener: None,
+            scrub_interval: None,
+            mirror_location: None,
+            fallback_location: None,
+            fencing: false,
+            slow_threshold: None,
+            write_limit: None,
+            int_encoding: IntEncoding::Fixint,
+            max_record_size: None,
+            power_loss_safe: false,
+            hash_chain: false,
+            checksum: Checksum::None,
+            #[cfg(feature = "signing")]
+            signing_key: None,
+            lazy_init: false,
+            format_policy: FormatPolicy::Lenient,
+            sortable_names: false,
+            page_size: None,
+            preallocate: false,
+            disk_reserve: None,
+            max_unflushed: None,
+            sync_window: None,
+            _phantom: PhantomData,
         }
     }
 
@@ -49,6 +130,18 @@ impl WalBuilder {
         self
     }
 
+    /// Coalesce the syncs of concurrent writes, with [WalBuilder::enable_fsync]
+    ///
+    /// Instead of syncing every write on its own, the first write waits for the window, then
+    /// syncs the file once for all the writes made meanwhile, every one of them returning once
+    /// it's synced. A window of a few hundred microseconds trades that much latency for far
+    /// fewer syncs under concurrent writers. How many syncs were saved is reported by
+    /// [WalStats::coalesced_syncs](crate::WalStats::coalesced_syncs).
+    pub fn sync_window(mut self, window: Duration) -> Self {
+        self.sync_window = Some(window);
+        self
+    }
+
     /// Disable the use of in-memory buffer to write directly to the disk
     pub fn disable_buffer(mut self) -> Self {
         self.buffer_enabled = false;
@@ -61,48 +154,564 @@ impl WalBuilder {
         self
     }
 
+    /// Bound the data not written to disk yet, and so lost in a crash, across the whole [Wal]
+    ///
+    /// Besides the logs in the buffer, this counts the filled buffers and the logs written
+    /// around the buffer that wait for their turn to be written. Once the limit is reached, the
+    /// buffer is written right away, before it's full, and the thread writing the log waits
+    /// for the writes ahead of it.
+    pub fn max_unflushed(mut self, size: Size) -> Self {
+        self.max_unflushed = Some(size);
+        self
+    }
+
+    /// Set what happens to a log that doesn't fit in the space left in the buffer
+    ///
+    /// Defaults to [BufferOverflow::Grow]. With [BufferOverflow::Reject], [Wal::try_write] gives
+    /// back the logs refused, while [Wal::write] drops them with a warning.
+    pub fn buffer_overflow(mut self, policy: BufferOverflow) -> Self {
+        self.buffer_overflow = Some(policy);
+        self
+    }
+
     /// Set a storage size limit
     pub fn storage_size(mut self, size: Size) -> Self {
         self.storage_size = Some(size);
         self
     }
 
-    pub fn build<T>(self) -> Result<Wal<T>, String>
-    where
-        T: Serialize + for<'a> Deserialize<'a>,
-    {
+    /// Allow multiple processes to append to the same WAL
+    ///
+    /// Every write to disk is done under an exclusive lock on a lock file in the log directory,
+    /// after catching up with the files rotated by the other processes.
+    /// Note: Each process must use the same storage size
+    pub fn multi_process(mut self) -> Self {
+        self.multi_process = true;
+        self
+    }
+
+    /// Set a cold storage location, e.g. a mounted network or object storage
+    ///
+    /// Instead of deleting the older files once the storage size limit is reached, they are moved
+    /// to the cold storage. Reading the logs transparently streams them back from there, so the
+    /// retained history can exceed the local storage size.
+    pub fn cold_storage(mut self, loc: &str) -> Self {
+        self.cold_location = Some(loc.to_string());
+        self
+    }
+
+    /// Compress the log files with zstd once they are filled
+    ///
+    /// Writes always go to an uncompressed file, keeping the hot path fast,
+    /// while the older logs take up less space.
+    #[cfg(feature = "compression")]
+    pub fn compress_segments(mut self) -> Self {
+        self.compress_segments = true;
+        self
+    }
+
+    /// Compress every log at least `threshold` bytes long on its own, with zstd
+    ///
+    /// Large logs, such as JSON documents, take up less space, while the small ones skip the
+    /// overhead of the compressor. This is independent of [WalBuilder::compress_segments].
+    ///
+    /// Note: Every log takes 1 more byte on disk, marking whether it's compressed
+    #[cfg(feature = "compression")]
+    pub fn compress_records(mut self, threshold: usize) -> Self {
+        self.compress_records = Some(threshold);
+        self
+    }
+
+    /// Enable writer fencing with epochs
+    ///
+    /// Every writer opening the WAL increments an epoch stored in meta. Before every write to disk,
+    /// the writer checks the epoch and refuses to append if a newer writer has opened the WAL since,
+    /// e.g. after a failover. This protects against split-brain when the location is on shared storage.
+    ///
+    /// Note: This can't be combined with [WalBuilder::multi_process]
+    pub fn enable_fencing(mut self) -> Self {
+        self.fencing = true;
+        self
+    }
+
+    /// Set a mirror location, ideally on a different disk
+    ///
+    /// Everything written to the log files is written to the mirror location as well.
+    /// Reading the logs prefers the primary location and falls back to the mirror,
+    /// so that the logs survive the loss of a single disk.
+    pub fn mirror(mut self, loc: &str) -> Self {
+        self.mirror_location = Some(loc.to_string());
+        self
+    }
+
+    /// Set a fallback location, ideally on a different disk
+    ///
+    /// Once the writes to the primary location fail 3 times in a row, e.g. because its disk
+    /// failed, the writer switches to the fallback location for good, so that the logs keep
+    /// being written during the incident. The logs written from then on, along with the
+    /// pointers, go to the fallback location, and the older logs are read from the primary one.
+    /// The switch is recorded in a `failover` file in the fallback location, which the readers
+    /// and the writers opening the WAL later on follow.
+    ///
+    /// The other files, e.g. the consumer offsets and snapshots, stay in the primary location.
+    ///
+    /// Note: This can't be combined with [WalBuilder::multi_process]
+    pub fn fallback(mut self, loc: &str) -> Self {
+        self.fallback_location = Some(loc.to_string());
+        self
+    }
+
+    /// Set a listener to receive notifications about events inside [Wal]
+    pub fn listener(mut self, listener: Arc<dyn WalListener>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Enable a low-priority background scrubber, which re-reads the filled log files once every
+    /// `interval` and validates their checksums
+    ///
+    /// Any corrupted file is reported to the [WalListener], so that bad sectors are found
+    /// before a recovery depends on that data.
+    pub fn scrub_interval(mut self, interval: Duration) -> Self {
+        self.scrub_interval = Some(interval);
+        self
+    }
+
+    /// Warn about the operations taking longer than the threshold
+    ///
+    /// Flushes, fsyncs, file rotations and garbage collection runs are timed, and the slow ones
+    /// are reported to the [WalListener], or printed to stderr if there is no listener.
+    /// Handy for diagnosing spikes in write latency.
+    pub fn slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Cap the rate of the writes, in logs or bytes per second
+    ///
+    /// The writes past the rate wait for their turn, so that a misbehaving upstream can't flood
+    /// the WAL and blow through the retention. The limit can be changed later with
+    /// [Wal::set_write_limit].
+    pub fn write_limit(mut self, limit: WriteLimit) -> Self {
+        self.write_limit = Some(limit);
+        self
+    }
+
+    /// Set how bincode encodes the integers inside the logs
+    ///
+    /// The default [IntEncoding::Fixint] matches `bincode::serialize`. Use [IntEncoding::Varint]
+    /// for smaller logs, or to match a wire format used elsewhere.
+    /// Note: Logs written with one encoding can't be read with the other
+    pub fn int_encoding(mut self, encoding: IntEncoding) -> Self {
+        self.int_encoding = encoding;
+        self
+    }
+
+    /// Set the maximum size of a serialized log, in bytes
+    ///
+    /// Larger logs are refused when writing, and reading never allocates more than this for a
+    /// single log, even if a length inside it is corrupted.
+    pub fn max_record_size(mut self, bytes: usize) -> Self {
+        self.max_record_size = Some(bytes);
+        self
+    }
+
+    /// Tune the writes for flash storage that loses power without warning, such as SD cards
+    ///
+    /// Every write covers whole pages, aligned to the page size and padded with zeros, and each
+    /// page carries a checksum. A write cut short by a power loss is dropped entirely at the next
+    /// open, however it was torn. The pointers are kept in two slots written alternately, instead
+    /// of being rewritten in place.
+    ///
+    /// Note: Small writes take up a whole page each, so a larger buffer wastes less space
+    pub fn power_loss_safe(mut self) -> Self {
+        self.power_loss_safe = true;
+        self
+    }
+
+    /// Chain the logs together for tamper evidence, e.g. for audit logs
+    ///
+    /// Every log carries the SHA-256 hash of the log before it, along with all the logs before
+    /// that, so [Wal::verify] detects any log modified or deleted within the logs kept.
+    ///
+    /// Note: Each log takes 32 more bytes on disk, and it can't be combined with multi-process
+    pub fn hash_chain(mut self) -> Self {
+        self.hash_chain = true;
+        self
+    }
+
+    /// Store a checksum in front of every log, computed with the given algorithm
+    ///
+    /// Reading checks every log against its checksum, and skips the ones that don't match.
+    /// [Wal::read_raw] reports the outcome in [RecordMeta::crc_ok](crate::RecordMeta::crc_ok).
+    /// Defaults to [Checksum::None], relying on the checksum of the whole file.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Set how to treat the log files written by a newer version of walcraft, see [FormatPolicy]
+    ///
+    /// Defaults to [FormatPolicy::Lenient]. With [FormatPolicy::Strict], [WalBuilder::build]
+    /// fails while the WAL holds such files.
+    pub fn format_policy(mut self, policy: FormatPolicy) -> Self {
+        self.format_policy = policy;
+        self
+    }
+
+    /// Name the new log files like `log_00000000000000000042_20240501T120000.bin`, i.e. with
+    /// the index zero-padded and followed by the UTC time the file was created at
+    ///
+    /// Listing the directory in alphabetical order lists the files in the order of the logs,
+    /// which helps external tools shipping or archiving them. The files named before, as
+    /// `log_42.bin`, are still read and collected.
+    pub fn sortable_file_names(mut self) -> Self {
+        self.sortable_names = true;
+        self
+    }
+
+    /// Set the size of the pages, a power of two from 4 KB to 1 MB, defaulting to 4 KB
+    ///
+    /// Every file takes at least a page, and in the power-loss-safe mode the logs are written in
+    /// whole checksummed pages, so the size is best matched to the block size of the storage,
+    /// e.g. larger for NVMe drives than for SD cards. It's recorded in every file, so files with
+    /// pages of different sizes can be read along each other.
+    pub fn page_size(mut self, size: Size) -> Self {
+        self.page_size = Some(size);
+        self
+    }
+
+    /// Reserve the whole storage size on disk up front, for appliances with strict capacity
+    /// planning
+    ///
+    /// All the files up to the storage limit are created at once, and given the disk blocks of
+    /// a full file, so the disk usage stays constant from the start and the writes never run
+    /// out of space. A file freed by the garbage collection is replaced by a new one right away.
+    /// The files keep the size of the logs in them, and are read as usual.
+    ///
+    /// Requires [WalBuilder::storage_size], and can't be combined with `compress_segments`. On other platforms than Linux, the files are created
+    /// without reserving their disk blocks.
+    ///
+    /// Note: With [WalBuilder::sortable_file_names], the time in the name of a file is the time
+    /// it was created at, ahead of being written to
+    pub fn preallocate(mut self) -> Self {
+        self.preallocate = true;
+        self
+    }
+
+    /// Keep some space free on the disk of the location, checked before every write to disk
+    ///
+    /// A write that would leave less than the reserve free is handled by the policy before
+    /// anything is written, instead of running out of space halfway through the buffer. The
+    /// refused writes are reported as failed, see [Wal::last_error], and count towards
+    /// switching to the [WalBuilder::fallback] location.
+    ///
+    /// Note: The free space is only checked on Linux
+    pub fn disk_reserve(mut self, reserve: Size, policy: LowDiskSpace) -> Self {
+        self.disk_reserve = Some((reserve, policy));
+        self
+    }
+
+    /// Sign every log file with an Ed25519 key once it's filled, for audit logs
+    ///
+    /// The signature covers the whole content of the file, and is recorded in the manifest.
+    /// Anyone holding the public key can then check the files with
+    /// [verify_signed](crate::verify_signed), e.g. after they were exported.
+    ///
+    /// Note: The disk blocks of signed files are never released by
+    /// [Wal::delete_segments_before], as that would change their content
+    #[cfg(feature = "signing")]
+    pub fn signing_key(mut self, secret_key: [u8; 32]) -> Self {
+        self.signing_key = Some(secret_key);
+        self
+    }
+
+    /// Defer creating the directories and the files until the first log is written to disk,
+    /// or [Wal::init] is called
+    ///
+    /// Tools that mostly read, and misconfigured locations, then leave nothing behind.
+    /// The WAL is read as empty until then.
+    ///
+    /// Note: With [WalBuilder::enable_fencing], the newer epoch is taken once the files are opened
+    pub fn lazy_init(mut self) -> Self {
+        self.lazy_init = true;
+        self
+    }
+
+    /// Create the [Wal] with the configuration
+    ///
+    /// ## Returns
+    /// A [ConfigError] naming the option at fault, if the options conflict or the locations
+    /// can't be created
+    pub fn build(self) -> Result<Wal<T>, ConfigError> {
         // validate location
         let location = match self.location {
-            None => {
-                return Err("Location field is required".to_string());
-            }
-            Some(loc) => loc,
+            None => return Err(ConfigError::new("location", "A location is required")),
+            Some(loc) => PathBuf::from(loc),
         };
-        let location = PathBuf::from(location);
-        if let Err(e) = std::fs::create_dir_all(location.as_path()) {
-            let s = format!("Failed to access location: {}", e.to_string());
-            return Err(s);
-        }
-        // buffer size in KBs
-        let buffer_size = match self.buffer_enabled {
-            true => self.buffer_size.map(|size| size.to_bytes()).unwrap_or(0),
-            false => 0,
+        let cold_location = self.cold_location.map(PathBuf::from);
+        // WASI has neither file locks nor threads
+        if cfg!(target_os = "wasi") && self.multi_process {
+            return Err(ConfigError::new(
+                "multi_process",
+                "Multi-process WAL isn't supported on WASI",
+            ));
+        }
+        if cfg!(target_os = "wasi") && self.scrub_interval.is_some() {
+            return Err(ConfigError::new(
+                "scrub_interval",
+                "Scrubbing isn't supported on WASI",
+            ));
+        }
+        if self.fencing && self.multi_process {
+            return Err(ConfigError::new(
+                "enable_fencing",
+                "Fencing can't be enabled for multi-process WAL",
+            ));
+        }
+        // the link of the last log isn't shared between the processes
+        if self.hash_chain && self.multi_process {
+            return Err(ConfigError::new(
+                "hash_chain",
+                "Hash chain can't be enabled for multi-process WAL",
+            ));
+        }
+        let mirror_location = self.mirror_location.map(PathBuf::from);
+        // the other processes would keep writing to the primary location
+        if self.fallback_location.is_some() && self.multi_process {
+            return Err(ConfigError::new(
+                "fallback",
+                "A fallback location can't be set for multi-process WAL",
+            ));
+        }
+        let fallback_location = self.fallback_location.map(PathBuf::from);
+        if fallback_location.as_ref() == Some(&location) {
+            return Err(ConfigError::new(
+                "fallback",
+                "The fallback location must differ from the location",
+            ));
+        }
+        // buffer size in bytes
+        let buffer_size = match (self.buffer_enabled, self.buffer_size) {
+            (true, Some(size)) => size.to_bytes(),
+            (true, None) => DEFAULT_BUFFER_SIZE,
+            (false, None) => 0,
+            (false, Some(_)) => {
+                return Err(ConfigError::new(
+                    "buffer_size",
+                    "A buffer size can't be set along with a disabled buffer",
+                ))
+            }
         };
+        if !self.buffer_enabled && self.buffer_overflow.is_some() {
+            return Err(ConfigError::new(
+                "buffer_overflow",
+                "A buffer overflow policy can't be set along with a disabled buffer",
+            ));
+        }
+        if self.buffer_enabled && buffer_size == 0 {
+            return Err(ConfigError::new(
+                "buffer_size",
+                "The buffer size must be above zero, or the buffer disabled",
+            ));
+        }
+        if self.write_limit.is_some_and(|limit| limit.rate() == 0) {
+            return Err(ConfigError::new(
+                "write_limit",
+                "The write limit must be above zero",
+            ));
+        }
+        let page_size = self.page_size.map_or(PAGE_SIZE, |size| size.to_bytes());
+        if !page_size.is_power_of_two() || !(PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(ConfigError::new(
+                "page_size",
+                "The page size must be a power of two from 4 KB to 1 MB",
+            ));
+        }
+        let size = self
+            .storage_size
+            .map(|size| size.to_bytes())
+            .unwrap_or(usize::MAX);
+        // the storage is split into several files, each at least a page long
+        if size < min_storage_size(page_size) {
+            return Err(ConfigError::new(
+                "storage_size",
+                format!(
+                    "The storage size must be at least {} bytes",
+                    min_storage_size(page_size)
+                ),
+            ));
+        }
+        if self.preallocate && self.storage_size.is_none() {
+            return Err(ConfigError::new(
+                "preallocate",
+                "A storage size is required to preallocate it",
+            ));
+        }
+        if self.sync_window.is_some() && !self.fsync {
+            return Err(ConfigError::new(
+                "sync_window",
+                "Fsync must be enabled for its syncs to be coalesced",
+            ));
+        }
+        // the space of the preallocated files is taken already
+        if self.preallocate && self.disk_reserve.is_some() {
+            return Err(ConfigError::new(
+                "disk_reserve",
+                "The disk space is reserved already when the storage is preallocated",
+            ));
+        }
+        // the compressed files would give back the space reserved for them
+        if self.preallocate && self.compress_segments {
+            return Err(ConfigError::new(
+                "preallocate",
+                "The storage can't be preallocated along with compressed files",
+            ));
+        }
         // create Wal
         let config = WalConfig {
             location,
-            size: self
-                .storage_size
-                .map(|size| size.to_bytes())
-                .unwrap_or(usize::MAX),
+            size,
             fsync: self.fsync,
             buffer_size,
+            buffer_overflow: self.buffer_overflow.unwrap_or_default(),
+            multi_process: self.multi_process,
+            cold_location,
+            compress_segments: self.compress_segments,
+            compress_records: self.compress_records,
+            listener: self.listener,
+            scrub_interval: self.scrub_interval,
+            mirror_location,
+            fallback_location,
+            fencing: self.fencing,
+            slow_threshold: self.slow_threshold,
+            write_limit: self.write_limit,
+            codec: Codec {
+                int_encoding: self.int_encoding,
+                limit: self.max_record_size.map(|bytes| bytes as u64),
+            },
+            power_loss_safe: self.power_loss_safe,
+            hash_chain: self.hash_chain,
+            checksum: self.checksum,
+            #[cfg(feature = "signing")]
+            signing_key: self
+                .signing_key
+                .map(|key| ed25519_dalek::SigningKey::from_bytes(&key)),
+            lazy_init: self.lazy_init,
+            format_policy: self.format_policy,
+            sortable_names: self.sortable_names,
+            page_size,
+            preallocate: self.preallocate,
+            disk_reserve: self
+                .disk_reserve
+                .map(|(reserve, policy)| (reserve.to_bytes(), policy)),
+            max_unflushed: self.max_unflushed.map(|size| size.to_bytes()),
+            sync_window: self.sync_window,
+            ..Default::default()
         };
+        if let Some(bytes) = self.max_record_size {
+            // a limit past the size of a frame would let through logs that can't be written
+            let max = config.max_record_size();
+            if bytes > max {
+                return Err(ConfigError::new(
+                    "max_record_size",
+                    format!(
+                        "Max record size of {} logs can be at most {} bytes",
+                        std::any::type_name::<T>(),
+                        max
+                    ),
+                ));
+            }
+            // the largest log must fit in the buffer along with its frame, size and kind
+            if buffer_size > 0 && buffer_size < bytes + 3 {
+                return Err(ConfigError::new(
+                    "buffer_size",
+                    format!(
+                        "The buffer size must be at least {} bytes, to hold the largest log",
+                        bytes + 2
+                    ),
+                ));
+            }
+        }
+        if self.format_policy == FormatPolicy::Strict {
+            let newer = crate::iter::newer_segments(&config);
+            if !newer.is_empty() {
+                return Err(ConfigError::new(
+                    "format_policy",
+                    format!(
+                        "Files {:?} were written by a newer version of walcraft",
+                        newer
+                    ),
+                ));
+            }
+        }
+        // validate the locations
+        if !config.lazy_init {
+            create_dirs(&config)?;
+        }
+        let lazy = config.lazy_init;
         let wal = Wal::with_config(config);
+        // the files are opened already, refused if they can't be, unless that's left for later
+        if !lazy {
+            wal.init()?;
+        }
         Ok(wal)
     }
 }
 
+/// An invalid configuration refused by [WalBuilder::build]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Name of the option at fault, after the method of [WalBuilder] setting it
+    pub field: &'static str,
+    /// What's wrong with it
+    pub reason: String,
+}
+
+impl ConfigError {
+    pub(crate) fn new(field: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            field,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid {}: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Create the directories of the log files, along with the cold storage, mirror and fallback ones
+///
+/// ## Returns
+/// An error if a directory can't be created, or isn't writable
+pub(crate) fn create_dirs(config: &WalConfig) -> Result<(), ConfigError> {
+    let dirs = [
+        ("location", Some(&config.location)),
+        ("cold_storage", config.cold_location.as_ref()),
+        ("mirror", config.mirror_location.as_ref()),
+        ("fallback", config.fallback_location.as_ref()),
+    ];
+    for (field, dir) in dirs {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => continue,
+        };
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            return Err(ConfigError::new(field, format!("Failed to access: {}", e)));
+        }
+        let readonly = std::fs::metadata(dir).map(|m| m.permissions().readonly());
+        if readonly.unwrap_or(true) {
+            return Err(ConfigError::new(field, "The directory isn't writable"));
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -115,7 +724,9 @@ mod tests {
 
     #[test]
     fn it_works() {
-        let wal = WalBuilder::new().location("./tmp/dupe").build::<Log>();
+        let wal = WalBuilder::<Log>::new()
+            .location("./tmp/builder_it_works")
+            .build();
         assert!(wal.is_ok());
     }
 
@@ -125,24 +736,108 @@ mod tests {
         std::fs::remove_dir_all(location).ok();
 
         // write some data
-        let wal = WalBuilder::new()
+        let wal = WalBuilder::<Log>::new()
             .location(location)
             .disable_buffer()
-            .build::<Log>()
+            .build()
             .unwrap();
-        wal.write(Log { id: 1, value: 3.14 });
-        wal.write(Log { id: 2, value: 6.14 });
-        wal.write(Log { id: 3, value: 9.14 });
+        wal.write(Log { id: 1, value: 3.25 });
+        wal.write(Log { id: 2, value: 6.25 });
+        wal.write(Log { id: 3, value: 9.25 });
         drop(wal);
 
         // try reading data
-        let wal = WalBuilder::new()
+        let wal = WalBuilder::<Log>::new()
             .location(location)
             .disable_buffer()
-            .build::<Log>()
+            .build()
             .unwrap();
         wal.flush();
         let data = wal.read().unwrap().collect::<Vec<_>>();
         assert_eq!(data.len(), 3);
     }
+
+    #[test]
+    fn max_record_size() {
+        let builder = || {
+            WalBuilder::<Log>::new()
+                .location("./tmp/builder_max_record_size")
+                .disable_buffer()
+        };
+        assert!(builder().max_record_size(1024).build().is_ok());
+        assert!(builder()
+            .max_record_size(u16::MAX as usize - 1)
+            .build()
+            .is_ok());
+        // the kind and the checksum take up some of the frame
+        assert!(builder()
+            .max_record_size(u16::MAX as usize)
+            .build()
+            .is_err());
+        let result = builder()
+            .checksum(Checksum::Crc32c)
+            .max_record_size(u16::MAX as usize - 1)
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn config_errors() {
+        let field = |builder: WalBuilder<Log>| builder.build().err().map(|e| e.field);
+        let location = "./tmp/builder_config_errors";
+        let builder = || WalBuilder::<Log>::new().location(location);
+        // a regular file can't hold the files of the mirror
+        std::fs::create_dir_all(location).unwrap();
+        let file = format!("{}/not_a_dir", location);
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(field(WalBuilder::new()), Some("location"));
+        assert_eq!(
+            field(builder().disable_buffer().buffer_size(Size::Kb(8))),
+            Some("buffer_size")
+        );
+        assert_eq!(
+            field(builder().buffer_size(Size::Kb(0))),
+            Some("buffer_size")
+        );
+        assert_eq!(field(builder().max_record_size(8000)), Some("buffer_size"));
+        assert_eq!(
+            field(builder().storage_size(Size::Kb(8))),
+            Some("storage_size")
+        );
+        assert_eq!(
+            field(builder().multi_process().hash_chain()),
+            Some("hash_chain")
+        );
+        assert_eq!(field(builder().mirror(&file)), Some("mirror"));
+        assert_eq!(
+            field(builder().multi_process().fallback("./tmp/builder_fallback")),
+            Some("fallback")
+        );
+        assert_eq!(field(builder().fallback(location)), Some("fallback"));
+        assert_eq!(field(builder().page_size(Size::Kb(6))), Some("page_size"));
+        assert_eq!(field(builder().page_size(Size::Kb(2))), Some("page_size"));
+        assert_eq!(field(builder().page_size(Size::Mb(2))), Some("page_size"));
+        assert_eq!(
+            field(builder().page_size(Size::Mb(1)).storage_size(Size::Mb(2))),
+            Some("storage_size")
+        );
+        assert_eq!(
+            field(builder().disable_buffer().storage_size(Size::Kb(16))),
+            None
+        );
+    }
+
+    #[test]
+    fn unopenable_location() {
+        let location = "./tmp/builder_unopenable";
+        std::fs::remove_dir_all(location).ok();
+        // a directory stands in the way of the first file
+        std::fs::create_dir_all(format!("{}/log_0.bin", location)).unwrap();
+        let builder = || WalBuilder::<Log>::new().location(location);
+        let error = builder().build().err().map(|e| e.field);
+        assert_eq!(error, Some("location"));
+        // the files of a lazily initialized WAL are only opened by init
+        let wal = builder().lazy_init().build().unwrap();
+        assert_eq!(wal.init().err().map(|e| e.field), Some("location"));
+    }
 }
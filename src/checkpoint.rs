@@ -0,0 +1,223 @@
+use crate::stats::Monitor;
+use crate::{Lsn, Size, Wal};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the [Checkpointer] checks whether a checkpoint is due
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Delay before retrying a failed checkpoint, doubled on every consecutive failure
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Longest delay between retries of a failed checkpoint
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// When a [Checkpointer] takes a checkpoint, whichever comes first
+///
+/// With neither set, no checkpoint is ever taken.
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointPolicy {
+    /// Time since the last checkpoint
+    pub interval: Option<Duration>,
+    /// Amount of logs written since the last checkpoint
+    pub bytes: Option<Size>,
+}
+
+/// Outcome of the checkpoints taken by a [Checkpointer]
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointStats {
+    /// Number of checkpoints taken
+    pub succeeded: u64,
+    /// Number of checkpoints that failed
+    pub failed: u64,
+    /// [Lsn] covered by the last checkpoint taken
+    pub last_lsn: Option<Lsn>,
+    /// Time taken by the last checkpoint, including the truncation of the logs
+    pub last_duration: Option<Duration>,
+    /// Error of the last checkpoint, if it failed
+    pub last_error: Option<String>,
+}
+
+/// Background task which takes checkpoints with [Wal::snapshot_with] according to a
+/// [CheckpointPolicy], so that the logs don't grow unbounded in long-running services
+///
+/// A failed checkpoint is reported to the [WalListener](crate::WalListener) and retried with
+/// an exponential backoff. The task stops once the [Checkpointer] is dropped.
+///
+/// ### Example
+/// ```no_run
+/// use std::io::Write;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use walcraft::{CheckpointPolicy, Checkpointer, Size, Wal};
+///
+/// let wal: Wal<u64> = Wal::new("/tmp/logz", None);
+/// let state = Arc::new(Mutex::new(0u64));
+/// let policy = CheckpointPolicy {
+///     interval: Some(Duration::from_secs(300)),
+///     bytes: Some(Size::Mb(64)),
+/// };
+/// let shared = state.clone();
+/// let checkpointer = Checkpointer::start(wal.clone(), policy, move |writer| {
+///     writer.write_all(&shared.lock().unwrap().to_le_bytes())
+/// });
+/// ```
+pub struct Checkpointer {
+    stop: Arc<AtomicBool>,
+    stats: Arc<Mutex<CheckpointStats>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    /// Start taking checkpoints of the [Wal]
+    ///
+    /// The closure writes the snapshot of the application state, as with [Wal::snapshot_with].
+    /// It must not write to the [Wal].
+    pub fn start<T, F>(wal: Wal<T>, policy: CheckpointPolicy, snapshot: F) -> Self
+    where
+        T: Serialize + for<'a> Deserialize<'a> + Send + Sync + 'static,
+        F: FnMut(&mut dyn Write) -> std::io::Result<()> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(Mutex::new(CheckpointStats::default()));
+        let (flag, shared) = (stop.clone(), stats.clone());
+        let handle = std::thread::Builder::new()
+            .name("walcraft-checkpointer".to_string())
+            .spawn(move || Self::run(wal, policy, snapshot, flag, shared))
+            .ok();
+        Self {
+            stop,
+            stats,
+            handle,
+        }
+    }
+
+    /// Outcome of the checkpoints taken so far
+    pub fn stats(&self) -> CheckpointStats {
+        self.stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn run<T, F>(
+        wal: Wal<T>,
+        policy: CheckpointPolicy,
+        mut snapshot: F,
+        stop: Arc<AtomicBool>,
+        stats: Arc<Mutex<CheckpointStats>>,
+    ) where
+        T: Serialize + for<'a> Deserialize<'a>,
+        F: FnMut(&mut dyn Write) -> std::io::Result<()>,
+    {
+        let mut last = Instant::now();
+        let mut written = wal.stats().bytes_written;
+        let mut failures = 0;
+        let mut retry_at = None;
+        while !stop.load(Relaxed) {
+            std::thread::park_timeout(POLL_INTERVAL);
+            let now = Instant::now();
+            if stop.load(Relaxed) || retry_at.is_some_and(|at| now < at) {
+                continue;
+            }
+            let elapsed = policy.interval.is_some_and(|i| now - last >= i);
+            let filled = policy
+                .bytes
+                .is_some_and(|size| wal.stats().bytes_written - written >= size.to_bytes() as u64);
+            if !elapsed && !filled {
+                continue;
+            }
+            let result = wal.snapshot_with(&mut snapshot);
+            let mut stats = stats.lock().unwrap_or_else(PoisonError::into_inner);
+            match result {
+                Ok(lsn) => {
+                    last = now;
+                    written = wal.stats().bytes_written;
+                    failures = 0;
+                    retry_at = None;
+                    stats.succeeded += 1;
+                    stats.last_lsn = Some(lsn);
+                    stats.last_duration = Some(now.elapsed());
+                    stats.last_error = None;
+                }
+                Err(e) => {
+                    let delay = RETRY_DELAY.saturating_mul(1 << failures.min(6));
+                    failures += 1;
+                    retry_at = Some(now + delay.min(MAX_RETRY_DELAY));
+                    stats.failed += 1;
+                    stats.last_error = Some(e.clone());
+                    if let Some(listener) = wal.inner.config.listener.as_ref() {
+                        listener.on_checkpoint_failure(&e);
+                    }
+                    let message = format!("WAL checkpoint failed: {}", e);
+                    Monitor::new(&wal.inner.config).report(message, None);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoints() {
+        let location = "./tmp/checkpoints";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal: Wal<String> = Wal::new(location, None);
+        let policy = CheckpointPolicy {
+            interval: None,
+            bytes: Some(Size::Kb(1)),
+        };
+        let checkpointer =
+            Checkpointer::start(wal.clone(), policy, |writer| writer.write_all(b"state"));
+        // not due until enough logs are written
+        std::thread::sleep(POLL_INTERVAL * 3);
+        assert_eq!(checkpointer.stats().succeeded, 0);
+        wal.write_iter((0..100).map(|i| format!("log {}", i)));
+        wal.flush();
+        let start = Instant::now();
+        while checkpointer.stats().succeeded == 0 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        let stats = checkpointer.stats();
+        assert_eq!((stats.succeeded, stats.failed), (1, 0));
+        assert_eq!(stats.last_lsn, Some(100));
+        assert_eq!(wal.snapshot().unwrap().unwrap().0, 100);
+    }
+
+    #[test]
+    fn failures() {
+        let location = "./tmp/checkpoint_failures";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal: Wal<String> = Wal::new(location, None);
+        let policy = CheckpointPolicy {
+            interval: Some(Duration::ZERO),
+            bytes: None,
+        };
+        let checkpointer = Checkpointer::start(wal.clone(), policy, |_| {
+            Err(std::io::Error::other("disk full"))
+        });
+        std::thread::sleep(POLL_INTERVAL * 5);
+        // retried after a backoff, instead of on every poll
+        let stats = checkpointer.stats();
+        assert_eq!(stats.failed, 1);
+        assert!(stats.last_error.unwrap().contains("disk full"));
+        assert!(wal.snapshot().unwrap().is_none());
+    }
+}
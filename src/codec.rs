@@ -0,0 +1,130 @@
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Encoding of the integers inside the logs, set with [WalBuilder::int_encoding](crate::WalBuilder::int_encoding)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IntEncoding {
+    /// Integers take up their full size, e.g. 8 bytes for a `u64`
+    #[default]
+    Fixint,
+    /// Smaller integers take up fewer bytes
+    Varint,
+}
+
+/// The bincode options used to serialize and deserialize the logs
+///
+/// The defaults produce the same bytes as `bincode::serialize`, so existing logs stay readable.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub(crate) struct Codec {
+    pub int_encoding: IntEncoding,
+    // maximum size of a serialized log in bytes
+    pub limit: Option<u64>,
+}
+
+/// Run `$body` with the bincode options `$o`, built from the integer encoding and the byte limit
+macro_rules! with_options {
+    ($encoding:expr, $limit:expr, |$o:ident| $body:expr) => {{
+        let options = bincode::DefaultOptions::new().allow_trailing_bytes();
+        match ($encoding, $limit) {
+            (IntEncoding::Fixint, None) => {
+                let $o = options.with_fixint_encoding();
+                $body
+            }
+            (IntEncoding::Fixint, Some(limit)) => {
+                let $o = options.with_fixint_encoding().with_limit(limit);
+                $body
+            }
+            (IntEncoding::Varint, None) => {
+                let $o = options.with_varint_encoding();
+                $body
+            }
+            (IntEncoding::Varint, Some(limit)) => {
+                let $o = options.with_varint_encoding().with_limit(limit);
+                $body
+            }
+        }
+    }};
+}
+
+impl Codec {
+    pub fn serialize<T: Serialize>(&self, item: &T) -> bincode::Result<Vec<u8>> {
+        with_options!(self.int_encoding, self.limit, |o| o.serialize(item))
+    }
+
+    /// Size of a log once serialized, without serializing it
+    pub fn serialized_size<T: Serialize>(&self, item: &T) -> bincode::Result<u64> {
+        with_options!(self.int_encoding, self.limit, |o| o.serialized_size(item))
+    }
+
+    pub fn serialize_into<W: Write, T: Serialize>(
+        &self,
+        writer: W,
+        item: &T,
+    ) -> bincode::Result<()> {
+        with_options!(self.int_encoding, self.limit, |o| o
+            .serialize_into(writer, item))
+    }
+
+    /// Deserialize a log, never reading or allocating more than the size of the record
+    /// even if a length inside it is corrupted
+    pub fn deserialize<T: for<'a> Deserialize<'a>>(&self, bytes: &[u8]) -> bincode::Result<T> {
+        let limit = self.limit.unwrap_or(u64::MAX).min(bytes.len() as u64);
+        with_options!(self.int_encoding, Some(limit), |o| o.deserialize(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Log {
+        id: u64,
+        name: String,
+    }
+
+    #[test]
+    fn default_matches_bincode() {
+        let log = Log {
+            id: 7,
+            name: "seven".to_string(),
+        };
+        let codec = Codec::default();
+        let bytes = codec.serialize(&log).unwrap();
+        assert_eq!(bytes, bincode::serialize(&log).unwrap());
+        assert_eq!(codec.deserialize::<Log>(&bytes).unwrap(), log);
+    }
+
+    #[test]
+    fn varint() {
+        let log = Log {
+            id: 7,
+            name: "seven".to_string(),
+        };
+        let codec = Codec {
+            int_encoding: IntEncoding::Varint,
+            limit: None,
+        };
+        let bytes = codec.serialize(&log).unwrap();
+        assert_eq!(bytes.len(), 7);
+        assert_eq!(codec.deserialize::<Log>(&bytes).unwrap(), log);
+    }
+
+    #[test]
+    fn limits() {
+        let log = Log {
+            id: 7,
+            name: "x".repeat(100),
+        };
+        let codec = Codec {
+            int_encoding: IntEncoding::Fixint,
+            limit: Some(64),
+        };
+        assert!(codec.serialize(&log).is_err());
+        // a corrupted length claiming a huge string is rejected up front
+        let mut bytes = Codec::default().serialize(&log).unwrap();
+        bytes[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Codec::default().deserialize::<Log>(&bytes).is_err());
+    }
+}
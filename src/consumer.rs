@@ -0,0 +1,363 @@
+use crate::segments;
+use crate::{Lsn, Wal, WalConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Prefix of the files storing the offsets of the consumers, in the location of the log files
+const OFFSET_FILE_PREFIX: &str = "consumer.";
+
+/// Consumes the logs of a [Wal] at least once, as a lightweight durable task queue
+///
+/// Every consumer belongs to a named group, with its own offset, so several groups read the
+/// same logs independently, e.g. an indexer and a metrics exporter, each at its own pace.
+/// A group is meant to have a single [Consumer] at a time, shared between the workers of
+/// the group as needed. The groups are listed by [Wal::consumer_groups].
+///
+/// Logs are leased in batches, and each one must be acknowledged once processed. A log that
+/// isn't acknowledged within the visibility timeout is delivered again by a later lease.
+///
+/// The offset of the group, i.e. the [Lsn] before which all the logs are acknowledged, is
+/// stored in the location of the log files, so a restarted consumer carries on from there.
+/// The logs leased but not acknowledged before a restart are delivered again.
+///
+/// Only the logs on disk are leased, so the logs waiting in the buffer aren't seen before
+/// [Wal::flush]. The logs deleted before being acknowledged, e.g. by the storage limit,
+/// are skipped.
+///
+/// ### Example
+/// ```no_run
+/// use std::time::Duration;
+/// use walcraft::{Consumer, Wal};
+///
+/// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+/// let consumer = Consumer::open(wal, "mailer", Duration::from_secs(30)).unwrap();
+/// for (lsn, task) in consumer.lease(10).unwrap() {
+///     println!("{}", task);
+///     consumer.ack(lsn).unwrap();
+/// }
+/// ```
+pub struct Consumer<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    wal: Wal<T>,
+    group: String,
+    path: PathBuf,
+    visibility_timeout: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// [Lsn] of the next log never delivered
+    next: Lsn,
+    /// Logs delivered and not acknowledged yet, with the moment they are due to be delivered again
+    pending: BTreeMap<Lsn, Instant>,
+    /// The stored offset
+    committed: Lsn,
+}
+
+impl<T> Consumer<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Open a consumer of the [Wal], resuming from the stored offset of its group if any
+    ///
+    /// ## Arguments
+    /// - `wal`: The [Wal] to consume
+    /// - `group`: Name of the group, made of ASCII letters, digits, `-` and `_`
+    /// - `visibility_timeout`: How long a leased log waits for its acknowledgement before it's
+    ///   delivered again
+    pub fn open(wal: Wal<T>, group: &str, visibility_timeout: Duration) -> Result<Self, String> {
+        let path = offset_path(&wal.inner.config, group)?;
+        let committed = match std::fs::read_to_string(&path) {
+            Ok(content) => content
+                .trim()
+                .parse()
+                .map_err(|_| format!("Corrupted offset of consumer group {}", group))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(format!("Failed to read consumer offset: {}", e)),
+        };
+        let state = State {
+            next: committed,
+            pending: BTreeMap::new(),
+            committed,
+        };
+        Ok(Self {
+            wal,
+            group: group.to_string(),
+            path,
+            visibility_timeout,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Lease up to `max` logs, along with their [Lsn]
+    ///
+    /// The logs whose visibility timeout has expired come first, followed by the logs never
+    /// delivered, in order.
+    pub fn lease(&self, max: usize) -> Result<Vec<(Lsn, T)>, String> {
+        let mut state = self.state();
+        let now = Instant::now();
+        let mut expired = state
+            .pending
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(lsn, _)| *lsn)
+            .take(max)
+            .collect::<BTreeSet<_>>();
+        let mut leased = Vec::new();
+        if max == 0 {
+            return Ok(leased);
+        }
+        let start = expired.first().copied().unwrap_or(state.next);
+        let mut exhausted = true;
+        let mut last = None;
+        for (lsn, item) in self.wal.read_from(start)? {
+            if leased.len() == max {
+                exhausted = false;
+                break;
+            }
+            last = Some(lsn);
+            if lsn < state.next && !expired.remove(&lsn) {
+                continue;
+            }
+            state.next = state.next.max(lsn + 1);
+            state.pending.insert(lsn, now + self.visibility_timeout);
+            leased.push((lsn, item));
+        }
+        // the expired logs that were passed by are gone
+        for lsn in expired {
+            if exhausted || last.is_some_and(|last| lsn < last) {
+                state.pending.remove(&lsn);
+            }
+        }
+        self.commit(&mut state)?;
+        Ok(leased)
+    }
+
+    /// Acknowledge a leased log, so it's never delivered again
+    ///
+    /// ## Returns
+    /// Whether the log was waiting for its acknowledgement
+    pub fn ack(&self, lsn: Lsn) -> Result<bool, String> {
+        self.ack_many(&[lsn]).map(|acked| acked == 1)
+    }
+
+    /// Acknowledge several leased logs, storing the offset only once
+    ///
+    /// ## Returns
+    /// The number of logs that were waiting for their acknowledgement
+    pub fn ack_many(&self, lsns: &[Lsn]) -> Result<usize, String> {
+        let mut state = self.state();
+        let acked = lsns
+            .iter()
+            .filter(|lsn| state.pending.remove(lsn).is_some())
+            .count();
+        self.commit(&mut state)?;
+        Ok(acked)
+    }
+
+    /// Give a leased log back, so that the next lease delivers it again right away
+    ///
+    /// ## Returns
+    /// Whether the log was waiting for its acknowledgement
+    pub fn nack(&self, lsn: Lsn) -> bool {
+        match self.state().pending.get_mut(&lsn) {
+            Some(due) => {
+                *due = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Name of the group of the consumer
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    /// The stored offset: all the logs before this [Lsn] are acknowledged
+    pub fn offset(&self) -> Lsn {
+        self.state().committed
+    }
+
+    /// Number of logs on disk after the offset, i.e. not acknowledged yet
+    ///
+    /// ## Returns
+    /// `None` if the [Lsn] of the latest log is unknown, e.g. for logs written by older versions
+    pub fn lag(&self) -> Option<u64> {
+        let next = segments::next_lsn(&self.wal.inner.config)?;
+        Some(next.saturating_sub(self.offset()))
+    }
+
+    /// Number of logs leased and waiting for their acknowledgement
+    pub fn pending(&self) -> usize {
+        self.state().pending.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Store the offset once it moves, i.e. the oldest log waiting for its acknowledgement
+    fn commit(&self, state: &mut State) -> Result<(), String> {
+        let offset = state.pending.keys().next().copied().unwrap_or(state.next);
+        if offset == state.committed {
+            return Ok(());
+        }
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let result = File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(offset.to_string().as_bytes())
+                    .and(file.sync_all())
+            })
+            .and_then(|_| std::fs::rename(&temp, &self.path));
+        match result {
+            Ok(()) => {
+                state.committed = offset;
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to store consumer offset: {}", e)),
+        }
+    }
+}
+
+/// A group of consumers of a [Wal], as listed by [Wal::consumer_groups]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerGroup {
+    /// Name of the group
+    pub name: String,
+    /// The stored offset: all the logs before this [Lsn] are acknowledged
+    pub offset: Lsn,
+    /// Number of logs on disk after the offset, unknown if the [Lsn] of the latest log is unknown
+    pub lag: Option<u64>,
+}
+
+/// Path of the file storing the offset of a group
+fn offset_path(config: &WalConfig, group: &str) -> Result<PathBuf, String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if group.is_empty() || !group.chars().all(valid) {
+        return Err(format!("Invalid consumer group name: {:?}", group));
+    }
+    Ok(config
+        .location
+        .join(format!("{}{}", OFFSET_FILE_PREFIX, group)))
+}
+
+/// List the consumer groups with a stored offset, sorted by name
+pub(crate) fn groups(config: &WalConfig) -> Vec<ConsumerGroup> {
+    let entries = match std::fs::read_dir(&config.location) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let next = segments::next_lsn(config);
+    let mut groups = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            let name = file_name.strip_prefix(OFFSET_FILE_PREFIX)?;
+            // skip the temp files of the offsets being stored
+            offset_path(config, name).ok()?;
+            let offset = std::fs::read_to_string(entry.path())
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+            Some(ConsumerGroup {
+                name: name.to_string(),
+                offset,
+                lag: next.map(|next: Lsn| next.saturating_sub(offset)),
+            })
+        })
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+    groups
+}
+
+/// Delete the stored offset of a group
+///
+/// ## Returns
+/// Whether the group existed
+pub(crate) fn remove_group(config: &WalConfig, group: &str) -> Result<bool, String> {
+    match std::fs::remove_file(offset_path(config, group)?) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(format!("Failed to remove consumer group: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redelivery() {
+        let location = "./tmp/consumer";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal: Wal<u64> = Wal::new(location, None);
+        wal.write_iter(0..5);
+        wal.flush();
+        let timeout = Duration::from_millis(50);
+        let consumer = Consumer::open(wal.clone(), "tasks", timeout).unwrap();
+        let lsns = |leased: Vec<(Lsn, u64)>| leased.into_iter().map(|(l, _)| l).collect::<Vec<_>>();
+        assert_eq!(lsns(consumer.lease(3).unwrap()), [0, 1, 2]);
+        assert_eq!(consumer.ack_many(&[0, 2]).unwrap(), 2);
+        assert_eq!(consumer.offset(), 1);
+        assert_eq!(lsns(consumer.lease(5).unwrap()), [3, 4]);
+        assert!(consumer.lease(5).unwrap().is_empty());
+        // the logs not acknowledged in time come back
+        std::thread::sleep(timeout);
+        assert!(consumer.nack(4));
+        assert_eq!(lsns(consumer.lease(5).unwrap()), [1, 3, 4]);
+        assert!(consumer.ack(1).unwrap());
+        assert!(!consumer.ack(1).unwrap());
+        assert_eq!(consumer.offset(), 3);
+        // a restarted consumer delivers the logs not acknowledged again
+        drop(consumer);
+        let consumer = Consumer::open(wal.clone(), "tasks", timeout).unwrap();
+        assert_eq!(consumer.offset(), 3);
+        assert_eq!(lsns(consumer.lease(5).unwrap()), [3, 4]);
+        consumer.ack_many(&[3, 4]).unwrap();
+        wal.write(5);
+        wal.flush();
+        assert_eq!(consumer.lease(5).unwrap(), [(5, 5)]);
+        assert!(Consumer::open(wal, "../tasks", timeout).is_err());
+    }
+
+    #[test]
+    fn groups() {
+        let location = "./tmp/consumer_groups";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal: Wal<u64> = Wal::new(location, None);
+        wal.write_iter(0..10);
+        wal.flush();
+        let timeout = Duration::from_secs(30);
+        let indexer = Consumer::open(wal.clone(), "indexer", timeout).unwrap();
+        let exporter = Consumer::open(wal.clone(), "exporter", timeout).unwrap();
+        // each group reads all the logs, at its own pace
+        for (lsn, _) in indexer.lease(8).unwrap() {
+            indexer.ack(lsn).unwrap();
+        }
+        for (lsn, _) in exporter.lease(3).unwrap() {
+            exporter.ack(lsn).unwrap();
+        }
+        assert_eq!(exporter.lease(1).unwrap(), [(3, 3)]);
+        assert_eq!((indexer.lag(), exporter.lag()), (Some(2), Some(7)));
+        let groups = wal.consumer_groups();
+        let names = groups.iter().map(|g| g.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["exporter", "indexer"]);
+        assert_eq!((groups[0].offset, groups[0].lag), (3, Some(7)));
+        assert_eq!((groups[1].offset, groups[1].lag), (8, Some(2)));
+        assert!(wal.remove_consumer_group("exporter").unwrap());
+        assert!(!wal.remove_consumer_group("exporter").unwrap());
+        assert_eq!(wal.consumer_groups().len(), 1);
+    }
+}
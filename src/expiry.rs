@@ -0,0 +1,38 @@
+use std::time::SystemTime;
+
+/// Logs that expire after some time, read with [Wal::read_unexpired](crate::Wal::read_unexpired)
+///
+/// The expiry is derived from the log itself, e.g. from a field holding a deadline, so it's
+/// stored along with the log without any change to the format of the files.
+///
+/// ### Example
+/// ```no_run
+/// use serde::{Deserialize, Serialize};
+/// use std::time::{Duration, SystemTime, UNIX_EPOCH};
+/// use walcraft::{Expiry, Wal};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Session {
+///     id: usize,
+///     // seconds since the unix epoch
+///     valid_until: u64,
+/// }
+///
+/// impl Expiry for Session {
+///     fn expires_at(&self) -> Option<SystemTime> {
+///         Some(UNIX_EPOCH + Duration::from_secs(self.valid_until))
+///     }
+/// }
+///
+/// let wal: Wal<Session> = Wal::new("/tmp/logz", None);
+/// let live_sessions = wal.read_unexpired().unwrap().collect::<Vec<_>>();
+/// ```
+pub trait Expiry {
+    /// Moment after which the log is expired, `None` for logs that never expire
+    fn expires_at(&self) -> Option<SystemTime>;
+
+    /// Whether the log has expired at the given moment
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at().is_some_and(|at| at <= now)
+    }
+}
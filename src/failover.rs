@@ -0,0 +1,66 @@
+use crate::writer::manager::Meta;
+use crate::writer::manifest::{Manifest, SegmentInfo};
+use crate::WalConfig;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File in the fallback location recording the switch to it
+pub(crate) const FAILOVER_FILE: &str = "failover";
+/// Number of consecutive failed writes to the primary location before switching to the fallback
+pub(crate) const FAILOVER_AFTER: usize = 3;
+
+/// The fallback location, if the writer has switched to it
+pub(crate) fn failed_over(config: &WalConfig) -> Option<&PathBuf> {
+    config
+        .fallback_location
+        .as_ref()
+        .filter(|fallback| fallback.join(FAILOVER_FILE).exists())
+}
+
+/// Location holding the pointers of the WAL, i.e. the fallback one once switched to it
+pub(crate) fn active_meta(config: &WalConfig) -> Meta {
+    let location = failed_over(config).unwrap_or(&config.location);
+    Meta::new(location.clone())
+}
+
+/// The manifest of the WAL, along with the one of the fallback location once switched to it
+pub(crate) fn manifest(config: &WalConfig) -> BTreeMap<usize, SegmentInfo> {
+    let mut manifest = Manifest::new(config.location.clone()).read();
+    if let Some(fallback) = failed_over(config) {
+        manifest.extend(Manifest::new(fallback.clone()).read());
+    }
+    manifest
+}
+
+/// Record the switch to the fallback location, which the readers and the writers opening the
+/// WAL from then on follow
+///
+/// ## Arguments
+/// - `from`: The primary location
+/// - `segment`: Index of the first file written to the fallback location
+/// - `reason`: The error that caused the switch
+pub(crate) fn record(
+    fallback: &Path,
+    from: &Path,
+    segment: usize,
+    reason: &str,
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(fallback)?;
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let content = format!(
+        "from: {}\nsegment: {}\ntime: {}\nreason: {}\n",
+        from.display(),
+        segment,
+        time,
+        reason
+    );
+    let mut file = File::create(fallback.join(FAILOVER_FILE))?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()
+}
@@ -0,0 +1,199 @@
+use crate::failover;
+use crate::writer::Writer;
+use crate::WalConfig;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Share of the disk left free below which the disk is reported as nearly full
+const LOW_DISK_SPACE: f64 = 0.05;
+/// Number of files kept beyond the storage limit above which the garbage collection is
+/// reported as backed up
+const GC_BACKLOG_LIMIT: usize = 4;
+
+/// What happens to a write that would leave less than the reserve free on the disk, set with
+/// [WalBuilder::disk_reserve](crate::WalBuilder::disk_reserve)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LowDiskSpace {
+    /// Refuse the write, dropping its logs as with any failed write
+    #[default]
+    Refuse,
+    /// Delete the oldest files, whatever the storage limit, until there's room for the write,
+    /// refusing it only once nothing but the current file and the pinned ones is left
+    DeleteOldest,
+}
+
+/// Health of a [Wal](crate::Wal), as reported by [Wal::health](crate::Wal::health)
+///
+/// Meant to be wired into the readiness endpoint of a service: a degraded WAL still accepts
+/// the logs, while a failed one doesn't, or loses them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Health {
+    /// Writing the logs normally
+    Healthy,
+    /// Writing the logs, with problems needing attention
+    Degraded(Vec<String>),
+    /// Unable to write the logs
+    Failed(Vec<String>),
+}
+
+impl Health {
+    /// Whether there's no problem at all
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, Health::Healthy)
+    }
+
+    /// Whether the logs can still be written, even if degraded
+    pub fn is_ready(&self) -> bool {
+        !matches!(self, Health::Failed(_))
+    }
+
+    /// Descriptions of the problems found
+    pub fn reasons(&self) -> &[String] {
+        match self {
+            Health::Healthy => &[],
+            Health::Degraded(reasons) | Health::Failed(reasons) => reasons,
+        }
+    }
+}
+
+/// Check the health of the writer of a WAL
+pub(crate) fn check(config: &WalConfig, writer: &Writer) -> Health {
+    let mut failures = Vec::new();
+    let mut problems = Vec::new();
+    if writer.is_fenced() {
+        failures.push("Fenced off by a newer writer".to_string());
+    }
+    if writer.is_read_only() {
+        failures.push("Read-only, the WAL holds files written by a newer version".to_string());
+    }
+    let stats = config.stats.snapshot();
+    let last_error = config.stats.last_write_error().unwrap_or_default();
+    if config.stats.is_failing() {
+        failures.push(format!("Writes are failing: {}", last_error));
+    } else if stats.write_errors > 0 {
+        problems.push(format!(
+            "{} writes failed since open, the last error being: {}",
+            stats.write_errors, last_error
+        ));
+    }
+    if stats.sync_failures > 0 {
+        problems.push(format!(
+            "{} syncs to disk failed, the logs written before them may not be durable",
+            stats.sync_failures
+        ));
+    }
+    if let Some((available, total)) = disk_space(&config.location) {
+        if (available as f64) < total as f64 * LOW_DISK_SPACE {
+            problems.push(format!(
+                "Disk nearly full, {} bytes left out of {}",
+                available, total
+            ));
+        }
+    }
+    if let Some(fallback) = failover::failed_over(config) {
+        problems.push(format!(
+            "Writing to the fallback location {}",
+            fallback.display()
+        ));
+    }
+    let backlog = writer.gc_backlog();
+    if backlog > GC_BACKLOG_LIMIT {
+        problems.push(format!(
+            "{} files kept beyond the storage limit, waiting for the garbage collection",
+            backlog
+        ));
+    }
+    match (failures.is_empty(), problems.is_empty()) {
+        (true, true) => Health::Healthy,
+        (true, false) => Health::Degraded(problems),
+        (false, _) => {
+            failures.extend(problems);
+            Health::Failed(failures)
+        }
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding the path, along with its
+/// total size
+#[cfg(target_os = "linux")]
+pub(crate) fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs is plain old data, for which all zeros is a valid value
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is a valid C string, and the stat outlives the call
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some((stat.f_bavail as u64 * block, stat.f_blocks as u64 * block))
+}
+
+/// The free space is only checked on Linux
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn disk_space(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Monitor;
+    use crate::{Size, Wal, WalBuilder};
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn health() {
+        let location = "./tmp/health";
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<String> = WalBuilder::new()
+            .location(location)
+            .storage_size(Size::Kb(16))
+            .disable_buffer()
+            .build()
+            .unwrap();
+        wal.write("hello".to_string());
+        assert_eq!(wal.health(), Health::Healthy);
+        assert!(disk_space(Path::new(location)).is_some_and(|(a, t)| a <= t && t > 0));
+
+        // failing writes
+        let monitor = Monitor::new(&wal.inner.config);
+        monitor.write_result(Some(&Error::from(ErrorKind::StorageFull)));
+        let health = wal.health();
+        assert!(!health.is_ready());
+        assert_eq!(health.reasons().len(), 1);
+        assert!(health.reasons()[0].starts_with("Writes are failing"));
+
+        // recovered, with a sync failure since
+        wal.write("world".to_string());
+        monitor.sync_failed(&Error::from(ErrorKind::Other));
+        let health = wal.health();
+        assert!(health.is_ready() && !health.is_healthy());
+        assert_eq!(health.reasons().len(), 2);
+        assert_eq!(wal.stats().write_errors, 1);
+        assert_eq!(wal.stats().sync_failures, 1);
+
+        // the files pinned by a reader pile up
+        let location = "./tmp/health_gc";
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<String> = WalBuilder::new()
+            .location(location)
+            .storage_size(Size::Kb(16))
+            .disable_buffer()
+            .build()
+            .unwrap();
+        let pin = wal.inner.config.pins.pin(0);
+        for _ in 0..50 {
+            wal.write("x".repeat(1000));
+        }
+        let health = wal.health();
+        assert!(matches!(health, Health::Degraded(_)));
+        assert!(health.reasons()[0].contains("garbage collection"));
+        // collected at the next rotation
+        drop(pin);
+        for _ in 0..5 {
+            wal.write("x".repeat(1000));
+        }
+        assert_eq!(wal.health(), Health::Healthy);
+    }
+}
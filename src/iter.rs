@@ -1,164 +1,763 @@
-use crate::wal::{Wal, MODE_IDLE};
-use crate::writer::manager::Meta;
+use crate::failover;
+use crate::stats::Monitor;
+use crate::throttle::Throttle;
+use crate::wal::Wal;
+use crate::writer::chain::LINK_SIZE;
+use crate::writer::compress::{self, Dictionary};
+use crate::writer::frame::{self, count_records, RecordHeader, RecordKind, PADDING};
+use crate::writer::header::{read_header, Format};
+use crate::writer::manager::{open_segment, segment_path, Meta, COMPRESSED_EXT};
+use crate::writer::manifest::SegmentInfo;
+use crate::writer::pins::Pin;
+use crate::{ChecksumVerification, Lsn, ReadOptions, SkippedRegion, WalConfig};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::fs::File;
-use std::io::Read;
-use std::sync::atomic::Ordering::Relaxed;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::io::{Cursor, ErrorKind, Read};
+use std::sync::Arc;
 
 const BUFFER_SIZE: usize = 1024 * 1024 * 16; // 16 MB
+/// Largest number of logs the room is reserved for up front, by [Wal::read_into] and in a
+/// chunk of [Chunks]
+pub(crate) const READ_RESERVE: usize = 64 * 1024;
+
+/// Called with a log that was read intact but couldn't be deserialized, see
+/// [WalIterator::on_decode_error]
+type DecodeErrorHandler<T> = Box<dyn FnMut(&RecordMeta, &[u8], &dyn Error) -> Option<T>>;
 
 /// Iterator to read data from WAL
 pub struct WalIterator<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
 {
-    /// Handle to WAL instance
-    wal: Wal<T>,
-    /// Whether the init process has been done
+    /// Handle to WAL instance, `None` when reading without a writer
+    wal: Option<Wal<T>>,
+    /// Configuration of the WAL
+    config: WalConfig,
+    /// Whether the first file has been opened
     /// this would be false until the consumption of [WalIterator] starts
     started: bool,
     /// Identifier for when all the files has been read and the iterator has reached the end
     ended: bool,
     /// Handle to the current file
-    file: Option<File>,
+    file: Option<Box<dyn Read>>,
     /// Queue of all the files to read in the right sequence
     files: VecDeque<usize>,
     /// Buffer where the data is loaded from the file
     /// The [WalIterator] reads large files in chunks and stores them in the buffer
     /// This helps in reducing RAM usage for the iterator when reading from large files
     buffer: VecDeque<u8>,
+    /// Information of the closed files, used to find the [Lsn] each file starts at
+    manifest: Arc<BTreeMap<usize, SegmentInfo>>,
+    /// [Lsn] of the next log in the buffer
+    lsn: Lsn,
+    /// Logs before this [Lsn] are skipped
+    from: Lsn,
+    /// Index of the current file
+    segment: usize,
+    /// Number of bytes read from the current file
+    position: u64,
+    /// Layout of the current file
+    format: Format,
+    /// Dictionary the records of the current file are compressed with
+    dictionary: Option<Dictionary>,
+    /// Pin on the current file, which keeps it and the files after it from being deleted
+    pin: Option<Pin>,
+    /// Size of the last file when the iterator was created, the data appended later is ignored
+    tail: Option<u64>,
+    /// Data waiting in the buffer when the iterator was created, read after all the files
+    buffered: Option<Vec<u8>>,
+    /// Size of the last record read as stored in the file, along with its frame
+    stored: u64,
+    /// Counts the corrupted regions skipped, and reports them to the listener
+    monitor: Monitor,
+    /// Size of every file to read, as it was when the iterator was created
+    sizes: BTreeMap<usize, u64>,
+    /// Bytes of all the files to read, along with the buffered data
+    total: u64,
+    /// Index of the file being read, `None` while reading the buffered data
+    reading: Option<usize>,
+    /// Size of the file or the buffered data being read
+    current: u64,
+    /// Caps the bytes read per second, see [ReadOptions::max_bandwidth]
+    throttle: Option<Throttle>,
+    /// Handles the logs that can't be deserialized, instead of reporting them
+    on_decode_error: Option<DecodeErrorHandler<T>>,
+    /// How the logs are read, carried over to the iterators of [WalIterator::by_segment]
+    options: ReadOptions,
 }
 
 impl<T> WalIterator<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
 {
-    pub fn new(wal: Wal<T>) -> Self {
-        Self {
+    pub(crate) fn new(wal: Wal<T>, options: ReadOptions) -> Self {
+        let config = wal.inner.config.clone();
+        Self::with_source(Some(wal), config, options)
+    }
+
+    /// Read the logs of a WAL that isn't open for writing in this process
+    pub(crate) fn detached(config: WalConfig, options: ReadOptions) -> Self {
+        Self::with_source(None, config, options)
+    }
+
+    fn with_source(wal: Option<Wal<T>>, config: WalConfig, options: ReadOptions) -> Self {
+        let monitor = Monitor::new(&config);
+        let mut iter = Self {
             wal,
+            config,
             started: false,
             ended: false,
             file: None,
             files: VecDeque::new(),
             buffer: VecDeque::with_capacity(BUFFER_SIZE), // 8 KB buffer
+            manifest: Arc::default(),
+            lsn: 0,
+            from: 0,
+            segment: 0,
+            position: 0,
+            format: Format::default(),
+            dictionary: None,
+            pin: None,
+            tail: None,
+            buffered: None,
+            stored: 0,
+            monitor,
+            sizes: BTreeMap::new(),
+            total: 0,
+            reading: None,
+            current: 0,
+            throttle: options
+                .max_bandwidth
+                .and_then(|size| Throttle::new(size.to_bytes() as u64)),
+            on_decode_error: None,
+            options,
+        };
+        iter.snapshot(options);
+        iter
+    }
+
+    /// Skip the logs before the given [Lsn]
+    ///
+    /// The files that only have older logs in them aren't opened at all
+    pub fn start_from(mut self, lsn: Lsn) -> Self {
+        self.from = lsn;
+        self
+    }
+
+    /// Handle the logs that were read intact, but can't be deserialized, e.g. after the type of
+    /// the logs changed
+    ///
+    /// The closure receives the [RecordMeta] of the log, its raw bytes and the error, and may
+    /// decode the log another way: the log it returns is yielded in place of the one that
+    /// failed, and the log is skipped otherwise. Without a handler, the failures are reported
+    /// to [WalListener::on_error](crate::WalListener::on_error).
+    ///
+    /// ### Example
+    /// ```no_run
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<u64> = Wal::new("/tmp/logz", None);
+    /// let logs = wal.read().unwrap().on_decode_error(|meta, bytes, _| {
+    ///     eprintln!("log {} of file {} is {:?}", meta.lsn, meta.segment, bytes);
+    ///     // written as a 32-bit number by an older version
+    ///     Some(u32::from_le_bytes(bytes.try_into().ok()?) as u64)
+    /// });
+    /// ```
+    pub fn on_decode_error(
+        mut self,
+        f: impl FnMut(&RecordMeta, &[u8], &dyn Error) -> Option<T> + 'static,
+    ) -> Self {
+        self.on_decode_error = Some(Box::new(f));
+        self
+    }
+
+    /// How far the iterator has read through the logs, e.g. to drive a progress bar during a
+    /// long recovery
+    ///
+    /// The progress is measured in bytes of the files, and is approximate while reading a
+    /// compressed file.
+    pub fn progress(&self) -> ReadProgress {
+        if self.ended {
+            return ReadProgress {
+                bytes_read: self.total,
+                total_bytes: self.total,
+                segment: None,
+            };
+        }
+        let queued = self
+            .files
+            .iter()
+            .filter_map(|f| self.sizes.get(f))
+            .sum::<u64>()
+            + self.buffered.as_ref().map_or(0, |b| b.len() as u64);
+        // the data loaded in the buffer isn't processed yet
+        let processed = self.position.saturating_sub(self.buffer.len() as u64);
+        let current = match self.started {
+            true => self.current.saturating_sub(processed),
+            false => 0,
+        };
+        ReadProgress {
+            bytes_read: self.total.saturating_sub(queued + current),
+            total_bytes: self.total,
+            segment: self.reading,
         }
     }
 
-    fn init(&mut self) {
-        match Meta::new(self.wal.inner.config.location.clone()).read() {
-            None => {
-                self.ended = true;
-            }
-            Some((garbage_pointer, current_pointer)) => {
-                // calculate order of files to read in
-                if current_pointer > garbage_pointer {
-                    self.files = VecDeque::from_iter(garbage_pointer..=current_pointer);
-                } else if garbage_pointer > current_pointer {
-                    let mut files = VecDeque::from_iter(garbage_pointer..=(usize::MAX));
-                    files.extend(0..=current_pointer);
-                    self.files = files;
-                } else {
-                    self.files.push_back(current_pointer);
-                }
-                // check if the file is actually present
-                if self.next_file().is_none() {
-                    self.ended = true;
-                }
+    /// Decode up to `limit` more logs onto the end of a vector, in a single call
+    ///
+    /// The reading picks up where the iterator is, so a large WAL can be loaded in batches.
+    ///
+    /// ## Returns
+    /// The number of logs appended, fewer than `limit` once all the logs have been read
+    pub fn read_into(&mut self, out: &mut Vec<T>, limit: usize) -> usize {
+        let start = out.len();
+        while out.len() - start < limit {
+            match self.get_next() {
+                Some((_, item)) => out.push(item),
+                None => break,
             }
+        }
+        out.len() - start
+    }
+
+    /// Yield the logs in chunks of up to `size` logs, e.g. to insert them into a database in bulk
+    ///
+    /// Every chunk but the last one is full. A size of zero is taken as one.
+    pub fn chunks(self, size: usize) -> Chunks<T> {
+        Chunks {
+            iter: self,
+            size: size.max(1),
+        }
+    }
+
+    /// Yield the undecoded bytes of every log along with its [RecordMeta]
+    pub fn raw(self) -> RawRecords<T> {
+        RawRecords(self)
+    }
+
+    /// Yield the [Lsn] of every log along with it
+    pub fn with_positions(self) -> WithPositions<T> {
+        WithPositions(self)
+    }
+
+    /// Yield the [Lsn] and the term of every log along with it, the term being `None` for the
+    /// logs written without one, see [WriteOptions::term](crate::WriteOptions::term)
+    pub fn with_terms(self) -> WithTerms<T> {
+        WithTerms(self)
+    }
+
+    /// Capture the files to read and the size of the last one, for a point-in-time view of the logs
+    ///
+    /// This happens while no data is written to disk by this process, and the files
+    /// are pinned right away so that they can't be deleted by the garbage collection.
+    fn snapshot(&mut self, options: ReadOptions) {
+        let config = &self.config;
+        let capture = |buffered: &[u8]| {
+            let files = segments(config);
+            let pin = files
+                .as_ref()
+                .and_then(|files| files.front())
+                .map(|index| config.pins.pin(*index));
+            let tail = files
+                .as_ref()
+                .and_then(|files| files.back())
+                .and_then(|index| segment_path(config, *index))
+                .filter(|path| !path.to_string_lossy().ends_with(COMPRESSED_EXT))
+                .and_then(|path| std::fs::metadata(path).ok())
+                .map(|metadata| metadata.len());
+            let manifest = manifest(config);
+            let buffered = options.include_buffered.then(|| buffered.to_vec());
+            (manifest, files, pin, tail, buffered)
         };
-        self.started = true;
+        let (manifest, files, pin, tail, buffered) = match self.wal.as_ref() {
+            Some(wal) => wal.inner.writer.paused(capture),
+            // without a writer in this process, the last file may be written to meanwhile
+            None => capture(&[]),
+        };
+        self.manifest = Arc::new(manifest);
+        self.buffered = buffered.filter(|buffered| !buffered.is_empty());
+        // the last file is only read up to its size at the time of the snapshot
+        let last = files.as_ref().and_then(|files| files.back()).copied();
+        self.sizes = files
+            .iter()
+            .flatten()
+            .filter_map(|index| {
+                let size = match tail.filter(|_| Some(*index) == last) {
+                    Some(tail) => tail,
+                    None => std::fs::metadata(segment_path(config, *index)?).ok()?.len(),
+                };
+                Some((*index, size))
+            })
+            .collect();
+        self.total =
+            self.sizes.values().sum::<u64>() + self.buffered.as_ref().map_or(0, |b| b.len() as u64);
+        self.pin = pin;
+        self.tail = tail;
+        match files {
+            None => self.ended = true,
+            Some(files) => self.files = files,
+        }
     }
 
-    fn get_next(&mut self) -> Option<T> {
-        // lazy initialization
+    /// Split the logs by file, yielding the index of every file along with an iterator over
+    /// its logs, oldest first
+    ///
+    /// This lets the logs be processed a file at a time, e.g. to take a checkpoint after every
+    /// file, or to ship the files downstream as a whole. Every iterator reads its file as it
+    /// was when this iterator was created, and keeps it from being deleted until dropped.
+    ///
+    /// The [ReadOptions] and [WalIterator::start_from] carry over to the iterators, while
+    /// [WalIterator::on_decode_error] doesn't. The buffered logs included with
+    /// [ReadOptions::include_buffered] are read along with the last file.
+    ///
+    /// Once logs were read from this iterator, the split starts from the next file.
+    ///
+    /// ### Example
+    /// ```no_run
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+    /// for (index, logs) in wal.read().unwrap().by_segment() {
+    ///     let count = logs.count();
+    ///     println!("file {} has {} logs", index, count);
+    /// }
+    /// ```
+    pub fn by_segment(self) -> BySegment<T> {
+        BySegment(self)
+    }
+
+    /// An iterator over the logs of the next file, see [WalIterator::by_segment]
+    fn next_segment(&mut self) -> Option<(usize, WalIterator<T>)> {
+        if self.ended {
+            return None;
+        }
         if !self.started {
-            self.init();
+            self.skip_older();
+            self.started = true;
+        }
+        let index = self.files.pop_front()?;
+        let pin = self.config.pins.pin(index);
+        // the files left to split stay pinned
+        self.pin = self.files.front().map(|next| self.config.pins.pin(*next));
+        let last = self.files.is_empty();
+        self.ended = last;
+        let buffered = match last {
+            true => self.buffered.take(),
+            false => None,
+        };
+        let size = self.sizes.get(&index).copied().unwrap_or(0);
+        let iter = Self {
+            wal: self.wal.clone(),
+            config: self.config.clone(),
+            started: false,
+            ended: false,
+            file: None,
+            files: VecDeque::from([index]),
+            buffer: VecDeque::new(),
+            manifest: self.manifest.clone(),
+            lsn: 0,
+            from: self.from,
+            segment: index,
+            position: 0,
+            format: Format::default(),
+            dictionary: None,
+            pin: Some(pin),
+            tail: self.tail.filter(|_| last),
+            total: size + buffered.as_ref().map_or(0, |b| b.len() as u64),
+            buffered,
+            stored: 0,
+            monitor: self.monitor.clone(),
+            sizes: BTreeMap::from([(index, size)]),
+            reading: None,
+            current: 0,
+            throttle: self
+                .options
+                .max_bandwidth
+                .and_then(|size| Throttle::new(size.to_bytes() as u64)),
+            on_decode_error: None,
+            options: self.options,
+        };
+        Some((index, iter))
+    }
+
+    /// Skip the files that end before the first wanted log
+    fn skip_older(&mut self) {
+        while self.files.len() > 1 {
+            let front = self.files[0];
+            match self.manifest.get(&front).and_then(|i| i.next_lsn()) {
+                Some(next) if next <= self.from => self.files.pop_front(),
+                _ => break,
+            };
         }
+    }
+
+    fn init(&mut self) {
+        self.skip_older();
+        // check if the file is actually present
+        if self.next_file().is_none() {
+            self.ended = true;
+        }
+        self.started = true;
+    }
+
+    fn get_next(&mut self) -> Option<(RecordMeta, T)> {
+        loop {
+            let (meta, bytes) = self.next_record()?;
+            if meta.crc_ok == Some(false) {
+                let message = format!("walcraft checksum error - log {}", meta.lsn);
+                self.monitor.report(message, Some(ErrorKind::InvalidData));
+                self.skipped(meta.offset, self.stored, 1);
+                continue;
+            }
+            if meta.kind.is_control() {
+                continue;
+            }
+            // convert bytes to log
+            let err = match self.config.codec.deserialize(&bytes) {
+                Ok(item) => return Some((meta, item)),
+                Err(err) => err,
+            };
+            match self.on_decode_error.as_mut() {
+                Some(f) => {
+                    if let Some(item) = f(&meta, &bytes, &err) {
+                        return Some((meta, item));
+                    }
+                }
+                None => {
+                    let message =
+                        format!("walcraft serialization error - log {}: {}", meta.lsn, err);
+                    self.monitor.report(message, Some(ErrorKind::InvalidData));
+                }
+            }
+        }
+    }
+
+    fn next_record(&mut self) -> Option<(RecordMeta, Vec<u8>)> {
         // the file list has been exhausted
         if self.ended {
             return None;
         }
+        // lazy initialization
+        if !self.started {
+            self.init();
+            if self.ended {
+                return None;
+            }
+        }
         // get data from buffer
         self.read_buffer()
     }
 
-    fn read_buffer(&mut self) -> Option<T> {
+    fn read_buffer(&mut self) -> Option<(RecordMeta, Vec<u8>)> {
         loop {
             if !self.ensure_buffer() {
                 return None;
             }
-            let size = self.buffer.drain(0..2).collect::<Vec<_>>();
-            let size = u16::from_ne_bytes([size[0], size[1]]) as usize;
+            let offset = self.position - self.buffer.len() as u64;
+            let size = self.format.frame_size([self.buffer[0], self.buffer[1]]);
+            if size == PADDING {
+                let len = self.entry_len().unwrap_or(self.buffer.len());
+                self.buffer.drain(0..len);
+                continue;
+            }
+            self.buffer.drain(0..2);
+            let size = size as usize;
             // insufficient or corrupted data
-            if size == 0 || size > self.buffer.len() {
+            if size > self.buffer.len() {
                 return None;
             }
-            // convert bytes to log
-            let bytes = self.buffer.drain(0..size).collect::<Vec<_>>();
-            match bincode::deserialize(&bytes) {
-                Ok(item) => return Some(item),
-                Err(err) => {
-                    println!("walcraft serialization error - {}", err);
+            let lsn = self.lsn;
+            self.lsn += 1;
+            if lsn < self.from {
+                self.buffer.drain(0..size);
+                continue;
+            }
+            let mut bytes = self.buffer.drain(0..size).collect::<Vec<_>>();
+            self.stored = 2 + size as u64;
+            // the checksum covers the rest of the record
+            let crc_ok = match self.options.checksums {
+                ChecksumVerification::Eager => self.format.checksum.strip(&mut bytes),
+                ChecksumVerification::Lazy => {
+                    self.format.checksum.skip(&mut bytes);
+                    None
                 }
+            };
+            // the link in front of the record is only of use to verify the chain
+            if self.format.chained {
+                bytes.drain(0..LINK_SIZE.min(bytes.len()));
+            }
+            // a record failing its checksum is left as it is, to be reported as such
+            let intact = crc_ok != Some(false);
+            if self.format.compressed
+                && intact
+                && !compress::decompress(&mut bytes, self.dictionary.as_ref())
+            {
+                let message = format!("walcraft decompression error - log {}", lsn);
+                self.monitor.report(message, Some(ErrorKind::InvalidData));
+                self.skipped(offset, self.stored, 1);
+                continue;
             }
+            // the header of a record failing its checksum can't be trusted, it's left in place
+            let header = match self.format.kinds && intact {
+                true => RecordHeader::strip(&mut bytes).unwrap_or_default(),
+                false => RecordHeader::default(),
+            };
+            let meta = RecordMeta {
+                lsn,
+                segment: self.segment,
+                offset,
+                len: bytes.len(),
+                crc_ok,
+                kind: header.kind,
+                term: header.term,
+            };
+            return Some((meta, bytes));
         }
     }
 
     fn ensure_buffer(&mut self) -> bool {
         loop {
-            // Clear an empty buffer
-            if let Some(val) = self.buffer.get(0) {
-                if *val == 0 {
-                    self.buffer.clear();
-                }
-            }
-            // has enough data in buffer to return one item
-            if self.buffer.len() > 2 {
-                let size = u16::from_ne_bytes([self.buffer[0], self.buffer[1]]) as usize;
-                if size != 0 && self.buffer.len() >= (size + 2) {
-                    return true;
-                }
+            // has enough data in buffer to return one item, or to skip a run of padding
+            if self.entry_len().is_some_and(|len| self.buffer.len() >= len) {
+                return true;
             }
             // in case of insufficient data, read next chunk
             // this will read from the same file, if there's more data in the file
             // otherwise it will try to open next file and read from it
             let file = self.file.as_mut().unwrap();
-            let mut data = vec![0; BUFFER_SIZE];
+            let chunk = match self.throttle.as_ref() {
+                Some(throttle) => throttle.chunk(BUFFER_SIZE),
+                None => BUFFER_SIZE,
+            };
+            let mut data = vec![0; chunk];
             let bytes_read = file.read(&mut data).unwrap_or(0);
+            if let Some(throttle) = self.throttle.as_mut() {
+                throttle.consume(bytes_read);
+            }
             if bytes_read == 0 {
                 if self.next_file().is_none() {
                     return false;
                 }
             } else {
-                self.buffer.extend(data);
+                self.buffer.extend(&data[..bytes_read]);
+                self.position += bytes_read as u64;
             }
         }
     }
 
-    fn next_file(&mut self) -> Option<&File> {
+    /// Number of bytes taken by the record or the padding at the front of the buffer, along
+    /// with their frames
+    ///
+    /// ## Returns
+    /// `None` until enough of the buffer is filled to tell
+    fn entry_len(&self) -> Option<usize> {
+        if self.buffer.len() < 2 {
+            return None;
+        }
+        let size = self.format.frame_size([self.buffer[0], self.buffer[1]]);
+        if size != PADDING {
+            return Some(2 + size as usize);
+        }
+        if self.buffer.len() < 4 {
+            return None;
+        }
+        Some(4 + self.format.frame_size([self.buffer[2], self.buffer[3]]) as usize)
+    }
+
+    /// Report a corrupted region of the current file as skipped
+    fn skipped(&self, offset: u64, bytes: u64, records: u64) {
+        self.monitor.skipped(SkippedRegion {
+            segment: self.segment,
+            offset,
+            bytes,
+            records,
+        });
+    }
+
+    fn next_file(&mut self) -> Option<&mut Box<dyn Read>> {
+        // records never span files, so the data left over is a corrupted frame or a torn write
+        if !self.buffer.is_empty() {
+            let bytes = self.buffer.len() as u64;
+            let message = format!(
+                "walcraft skipped {} bytes of corrupted data - file {}",
+                bytes, self.segment
+            );
+            self.monitor.report(message, Some(ErrorKind::InvalidData));
+            self.skipped(self.position - bytes, bytes, 0);
+            self.buffer.clear();
+        }
         loop {
             match self.files.pop_front() {
+                // the buffered data comes after all the files
+                None if self.buffered.is_some() => {
+                    self.buffer.clear();
+                    // the buffer has no header
+                    self.format = Format {
+                        header_len: 0,
+                        ..Format::default()
+                    };
+                    self.current = self.buffered.as_ref().map_or(0, |b| b.len() as u64);
+                    self.position = 0;
+                    self.reading = None;
+                    self.file = self.buffered.take().map(|b| Box::new(Cursor::new(b)) as _);
+                    break self.file.as_mut();
+                }
                 None => {
                     self.ended = true;
+                    self.pin = None;
                     break None;
                 }
                 Some(f) => {
-                    let file_name = format!("log_{}.bin", f);
-                    let mut path = self.wal.inner.config.location.clone();
-                    path.push(&file_name);
-                    let file = match File::open(path) {
-                        Ok(f) => f,
+                    let mut file = match open_segment(&self.config, f) {
+                        Some(file) => file,
+                        None => continue,
+                    };
+                    // ignore the data appended to the last file after the snapshot
+                    if let (true, Some(tail)) = (self.files.is_empty(), self.tail) {
+                        file = Box::new(file.take(tail));
+                    }
+                    // the file starts where the previous one ended, unless recorded otherwise
+                    let manifest = &self.manifest;
+                    if let Some(lsn) = manifest
+                        .get(&f)
+                        .and_then(|info| info.first)
+                        .or_else(|| manifest.get(&f.wrapping_sub(1))?.next_lsn())
+                    {
+                        self.lsn = lsn;
+                    }
+                    // records never span files, so a partial record left over is a torn write
+                    self.buffer.clear();
+                    let (format, prefix) = match read_header(&mut file) {
+                        Ok(v) => v,
+                        // written by a newer version, whose records can't be told apart
+                        Err(e) if e.kind() == ErrorKind::Unsupported => {
+                            self.monitor.report(
+                                format!("walcraft skipped file {}: {}", f, e),
+                                Some(e.kind()),
+                            );
+                            continue;
+                        }
                         Err(_) => continue,
                     };
+                    self.buffer.extend(prefix.iter().skip(format.header_len));
+                    self.position = prefix.len() as u64;
+                    let mut file = frame::records(file, format);
+                    // skip the logs trimmed from the start of the file
+                    if let Some(info) = manifest.get(&f).filter(|info| info.trim_offset > 0) {
+                        let skip = info.trim_offset.saturating_sub(self.position);
+                        match std::io::copy(&mut (&mut file).take(skip), &mut std::io::sink()) {
+                            Ok(skipped) if skipped == skip => {}
+                            _ => continue,
+                        }
+                        self.lsn += info.trimmed;
+                        self.position = info.trim_offset;
+                    }
+                    // the dictionaries are kept for good, so they're found even for cold files
+                    let id = format.dictionary;
+                    if id != 0 && self.dictionary.as_ref().is_none_or(|d| d.id != id) {
+                        self.dictionary = std::iter::once(&self.config.location)
+                            .chain(self.config.mirror_location.as_ref())
+                            .chain(self.config.fallback_location.as_ref())
+                            .find_map(|location| Dictionary::load(location, id));
+                    }
+                    self.format = format;
+                    self.segment = f;
+                    self.reading = Some(f);
+                    self.current = self.sizes.get(&f).copied().unwrap_or(0);
+                    self.pin = Some(self.config.pins.pin(f));
                     self.file = Some(file);
-                    break self.file.as_ref();
+                    break self.file.as_mut();
                 }
             }
         }
     }
 }
 
+/// Indexes of all the log files in the order to read them in, including the ones in cold storage
+///
+/// ## Returns
+/// `None` if there's no WAL at the location
+pub(crate) fn segments(config: &WalConfig) -> Option<VecDeque<usize>> {
+    // fall back to the mirror, in case the primary location is lost
+    let meta = failover::active_meta(config).read().or_else(|| {
+        let mirror = config.mirror_location.clone()?;
+        Meta::new(mirror).read()
+    });
+    let (garbage_pointer, current_pointer) = meta?;
+    // start from the older files in cold storage, if there are any
+    let start = config
+        .cold_location
+        .as_ref()
+        .and_then(|cold| Meta::new(cold.clone()).read())
+        .map(|(start, _)| start)
+        .unwrap_or(garbage_pointer);
+    // calculate order of files to read in
+    let files = if current_pointer > start {
+        VecDeque::from_iter(start..=current_pointer)
+    } else if start > current_pointer {
+        let mut files = VecDeque::from_iter(start..=(usize::MAX));
+        files.extend(0..=current_pointer);
+        files
+    } else {
+        VecDeque::from([current_pointer])
+    };
+    Some(files)
+}
+
+/// Indexes of the log files written by a newer version, in a format unknown to this one
+pub(crate) fn newer_segments(config: &WalConfig) -> Vec<usize> {
+    segments(config)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|index| {
+            open_segment(config, *index).is_some_and(|mut file| {
+                read_header(&mut file).is_err_and(|e| e.kind() == ErrorKind::Unsupported)
+            })
+        })
+        .collect()
+}
+
+/// Information of the closed files, along with the logs trimmed from the current file
+pub(crate) fn manifest(config: &WalConfig) -> BTreeMap<usize, SegmentInfo> {
+    let mut manifest = failover::manifest(config);
+    let meta = failover::active_meta(config);
+    if let Some(trim) = meta.trim().filter(|trim| {
+        meta.read()
+            .is_some_and(|(_, current)| current == trim.index)
+    }) {
+        let info = manifest.entry(trim.index).or_insert_with(|| SegmentInfo {
+            index: trim.index,
+            ..Default::default()
+        });
+        info.trim_offset = trim.offset;
+        info.trimmed = trim.records;
+    }
+    manifest
+}
+
+/// Count the logs in all the log files
+///
+/// The counts recorded in the manifest are used for the closed files,
+/// so only the current file, and the files written by older versions, are scanned.
+pub(crate) fn count(config: &WalConfig) -> u64 {
+    let manifest = manifest(config);
+    segments(config)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|index| {
+            let info = manifest.get(&index);
+            let records = match info.and_then(|info| info.records) {
+                Some(records) => records,
+                None => open_segment(config, index)
+                    .and_then(|file| count_records(file).ok())
+                    .unwrap_or(0),
+            };
+            records.saturating_sub(info.map_or(0, |info| info.trimmed))
+        })
+        .sum()
+}
+
 impl<T> Iterator for WalIterator<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
@@ -166,26 +765,155 @@ where
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let out = self.get_next();
-        if out.is_none() {
-            self.wal.inner.mode.store(MODE_IDLE, Relaxed);
+        self.get_next().map(|(_, item)| item)
+    }
+}
+
+/// How far a [WalIterator] has read through the logs, see [WalIterator::progress]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadProgress {
+    /// Bytes of the files read so far, counting the files skipped as a whole
+    pub bytes_read: u64,
+    /// Bytes of all the files to read, as they were when the iterator was created, along with
+    /// the buffered logs included by [ReadOptions::include_buffered]
+    pub total_bytes: u64,
+    /// Index of the file being read, `None` before the first file is opened, while reading the
+    /// buffered logs, and once all the logs have been read
+    pub segment: Option<usize>,
+}
+
+impl ReadProgress {
+    /// Share of the bytes read so far, between 0 and 1
+    pub fn fraction(&self) -> f64 {
+        match self.total_bytes {
+            0 => 1.0,
+            total => self.bytes_read as f64 / total as f64,
         }
-        out
     }
 }
 
-impl<T> Drop for WalIterator<T>
+/// Metadata of a log, as stored in a log file
+///
+/// Yielded by [Wal::read_raw](crate::Wal::read_raw) along with the undecoded bytes of the log,
+/// or of a control record, see [RecordKind]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordMeta {
+    /// Log Sequence Number of the log
+    pub lsn: Lsn,
+    /// Index of the file the log is stored in
+    pub segment: usize,
+    /// Position of the log in the (uncompressed) file, in bytes
+    ///
+    /// For the files written in the power-loss-safe mode, this leaves out the page framing.
+    pub offset: u64,
+    /// Size of the serialized log, in bytes
+    pub len: usize,
+    /// Whether the log matches its checksum, `None` for the logs written without one,
+    /// see [WalBuilder::checksum](crate::WalBuilder::checksum), or read without verifying it,
+    /// see [ReadOptions::checksums]
+    pub crc_ok: Option<bool>,
+    /// Whether the record is a log or a control record
+    pub kind: RecordKind,
+    /// Term the log was written in, `None` for the logs written without one, see
+    /// [WriteOptions::term](crate::WriteOptions::term)
+    pub term: Option<u64>,
+}
+
+/// Iterator over the log files of a WAL, yielding the index of every file along with an
+/// iterator over its logs, see [WalIterator::by_segment]
+pub struct BySegment<T>(WalIterator<T>)
+where
+    T: Serialize + for<'a> Deserialize<'a>;
+
+impl<T> Iterator for BySegment<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Item = (usize, WalIterator<T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_segment()
+    }
+}
+
+/// Iterator to read data from WAL in chunks of logs, see [WalIterator::chunks]
+pub struct Chunks<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
 {
-    fn drop(&mut self) {
-        self.wal.inner.mode.store(MODE_IDLE, Relaxed);
+    iter: WalIterator<T>,
+    size: usize,
+}
+
+impl<T> Iterator for Chunks<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = Vec::with_capacity(self.size.min(READ_RESERVE));
+        match self.iter.read_into(&mut chunk, self.size) {
+            0 => None,
+            _ => Some(chunk),
+        }
+    }
+}
+
+/// Iterator to read the undecoded logs from WAL, along with their [RecordMeta]
+pub struct RawRecords<T>(WalIterator<T>)
+where
+    T: Serialize + for<'a> Deserialize<'a>;
+
+impl<T> Iterator for RawRecords<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Item = (RecordMeta, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_record()
+    }
+}
+
+/// Iterator to read data from WAL, along with the [Lsn] of every log
+pub struct WithPositions<T>(WalIterator<T>)
+where
+    T: Serialize + for<'a> Deserialize<'a>;
+
+impl<T> Iterator for WithPositions<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Item = (Lsn, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.get_next().map(|(meta, item)| (meta.lsn, item))
+    }
+}
+
+/// Iterator to read data from WAL, along with the [Lsn] and the term of every log
+pub struct WithTerms<T>(WalIterator<T>)
+where
+    T: Serialize + for<'a> Deserialize<'a>;
+
+impl<T> Iterator for WithTerms<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Item = (Lsn, Option<u64>, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .get_next()
+            .map(|(meta, item)| (meta.lsn, meta.term, item))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Wal;
+    use crate::writer::header::HEADER_SIZE;
+    use crate::{Checksum, ChecksumVerification, ReadOptions, Size, Wal, WalBuilder};
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -221,4 +949,345 @@ mod tests {
         }
         assert_eq!(counter, 100000);
     }
+
+    #[test]
+    fn read_from_cold_storage() {
+        let location = "./tmp/iter_cold";
+        let cold_location = "./tmp/iter_cold_tier";
+        let _ = std::fs::remove_dir_all(location);
+        let _ = std::fs::remove_dir_all(cold_location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .cold_storage(cold_location)
+            .storage_size(Size::Kb(16))
+            .build()
+            .unwrap();
+        for i in 1..=2000 {
+            wal.write(Log {
+                id: i,
+                text: String::from(TEXT),
+            });
+        }
+        wal.flush();
+        drop(wal);
+        // most of the logs no longer fit the local storage, but are still readable
+        let wal = WalBuilder::<Log>::new()
+            .location(location)
+            .cold_storage(cold_location)
+            .storage_size(Size::Kb(16))
+            .build()
+            .unwrap();
+        let ids = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=2000).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn read_compressed() {
+        let location = "./tmp/iter_compressed";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .compress_segments()
+            .storage_size(Size::Kb(64))
+            .build()
+            .unwrap();
+        for i in 1..=200 {
+            wal.write(Log {
+                id: i,
+                text: String::from(TEXT),
+            });
+        }
+        wal.flush();
+        drop(wal);
+        let wal: Wal<Log> = Wal::new(location, None);
+        let ids = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn positions() {
+        let location = "./tmp/iter_positions";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .storage_size(Size::Kb(64))
+            .build()
+            .unwrap();
+        for i in 0..2000 {
+            wal.write(Log {
+                id: i,
+                text: String::from(TEXT),
+            });
+        }
+        wal.flush();
+        drop(wal);
+        // the oldest files are gone, yet the numbering carries on from them
+        let wal = WalBuilder::<Log>::new()
+            .location(location)
+            .storage_size(Size::Kb(64))
+            .build()
+            .unwrap();
+        let logs = wal.read_with_positions().unwrap().collect::<Vec<_>>();
+        assert!(logs[0].0 > 0);
+        assert!(logs.iter().all(|(lsn, log)| *lsn == log.id as u64));
+        assert_eq!(logs.last().unwrap().0, 1999);
+        // resume from the middle
+        let resumed = wal.read_from(1900).unwrap().collect::<Vec<_>>();
+        assert_eq!(resumed.len(), 100);
+        assert!(resumed.iter().all(|(lsn, log)| *lsn == log.id as u64));
+    }
+
+    #[test]
+    fn raw_records() {
+        let location = "./tmp/iter_raw";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        for i in 0..3 {
+            wal.write(Log {
+                id: i,
+                text: "raw".repeat(i),
+            });
+        }
+        wal.flush();
+        let records = wal.read_raw().unwrap().collect::<Vec<_>>();
+        assert_eq!(records.len(), 3);
+        let mut offset = HEADER_SIZE as u64;
+        for (i, (meta, bytes)) in records.into_iter().enumerate() {
+            assert_eq!(meta.lsn, i as u64);
+            assert_eq!(meta.segment, 0);
+            assert_eq!(meta.offset, offset);
+            assert_eq!(meta.len, bytes.len());
+            let log: Log = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(log.id, i);
+            offset += 3 + bytes.len() as u64;
+        }
+    }
+
+    #[test]
+    fn count() {
+        let location = "./tmp/iter_count";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .storage_size(Size::Kb(64))
+            .build()
+            .unwrap();
+        assert_eq!(wal.count(), 0);
+        for i in 0..1000 {
+            wal.write(Log {
+                id: i,
+                text: String::from(TEXT),
+            });
+        }
+        wal.flush();
+        assert_eq!(wal.count(), wal.read().unwrap().count() as u64);
+    }
+
+    #[test]
+    fn snapshot() {
+        let location = "./tmp/iter_snapshot";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .storage_size(Size::Kb(64))
+            .build()
+            .unwrap();
+        let write = |ids: std::ops::Range<usize>| {
+            for i in ids {
+                wal.write(Log {
+                    id: i,
+                    text: String::from(TEXT),
+                });
+            }
+            wal.flush();
+        };
+        write(0..100);
+        let logs = wal.read().unwrap();
+        // append, rotate and collect garbage before the iteration starts
+        write(100..1000);
+        let ids = logs.map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn include_buffered() {
+        let location = "./tmp/iter_buffered";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .buffer_size(Size::Kb(64))
+            .build()
+            .unwrap();
+        let write = |ids: std::ops::Range<usize>| {
+            for i in ids {
+                wal.write(Log {
+                    id: i,
+                    text: String::from(TEXT),
+                });
+            }
+        };
+        write(0..10);
+        wal.flush();
+        write(10..20);
+        assert_eq!(wal.read().unwrap().count(), 10);
+        let options = ReadOptions {
+            include_buffered: true,
+            ..Default::default()
+        };
+        let ids = wal.read_with(options).unwrap().map(|log| log.id);
+        assert_eq!(ids.collect::<Vec<_>>(), (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn max_bandwidth() {
+        let location = "./tmp/iter_bandwidth";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new().location(location).build().unwrap();
+        for i in 0..200 {
+            wal.write(Log {
+                id: i,
+                text: String::from(TEXT),
+            });
+        }
+        wal.flush();
+        let size = std::fs::metadata(format!("{}/log_0.bin", location))
+            .unwrap()
+            .len();
+        // about half a second worth of reading
+        let options = ReadOptions {
+            max_bandwidth: Some(Size::Kb((size as usize * 2).div_ceil(1024))),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        assert_eq!(wal.read_with(options).unwrap().count(), 200);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(400));
+    }
+
+    #[test]
+    fn lazy_checksums() {
+        let location = "./tmp/iter_lazy_checksums";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .checksum(Checksum::Crc32c)
+            .build()
+            .unwrap();
+        for i in 0..10 {
+            wal.write(Log {
+                id: i,
+                text: String::from("lazy"),
+            });
+        }
+        wal.flush();
+        // flip a bit of the text of the last log
+        let path = format!("{}/log_0.bin", location);
+        let mut data = std::fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, data).unwrap();
+        let eager = wal.read_raw().unwrap().map(|(meta, _)| meta.crc_ok);
+        assert_eq!(eager.last(), Some(Some(false)));
+        assert_eq!(wal.read().unwrap().count(), 9);
+        let options = ReadOptions {
+            checksums: ChecksumVerification::Lazy,
+            ..Default::default()
+        };
+        let lazy = wal.read_with(options).unwrap().collect::<Vec<_>>();
+        assert_eq!(lazy.len(), 10);
+        assert_eq!(lazy[9].text, "lazx");
+        let raw = wal
+            .read_with(options)
+            .unwrap()
+            .raw()
+            .map(|(meta, _)| meta.crc_ok);
+        assert!(raw.into_iter().all(|crc_ok| crc_ok.is_none()));
+    }
+
+    #[test]
+    fn read_from_mirror() {
+        let location = "./tmp/iter_mirror_primary";
+        let mirror_location = "./tmp/iter_mirror_secondary";
+        let _ = std::fs::remove_dir_all(location);
+        let _ = std::fs::remove_dir_all(mirror_location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .mirror(mirror_location)
+            .build()
+            .unwrap();
+        for i in 1..=100 {
+            wal.write(Log {
+                id: i,
+                text: String::from(TEXT),
+            });
+        }
+        wal.flush();
+        drop(wal);
+        // lose the primary disk
+        std::fs::remove_dir_all(location).unwrap();
+        let wal = WalBuilder::<Log>::new()
+            .location(location)
+            .mirror(mirror_location)
+            .build()
+            .unwrap();
+        let ids = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn by_segment() {
+        let location = "./tmp/iter_by_segment";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = WalBuilder::new()
+            .location(location)
+            .storage_size(Size::Kb(64))
+            .build()
+            .unwrap();
+        for i in 0..2000 {
+            wal.write(Log {
+                id: i,
+                text: String::from(TEXT),
+            });
+        }
+        wal.flush();
+        let indexes = wal
+            .list_segments()
+            .iter()
+            .map(|segment| segment.index)
+            .collect::<Vec<_>>();
+        let mut ids = Vec::new();
+        let mut split = Vec::new();
+        for (index, logs) in wal.read().unwrap().by_segment() {
+            split.push(index);
+            for (meta, _) in logs.raw() {
+                assert_eq!(meta.segment, index);
+                ids.push(meta.lsn);
+            }
+        }
+        assert_eq!(split, indexes);
+        let all = wal.read_with_positions().unwrap().map(|(lsn, _)| lsn);
+        assert_eq!(ids, all.collect::<Vec<_>>());
+
+        // the files before the first wanted log are skipped
+        let mut segments = wal.read().unwrap().start_from(1990).by_segment();
+        let (index, logs) = segments.next().unwrap();
+        assert!(index > indexes[0]);
+        let first = logs.map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(first[0], 1990);
+        let rest = segments.map(|(_, logs)| logs.count()).sum::<usize>();
+        assert_eq!(first.len() + rest, 10);
+
+        // the buffered logs come along with the last file
+        wal.write(Log {
+            id: 2000,
+            text: String::from(TEXT),
+        });
+        let options = ReadOptions {
+            include_buffered: true,
+            ..Default::default()
+        };
+        let (index, logs) = wal.read_with(options).unwrap().by_segment().last().unwrap();
+        assert_eq!(index, *indexes.last().unwrap());
+        assert_eq!(logs.last().unwrap().id, 2000);
+    }
 }
@@ -33,26 +33,101 @@
 //! wal.write(Log{id: 2, value: 4.20});
 //!
 //! // Flush to disk early/manually, before the buffer is filled
-//! wal.flush();
+//! // and wait for the data to be synced to disk
+//! wal.flush().wait().unwrap();
 //!```
 
 mod builder;
+mod checkpoint;
+mod codec;
+mod consumer;
+mod expiry;
+mod failover;
+mod health;
 mod iter;
+mod listener;
+mod merkle;
+mod raft;
+mod reader;
+mod recovery;
+mod replay;
+mod replication;
+mod ring;
+mod scrubber;
+mod segments;
+mod set;
+#[cfg(feature = "simulation")]
+pub mod sim;
+mod snapshot;
+mod stats;
+mod throttle;
+mod verify;
 mod wal;
 pub(crate) mod writer;
 
-pub use self::builder::WalBuilder;
+pub use self::builder::{ConfigError, WalBuilder};
+pub use self::checkpoint::{CheckpointPolicy, CheckpointStats, Checkpointer};
+pub use self::codec::IntEncoding;
+pub use self::consumer::{Consumer, ConsumerGroup};
+pub use self::expiry::Expiry;
+pub use self::health::{Health, LowDiskSpace};
+pub use self::iter::{BySegment, Chunks, ReadProgress, RecordMeta, WalIterator};
+pub use self::listener::{
+    CollectedSegment, GcEvent, GcRule, Operation, SlowOperation, WalListener,
+};
+pub use self::merkle::{MerkleHash, MerkleTree};
+pub use self::raft::{HardState, RaftEntry, RaftLog};
+pub use self::reader::WalReader;
+pub use self::recovery::{RecoveryReport, SkippedRegion};
+pub use self::replication::{Replica, ShipBatch, ShippedRecord, Shipper, ShipperConfig};
+pub use self::ring::RingWal;
+pub use self::segments::{Segment, SegmentBound};
+pub use self::set::{WalSet, WalSetOptions, WalSetStats};
+pub use self::stats::{Latency, LifetimeStats, SegmentIo, WalError, WalStats};
+pub use self::throttle::WriteLimit;
+#[cfg(feature = "signing")]
+pub use self::verify::verify_signed;
+pub use self::verify::VerifyReport;
 pub use self::wal::Wal;
+pub use self::writer::{
+    decode_record, encode_record, BufferOverflow, Checksum, ChecksumVerification, FlushHandle,
+    FormatPolicy, RecordKind,
+};
+use crate::codec::Codec;
+use crate::stats::Stats;
+use crate::writer::pins::Pins;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub const DEFAULT_BUFFER_SIZE: usize = 4096; // 4 KB
 
-/// Represents size of data on KBs, MBs or GBs, such as:
+/// Log Sequence Number, the position of a log in the [Wal]
+///
+/// Logs are numbered sequentially from zero, in the order they were written to disk.
+pub type Lsn = u64;
+
+/// Represents size of data in bytes, KBs, MBs or GBs, such as:
+/// - `Size::Bytes(100)` means 100 bytes
 /// - `Size::Kb(8)` means 8 KB
 /// - `Size::Mb(16)` means 16 MB
 /// - `Size::Gb(2)` means 2 GB
+///
+/// Sizes compare by their number of bytes, so `Size::Kb(1) == Size::Bytes(1024)`, and the
+/// arithmetic on them results in [Size::Bytes]. A size can be parsed from a string as well,
+/// e.g. from a config file, with an optional unit of `B`, `KB`, `MB` or `GB`:
+///
+/// ```
+/// use walcraft::Size;
+///
+/// assert_eq!("512KB".parse::<Size>().unwrap(), Size::Kb(512));
+/// assert_eq!("1.5 GB".parse::<Size>().unwrap(), Size::Mb(1536));
+/// assert_eq!(Size::Mb(1) + Size::Kb(512), Size::Kb(1536));
+/// ```
+#[derive(Debug, Clone, Copy)]
 pub enum Size {
+    Bytes(usize),
     Kb(usize),
     Mb(usize),
     Gb(usize),
@@ -61,6 +136,7 @@ pub enum Size {
 impl Size {
     pub fn to_bytes(&self) -> usize {
         match self {
+            Size::Bytes(bytes) => *bytes,
             Size::Kb(kb) => *kb * 1024,
             Size::Mb(mb) => *mb * 1024 * 1024,
             Size::Gb(gb) => *gb * 1024 * 1024 * 1024,
@@ -68,6 +144,148 @@ impl Size {
     }
 }
 
+impl PartialEq for Size {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for Size {}
+
+impl PartialOrd for Size {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Size {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.to_bytes().cmp(&other.to_bytes())
+    }
+}
+
+impl std::str::FromStr for Size {
+    type Err = String;
+
+    /// Parse a size such as `4096`, `512KB` or `1.5GB`
+    ///
+    /// The unit is case-insensitive, and may be shortened to `K`, `M` or `G`. A fraction is
+    /// rounded down to a whole number of bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid size: {:?}", s);
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let (multiplier, size): (usize, fn(usize) -> Size) =
+            match unit.trim().to_ascii_uppercase().as_str() {
+                "" | "B" => (1, Size::Bytes),
+                "K" | "KB" => (1024, Size::Kb),
+                "M" | "MB" => (1024 * 1024, Size::Mb),
+                "G" | "GB" => (1024 * 1024 * 1024, Size::Gb),
+                _ => return Err(invalid()),
+            };
+        // whole numbers are parsed exactly, as a float can't represent every large number
+        if let Ok(number) = number.parse::<usize>() {
+            number.checked_mul(multiplier).ok_or_else(invalid)?;
+            return Ok(size(number));
+        }
+        let number = number.parse::<f64>().map_err(|_| invalid())?;
+        let bytes = number * multiplier as f64;
+        if !bytes.is_finite() || bytes >= usize::MAX as f64 {
+            return Err(invalid());
+        }
+        Ok(Size::Bytes(bytes as usize))
+    }
+}
+
+impl std::ops::Add for Size {
+    type Output = Size;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Size::Bytes(self.to_bytes() + rhs.to_bytes())
+    }
+}
+
+impl std::ops::Sub for Size {
+    type Output = Size;
+
+    /// The difference of the sizes, or zero if `rhs` is the larger one
+    fn sub(self, rhs: Self) -> Self::Output {
+        Size::Bytes(self.to_bytes().saturating_sub(rhs.to_bytes()))
+    }
+}
+
+impl std::ops::Mul<usize> for Size {
+    type Output = Size;
+
+    fn mul(self, rhs: usize) -> Self::Output {
+        Size::Bytes(self.to_bytes() * rhs)
+    }
+}
+
+impl std::ops::Div<usize> for Size {
+    type Output = Size;
+
+    fn div(self, rhs: usize) -> Self::Output {
+        Size::Bytes(self.to_bytes() / rhs)
+    }
+}
+
+/// Per-write options, used with [Wal::write_with]
+///
+/// ### Example
+/// ```no_run
+/// use walcraft::{Wal, WriteOptions};
+///
+/// let wal = Wal::new("/tmp/logz", None);
+/// // this log is synced to disk before returning, even though fsync is disabled for the wal
+/// wal.write_with("important".to_string(), WriteOptions { fsync: true, ..Default::default() });
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Sync the log to disk before returning, regardless of the fsync setting of [Wal].
+    /// This implies `priority`, as the log must reach the file before it can be synced.
+    pub fsync: bool,
+    /// Write the log straight to the file, bypassing the in-memory buffer
+    pub priority: bool,
+    /// Term, or epoch, to store along with the log, returned when reading it with
+    /// [WalIterator::with_terms]
+    ///
+    /// Meant for consensus implementations, to tell the logs appended by a deposed leader
+    /// without embedding the term in the logs. It takes up 8 more bytes in front of the log.
+    pub term: Option<u64>,
+}
+
+/// Options for reading the logs, used with [Wal::read_with]
+///
+/// ### Example
+/// ```no_run
+/// use walcraft::{ReadOptions, Size, Wal};
+///
+/// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+/// // a background replay, leaving most of the disk bandwidth to the writes
+/// let options = ReadOptions {
+///     max_bandwidth: Some(Size::Mb(20)),
+///     ..Default::default()
+/// };
+/// let logs = wal.read_with(options).unwrap().collect::<Vec<_>>();
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// Include the logs still waiting in the in-memory buffer of this process, after the logs on disk.
+    /// These logs are not durable yet, and are lost if the process crashes before they're flushed.
+    pub include_buffered: bool,
+    /// Cap on the bytes read from the files per second, so that a background read on a shared
+    /// disk doesn't starve the writes. No limit by default.
+    pub max_bandwidth: Option<Size>,
+    /// Whether the checksum of every log is verified as it's read, the default, or left
+    /// unverified, see [WalBuilder::checksum]. [RecordMeta::crc_ok] is `None` for the logs left
+    /// unverified.
+    pub checksums: ChecksumVerification,
+}
+
 /// A Data object that holds configuration for [Wal]
 #[derive(Serialize, Deserialize, Clone)]
 struct WalConfig {
@@ -79,6 +297,68 @@ struct WalConfig {
     fsync: bool,
     // a value of zero means buffer is disabled
     buffer_size: usize,
+    // what happens to a log that doesn't fit in the buffer
+    buffer_overflow: BufferOverflow,
+    // delete the location once the last handle to wal is dropped
+    temporary: bool,
+    // allow multiple processes to append to the same wal
+    multi_process: bool,
+    // location where older files are moved to, instead of being deleted
+    cold_location: Option<PathBuf>,
+    // compress files with zstd once they are filled
+    compress_segments: bool,
+    // receives notifications about events inside wal
+    #[serde(skip)]
+    listener: Option<Arc<dyn WalListener>>,
+    // how often the scrubber validates the filled files, if at all
+    scrub_interval: Option<Duration>,
+    // secondary location receiving a copy of every write
+    mirror_location: Option<PathBuf>,
+    // location the writer switches to once the writes to the primary one keep failing
+    fallback_location: Option<PathBuf>,
+    // refuse to write once a newer writer has opened the wal
+    fencing: bool,
+    // files pinned by the readers, shared by all the clones of the config
+    #[serde(skip)]
+    pins: Pins,
+    // statistics of the operations, shared by all the clones of the config
+    #[serde(skip)]
+    stats: Stats,
+    // operations taking longer than this are reported as slow
+    slow_threshold: Option<Duration>,
+    // bincode options used for the logs
+    codec: Codec,
+    // write whole checksummed pages and keep meta in two slots, to survive power cuts
+    power_loss_safe: bool,
+    // every log carries the hash of the log before it, so that tampering can be detected
+    hash_chain: bool,
+    // checksum written in front of every log
+    checksum: Checksum,
+    // logs at least this long are compressed on their own
+    compress_records: Option<usize>,
+    // key signing the files once they are filled
+    #[cfg(feature = "signing")]
+    #[serde(skip)]
+    signing_key: Option<ed25519_dalek::SigningKey>,
+    // open the files on the first write to disk, instead of at creation
+    lazy_init: bool,
+    // cap on the rate of the writes
+    #[serde(skip)]
+    write_limit: Option<WriteLimit>,
+    // how to treat the files written by a newer version
+    format_policy: FormatPolicy,
+    // name the new files with a zero-padded index and their creation time
+    sortable_names: bool,
+    // size of the pages of the files
+    page_size: usize,
+    // create the files up to the storage limit, taking up their full size on disk
+    preallocate: bool,
+    // disk space kept free, and what happens to the writes that would take it
+    disk_reserve: Option<(usize, LowDiskSpace)>,
+    // write the buffer before it's full once this much data isn't written to disk yet
+    max_unflushed: Option<usize>,
+    // wait this long for concurrent writes before syncing them together
+    sync_window: Option<Duration>,
 }
 
 impl Default for WalConfig {
@@ -88,6 +368,88 @@ impl Default for WalConfig {
             size: usize::MAX,
             fsync: false,
             buffer_size: DEFAULT_BUFFER_SIZE,
+            buffer_overflow: BufferOverflow::Grow,
+            temporary: false,
+            multi_process: false,
+            cold_location: None,
+            compress_segments: false,
+            listener: None,
+            scrub_interval: None,
+            mirror_location: None,
+            fallback_location: None,
+            fencing: false,
+            pins: Pins::default(),
+            stats: Stats::default(),
+            slow_threshold: None,
+            codec: Codec::default(),
+            power_loss_safe: false,
+            hash_chain: false,
+            checksum: Checksum::None,
+            compress_records: None,
+            write_limit: None,
+            #[cfg(feature = "signing")]
+            signing_key: None,
+            lazy_init: false,
+            format_policy: FormatPolicy::Lenient,
+            sortable_names: false,
+            page_size: writer::page::PAGE_SIZE,
+            preallocate: false,
+            disk_reserve: None,
+            max_unflushed: None,
+            sync_window: None,
         }
     }
 }
+
+impl WalConfig {
+    /// Largest serialized log that fits in a frame, less the bytes taken by the hash chain, the
+    /// checksum, the compression marker and the kind in front of every log
+    pub(crate) fn max_record_size(&self) -> usize {
+        let mut max = u16::MAX as usize - self.checksum.size() - 1;
+        if self.hash_chain {
+            max -= writer::chain::LINK_SIZE;
+        }
+        if self.compress_records.is_some() {
+            max -= 1;
+        }
+        max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size() {
+        let parse = |s: &str| s.parse::<Size>();
+        assert_eq!(parse("4096").unwrap(), Size::Bytes(4096));
+        assert!(matches!(parse("512KB").unwrap(), Size::Kb(512)));
+        assert!(matches!(parse("16 mb").unwrap(), Size::Mb(16)));
+        assert!(matches!(parse("2G").unwrap(), Size::Gb(2)));
+        assert_eq!(parse("1.5GB").unwrap().to_bytes(), 1536 * 1024 * 1024);
+        assert_eq!(parse("0.5k").unwrap(), Size::Bytes(512));
+        for invalid in [
+            "",
+            "KB",
+            "12TB",
+            "-1MB",
+            "1.2.3",
+            "nan",
+            "99999999999999999999GB",
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn size_arithmetic() {
+        assert_eq!(Size::Kb(1), Size::Bytes(1024));
+        assert!(Size::Mb(1) > Size::Kb(1000));
+        assert_eq!(Size::Mb(1) + Size::Kb(512), Size::Kb(1536));
+        assert_eq!(Size::Kb(1) - Size::Bytes(24), Size::Bytes(1000));
+        assert_eq!(Size::Kb(1) - Size::Mb(1), Size::Bytes(0));
+        assert_eq!(Size::Gb(1) / 4, Size::Mb(256));
+        assert_eq!(Size::Kb(4) * 3, Size::Kb(12));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{Lsn, SkippedRegion, WalError};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// An operation on the log files, reported by [WalListener::on_slow_operation]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Writing a filled buffer, or any other batch of logs, to the file
+    Flush,
+    /// Syncing a file to disk
+    Fsync,
+    /// Closing a filled file and opening the next one, including the garbage collection
+    Rotation,
+    /// Deleting the older files, or moving them to the cold storage
+    Gc,
+}
+
+/// An operation that took longer than the threshold set with
+/// [WalBuilder::slow_threshold](crate::WalBuilder::slow_threshold)
+#[derive(Debug, Clone)]
+pub struct SlowOperation {
+    /// The slow operation
+    pub operation: Operation,
+    /// How long the operation took
+    pub duration: Duration,
+    /// Index of the file involved, or the oldest one for the garbage collection
+    pub segment: usize,
+}
+
+/// What made the garbage collection delete log files, see [GcEvent]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcRule {
+    /// The files went over the storage limit set with
+    /// [WalBuilder::storage_size](crate::WalBuilder::storage_size)
+    StorageLimit,
+    /// The files were deleted on demand, e.g. with
+    /// [Wal::delete_segments_before](crate::Wal::delete_segments_before)
+    OnDemand,
+    /// The disk was about to run out of the space reserved with
+    /// [WalBuilder::disk_reserve](crate::WalBuilder::disk_reserve)
+    DiskReserve,
+}
+
+/// A log file deleted by the garbage collection, as described right before its deletion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectedSegment {
+    /// Index of the file, i.e. the postfix in its name
+    pub index: usize,
+    /// Path the file was deleted from, or moved from to the cold storage
+    pub path: PathBuf,
+    /// Range of [Lsn] of the logs in the file, unknown for files written by older versions
+    pub lsns: Option<Range<Lsn>>,
+    /// Size of the file on disk, in bytes
+    pub size: u64,
+    /// Whether the file was moved to the cold storage rather than deleted
+    pub offloaded: bool,
+}
+
+/// A run of the garbage collection that deleted log files, reported by [WalListener::on_gc]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcEvent {
+    /// What made the files be deleted
+    pub rule: GcRule,
+    /// The files deleted, or moved to the cold storage, oldest first
+    pub segments: Vec<CollectedSegment>,
+    /// Total size of the files, in bytes
+    pub reclaimed: u64,
+}
+
+/// Receives notifications about events happening inside [Wal](crate::Wal)
+///
+/// All the methods have an empty default implementation, so only the events of interest
+/// need to be implemented. The methods are called from the thread that ran into the event,
+/// which might be a background thread, and hence shall return quickly.
+///
+/// ### Example
+/// ```no_run
+/// use std::sync::Arc;
+/// use walcraft::{Wal, WalBuilder, WalListener};
+///
+/// struct Alert;
+///
+/// impl WalListener for Alert {
+///     fn on_corruption(&self, index: usize) {
+///         eprintln!("log file {} is corrupted", index);
+///     }
+/// }
+///
+/// let wal: Wal<String> = WalBuilder::new()
+///     .location("/tmp/logz")
+///     .listener(Arc::new(Alert))
+///     .build()
+///     .unwrap();
+/// ```
+pub trait WalListener: Send + Sync {
+    /// A filled log file no longer matches the checksum recorded when it was closed,
+    /// or it can no longer be read
+    fn on_corruption(&self, _index: usize) {}
+
+    /// An operation took longer than the threshold set with
+    /// [WalBuilder::slow_threshold](crate::WalBuilder::slow_threshold)
+    fn on_slow_operation(&self, _operation: SlowOperation) {}
+
+    /// [Wal::replay](crate::Wal::replay) has applied `applied` logs out of about `total`
+    ///
+    /// The total is counted when the replay starts, and doesn't include the logs written since.
+    fn on_replay_progress(&self, _applied: u64, _total: u64) {}
+
+    /// A checkpoint taken by a [Checkpointer](crate::Checkpointer) failed, and will be retried
+    fn on_checkpoint_failure(&self, _error: &str) {}
+
+    /// A corrupted region of a log file was skipped while reading the logs, e.g. a log failing
+    /// its checksum, or the rest of a file after a corrupted frame
+    fn on_skipped_region(&self, _region: SkippedRegion) {}
+
+    /// A failure happened inside the [Wal](crate::Wal), e.g. a write to a file failed
+    ///
+    /// Without a listener, the failures are printed to stderr instead.
+    fn on_error(&self, _error: &WalError) {}
+
+    /// The garbage collection deleted log files, or moved them to the cold storage
+    ///
+    /// Nothing is reported when no file was deleted, e.g. when the oldest one is pinned.
+    fn on_gc(&self, _event: GcEvent) {}
+}
@@ -0,0 +1,206 @@
+use crate::writer::frame;
+use sha2::{Digest, Sha256};
+use std::io::{BufReader, Read};
+use std::ops::Range;
+use std::path::Path;
+
+/// SHA-256 digest of a node of a [MerkleTree]
+pub type MerkleHash = [u8; 32];
+
+/// Hash of a log, as a leaf of the tree
+fn leaf(record: &[u8]) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(record);
+    hasher.finalize().into()
+}
+
+/// Hash of a node over two others, distinct from the hashes of the leaves
+fn node(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Merkle tree over the logs of a log file, as listed in [Segment::merkle_root](crate::Segment::merkle_root)
+///
+/// The leaves are the hashes of the logs as stored in the file, in order. Every level pairs up
+/// the nodes of the level below, and a node left without a pair moves up as it is.
+///
+/// Replicas holding identical copies of a file get the same root. When the roots differ,
+/// comparing the nodes from the top down narrows the difference down to the logs that differ,
+/// without transferring the whole file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleTree {
+    /// The leaves first, up to the root
+    levels: Vec<Vec<MerkleHash>>,
+}
+
+impl MerkleTree {
+    /// Build the tree over a log file, e.g. a copy received by a replica
+    ///
+    /// Compressed files must be decompressed first.
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    /// Build the tree over a log file, read from its start
+    pub(crate) fn from_reader(reader: impl Read) -> std::io::Result<Self> {
+        let mut leaves = Vec::new();
+        frame::for_each_record(BufReader::new(reader), |record| leaves.push(leaf(record)))?;
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let below = &levels[levels.len() - 1];
+            let level = below
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(level);
+        }
+        Ok(Self { levels })
+    }
+
+    /// The root of the tree, `None` for a file without logs
+    pub fn root(&self) -> Option<MerkleHash> {
+        self.levels.last()?.first().copied()
+    }
+
+    /// Number of logs in the file
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Whether the file has no logs
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of levels, from the leaves up to the root
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The nodes of a level, with the leaves at level 0
+    pub fn level(&self, level: usize) -> &[MerkleHash] {
+        self.levels.get(level).map_or(&[], |nodes| nodes.as_slice())
+    }
+
+    /// Positions in the file of the logs under a node
+    pub fn covered(&self, level: usize, index: usize) -> Range<usize> {
+        let start = (index << level).min(self.len());
+        let end = ((index + 1) << level).min(self.len());
+        start..end
+    }
+
+    /// Positions in the file of the logs that differ from the ones of another tree
+    ///
+    /// Only the subtrees whose roots differ are descended into, the same way replicas narrow
+    /// down the logs to transfer by exchanging the nodes. The logs past the end of the shorter
+    /// tree all differ.
+    pub fn diff(&self, other: &MerkleTree) -> Vec<Range<usize>> {
+        let len = self.len().min(other.len());
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let top = self.depth().max(other.depth()) - 1;
+        let mut stack = vec![(top, 0)];
+        while let Some((level, index)) = stack.pop() {
+            // nodes over the same logs with the same hash
+            let same = self.covered(level, index) == other.covered(level, index)
+                && self.level(level).get(index) == other.level(level).get(index);
+            if same || index << level >= len {
+                continue;
+            }
+            if level == 0 {
+                match ranges.last_mut() {
+                    Some(range) if range.end == index => range.end += 1,
+                    _ => ranges.push(index..index + 1),
+                }
+                continue;
+            }
+            // the right child first, so that the ranges come out in order
+            stack.extend([(level - 1, index * 2 + 1), (level - 1, index * 2)]);
+        }
+        let max = self.len().max(other.len());
+        if len < max {
+            match ranges.last_mut() {
+                Some(range) if range.end == len => range.end = max,
+                _ => ranges.push(len..max),
+            }
+        }
+        ranges
+    }
+}
+
+/// Compute the root of the tree over a log file, read from its start
+///
+/// Only the nodes on the right edge of the tree built so far are kept, so files of any size
+/// take little memory.
+pub(crate) fn root(reader: impl Read) -> std::io::Result<Option<MerkleHash>> {
+    // the roots of the complete subtrees, with their heights, from the left
+    let mut peaks: Vec<(u32, MerkleHash)> = Vec::new();
+    frame::for_each_record(BufReader::new(reader), |record| {
+        let mut peak = (0, leaf(record));
+        while let Some((height, left)) = peaks.last().filter(|(h, _)| *h == peak.0) {
+            peak = (height + 1, node(left, &peak.1));
+            peaks.pop();
+        }
+        peaks.push(peak);
+    })?;
+    // the smaller subtrees on the right move up until they pair with the bigger ones
+    let root = peaks
+        .into_iter()
+        .rev()
+        .map(|(_, hash)| hash)
+        .reduce(|right, left| node(&left, &right));
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::header::header_with;
+
+    fn file(records: &[u8]) -> Vec<u8> {
+        let mut file = header_with(0, Default::default()).to_vec();
+        for record in records {
+            file.extend([1, 0, *record]);
+        }
+        file
+    }
+
+    #[test]
+    fn roots() {
+        for n in 0..20u8 {
+            let records = (0..n).collect::<Vec<_>>();
+            let tree = MerkleTree::from_reader(&file(&records)[..]).unwrap();
+            assert_eq!(tree.len(), n as usize);
+            assert_eq!(root(&file(&records)[..]).unwrap(), tree.root());
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let records = (0..11).collect::<Vec<_>>();
+        let tree = MerkleTree::from_reader(&file(&records)[..]).unwrap();
+        assert!(tree.diff(&tree).is_empty());
+        let mut changed = records.clone();
+        changed[3] = 100;
+        changed[4] = 100;
+        changed[10] = 100;
+        let other = MerkleTree::from_reader(&file(&changed)[..]).unwrap();
+        assert_eq!(tree.diff(&other), [3..5, 10..11]);
+        assert_eq!(tree.covered(1, 5), 10..11);
+        // missing logs at the end
+        let short = MerkleTree::from_reader(&file(&records[..9])[..]).unwrap();
+        assert_eq!(tree.diff(&short), vec![9..11]);
+        let longer = MerkleTree::from_reader(&file(&(0..17).collect::<Vec<_>>())[..]).unwrap();
+        assert_eq!(tree.diff(&longer), vec![11..17]);
+        let empty = MerkleTree::from_reader(&file(&[])[..]).unwrap();
+        assert_eq!(empty.diff(&tree), vec![0..11]);
+    }
+}
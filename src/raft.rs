@@ -0,0 +1,322 @@
+use crate::segments::{self, SegmentBound};
+use crate::{Lsn, Wal};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// An entry of a Raft log, as stored in a [RaftLog]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaftEntry<T> {
+    /// Position of the entry in the Raft log, starting at 1
+    pub index: u64,
+    /// Term of the leader that created the entry
+    pub term: u64,
+    /// The command carried by the entry
+    pub data: T,
+}
+
+/// The state a Raft node persists besides its entries, see [RaftLog::set_hard_state]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HardState {
+    /// Latest term the node has seen
+    pub term: u64,
+    /// Node voted for in the current term, if any
+    pub vote: Option<u64>,
+    /// Index of the last entry known to be committed
+    pub commit: u64,
+}
+
+/// Keys of the [HardState] in the state of the [Wal], see [Wal::set_state]
+const TERM_KEY: &str = "raft.term";
+const VOTE_KEY: &str = "raft.vote";
+const COMMIT_KEY: &str = "raft.commit";
+
+/// A durable Raft log on top of a [Wal], for a Raft implementation to keep its entries in
+///
+/// It offers the operations the log storage of Raft crates such as `raft-rs` and `openraft` is
+/// built from: appending entries, reading a range of them, looking up their terms, discarding
+/// the conflicting ones at the tail, compacting the entries covered by a snapshot, and keeping
+/// the [HardState] of the node. Neither crate is a dependency, so their storage traits aren't
+/// implemented here: an implementation of them in the application calls these methods.
+///
+/// Every entry is a log of the [Wal], the index of an entry being its [Lsn] plus one, and its
+/// term being stored along with it, see [WriteOptions::term](crate::WriteOptions::term).
+/// The [Wal] must hold nothing else.
+///
+/// ### Example
+/// ```no_run
+/// use walcraft::{RaftEntry, RaftLog, Wal};
+///
+/// let log = RaftLog::new(Wal::new("/tmp/raft", None));
+/// log.append(vec![RaftEntry { index: 1, term: 1, data: "set x=1".to_string() }])
+///     .unwrap();
+/// assert_eq!(log.last_index().unwrap(), 1);
+/// assert_eq!(log.term(1).unwrap(), Some(1));
+/// ```
+pub struct RaftLog<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    wal: Wal<T>,
+}
+
+impl<T> RaftLog<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Keep a Raft log in a [Wal] holding nothing else
+    pub fn new(wal: Wal<T>) -> Self {
+        Self { wal }
+    }
+
+    /// The [Wal] the entries are stored in
+    pub fn wal(&self) -> &Wal<T> {
+        &self.wal
+    }
+
+    /// Index of the first entry kept, one past the last entry if there's none
+    pub fn first_index(&self) -> Result<u64, String> {
+        let first = self
+            .wal
+            .list_segments()
+            .into_iter()
+            .find_map(|segment| segment.lsns.filter(|lsns| !lsns.is_empty()));
+        match first {
+            Some(lsns) => Ok(lsns.start + 1),
+            None => Ok(self.last_index()? + 1),
+        }
+    }
+
+    /// Index of the last entry, zero if none was ever appended
+    pub fn last_index(&self) -> Result<u64, String> {
+        segments::next_lsn(&self.wal.inner.config)
+            .ok_or("The LSN of the logs written by older versions is unknown".to_string())
+    }
+
+    /// Append entries received from the leader, returning once they're synced to disk
+    ///
+    /// The entries may start before the end of the log. The ones already in it are kept, and
+    /// from the first one whose term differs, the entries in the log are replaced along with
+    /// all the ones after them, see [Wal::append_at].
+    ///
+    /// ## Returns
+    /// An error, without writing anything, if the entries aren't contiguous or would leave a gap
+    /// after the last entry
+    pub fn append<I>(&self, entries: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = RaftEntry<T>>,
+    {
+        let entries = entries.into_iter().collect::<Vec<_>>();
+        let start = match entries.first() {
+            Some(entry) => entry.index,
+            None => return Ok(()),
+        };
+        let last = self.last_index()?;
+        if start == 0 || start > last + 1 {
+            return Err(format!(
+                "Entry {} doesn't follow the last entry of the log, {}",
+                start, last
+            ));
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.index != start + i as u64 {
+                return Err(format!(
+                    "Entry {} doesn't follow entry {}",
+                    entry.index,
+                    start + i as u64 - 1
+                ));
+            }
+        }
+        // the entries matching the ones in the log are left alone
+        let stored = self.wal.read()?.start_from(lsn(start)).raw();
+        let kept = entries
+            .iter()
+            .zip(stored)
+            .take_while(|(entry, (meta, _))| {
+                meta.lsn == lsn(entry.index) && meta.term.unwrap_or(0) == entry.term
+            })
+            .count();
+        if kept == entries.len() {
+            return Ok(());
+        }
+        let new = entries
+            .into_iter()
+            .skip(kept)
+            .map(|entry| (Some(entry.term), entry.data));
+        self.wal.append_at(lsn(start + kept as u64), new)?;
+        self.wal.flush().wait().map_err(|e| e.to_string())
+    }
+
+    /// Read the entries in a range of indexes
+    ///
+    /// ## Returns
+    /// The entries found, fewer than asked for past the last one, or an error if the first one
+    /// was compacted
+    pub fn entries(&self, range: Range<u64>) -> Result<Vec<RaftEntry<T>>, String> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let first = self.first_index()?;
+        if range.start < first {
+            return Err(format!(
+                "Entry {} was compacted, the log starts at {}",
+                range.start, first
+            ));
+        }
+        let entries = self
+            .wal
+            .read()?
+            .start_from(lsn(range.start))
+            .with_terms()
+            .take_while(|(lsn, _, _)| *lsn + 1 < range.end)
+            .map(|(lsn, term, data)| RaftEntry {
+                index: lsn + 1,
+                term: term.unwrap_or(0),
+                data,
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    /// The term of an entry
+    ///
+    /// ## Returns
+    /// `None` if there's no such entry, or if it was compacted
+    pub fn term(&self, index: u64) -> Result<Option<u64>, String> {
+        if index == 0 || index < self.first_index()? {
+            return Ok(None);
+        }
+        let term = self
+            .wal
+            .read()?
+            .start_from(lsn(index))
+            .raw()
+            .next()
+            .filter(|(meta, _)| meta.lsn == lsn(index))
+            .map(|(meta, _)| meta.term.unwrap_or(0));
+        Ok(term)
+    }
+
+    /// Persist the hard state of the node, once the entries appended before it are synced to
+    /// disk, see [Wal::set_state]
+    pub fn set_hard_state(&self, state: HardState) -> Result<(), String> {
+        let (term, commit) = (state.term.to_le_bytes(), state.commit.to_le_bytes());
+        // no vote is stored as no value, removing the key
+        let vote = state.vote.map(u64::to_le_bytes);
+        self.wal.set_state(&[
+            (TERM_KEY, &term),
+            (VOTE_KEY, vote.as_ref().map_or(&[], |v| v.as_slice())),
+            (COMMIT_KEY, &commit),
+        ])
+    }
+
+    /// The hard state of the node, the default one if it was never set
+    pub fn hard_state(&self) -> HardState {
+        let value = |key| {
+            let bytes = self.wal.state(key)?;
+            Some(u64::from_le_bytes(bytes.try_into().ok()?))
+        };
+        HardState {
+            term: value(TERM_KEY).unwrap_or(0),
+            vote: value(VOTE_KEY),
+            commit: value(COMMIT_KEY).unwrap_or(0),
+        }
+    }
+
+    /// Discard the entries from an index onwards, e.g. the ones conflicting with the leader's
+    ///
+    /// ## Returns
+    /// The number of entries discarded, or an error if the entry was compacted
+    pub fn truncate(&self, index: u64) -> Result<u64, String> {
+        self.wal.delete_from(lsn(index.max(1)))
+    }
+
+    /// Discard the entries before an index, once a snapshot covers them
+    ///
+    /// Whole files are deleted, and the entries before the index in the first file left are
+    /// trimmed, see [Wal::delete_segments_before].
+    pub fn compact(&self, index: u64) {
+        self.wal
+            .delete_segments_before(SegmentBound::Lsn(lsn(index)));
+    }
+}
+
+/// The [Lsn] an entry is stored at
+fn lsn(index: u64) -> Lsn {
+    index.saturating_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(terms: &[(u64, u64)]) -> Vec<RaftEntry<String>> {
+        terms
+            .iter()
+            .map(|(index, term)| RaftEntry {
+                index: *index,
+                term: *term,
+                data: format!("entry {}", index),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raft_log() {
+        let location = "./tmp/raft_log";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let log = RaftLog::new(Wal::new(location, None));
+        assert_eq!(log.first_index().unwrap(), 1);
+        assert_eq!(log.last_index().unwrap(), 0);
+        log.append(entries(&[(1, 1), (2, 1), (3, 2)])).unwrap();
+        // a gap, or entries out of order
+        assert!(log.append(entries(&[(5, 2)])).is_err());
+        assert!(log.append(entries(&[(4, 2), (6, 2)])).is_err());
+        log.append(entries(&[(4, 3)])).unwrap();
+        // the entries already there are kept, the conflicting ones replaced
+        log.append(entries(&[(2, 1), (3, 2)])).unwrap();
+        assert_eq!(log.last_index().unwrap(), 4);
+        log.append(entries(&[(3, 3)])).unwrap();
+        assert_eq!(log.last_index().unwrap(), 3);
+        log.append(entries(&[(3, 2), (4, 3)])).unwrap();
+        assert_eq!(log.last_index().unwrap(), 4);
+        assert_eq!(log.entries(2..4).unwrap(), entries(&[(2, 1), (3, 2)]));
+        assert_eq!(log.entries(4..10).unwrap(), entries(&[(4, 3)]));
+        assert_eq!(log.term(3).unwrap(), Some(2));
+        assert_eq!(log.term(5).unwrap(), None);
+        assert_eq!(log.term(0).unwrap(), None);
+        // the conflicting entries replaced
+        assert_eq!(log.truncate(4).unwrap(), 1);
+        assert_eq!(log.truncate(5).unwrap(), 0);
+        log.append(entries(&[(4, 4)])).unwrap();
+        assert_eq!(log.term(4).unwrap(), Some(4));
+
+        // the hard state
+        assert_eq!(log.hard_state(), HardState::default());
+        let state = HardState {
+            term: 4,
+            vote: Some(2),
+            commit: 3,
+        };
+        log.set_hard_state(state).unwrap();
+        assert_eq!(log.hard_state(), state);
+
+        // compacted, and reopened
+        log.compact(3);
+        drop(log);
+        let log = RaftLog::<String>::new(Wal::new(location, None));
+        assert_eq!(log.first_index().unwrap(), 3);
+        assert_eq!(log.hard_state(), state);
+        let state = HardState {
+            vote: None,
+            ..state
+        };
+        log.set_hard_state(state).unwrap();
+        assert_eq!(log.hard_state(), state);
+        assert_eq!(log.last_index().unwrap(), 4);
+        assert!(log.entries(1..3).is_err());
+        assert_eq!(log.term(2).unwrap(), None);
+        assert!(log.truncate(2).is_err());
+        assert_eq!(log.entries(3..5).unwrap(), entries(&[(3, 2), (4, 4)]));
+    }
+}
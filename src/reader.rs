@@ -0,0 +1,154 @@
+use crate::iter::{self, RecordMeta, WalIterator};
+use crate::segments::{self, Segment};
+use crate::verify::{self, VerifyReport};
+use crate::writer::manager::Meta;
+use crate::{IntEncoding, Lsn, ReadOptions, WalConfig};
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// Read-only access to the logs of an existing [Wal](crate::Wal)
+///
+/// Unlike [Wal::new](crate::Wal::new), opening a [WalReader] never creates nor modifies any
+/// file, so it's safe to point at a WAL owned by another process, or at a copy of one, e.g.
+/// for inspection tools and backups.
+///
+/// ### Example
+/// ```no_run
+/// use walcraft::WalReader;
+///
+/// let reader: WalReader<String> = WalReader::open("/tmp/logz").unwrap();
+/// for log in reader.read().unwrap() {
+///     println!("{}", log);
+/// }
+/// ```
+pub struct WalReader<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    config: WalConfig,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> WalReader<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Open the WAL in a directory for reading
+    ///
+    /// ## Returns
+    /// An error if the directory doesn't exist or doesn't hold a WAL
+    pub fn open(location: &str) -> Result<Self, String> {
+        let location = PathBuf::from(location);
+        if !location.is_dir() {
+            return Err(format!("{} is not a directory", location.display()));
+        }
+        if !Meta::new(location.clone()).exists() {
+            return Err(format!("No WAL found in {}", location.display()));
+        }
+        let config = WalConfig {
+            location,
+            ..Default::default()
+        };
+        Ok(Self {
+            config,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Set the encoding of the integers inside the logs, if the WAL was written with
+    /// [WalBuilder::int_encoding](crate::WalBuilder::int_encoding)
+    pub fn int_encoding(mut self, encoding: IntEncoding) -> Self {
+        self.config.codec.int_encoding = encoding;
+        self
+    }
+
+    /// Set the fallback location, if the WAL was written with
+    /// [WalBuilder::fallback](crate::WalBuilder::fallback), to read the logs written to it
+    /// once the writer switched
+    pub fn fallback(mut self, location: &str) -> Self {
+        self.config.fallback_location = Some(PathBuf::from(location));
+        self
+    }
+
+    /// Read all the logs, as with [Wal::read](crate::Wal::read)
+    ///
+    /// The logs appended by a writer while reading may or may not be included.
+    pub fn read(&self) -> Result<WalIterator<T>, String> {
+        Ok(WalIterator::detached(
+            self.config.clone(),
+            ReadOptions::default(),
+        ))
+    }
+
+    /// Read the logs starting at the given [Lsn], along with their [Lsn]
+    pub fn read_from(&self, lsn: Lsn) -> Result<impl Iterator<Item = (Lsn, T)>, String> {
+        let iter = WalIterator::detached(self.config.clone(), ReadOptions::default());
+        Ok(iter.start_from(lsn).with_positions())
+    }
+
+    /// Read the undecoded logs along with their [RecordMeta]
+    pub fn read_raw(&self) -> Result<impl Iterator<Item = (RecordMeta, Vec<u8>)>, String> {
+        let iter = WalIterator::<T>::detached(self.config.clone(), ReadOptions::default());
+        Ok(iter.raw())
+    }
+
+    /// Count the logs, as with [Wal::count](crate::Wal::count)
+    pub fn count(&self) -> u64 {
+        iter::count(&self.config)
+    }
+
+    /// List all the log files, from the oldest to the newest
+    pub fn list_segments(&self) -> Vec<Segment> {
+        segments::list(&self.config)
+    }
+
+    /// Verify the integrity of all the filled log files, as with [Wal::verify](crate::Wal::verify)
+    pub fn verify(&self) -> VerifyReport {
+        verify::verify(&self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Wal;
+
+    #[test]
+    fn read_only() {
+        let location = "./tmp/wal_reader";
+        let _ = std::fs::remove_dir_all(location);
+        assert!(WalReader::<u64>::open(location).is_err());
+        std::fs::create_dir_all(location).unwrap();
+        assert!(WalReader::<u64>::open(location).is_err());
+        // nothing was created while failing
+        assert_eq!(std::fs::read_dir(location).unwrap().count(), 0);
+        let wal: Wal<u64> = Wal::new(location, None);
+        wal.write_iter(0..100);
+        wal.flush();
+        drop(wal);
+        let files = || {
+            let mut files = std::fs::read_dir(location)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        let before = files();
+        let meta = std::fs::read(format!("{}/meta", location)).unwrap();
+        let reader: WalReader<u64> = WalReader::open(location).unwrap();
+        assert_eq!(
+            reader.read().unwrap().collect::<Vec<_>>(),
+            (0..100).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            reader.read_from(98).unwrap().collect::<Vec<_>>(),
+            [(98, 98), (99, 99)]
+        );
+        assert_eq!(reader.count(), 100);
+        assert!(reader.verify().is_ok());
+        assert_eq!(files(), before);
+        assert_eq!(std::fs::read(format!("{}/meta", location)).unwrap(), meta);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::writer::naming;
+use std::path::Path;
+
+/// Outcome of checking the log files against the pointers in meta, when the WAL was opened
+///
+/// Available with [Wal::recovery_report](crate::Wal::recovery_report)
+#[derive(Debug, Default, Clone)]
+pub struct RecoveryReport {
+    /// Pointers `(gc, current)` found in meta, before any repair
+    pub found: Option<(usize, usize)>,
+    /// Pointers `(gc, current)` the WAL was opened with
+    pub pointers: (usize, usize),
+    /// Whether the pointers had to be moved past missing files, e.g. deleted manually
+    pub repaired: bool,
+    /// Files between the pointers that couldn't be found, leaving a gap in the logs
+    pub gaps: Vec<usize>,
+    /// Bytes of a partial log cut off from the end of the current file, e.g. after a crash
+    pub truncated: u64,
+    /// Logs skipped as corrupted by the reads since the WAL was opened, e.g. by
+    /// [Wal::replay](crate::Wal::replay), leaving out the ones lost in a corrupted frame
+    pub skipped_records: u64,
+    /// Bytes of the files skipped as corrupted by the reads since the WAL was opened
+    pub skipped_bytes: u64,
+}
+
+/// A corrupted region of a log file, skipped while reading the logs
+///
+/// Reported to [WalListener::on_skipped_region](crate::WalListener::on_skipped_region), and
+/// counted in [WalStats](crate::WalStats) and the [RecoveryReport].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRegion {
+    /// Index of the file
+    pub segment: usize,
+    /// Position of the region in the (uncompressed) file, in bytes
+    pub offset: u64,
+    /// Size of the region, in bytes
+    pub bytes: u64,
+    /// Logs known to be lost, as a corrupted frame hides how many logs follow it
+    pub records: u64,
+}
+
+impl RecoveryReport {
+    /// Whether the files on disk matched the pointers in meta
+    pub fn is_consistent(&self) -> bool {
+        !self.repaired && self.gaps.is_empty() && self.truncated == 0
+    }
+}
+
+/// Check that the files between the pointers exist, and repair the pointers if they don't
+///
+/// The garbage pointer is moved past the missing oldest files, while the files missing in
+/// between existing ones are reported as gaps. If none of the files exist, the pointers are
+/// reset to start afresh from the current file.
+///
+/// The files are looked for in all the locations given, i.e. in the primary location as well
+/// once switched to the fallback one.
+pub(crate) fn check(locations: &[&Path], pointers: Option<(usize, usize)>) -> RecoveryReport {
+    let (gc, current) = pointers.unwrap_or((0, 0));
+    let mut report = RecoveryReport {
+        found: pointers,
+        pointers: (gc, current),
+        ..Default::default()
+    };
+    if pointers.is_none() {
+        return report;
+    }
+    // distance of each existing file from the garbage pointer, up to the current file
+    let span = current.wrapping_sub(gc);
+    let mut present = locations
+        .iter()
+        .flat_map(|location| segment_indexes(location))
+        .map(|index| index.wrapping_sub(gc))
+        .filter(|distance| *distance <= span)
+        .collect::<Vec<_>>();
+    present.sort_unstable();
+    let first = match present.first() {
+        Some(distance) => *distance,
+        None => {
+            // nothing left to read, only the current file may be created
+            report.repaired = span > 0;
+            report.pointers = (current, current);
+            return report;
+        }
+    };
+    if first > 0 {
+        report.repaired = true;
+        report.pointers = (gc.wrapping_add(first), current);
+    }
+    // files missing in between the existing ones
+    // the current file is simply created again when it's missing
+    let mut expected = first;
+    for distance in present.into_iter().chain(std::iter::once(span)) {
+        while expected < distance {
+            report.gaps.push(gc.wrapping_add(expected));
+            expected += 1;
+        }
+        expected = distance + 1;
+    }
+    report
+}
+
+/// Indexes of all the log files stored at the location
+fn segment_indexes(location: &Path) -> Vec<usize> {
+    let entries = match std::fs::read_dir(location) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            naming::parse(&name)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repair_pointers() {
+        let location = Path::new("./tmp/recovery_check");
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        for index in [3, 4, 6] {
+            std::fs::write(location.join(format!("log_{}.bin", index)), [1; 10]).unwrap();
+        }
+        // the oldest files were deleted, and a file in the middle too
+        let report = check(&[location], Some((1, 7)));
+        assert!(report.repaired);
+        assert_eq!(report.pointers, (3, 7));
+        assert_eq!(report.gaps, vec![5]);
+        // nothing is missing
+        let report = check(&[location], Some((6, 7)));
+        assert!(report.is_consistent());
+        // all of the files are gone
+        let report = check(&[location], Some((10, 12)));
+        assert!(report.repaired);
+        assert_eq!(report.pointers, (12, 12));
+        assert!(report.gaps.is_empty());
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{Lsn, WalConfig};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Prefix of the files storing the progress of the resumable replays, in the location of the
+/// log files
+const PROGRESS_FILE_PREFIX: &str = "replay.";
+
+/// Path of the file storing the progress of a resumable replay
+pub(crate) fn progress_path(config: &WalConfig, name: &str) -> Result<PathBuf, String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(format!("Invalid replay name: {:?}", name));
+    }
+    Ok(config
+        .location
+        .join(format!("{}{}", PROGRESS_FILE_PREFIX, name)))
+}
+
+/// Load the progress of a resumable replay
+///
+/// ## Returns
+/// The [Lsn] of the next log to apply along with the state built from the logs before it, or
+/// `None` if the replay never stored its progress
+pub(crate) fn load<S>(path: &PathBuf) -> Result<Option<(Lsn, S)>, String>
+where
+    S: for<'a> Deserialize<'a>,
+{
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read replay progress: {}", e)),
+    };
+    bincode::deserialize(&data)
+        .map(Some)
+        .map_err(|e| format!("Corrupted replay progress: {}", e))
+}
+
+/// Store the progress of a resumable replay
+///
+/// The progress is written to a temp file and renamed once synced, so a crash leaves either
+/// the previous progress or the new one, but never a partial file.
+pub(crate) fn store<S: Serialize>(path: &PathBuf, next: Lsn, state: &S) -> Result<(), String> {
+    let mut temp = path.clone().into_os_string();
+    temp.push(".tmp");
+    let result = File::create(&temp)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            bincode::serialize_into(&mut writer, &(next, state)).map_err(std::io::Error::other)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temp, path));
+    result.map_err(|e| format!("Failed to store replay progress: {}", e))
+}
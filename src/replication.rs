@@ -0,0 +1,321 @@
+use crate::writer::frame::RecordHeader;
+use crate::{Lsn, RecordKind, Size, Wal};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// How a [Shipper] batches the records, and how many batches it keeps in flight
+#[derive(Debug, Clone)]
+pub struct ShipperConfig {
+    /// Largest number of records in a batch
+    pub batch_records: usize,
+    /// Largest amount of record data in a batch, a single record larger than it being shipped
+    /// in a batch of its own
+    pub batch_bytes: Size,
+    /// Largest number of batches shipped and not acknowledged yet
+    pub window: usize,
+}
+
+impl Default for ShipperConfig {
+    fn default() -> Self {
+        Self {
+            batch_records: 1024,
+            batch_bytes: Size::Mb(1),
+            window: 8,
+        }
+    }
+}
+
+/// A record shipped from a [Wal] to a replica, undecoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShippedRecord {
+    /// Whether the record is a log or a control record
+    pub kind: RecordKind,
+    /// Term the log was written in, if any
+    pub term: Option<u64>,
+    /// The serialized log, or the payload of the control record
+    pub data: Vec<u8>,
+}
+
+/// A batch of consecutive records shipped by a [Shipper], to be applied by a [Replica]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShipBatch {
+    /// [Lsn] of the first record
+    pub first: Lsn,
+    /// The records, from `first` onwards
+    pub records: Vec<ShippedRecord>,
+}
+
+impl ShipBatch {
+    /// [Lsn] right after the last record, which the replica acknowledges once it applied them
+    pub fn end(&self) -> Lsn {
+        self.first + self.records.len() as Lsn
+    }
+}
+
+/// Ships the records of a [Wal] to a replica in batches, over any transport, with flow control
+///
+/// Several batches are kept in flight, so a link with a high latency stays busy, but no more
+/// than the configured window, so a slow replica isn't overrun. The replica acknowledges the
+/// batches by [Lsn], see [Replica::apply], and every acknowledgement frees the window up to it.
+/// After the link is lost, [Shipper::rewind] ships again the batches not acknowledged.
+///
+/// Only the records on disk are shipped, so the logs waiting in the buffer aren't seen before
+/// [Wal::flush]. The control records are shipped along with the logs.
+///
+/// ### Example
+/// ```no_run
+/// use walcraft::{Replica, Shipper, ShipperConfig, Wal};
+///
+/// let primary: Wal<String> = Wal::new("/tmp/primary", None);
+/// let replica = Replica::new(Wal::<String>::new("/tmp/replica", None));
+/// let shipper = Shipper::new(primary, replica.next_lsn().unwrap(), ShipperConfig::default());
+/// while let Some(batch) = shipper.next_batch().unwrap() {
+///     // sent over the network in practice, the acknowledgement coming back later
+///     let ack = replica.apply(&batch).unwrap();
+///     shipper.ack(ack);
+/// }
+/// ```
+pub struct Shipper<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    wal: Wal<T>,
+    config: ShipperConfig,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// [Lsn] of the next record never shipped
+    next: Lsn,
+    /// [Lsn] before which the replica has all the records
+    acked: Lsn,
+    /// End of every batch shipped and not acknowledged yet, oldest first
+    in_flight: VecDeque<Lsn>,
+}
+
+impl<T> Shipper<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Ship the records from the given [Lsn] onwards, usually [Replica::next_lsn] of the replica
+    pub fn new(wal: Wal<T>, from: Lsn, config: ShipperConfig) -> Self {
+        let state = State {
+            next: from,
+            acked: from,
+            in_flight: VecDeque::new(),
+        };
+        Self {
+            wal,
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The next batch to ship
+    ///
+    /// ## Returns
+    /// `None` if the window is full, or all the records on disk were shipped already
+    pub fn next_batch(&self) -> Result<Option<ShipBatch>, String> {
+        let mut state = self.state();
+        if state.in_flight.len() >= self.config.window.max(1) {
+            return Ok(None);
+        }
+        let max_bytes = self.config.batch_bytes.to_bytes();
+        let mut records = Vec::new();
+        let mut bytes = 0;
+        for (meta, data) in self.wal.read()?.start_from(state.next).raw() {
+            if meta.lsn != state.next + records.len() as Lsn {
+                // the records before the next one were deleted, e.g. by the storage limit
+                if records.is_empty() {
+                    return Err(format!(
+                        "Log {} was deleted before being shipped, the logs continue from {}",
+                        state.next, meta.lsn
+                    ));
+                }
+                break;
+            }
+            if records.len() >= self.config.batch_records
+                || (!records.is_empty() && bytes + data.len() > max_bytes)
+            {
+                break;
+            }
+            bytes += data.len();
+            records.push(ShippedRecord {
+                kind: meta.kind,
+                term: meta.term,
+                data,
+            });
+        }
+        if records.is_empty() {
+            return Ok(None);
+        }
+        let batch = ShipBatch {
+            first: state.next,
+            records,
+        };
+        state.next = batch.end();
+        state.in_flight.push_back(batch.end());
+        Ok(Some(batch))
+    }
+
+    /// Acknowledge that the replica has all the records before the given [Lsn]
+    ///
+    /// The batches ending at or before it leave the window. Acknowledgements older than the
+    /// latest one are ignored, as they can arrive out of order.
+    pub fn ack(&self, lsn: Lsn) {
+        let mut state = self.state();
+        if lsn <= state.acked {
+            return;
+        }
+        state.acked = lsn;
+        while state.in_flight.front().is_some_and(|end| *end <= lsn) {
+            state.in_flight.pop_front();
+        }
+        // the replica may be ahead of what was shipped, e.g. by another shipper
+        if lsn > state.next {
+            state.next = lsn;
+        }
+    }
+
+    /// Ship again the records not acknowledged, e.g. once the link is back after a failure
+    pub fn rewind(&self) {
+        let mut state = self.state();
+        state.next = state.acked;
+        state.in_flight.clear();
+    }
+
+    /// [Lsn] before which the replica has all the records
+    pub fn acked(&self) -> Lsn {
+        self.state().acked
+    }
+
+    /// Number of batches shipped and not acknowledged yet
+    pub fn in_flight(&self) -> usize {
+        self.state().in_flight.len()
+    }
+}
+
+/// The receiving end of a [Shipper], applying the batches to a [Wal] in the same order and at
+/// the same [Lsn] as in the shipping [Wal]
+pub struct Replica<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    wal: Wal<T>,
+}
+
+impl<T> Replica<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Apply the batches to a [Wal], holding nothing but the shipped records
+    pub fn new(wal: Wal<T>) -> Self {
+        Self { wal }
+    }
+
+    /// The [Wal] the records are applied to
+    pub fn wal(&self) -> &Wal<T> {
+        &self.wal
+    }
+
+    /// [Lsn] of the next record expected
+    pub fn next_lsn(&self) -> Result<Lsn, String> {
+        crate::segments::next_lsn(&self.wal.inner.config)
+            .ok_or("The LSN of the logs written by older versions is unknown".to_string())
+    }
+
+    /// Apply a batch, returning once its records are synced to disk
+    ///
+    /// The records the replica has already, e.g. from a batch shipped again, are skipped.
+    ///
+    /// ## Returns
+    /// The [Lsn] to acknowledge to the [Shipper], or an error if the batch starts after the
+    /// next record expected, as a batch before it was lost
+    pub fn apply(&self, batch: &ShipBatch) -> Result<Lsn, String> {
+        let next = self.next_lsn()?;
+        if batch.first > next {
+            return Err(format!(
+                "Batch starts at {}, while the replica expects {}",
+                batch.first, next
+            ));
+        }
+        if batch.end() <= next {
+            return Ok(next);
+        }
+        let records = batch.records[(next - batch.first) as usize..]
+            .iter()
+            .map(|record| {
+                let header = RecordHeader {
+                    kind: record.kind,
+                    term: record.term,
+                };
+                (header, record.data.clone())
+            })
+            .collect::<Vec<_>>();
+        self.wal
+            .inner
+            .writer
+            .append_at(next, &records)
+            .map_err(|e| format!("Failed to apply batch: {}", e))?;
+        self.wal.flush().wait().map_err(|e| e.to_string())?;
+        Ok(batch.end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipping() {
+        let location = "./tmp/shipping";
+        let _ = std::fs::remove_dir_all(location);
+        for dir in ["primary", "replica"] {
+            std::fs::create_dir_all(format!("{}/{}", location, dir)).unwrap();
+        }
+        let primary: Wal<u64> = Wal::new(&format!("{}/primary", location), None);
+        primary.write_iter(0..10);
+        primary
+            .append_control(RecordKind::Checkpoint, b"cp")
+            .unwrap();
+        primary.flush();
+        let replica = Replica::new(Wal::<u64>::new(&format!("{}/replica", location), None));
+        let config = ShipperConfig {
+            batch_records: 4,
+            window: 2,
+            ..Default::default()
+        };
+        let shipper = Shipper::new(primary.clone(), replica.next_lsn().unwrap(), config);
+        let first = shipper.next_batch().unwrap().unwrap();
+        let second = shipper.next_batch().unwrap().unwrap();
+        assert_eq!((first.first, first.end(), second.end()), (0, 4, 8));
+        // the window is full
+        assert_eq!(shipper.next_batch().unwrap(), None);
+        assert_eq!(shipper.in_flight(), 2);
+        assert_eq!(replica.apply(&first).unwrap(), 4);
+        shipper.ack(4);
+        let third = shipper.next_batch().unwrap().unwrap();
+        // the second batch is lost on the way
+        assert!(replica.apply(&third).is_err());
+        shipper.rewind();
+        while let Some(batch) = shipper.next_batch().unwrap() {
+            let ack = replica.apply(&batch).unwrap();
+            // applied again, as if shipped twice
+            assert_eq!(replica.apply(&batch).unwrap(), ack);
+            shipper.ack(ack);
+        }
+        assert_eq!(shipper.acked(), 11);
+        assert_eq!(shipper.in_flight(), 0);
+        assert_eq!(
+            replica.wal().read().unwrap().collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        let kinds = replica.wal().read_raw().unwrap().map(|(meta, _)| meta.kind);
+        assert_eq!(kinds.last(), Some(RecordKind::Checkpoint));
+    }
+}
@@ -0,0 +1,520 @@
+use crate::codec::Codec;
+use crate::{Lsn, Size};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Identifies a ring file, followed by the capacity of its data area
+const MAGIC: &[u8; 8] = b"WALRING1";
+/// Size of the header, holding the magic, the capacity and two slots for the pointers
+const HEADER_SIZE: u64 = 128;
+/// Offset of the first slot for the pointers, the second one follows it
+const SLOT_OFFSET: u64 = 32;
+/// Size of a slot for the pointers
+const SLOT_SIZE: u64 = 48;
+/// Size of the length and the checksum in front of every log
+const FRAME_SIZE: u64 = 8;
+/// Length marking that the logs continue from the start of the data area
+const WRAP: u32 = u32::MAX;
+
+/// Pointers to the logs in the ring, as stored in a slot of the header
+///
+/// The positions grow forever, and are mapped to the data area modulo its capacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Pointers {
+    /// Incremented on every update, the slot with the highest one is the latest
+    seq: u64,
+    /// Position of the oldest log
+    head: u64,
+    /// Position right after the newest log
+    tail: u64,
+    /// [Lsn] of the oldest log
+    head_lsn: Lsn,
+    /// [Lsn] of the next log to be written
+    tail_lsn: Lsn,
+}
+
+impl Pointers {
+    fn to_bytes(self) -> [u8; SLOT_SIZE as usize] {
+        let mut bytes = [0; SLOT_SIZE as usize];
+        for (i, v) in [self.seq, self.head, self.tail, self.head_lsn, self.tail_lsn]
+            .into_iter()
+            .enumerate()
+        {
+            bytes[i * 8..i * 8 + 8].copy_from_slice(&v.to_le_bytes());
+        }
+        let checksum = crc32fast::hash(&bytes[..40]);
+        bytes[40..44].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// ## Returns
+    /// `None` if the slot is torn or was never written
+    fn from_bytes(bytes: &[u8; SLOT_SIZE as usize]) -> Option<Self> {
+        let checksum = u32::from_le_bytes(bytes[40..44].try_into().ok()?);
+        if checksum != crc32fast::hash(&bytes[..40]) {
+            return None;
+        }
+        let v = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        Some(Self {
+            seq: v(0),
+            head: v(1),
+            tail: v(2),
+            head_lsn: v(3),
+            tail_lsn: v(4),
+        })
+    }
+}
+
+/// The ring file, and the pointers last committed to it
+struct Ring {
+    file: File,
+    /// Size of the data area, after the header
+    capacity: u64,
+    fsync: bool,
+    pointers: Pointers,
+    /// The most recent failure to read the logs, see [RingWal::last_error]
+    last_error: Option<String>,
+}
+
+impl Ring {
+    fn open(path: &Path, capacity: u64, fsync: bool) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut ring = Self {
+            capacity,
+            fsync,
+            pointers: Pointers::default(),
+            last_error: None,
+            file: file.try_clone()?,
+        };
+        if file.metadata()?.len() == 0 {
+            // a new ring, preallocated to its full size
+            file.set_len(HEADER_SIZE + capacity)?;
+            let mut header = MAGIC.to_vec();
+            header.extend(capacity.to_le_bytes());
+            file.write_all(&header)?;
+            ring.commit()?;
+            return Ok(ring);
+        }
+        let mut header = [0; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a ring file",
+            ));
+        }
+        let stored = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        if stored != capacity {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The ring was created with a capacity of {} bytes", stored),
+            ));
+        }
+        // the latest of the slots that are intact
+        ring.pointers = (0..2)
+            .filter_map(|slot| {
+                let start = (SLOT_OFFSET + slot * SLOT_SIZE) as usize;
+                Pointers::from_bytes(header[start..start + SLOT_SIZE as usize].try_into().ok()?)
+            })
+            .max_by_key(|pointers| pointers.seq)
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "Corrupted ring header")
+            })?;
+        Ok(ring)
+    }
+
+    /// Persist the pointers, to the slot not holding the previous ones
+    ///
+    /// A torn write of the slot leaves the previous pointers in place. With fsync, the logs
+    /// written so far are synced first, so the pointers never cover logs not on disk.
+    fn commit(&mut self) -> std::io::Result<()> {
+        if self.fsync {
+            self.file.sync_data()?;
+        }
+        self.pointers.seq += 1;
+        let slot = self.pointers.seq % 2;
+        self.write_at(SLOT_OFFSET + slot * SLOT_SIZE, &self.pointers.to_bytes())?;
+        if self.fsync {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(data)
+    }
+
+    /// Position of the start of the data area following `position`
+    fn wrapped(&self, position: u64) -> u64 {
+        (position / self.capacity + 1) * self.capacity
+    }
+
+    /// Find the log at a position, skipping the end of the data area if the logs wrap there
+    ///
+    /// ## Returns
+    /// The position of the log and its length
+    fn frame_at(&mut self, mut position: u64) -> std::io::Result<(u64, u32)> {
+        loop {
+            let offset = position % self.capacity;
+            if self.capacity - offset < FRAME_SIZE {
+                position = self.wrapped(position);
+                continue;
+            }
+            let mut len = [0; 4];
+            self.read_at(HEADER_SIZE + offset, &mut len)?;
+            match u32::from_le_bytes(len) {
+                WRAP => position = self.wrapped(position),
+                len => return Ok((position, len)),
+            }
+        }
+    }
+
+    /// Read the log at a position
+    ///
+    /// ## Returns
+    /// The log, and the position of the one after it
+    fn read(&mut self, position: u64) -> std::io::Result<(Vec<u8>, u64)> {
+        let (position, len) = self.frame_at(position)?;
+        let offset = HEADER_SIZE + position % self.capacity;
+        let mut checksum = [0; 4];
+        self.read_at(offset + 4, &mut checksum)?;
+        if len as u64 > self.capacity - FRAME_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Corrupted log length",
+            ));
+        }
+        let mut data = vec![0; len as usize];
+        self.read_at(offset + FRAME_SIZE, &mut data)?;
+        if u32::from_le_bytes(checksum) != crc32fast::hash(&data) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Checksum mismatch",
+            ));
+        }
+        Ok((data, position + FRAME_SIZE + len as u64))
+    }
+
+    /// Append logs after the newest one, overwriting the oldest ones once the ring is full
+    fn append(&mut self, logs: &[Vec<u8>]) -> std::io::Result<()> {
+        for log in logs {
+            let size = FRAME_SIZE + log.len() as u64;
+            let mut start = self.pointers.tail;
+            let offset = start % self.capacity;
+            let wrap = self.capacity - offset < size;
+            if wrap {
+                start = self.wrapped(start);
+            }
+            // make room by dropping the oldest logs, and persist it before their bytes are reused
+            let head = self.pointers.head;
+            while self.pointers.head < self.pointers.tail
+                && start + size - self.pointers.head > self.capacity
+            {
+                let (position, len) = self.frame_at(self.pointers.head)?;
+                self.pointers.head = position + FRAME_SIZE + len as u64;
+                self.pointers.head_lsn += 1;
+            }
+            if self.pointers.head >= self.pointers.tail {
+                self.pointers.head = start;
+            }
+            if self.pointers.head != head {
+                self.commit()?;
+            }
+            if wrap && self.capacity - offset >= FRAME_SIZE {
+                self.write_at(HEADER_SIZE + offset, &WRAP.to_le_bytes())?;
+            }
+            let mut frame = Vec::with_capacity(size as usize);
+            frame.extend((log.len() as u32).to_le_bytes());
+            frame.extend(crc32fast::hash(log).to_le_bytes());
+            frame.extend(log);
+            self.write_at(HEADER_SIZE + start % self.capacity, &frame)?;
+            self.pointers.tail = start + size;
+            self.pointers.tail_lsn += 1;
+        }
+        self.commit()
+    }
+}
+
+/// A WAL stored in a single preallocated file of a fixed size, used as a ring buffer
+///
+/// Meant for embedded deployments, where the number of files and the space taken on flash must
+/// stay fixed. Once the ring is full, every new log overwrites the oldest ones.
+///
+/// The file starts with a header holding the pointers to the oldest and the newest logs, which
+/// are updated after every write. There's no in-memory buffer, so each write reaches the file
+/// right away, and is synced to disk as well if fsync is enabled.
+///
+/// ### Example
+/// ```no_run
+/// use walcraft::{RingWal, Size};
+///
+/// let wal: RingWal<String> = RingWal::open("/tmp/wal.ring", Size::Mb(1), false).unwrap();
+/// wal.write("hello".to_string()).unwrap();
+/// for log in wal.read().unwrap() {
+///     dbg!(log);
+/// }
+/// ```
+pub struct RingWal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    ring: Arc<Mutex<Ring>>,
+    codec: Codec,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for RingWal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            ring: self.ring.clone(),
+            codec: self.codec,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> RingWal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Open the ring file at `path`, creating it if it doesn't exist
+    ///
+    /// ## Arguments
+    /// - `path`: Path of the ring file
+    /// - `capacity`: Space for the logs, the file takes up a small header on top of it
+    /// - `fsync`: Whether to sync every write to disk before returning
+    ///
+    /// ## Returns
+    /// An error if the file exists with a different capacity, or isn't a ring file
+    pub fn open(path: &str, capacity: Size, fsync: bool) -> Result<Self, String> {
+        let capacity = capacity.to_bytes() as u64;
+        if capacity < FRAME_SIZE * 2 {
+            return Err(format!("Ring capacity of {} bytes is too small", capacity));
+        }
+        let ring = Ring::open(Path::new(path), capacity, fsync)
+            .map_err(|e| format!("Failed to open ring file {}: {}", path, e))?;
+        Ok(Self {
+            ring: Arc::new(Mutex::new(ring)),
+            codec: Codec::default(),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Write a new log
+    pub fn write(&self, item: T) -> Result<(), String> {
+        self.write_iter([item])
+    }
+
+    /// Write all the logs from an iterator, updating the header only once
+    ///
+    /// ## Returns
+    /// An error, without writing anything, if a log doesn't fit in the ring
+    pub fn write_iter<I>(&self, items: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut ring = self.ring();
+        let logs = items
+            .into_iter()
+            .map(|item| self.codec.serialize(&item).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(log) = logs
+            .iter()
+            .find(|log| FRAME_SIZE + log.len() as u64 > ring.capacity)
+        {
+            return Err(format!(
+                "Log of {} bytes doesn't fit in the ring of {} bytes",
+                log.len(),
+                ring.capacity
+            ));
+        }
+        ring.append(&logs)
+            .map_err(|e| format!("Failed to write to ring file: {}", e))
+    }
+
+    /// Read the logs, from the oldest to the newest
+    ///
+    /// The logs written after this method is called are not included. The logs overwritten
+    /// while iterating are skipped.
+    pub fn read(&self) -> Result<impl Iterator<Item = T>, String> {
+        Ok(self.read_from(0)?.map(|(_, item)| item))
+    }
+
+    /// Read the logs starting at the given [Lsn], along with their [Lsn]
+    pub fn read_from(&self, lsn: Lsn) -> Result<impl Iterator<Item = (Lsn, T)>, String> {
+        let pointers = self.ring().pointers;
+        Ok(RingIterator {
+            ring: self.ring.clone(),
+            codec: self.codec,
+            position: pointers.head,
+            lsn: pointers.head_lsn,
+            from: lsn,
+            end: pointers.tail_lsn,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// The most recent failure to read the ring file, which ended the reading early
+    pub fn last_error(&self) -> Option<String> {
+        self.ring().last_error.clone()
+    }
+
+    /// Number of logs in the ring
+    pub fn count(&self) -> u64 {
+        let pointers = self.ring().pointers;
+        pointers.tail_lsn - pointers.head_lsn
+    }
+
+    fn ring(&self) -> MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Iterator to read the logs from a [RingWal]
+struct RingIterator<T> {
+    ring: Arc<Mutex<Ring>>,
+    codec: Codec,
+    /// Position of the next log in the ring
+    position: u64,
+    /// [Lsn] of the next log
+    lsn: Lsn,
+    /// Logs before this [Lsn] are skipped
+    from: Lsn,
+    /// [Lsn] of the first log written after the iterator was created
+    end: Lsn,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Iterator for RingIterator<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Item = (Lsn, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut ring = self.ring.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            // the logs overwritten since the last one read are gone
+            if self.lsn < ring.pointers.head_lsn {
+                self.position = ring.pointers.head;
+                self.lsn = ring.pointers.head_lsn;
+            }
+            if self.lsn >= self.end {
+                return None;
+            }
+            let (data, next) = match ring.read(self.position) {
+                Ok(v) => v,
+                Err(e) => {
+                    ring.last_error = Some(format!(
+                        "Failed to read ring file at log {}: {}",
+                        self.lsn, e
+                    ));
+                    self.end = self.lsn;
+                    return None;
+                }
+            };
+            let lsn = self.lsn;
+            self.position = next;
+            self.lsn += 1;
+            if lsn < self.from {
+                continue;
+            }
+            if let Ok(item) = self.codec.deserialize(&data) {
+                return Some((lsn, item));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        let location = format!("./tmp/{}", name);
+        let _ = std::fs::remove_dir_all(&location);
+        std::fs::create_dir_all(&location).unwrap();
+        format!("{}/wal.ring", location)
+    }
+
+    #[test]
+    fn write_and_read() {
+        let path = path("ring_write_and_read");
+        let wal: RingWal<String> = RingWal::open(&path, Size::Kb(4), false).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), HEADER_SIZE + 4096);
+        wal.write("first".to_string()).unwrap();
+        wal.write_iter(["second".to_string(), "third".to_string()])
+            .unwrap();
+        assert_eq!(wal.count(), 3);
+        let logs = wal.read().unwrap().collect::<Vec<_>>();
+        assert_eq!(logs, ["first", "second", "third"]);
+        let lsns = wal.read_from(1).unwrap().map(|(lsn, _)| lsn);
+        assert_eq!(lsns.collect::<Vec<_>>(), [1, 2]);
+        // the logs are kept across restarts
+        drop(wal);
+        let wal: RingWal<String> = RingWal::open(&path, Size::Kb(4), false).unwrap();
+        assert_eq!(wal.read().unwrap().count(), 3);
+        // but not with another capacity
+        assert!(RingWal::<String>::open(&path, Size::Kb(8), false).is_err());
+    }
+
+    #[test]
+    fn overwrite_oldest() {
+        let path = path("ring_overwrite_oldest");
+        let wal: RingWal<u64> = RingWal::open(&path, Size::Kb(1), true).unwrap();
+        // 16 bytes per log, with the frame
+        wal.write_iter(0..200).unwrap();
+        assert_eq!(wal.count(), 64);
+        let logs = wal.read_from(0).unwrap().collect::<Vec<_>>();
+        assert_eq!(logs.first(), Some(&(136, 136)));
+        assert_eq!(logs.last(), Some(&(199, 199)));
+        // logs of varying sizes wrap around the end of the data area
+        let path = self::path("ring_wrap");
+        for i in 0..100u64 {
+            let wal: RingWal<Vec<u64>> = RingWal::open(&path, Size::Kb(1), false).unwrap();
+            wal.write(vec![i; (i % 7) as usize]).unwrap();
+            let logs = wal.read().unwrap().collect::<Vec<_>>();
+            assert_eq!(logs.last(), Some(&vec![i; (i % 7) as usize]));
+            assert_eq!(logs.len() as u64, wal.count());
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), HEADER_SIZE + 1024);
+        let wal: RingWal<Vec<u64>> = RingWal::open(&path, Size::Kb(1), false).unwrap();
+        assert!(wal.write(vec![0; 200]).is_err());
+    }
+
+    #[test]
+    fn torn_header() {
+        let path = path("ring_torn_header");
+        let wal: RingWal<u64> = RingWal::open(&path, Size::Kb(1), false).unwrap();
+        wal.write_iter(0..3).unwrap();
+        wal.write(3).unwrap();
+        drop(wal);
+        // the latest slot is torn, the previous pointers are used instead
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        let seq = 3;
+        file.seek(SeekFrom::Start(SLOT_OFFSET + (seq % 2) * SLOT_SIZE + 8))
+            .unwrap();
+        file.write_all(&[0xff; 4]).unwrap();
+        drop(file);
+        let wal: RingWal<u64> = RingWal::open(&path, Size::Kb(1), false).unwrap();
+        assert_eq!(wal.read().unwrap().collect::<Vec<_>>(), [0, 1, 2]);
+    }
+}
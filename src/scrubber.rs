@@ -0,0 +1,121 @@
+use crate::verify::{sealed, verify_segment};
+use crate::WalConfig;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Pause between checking two files, to keep the scrubber from hogging the disk
+const PAUSE: Duration = Duration::from_millis(10);
+
+/// Background task which periodically re-reads the filled log files and validates their
+/// checksums, so that latent corruption is found before a recovery depends on the data
+pub(crate) struct Scrubber {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Scrubber {
+    /// Start scrubbing the log files once every `interval`
+    pub fn start(config: WalConfig, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name("walcraft-scrubber".to_string())
+            .spawn(move || Self::run(config, interval, flag))
+            .ok();
+        Self { stop, handle }
+    }
+
+    fn run(config: WalConfig, interval: Duration, stop: Arc<AtomicBool>) {
+        // corrupted files are only reported once
+        let mut reported = HashSet::new();
+        loop {
+            // sleep until the next pass, unless asked to stop
+            let deadline = Instant::now() + interval;
+            while !stop.load(Relaxed) {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                std::thread::park_timeout(deadline - now);
+            }
+            if stop.load(Relaxed) {
+                return;
+            }
+            for (index, info) in sealed(&config) {
+                if stop.load(Relaxed) {
+                    return;
+                }
+                if verify_segment(&config, index, info.checksum) != Some(true)
+                    && reported.insert(index)
+                {
+                    if let Some(listener) = config.listener.as_ref() {
+                        listener.on_corruption(index);
+                    }
+                }
+                std::thread::sleep(PAUSE);
+            }
+        }
+    }
+}
+
+impl Drop for Scrubber {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::manifest::Manifest;
+    use crate::{Wal, WalListener};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Corruptions(Mutex<Vec<usize>>);
+
+    impl WalListener for Corruptions {
+        fn on_corruption(&self, index: usize) {
+            self.0.lock().unwrap().push(index);
+        }
+    }
+
+    #[test]
+    fn reports_corruption() {
+        let location = "./tmp/scrubber";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, Some(1));
+        wal.write_iter((0..100_000).map(|i: usize| i.to_string()));
+        wal.flush();
+        drop(wal);
+        // corrupt one of the filled files
+        let index = *Manifest::new(location.into()).read().keys().next().unwrap();
+        let path = format!("{}/log_{}.bin", location, index);
+        let mut data = std::fs::read(&path).unwrap();
+        data[10] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let listener = Arc::new(Corruptions::default());
+        let config = WalConfig {
+            location: location.into(),
+            listener: Some(listener.clone()),
+            ..Default::default()
+        };
+        let scrubber = Scrubber::start(config, Duration::from_millis(10));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while listener.0.lock().unwrap().is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(scrubber);
+        // reported once, even though the scrubber may have made several passes
+        assert_eq!(*listener.0.lock().unwrap(), vec![index]);
+    }
+}
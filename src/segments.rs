@@ -0,0 +1,123 @@
+use crate::failover;
+use crate::iter;
+use crate::stats::SegmentIo;
+use crate::writer::frame::count_records;
+use crate::writer::manager::{open_segment, segment_path, COMPRESSED_EXT};
+use crate::{Lsn, MerkleHash, WalConfig};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// A log file of the [Wal](crate::Wal), as listed by [Wal::list_segments](crate::Wal::list_segments)
+#[derive(Debug, Clone)]
+pub struct Segment {
+    /// Index of the file, i.e. the postfix in its name
+    pub index: usize,
+    /// Path to the file, which may be in the mirror or cold storage
+    pub path: PathBuf,
+    /// Size of the file on disk, in bytes
+    pub size: u64,
+    /// Range of [Lsn] of the logs in the file, unknown for files written by older versions
+    pub lsns: Option<Range<Lsn>>,
+    /// When the file was created, if supported by the platform
+    pub created: Option<SystemTime>,
+    /// When the file was last written to
+    pub modified: Option<SystemTime>,
+    /// Whether the file is filled and closed, as opposed to the live file being written to
+    pub sealed: bool,
+    /// Whether the file has been compressed
+    pub compressed: bool,
+    /// Whether the file has been moved to the cold storage
+    pub cold: bool,
+    /// IO statistics of the file, unless it was written before this [Wal](crate::Wal) was created
+    pub io: Option<SegmentIo>,
+    /// Root of the [MerkleTree](crate::MerkleTree) over the logs of a filled file, recorded
+    /// when it was closed
+    ///
+    /// Unknown for the live file, and for files without logs or written by older versions.
+    pub merkle_root: Option<MerkleHash>,
+}
+
+/// Where to stop deleting the log files with [Wal::delete_segments_before](crate::Wal::delete_segments_before)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentBound {
+    /// Delete the files before the file with this index
+    Segment(usize),
+    /// Delete the files that only have logs before this [Lsn]
+    Lsn(Lsn),
+}
+
+/// Find the first file that has a log at or after the [Lsn]
+///
+/// The files written by older versions, whose logs are unknown, are never skipped
+pub(crate) fn segment_of(config: &WalConfig, lsn: Lsn) -> Option<usize> {
+    let manifest = failover::manifest(config);
+    iter::segments(config)?.into_iter().find(|index| {
+        !matches!(manifest.get(index).and_then(|info| info.next_lsn()), Some(next) if next <= lsn)
+    })
+}
+
+/// List all the log files that exist, from the oldest to the newest
+pub(crate) fn list(config: &WalConfig) -> Vec<Segment> {
+    let manifest = iter::manifest(config);
+    let files = iter::segments(config).unwrap_or_default();
+    let current = files.back().copied();
+    let mut segments = Vec::new();
+    for index in files {
+        let path = match segment_path(config, index) {
+            Some(path) => path,
+            None => continue,
+        };
+        let metadata = std::fs::metadata(&path).ok();
+        let sealed = Some(index) != current;
+        let lsns = match manifest.get(&index) {
+            // the logs trimmed from the start of the file are gone
+            Some(info) if sealed => info
+                .first
+                .zip(info.next_lsn())
+                .map(|(a, b)| a + info.trimmed..b),
+            // the live file starts where the previous one ended
+            info if !sealed => {
+                let first = match manifest.get(&index.wrapping_sub(1)) {
+                    Some(info) => info.next_lsn(),
+                    None if segments.is_empty() => Some(0),
+                    None => None,
+                };
+                let records = open_segment(config, index).and_then(|f| count_records(f).ok());
+                let trimmed = info.map_or(0, |info| info.trimmed);
+                first
+                    .zip(records)
+                    .map(|(first, records)| first + trimmed..first + records)
+            }
+            _ => None,
+        };
+        segments.push(Segment {
+            index,
+            size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+            lsns,
+            created: metadata.as_ref().and_then(|m| m.created().ok()),
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            sealed,
+            compressed: path.to_string_lossy().ends_with(COMPRESSED_EXT),
+            cold: config
+                .cold_location
+                .as_ref()
+                .is_some_and(|cold| path.starts_with(cold)),
+            io: config.stats.segment(index),
+            merkle_root: manifest
+                .get(&index)
+                .filter(|_| sealed)
+                .and_then(|i| i.merkle),
+            path,
+        });
+    }
+    segments
+}
+
+/// The [Lsn] the next log will be written at, unknown if the live file follows older files
+pub(crate) fn next_lsn(config: &WalConfig) -> Option<Lsn> {
+    match list(config).pop() {
+        Some(segment) => segment.lsns.map(|lsns| lsns.end),
+        None => Some(0),
+    }
+}
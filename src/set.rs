@@ -0,0 +1,356 @@
+use crate::writer::manager::{Meta, MIN_STORAGE_SIZE};
+use crate::{Size, Wal, WalBuilder, WalStats};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Configures the builder of every [Wal] opened by a [WalSet]
+type Configure<T> = Box<dyn Fn(WalBuilder<T>) -> WalBuilder<T> + Send + Sync>;
+
+/// Options of a [WalSet]
+#[derive(Debug, Clone, Default)]
+pub struct WalSetOptions {
+    /// Storage shared by all the WALs, split evenly between the open ones. Unlimited by default.
+    pub storage_size: Option<Size>,
+    /// How often a background task flushes the buffers of all the open WALs. Never by default.
+    pub flush_interval: Option<Duration>,
+    /// Memory shared by the logs not written to disk yet of all the WALs, split evenly between
+    /// the open ones, which caps their buffer size and their limit on the unflushed data, see
+    /// [WalBuilder::max_unflushed]. Unlimited by default.
+    pub memory_budget: Option<Size>,
+}
+
+/// Aggregate statistics of the WALs of a [WalSet]
+#[derive(Debug, Clone, Default)]
+pub struct WalSetStats {
+    /// Number of open WALs
+    pub wals: usize,
+    /// Bytes of logs written to the files by all the open WALs, including the framing
+    pub bytes_written: u64,
+    /// Size of the log files of all the open WALs, in bytes
+    pub storage_used: u64,
+    /// Statistics of every open WAL, by key
+    pub per_key: BTreeMap<String, WalStats>,
+}
+
+/// Many [Wal]s of the same logs, one per key, e.g. per tenant or per shard
+///
+/// Every WAL lives in the subdirectory of the root named after its key, and is opened, or
+/// created, on first use. The WALs share a storage budget and a memory budget, split evenly
+/// between the open ones and split again whenever one is opened or removed, along with a
+/// single background task flushing their buffers. The task syncs the WALs one after another,
+/// skipping the ones not written to since the last round, so that dozens of WALs don't need
+/// a timer and a sync thread each.
+///
+/// ### Example
+/// ```no_run
+/// use std::time::Duration;
+/// use walcraft::{Size, WalSet, WalSetOptions};
+///
+/// let options = WalSetOptions {
+///     storage_size: Some(Size::Gb(10)),
+///     flush_interval: Some(Duration::from_millis(100)),
+///     memory_budget: Some(Size::Mb(64)),
+/// };
+/// let set: WalSet<String> = WalSet::open("/tmp/tenants", options)
+///     .unwrap()
+///     .with_builder(|builder| builder.buffer_size(Size::Kb(64)));
+/// set.get("tenant-1").unwrap().write("hello".to_string());
+/// println!("{:?}", set.stats());
+/// ```
+pub struct WalSet<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    root: PathBuf,
+    storage_size: Option<usize>,
+    memory_budget: Option<usize>,
+    configure: Configure<T>,
+    wals: Arc<Mutex<BTreeMap<String, Wal<T>>>>,
+    stop: Arc<AtomicBool>,
+    flusher: Option<JoinHandle<()>>,
+}
+
+impl<T> WalSet<T>
+where
+    T: Serialize + for<'a> Deserialize<'a> + Send + Sync + 'static,
+{
+    /// Open the set of WALs under a root directory, creating it if needed
+    ///
+    /// No WAL is opened until it's first used with [WalSet::get].
+    pub fn open(root: &str, options: WalSetOptions) -> Result<Self, String> {
+        let root = PathBuf::from(root);
+        std::fs::create_dir_all(&root)
+            .map_err(|e| format!("Failed to create {}: {}", root.display(), e))?;
+        let storage_size = options.storage_size.map(|size| size.to_bytes());
+        if storage_size.is_some_and(|size| size < MIN_STORAGE_SIZE) {
+            return Err(format!(
+                "Storage size must be at least {} bytes",
+                MIN_STORAGE_SIZE
+            ));
+        }
+        let wals = Arc::new(Mutex::new(BTreeMap::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let flusher = options.flush_interval.and_then(|interval| {
+            let (wals, stop) = (wals.clone(), stop.clone());
+            std::thread::Builder::new()
+                .name("walcraft-set-flusher".to_string())
+                .spawn(move || Self::flush_every(interval, wals, stop))
+                .ok()
+        });
+        Ok(Self {
+            root,
+            storage_size,
+            memory_budget: options.memory_budget.map(|size| size.to_bytes()),
+            configure: Box::new(|builder| builder),
+            wals,
+            stop,
+            flusher,
+        })
+    }
+
+    /// Configure the builder of every WAL opened from now on, e.g. to set its buffer size
+    ///
+    /// The location is set by the [WalSet], and the storage size is overridden by the shared
+    /// budget, if any. The buffer size and the limit on the unflushed data are capped by the
+    /// shared memory budget, if any.
+    pub fn with_builder<F>(mut self, configure: F) -> Self
+    where
+        F: Fn(WalBuilder<T>) -> WalBuilder<T> + Send + Sync + 'static,
+    {
+        self.configure = Box::new(configure);
+        self
+    }
+
+    /// The [Wal] of a key, opening or creating it if needed
+    ///
+    /// ## Arguments
+    /// - `key`: Made of ASCII letters, digits, `-` and `_`
+    pub fn get(&self, key: &str) -> Result<Wal<T>, String> {
+        let mut wals = self.wals();
+        if let Some(wal) = wals.get(key) {
+            return Ok(wal.clone());
+        }
+        let location = self.location(key)?;
+        let location = location.to_str().ok_or("Invalid root directory")?;
+        let builder = (self.configure)(WalBuilder::new()).location(location);
+        let wal = builder.build().map_err(|e| e.to_string())?;
+        wals.insert(key.to_string(), wal.clone());
+        self.rebalance(&wals);
+        Ok(wal)
+    }
+
+    /// The keys of all the WALs under the root directory, open or not, in order
+    pub fn keys(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(&self.root) else {
+            return Vec::new();
+        };
+        let mut keys = entries
+            .flatten()
+            .filter(|entry| Meta::new(entry.path()).exists())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    /// Close the [Wal] of a key and delete all its logs
+    ///
+    /// The handles to it returned before must not be used anymore.
+    ///
+    /// ## Returns
+    /// Whether there was a WAL for the key
+    pub fn remove(&self, key: &str) -> Result<bool, String> {
+        let location = self.location(key)?;
+        let mut wals = self.wals();
+        let removed = wals.remove(key);
+        self.rebalance(&wals);
+        drop(wals);
+        if let Some(wal) = removed {
+            wal.purge();
+            return Ok(true);
+        }
+        match std::fs::remove_dir_all(&location) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Failed to remove {}: {}", location.display(), e)),
+        }
+    }
+
+    /// Flush the buffers of all the open WALs, and wait for the data to reach the disk
+    pub fn flush(&self) -> std::io::Result<()> {
+        let wals = self.wals().values().cloned().collect::<Vec<_>>();
+        wals.iter()
+            .map(|wal| wal.flush())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .try_for_each(|handle| handle.wait())
+    }
+
+    /// Aggregate statistics of the open WALs
+    pub fn stats(&self) -> WalSetStats {
+        let wals = self.wals().clone();
+        let mut stats = WalSetStats {
+            wals: wals.len(),
+            ..Default::default()
+        };
+        for (key, wal) in wals {
+            let wal_stats = wal.stats();
+            stats.bytes_written += wal_stats.bytes_written;
+            stats.storage_used += wal.list_segments().iter().map(|s| s.size).sum::<u64>();
+            stats.per_key.insert(key, wal_stats);
+        }
+        stats
+    }
+
+    /// Directory of the WAL of a key
+    fn location(&self, key: &str) -> Result<PathBuf, String> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if key.is_empty() || !key.chars().all(valid) {
+            return Err(format!("Invalid key: {:?}", key));
+        }
+        Ok(self.root.join(key))
+    }
+
+    /// Split the storage and memory budgets evenly between the open WALs
+    fn rebalance(&self, wals: &BTreeMap<String, Wal<T>>) {
+        let open = wals.len().max(1);
+        if let Some(budget) = self.storage_size {
+            let share = (budget / open).max(MIN_STORAGE_SIZE);
+            for wal in wals.values() {
+                wal.inner.writer.set_storage_size(share);
+            }
+        }
+        if let Some(budget) = self.memory_budget {
+            for wal in wals.values() {
+                wal.inner.writer.set_memory_budget(Some(budget / open));
+            }
+        }
+    }
+
+    fn wals(&self) -> MutexGuard<'_, BTreeMap<String, Wal<T>>> {
+        self.wals.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn flush_every(
+        interval: Duration,
+        wals: Arc<Mutex<BTreeMap<String, Wal<T>>>>,
+        stop: Arc<AtomicBool>,
+    ) {
+        while !stop.load(Relaxed) {
+            std::thread::park_timeout(interval);
+            let open = wals
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+            // synced on this thread rather than on a thread of their own, and the failures
+            // are reported by every WAL
+            for wal in open {
+                let _ = wal.inner.writer.flush_and_sync();
+            }
+        }
+    }
+}
+
+impl<T> Drop for WalSet<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(handle) = self.flusher.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wal_set() {
+        let root = "./tmp/wal_set";
+        let _ = std::fs::remove_dir_all(root);
+        let options = WalSetOptions {
+            storage_size: Some(Size::Mb(1)),
+            flush_interval: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let set: WalSet<u64> = WalSet::open(root, options).unwrap();
+        assert!(set.get("../escape").is_err());
+        set.get("a").unwrap().write_iter(0..10);
+        set.get("b").unwrap().write_iter(10..15);
+        assert_eq!(set.keys(), ["a", "b"]);
+
+        // flushed in the background
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(set.get("a").unwrap().read().unwrap().count(), 10);
+        let stats = set.stats();
+        assert_eq!(stats.wals, 2);
+        assert_eq!(stats.bytes_written, 15 * (3 + 8));
+        assert_eq!(
+            stats.bytes_written,
+            stats.per_key.values().map(|s| s.bytes_written).sum::<u64>()
+        );
+        assert!(stats.storage_used > 0);
+
+        assert!(set.remove("a").unwrap());
+        assert!(!set.remove("a").unwrap());
+        assert_eq!(set.keys(), ["b"]);
+        drop(set);
+
+        // reopened from disk
+        let set: WalSet<u64> = WalSet::open(root, WalSetOptions::default()).unwrap();
+        assert_eq!(set.keys(), ["b"]);
+        assert_eq!(
+            set.get("b").unwrap().read().unwrap().collect::<Vec<_>>(),
+            (10..15).collect::<Vec<_>>()
+        );
+        assert!(WalSet::<u64>::open(
+            root,
+            WalSetOptions {
+                storage_size: Some(Size::Kb(1)),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn shared_budgets() {
+        let root = "./tmp/wal_set_budgets";
+        let _ = std::fs::remove_dir_all(root);
+        let options = WalSetOptions {
+            flush_interval: Some(Duration::from_millis(10)),
+            memory_budget: Some(Size::Kb(4)),
+            ..Default::default()
+        };
+        let set: WalSet<String> = WalSet::open(root, options)
+            .unwrap()
+            .with_builder(|builder| builder.buffer_size(Size::Kb(64)));
+        let (a, b) = (set.get("a").unwrap(), set.get("b").unwrap());
+        // every WAL keeps less than its share in memory, despite the larger buffer
+        for i in 0..100 {
+            a.write("x".repeat(100));
+            assert!(a.unflushed_bytes() < 2048, "log {}", i);
+        }
+        assert!(a.read().unwrap().count() > 50);
+
+        // the idle WALs aren't synced again
+        b.write("y".to_string());
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(b.read().unwrap().count(), 1);
+        let syncs = b.stats().fsync.count;
+        assert!(syncs > 0);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(b.stats().fsync.count, syncs);
+    }
+}
@@ -0,0 +1,148 @@
+//! Deterministic simulation of crashes, behind the `simulation` feature
+//!
+//! A [Simulation] is driven by a seed, so that a failing scenario can be replayed exactly.
+//! It crashes the process writing to a [Wal] by dropping it without a flush, and tears the
+//! last write to the current file, as a power loss in the middle of a write would.
+//!
+//! The simulation runs on the files on disk, in real time: there's no in-memory storage to
+//! inject faults into the other writes, nor a virtual clock.
+//!
+//! ### Example
+//! ```no_run
+//! use walcraft::sim::Simulation;
+//! use walcraft::Wal;
+//!
+//! for seed in 0..100 {
+//!     let mut sim = Simulation::new(seed);
+//!     let wal = Wal::new("/tmp/logz", None);
+//!     for id in 0..sim.below(10_000) {
+//!         wal.write(id);
+//!     }
+//!     sim.crash(wal);
+//!     // whatever survived the crash is a prefix of what was written
+//!     let wal: Wal<u64> = Wal::new("/tmp/logz", None);
+//!     let logs = wal.read().unwrap().collect::<Vec<_>>();
+//!     assert!(logs.iter().copied().eq(0..logs.len() as u64));
+//!     wal.purge();
+//! }
+//! ```
+use crate::writer::manager::Meta;
+use crate::writer::naming;
+use crate::Wal;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+
+/// Largest number of bytes torn off the end of the current file in a crash
+const MAX_TEAR: u64 = 4096;
+
+/// A simulated environment, where everything random derives from a seed
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    state: u64,
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        // the state of xorshift must never be zero
+        Self {
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    /// Next pseudo-random number, with xorshift64*
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A pseudo-random number below the given bound
+    pub fn below(&mut self, bound: u64) -> u64 {
+        match bound {
+            0 => 0,
+            _ => self.next_u64() % bound,
+        }
+    }
+
+    /// Whether an event with the given probability happens
+    pub fn chance(&mut self, probability: f64) -> bool {
+        let sample = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+
+    /// Crash the process writing to the [Wal]
+    ///
+    /// The [Wal] is dropped without a flush, losing the logs in the buffer,
+    /// and up to 4 KB are torn off the end of the current file.
+    ///
+    /// ## Returns
+    /// The number of bytes torn off
+    pub fn crash<T>(&mut self, wal: Wal<T>) -> u64
+    where
+        T: Serialize + for<'a> Deserialize<'a>,
+    {
+        let location = wal.inner.config.location.clone();
+        // the buffer is lost with the process
+        std::mem::forget(wal);
+        let current = match Meta::new(location.clone()).read() {
+            Some((_, current)) => current,
+            None => return 0,
+        };
+        let path = naming::path(&location, current);
+        let file = match OpenOptions::new().write(true).open(path) {
+            Ok(file) => file,
+            Err(_) => return 0,
+        };
+        let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let tear = self.below(len.min(MAX_TEAR) + 1);
+        match file.set_len(len - tear) {
+            Ok(_) => tear,
+            Err(_) => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let mut a = Simulation::new(7);
+        let mut b = Simulation::new(7);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Simulation::new(8).next_u64(), Simulation::new(7).next_u64());
+    }
+
+    #[test]
+    fn survives_crashes() {
+        let location = "./tmp/sim_crashes";
+        for seed in 0..20 {
+            let _ = std::fs::remove_dir_all(location);
+            std::fs::create_dir_all(location).unwrap();
+            let mut sim = Simulation::new(seed);
+            let mut next = 0u64;
+            for _ in 0..5 {
+                let wal = Wal::new(location, Some(1));
+                for _ in 0..sim.below(5_000) {
+                    wal.write(next);
+                    next += 1;
+                    if sim.chance(0.001) {
+                        wal.flush();
+                    }
+                }
+                sim.crash(wal);
+                // the logs that survived are the oldest ones kept, without any gaps
+                let wal: Wal<u64> = Wal::new(location, Some(1));
+                let logs = wal.read().unwrap().collect::<Vec<_>>();
+                let first = logs.first().copied().unwrap_or(0);
+                assert!(logs.iter().copied().eq(first..first + logs.len() as u64));
+                // carry on writing after the crash
+                next = first + logs.len() as u64;
+            }
+        }
+    }
+}
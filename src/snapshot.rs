@@ -0,0 +1,48 @@
+use crate::{Lsn, WalConfig};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+/// Name of the snapshot file, in the location of the log files
+pub(crate) const SNAPSHOT_FILE: &str = "snapshot";
+/// Name of the file the snapshot is written to, before it replaces the previous snapshot
+const TEMP_FILE: &str = "snapshot.tmp";
+
+/// Write a snapshot of the application state, covering all the logs before `lsn`
+///
+/// The file starts with the [Lsn] as 8 bytes in little-endian, followed by the data written by
+/// the closure. It's written to a temp file and renamed once synced, so a crash leaves either
+/// the previous snapshot or the new one, but never a partial file.
+pub(crate) fn write<F>(config: &WalConfig, lsn: Lsn, f: F) -> std::io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> std::io::Result<()>,
+{
+    let temp = config.location.join(TEMP_FILE);
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    writer.write_all(&lsn.to_le_bytes())?;
+    f(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    std::fs::rename(&temp, config.location.join(SNAPSHOT_FILE))?;
+    // persist the rename too, where directories can be synced
+    if let Ok(dir) = File::open(&config.location) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Open the latest snapshot
+///
+/// ## Returns
+/// The [Lsn] covered by the snapshot, and the file positioned at the application data
+pub(crate) fn read(config: &WalConfig) -> std::io::Result<Option<(Lsn, File)>> {
+    let mut file = match File::open(config.location.join(SNAPSHOT_FILE)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut lsn = [0; 8];
+    file.read_exact(&mut lsn)?;
+    Ok(Some((Lsn::from_le_bytes(lsn), file)))
+}
@@ -0,0 +1,501 @@
+use crate::listener::{GcEvent, Operation, SlowOperation};
+use crate::{SkippedRegion, WalConfig, WalListener};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Number of histogram buckets, each one twice as wide as the one before it
+const BUCKETS: usize = 64;
+/// Number of the most recent log files whose IO statistics are kept
+const TRACKED_SEGMENTS: usize = 1024;
+/// Number of the most recent errors kept
+const RECENT_ERRORS: usize = 16;
+
+/// Statistics about the operations of a [Wal](crate::Wal), since it was created
+///
+/// Available with [Wal::stats](crate::Wal::stats)
+#[derive(Debug, Clone, Default)]
+pub struct WalStats {
+    /// Time taken to write a filled buffer, or any other batch of logs, to the file
+    pub flush: Latency,
+    /// Time taken to sync a file to disk
+    pub fsync: Latency,
+    /// Bytes of logs written to the files, including the framing
+    pub bytes_written: u64,
+    /// Logs skipped as corrupted while reading, see [SkippedRegion]
+    pub skipped_records: u64,
+    /// Bytes of the files skipped as corrupted while reading, see [SkippedRegion]
+    pub skipped_bytes: u64,
+    /// Writes to the files that failed
+    pub write_errors: u64,
+    /// Syncs of the files to disk that failed
+    pub sync_failures: u64,
+    /// Writes synced along with a concurrent write instead of on their own, see
+    /// [WalBuilder::sync_window](crate::WalBuilder::sync_window)
+    ///
+    /// Out of all the writes synced, `coalesced_syncs + fsync.count` of them.
+    pub coalesced_syncs: u64,
+}
+
+/// Activity of a [Wal](crate::Wal) over its whole life, kept across restarts
+///
+/// Available with [Wal::lifetime_stats](crate::Wal::lifetime_stats). The counters are stored
+/// in the log directory every time a file is filled, and when the [Wal](crate::Wal) is closed,
+/// so a crash loses the activity since the last file was filled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LifetimeStats {
+    /// Records ever appended, the logs along with the control records
+    pub records: u64,
+    /// Bytes ever written to the log files, including the framing
+    pub bytes: u64,
+    /// Log files ever deleted, or moved to the cold tier, to make room or on demand
+    pub segments_collected: u64,
+}
+
+impl LifetimeStats {
+    /// The counters of both added up
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        Self {
+            records: self.records + other.records,
+            bytes: self.bytes + other.bytes,
+            segments_collected: self.segments_collected + other.segments_collected,
+        }
+    }
+}
+
+/// A failure inside a [Wal](crate::Wal), e.g. while writing to or deleting a log file
+///
+/// The most recent ones are available with [Wal::last_error](crate::Wal::last_error) and
+/// [Wal::recent_errors](crate::Wal::recent_errors), and each one is reported to
+/// [WalListener::on_error] as it happens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalError {
+    /// What failed, along with the cause
+    pub message: String,
+    /// Kind of the IO error behind the failure, if any
+    pub kind: Option<ErrorKind>,
+    /// When the failure happened
+    pub time: SystemTime,
+}
+
+impl std::fmt::Display for WalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for WalError {}
+
+/// IO statistics of a single log file, since the [Wal](crate::Wal) was created
+///
+/// Available in [Segment::io](crate::Segment::io), to tell which periods of the log history were
+/// written while the disk was slow
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SegmentIo {
+    /// Bytes of logs written to the file, including the framing
+    pub bytes_written: u64,
+    /// Time taken by the writes to the file, counting the number of flushes
+    pub flush: Latency,
+    /// Time taken to sync the file to disk
+    pub fsync: Latency,
+}
+
+/// Distribution of the durations of an operation
+///
+/// The percentiles are approximate, rounded up to the next power of two microseconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Latency {
+    /// Number of times the operation ran
+    pub count: u64,
+    /// Median duration
+    pub p50: Duration,
+    /// 99th percentile of the durations
+    pub p99: Duration,
+    /// Longest duration
+    pub max: Duration,
+}
+
+/// Collects the statistics, shared by all the clones of the config
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Stats(Arc<StatsInner>);
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    flush: Histogram,
+    fsync: Histogram,
+    written: AtomicU64,
+    skipped_records: AtomicU64,
+    skipped_bytes: AtomicU64,
+    write_errors: AtomicU64,
+    sync_failures: AtomicU64,
+    coalesced_syncs: AtomicU64,
+    // whether the last write to the files failed
+    failing: AtomicBool,
+    last_write_error: Mutex<Option<String>>,
+    errors: Mutex<VecDeque<WalError>>,
+    segments: Mutex<BTreeMap<usize, SegmentStats>>,
+}
+
+/// Statistics of a single log file
+#[derive(Debug, Default)]
+struct SegmentStats {
+    flush: Histogram,
+    fsync: Histogram,
+    written: u64,
+}
+
+impl Stats {
+    pub fn snapshot(&self) -> WalStats {
+        WalStats {
+            flush: self.0.flush.latency(),
+            fsync: self.0.fsync.latency(),
+            bytes_written: self.0.written.load(Relaxed),
+            skipped_records: self.0.skipped_records.load(Relaxed),
+            skipped_bytes: self.0.skipped_bytes.load(Relaxed),
+            write_errors: self.0.write_errors.load(Relaxed),
+            sync_failures: self.0.sync_failures.load(Relaxed),
+            coalesced_syncs: self.0.coalesced_syncs.load(Relaxed),
+        }
+    }
+
+    /// Whether the last write to the files failed
+    pub fn is_failing(&self) -> bool {
+        self.0.failing.load(Relaxed)
+    }
+
+    /// The error of the last failed write
+    pub fn last_write_error(&self) -> Option<String> {
+        self.0
+            .last_write_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The most recent errors, from the oldest to the newest
+    pub fn errors(&self) -> Vec<WalError> {
+        self.0
+            .errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// The most recent error
+    pub fn last_error(&self) -> Option<WalError> {
+        self.0
+            .errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .back()
+            .cloned()
+    }
+
+    /// IO statistics of a log file, if it was written to since the [Wal](crate::Wal) was created
+    pub fn segment(&self, index: usize) -> Option<SegmentIo> {
+        let segments = self.segments();
+        segments.get(&index).map(|stats| SegmentIo {
+            bytes_written: stats.written,
+            flush: stats.flush.latency(),
+            fsync: stats.fsync.latency(),
+        })
+    }
+
+    /// Update the statistics of a log file, forgetting the oldest files beyond the limit
+    fn update_segment(&self, index: usize, f: impl FnOnce(&mut SegmentStats)) {
+        let mut segments = self.segments();
+        f(segments.entry(index).or_default());
+        if segments.len() > TRACKED_SEGMENTS {
+            segments.pop_first();
+        }
+    }
+
+    fn segments(&self) -> std::sync::MutexGuard<'_, BTreeMap<usize, SegmentStats>> {
+        self.0
+            .segments
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Measures the operations, recording their durations and warning about the slow ones
+#[derive(Clone)]
+pub(crate) struct Monitor {
+    stats: Stats,
+    threshold: Option<Duration>,
+    listener: Option<Arc<dyn WalListener>>,
+}
+
+impl Monitor {
+    pub fn new(config: &WalConfig) -> Self {
+        Self {
+            stats: config.stats.clone(),
+            threshold: config.slow_threshold,
+            listener: config.listener.clone(),
+        }
+    }
+
+    /// Record the bytes written to a file
+    pub fn written(&self, segment: usize, bytes: usize) {
+        self.stats.0.written.fetch_add(bytes as u64, Relaxed);
+        self.stats
+            .update_segment(segment, |stats| stats.written += bytes as u64);
+    }
+
+    /// Record the outcome of a write to a file
+    pub fn write_result(&self, error: Option<&std::io::Error>) {
+        self.stats.0.failing.store(error.is_some(), Relaxed);
+        if let Some(error) = error {
+            self.stats.0.write_errors.fetch_add(1, Relaxed);
+            let message = format!("Failed to write to WAL file: {}", error);
+            *self
+                .stats
+                .0
+                .last_write_error
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(message.clone());
+            self.report(message, Some(error.kind()));
+        }
+    }
+
+    /// Record a failure to sync a file to disk
+    pub fn sync_failed(&self, error: &std::io::Error) {
+        self.stats.0.sync_failures.fetch_add(1, Relaxed);
+        self.error("Failed to sync WAL file", error);
+    }
+
+    /// Record writes synced along with another one
+    pub fn coalesced(&self, writes: u64) {
+        self.stats.0.coalesced_syncs.fetch_add(writes, Relaxed);
+    }
+
+    /// Record a failed IO operation, described by the context
+    pub fn error(&self, context: &str, error: &std::io::Error) {
+        self.report(format!("{}: {}", context, error), Some(error.kind()));
+    }
+
+    /// Record a failure, reporting it to the listener, or to stderr without one
+    pub fn report(&self, message: String, kind: Option<ErrorKind>) {
+        let error = WalError {
+            message,
+            kind,
+            time: SystemTime::now(),
+        };
+        let mut errors = self
+            .stats
+            .0
+            .errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error.clone());
+        drop(errors);
+        match self.listener.as_ref() {
+            Some(listener) => listener.on_error(&error),
+            None => eprintln!("{}", error),
+        }
+    }
+
+    /// Record a corrupted region of a file skipped while reading
+    pub fn skipped(&self, region: SkippedRegion) {
+        self.stats
+            .0
+            .skipped_records
+            .fetch_add(region.records, Relaxed);
+        self.stats.0.skipped_bytes.fetch_add(region.bytes, Relaxed);
+        if let Some(listener) = self.listener.as_ref() {
+            listener.on_skipped_region(region);
+        }
+    }
+
+    /// Whether there's a listener to report the events to
+    pub fn listening(&self) -> bool {
+        self.listener.is_some()
+    }
+
+    /// Report the files deleted by the garbage collection to the listener
+    pub fn collected(&self, event: GcEvent) {
+        if let Some(listener) = self.listener.as_ref() {
+            listener.on_gc(event);
+        }
+    }
+
+    /// Record the time taken since the start of an operation on a file
+    pub fn observe(&self, operation: Operation, segment: usize, start: Instant) {
+        let duration = start.elapsed();
+        match operation {
+            Operation::Flush => {
+                self.stats.0.flush.record(duration);
+                self.stats
+                    .update_segment(segment, |stats| stats.flush.record(duration));
+            }
+            Operation::Fsync => {
+                self.stats.0.fsync.record(duration);
+                self.stats
+                    .update_segment(segment, |stats| stats.fsync.record(duration));
+            }
+            Operation::Rotation | Operation::Gc => {}
+        }
+        match self.threshold {
+            Some(threshold) if duration >= threshold => {}
+            _ => return,
+        }
+        let slow = SlowOperation {
+            operation,
+            duration,
+            segment,
+        };
+        match self.listener.as_ref() {
+            Some(listener) => listener.on_slow_operation(slow),
+            None => eprintln!("Slow WAL operation: {:?}", slow),
+        }
+    }
+}
+
+/// Lock-free histogram of durations, in buckets of powers of two microseconds
+#[derive(Debug)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Relaxed);
+        self.max.fetch_max(micros, Relaxed);
+    }
+
+    fn latency(&self) -> Latency {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Relaxed))
+            .collect::<Vec<_>>();
+        let count = counts.iter().sum::<u64>();
+        let max = self.max.load(Relaxed);
+        // upper bound of the bucket the given share of the durations falls in
+        let percentile = |share: f64| {
+            let rank = (count as f64 * share).ceil() as u64;
+            let mut seen = 0;
+            for (bucket, n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank.max(1) {
+                    let bound = 1u64.checked_shl(bucket as u32).unwrap_or(u64::MAX) - 1;
+                    return Duration::from_micros(bound.min(max));
+                }
+            }
+            Duration::from_micros(max)
+        };
+        Latency {
+            count,
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+            max: Duration::from_micros(max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct SlowOperations(Mutex<Vec<SlowOperation>>);
+
+    impl WalListener for SlowOperations {
+        fn on_slow_operation(&self, operation: SlowOperation) {
+            self.0.lock().unwrap().push(operation);
+        }
+    }
+
+    #[test]
+    fn slow_operations() {
+        let listener = Arc::new(SlowOperations::default());
+        let config = WalConfig {
+            listener: Some(listener.clone()),
+            slow_threshold: Some(Duration::from_millis(5)),
+            ..Default::default()
+        };
+        let monitor = Monitor::new(&config);
+        monitor.observe(Operation::Fsync, 3, Instant::now());
+        let start = Instant::now() - Duration::from_millis(10);
+        monitor.observe(Operation::Rotation, 4, start);
+        let slow = listener.0.lock().unwrap();
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].operation, Operation::Rotation);
+        assert_eq!(slow[0].segment, 4);
+        assert!(slow[0].duration >= Duration::from_millis(10));
+        // only flushes and fsyncs have a histogram in the stats
+        assert_eq!(config.stats.snapshot().fsync.count, 1);
+    }
+
+    #[derive(Default)]
+    struct Errors(Mutex<Vec<WalError>>);
+
+    impl WalListener for Errors {
+        fn on_error(&self, error: &WalError) {
+            self.0.lock().unwrap().push(error.clone());
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let listener = Arc::new(Errors::default());
+        let config = WalConfig {
+            listener: Some(listener.clone()),
+            ..Default::default()
+        };
+        let monitor = Monitor::new(&config);
+        assert_eq!(config.stats.last_error(), None);
+        for i in 0..20 {
+            monitor.report(format!("error {}", i), None);
+        }
+        monitor.write_result(Some(&std::io::Error::from(ErrorKind::StorageFull)));
+        assert!(config.stats.is_failing());
+        let last = config.stats.last_error().unwrap();
+        assert!(last.message.starts_with("Failed to write to WAL file"));
+        assert_eq!(last.kind, Some(ErrorKind::StorageFull));
+        // only the most recent ones are kept, while all of them reach the listener
+        let errors = config.stats.errors();
+        assert_eq!(errors.len(), RECENT_ERRORS);
+        assert_eq!(errors[0].message, "error 5");
+        assert_eq!(listener.0.lock().unwrap().len(), 21);
+        monitor.write_result(None);
+        assert!(!config.stats.is_failing());
+        assert_eq!(config.stats.snapshot().write_errors, 1);
+    }
+
+    #[test]
+    fn percentiles() {
+        let histogram = Histogram::default();
+        assert_eq!(histogram.latency(), Latency::default());
+        for _ in 0..98 {
+            histogram.record(Duration::from_micros(100));
+        }
+        histogram.record(Duration::from_millis(5));
+        histogram.record(Duration::from_millis(20));
+        let latency = histogram.latency();
+        assert_eq!(latency.count, 100);
+        assert_eq!(latency.p50, Duration::from_micros(127));
+        assert_eq!(latency.p99, Duration::from_micros(8191));
+        assert_eq!(latency.max, Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::Size;
+use std::time::{Duration, Instant};
+
+/// Most time the unused rate is carried over, so a pause doesn't lead to a burst
+const MAX_CREDIT: Duration = Duration::from_secs(1);
+
+/// Caps the rate of the bytes going through, by sleeping whenever they get ahead of it
+pub(crate) struct Throttle {
+    /// Bytes let through per second
+    rate: u64,
+    /// When the bytes started being counted
+    start: Instant,
+    /// Bytes let through since the start
+    consumed: u64,
+}
+
+impl Throttle {
+    /// ## Returns
+    /// `None` for a rate of zero, which is no limit at all
+    pub fn new(rate: u64) -> Option<Self> {
+        (rate > 0).then(|| Self {
+            rate,
+            start: Instant::now(),
+            consumed: 0,
+        })
+    }
+
+    /// Size of the chunks to work in, up to `max`, small enough for the bytes to be spread out
+    /// evenly over about 10 chunks per second
+    pub fn chunk(&self, max: usize) -> usize {
+        let chunk = usize::try_from(self.rate / 10).unwrap_or(max);
+        chunk.clamp(4096.min(max), max)
+    }
+
+    /// Let the bytes through, sleeping until the rate allows them
+    pub fn consume(&mut self, bytes: usize) {
+        let elapsed = self.start.elapsed();
+        if elapsed > self.due() + MAX_CREDIT {
+            self.start = Instant::now();
+            self.consumed = 0;
+        }
+        self.consumed += bytes as u64;
+        if let Some(wait) = self.due().checked_sub(self.start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Time it takes to let through the bytes consumed so far
+    fn due(&self) -> Duration {
+        Duration::from_secs_f64(self.consumed as f64 / self.rate as f64)
+    }
+}
+
+/// A cap on the rate of the writes, set with [WalBuilder::write_limit](crate::WalBuilder::write_limit)
+/// or [Wal::set_write_limit](crate::Wal::set_write_limit)
+///
+/// Bursts of up to a second worth of the rate go through right away, while the writes past it
+/// wait for their turn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteLimit {
+    /// Logs per second
+    Records(u64),
+    /// Bytes of serialized logs per second
+    Bytes(Size),
+}
+
+impl WriteLimit {
+    /// The rate, in logs or bytes per second
+    pub(crate) fn rate(&self) -> u64 {
+        match self {
+            WriteLimit::Records(records) => *records,
+            WriteLimit::Bytes(size) => size.to_bytes() as u64,
+        }
+    }
+
+    /// Tokens taken by the logs, in the unit of the rate
+    fn tokens(&self, records: usize, bytes: usize) -> u64 {
+        match self {
+            WriteLimit::Records(_) => records as u64,
+            WriteLimit::Bytes(_) => bytes as u64,
+        }
+    }
+}
+
+/// Token bucket holding up to a second worth of the rate of a [WriteLimit], shared by the
+/// threads writing
+pub(crate) struct TokenBucket {
+    limit: WriteLimit,
+    /// Tokens available, negative when owed by the writes waiting for their turn
+    tokens: f64,
+    /// When the bucket was last refilled
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// ## Returns
+    /// `None` for a rate of zero
+    pub fn new(limit: WriteLimit) -> Option<Self> {
+        (limit.rate() > 0).then(|| Self {
+            limit,
+            tokens: limit.rate() as f64,
+            last: Instant::now(),
+        })
+    }
+
+    /// Take the tokens for some logs, going into debt if there aren't enough of them
+    ///
+    /// ## Returns
+    /// How long to wait before writing the logs
+    pub fn take(&mut self, records: usize, bytes: usize) -> Duration {
+        let rate = self.limit.rate() as f64;
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refill).min(rate);
+        self.last = now;
+        self.tokens -= self.limit.tokens(records, bytes) as f64;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle() {
+        assert!(Throttle::new(0).is_none());
+        let mut throttle = Throttle::new(100_000).unwrap();
+        assert_eq!(throttle.chunk(1 << 20), 10_000);
+        assert_eq!(throttle.chunk(1000), 1000);
+        let start = Instant::now();
+        for _ in 0..5 {
+            throttle.consume(10_000);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        // the rate unused during a pause is only carried over for a while
+        std::thread::sleep(MAX_CREDIT * 2);
+        let start = Instant::now();
+        throttle.consume(20_000);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn token_bucket() {
+        assert!(TokenBucket::new(WriteLimit::Records(0)).is_none());
+        let mut bucket = TokenBucket::new(WriteLimit::Records(100)).unwrap();
+        // a second worth of logs goes through right away
+        assert_eq!(bucket.take(100, 1_000_000), Duration::ZERO);
+        let wait = bucket.take(50, 0);
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500));
+        let mut bucket = TokenBucket::new(WriteLimit::Bytes(Size::Kb(1))).unwrap();
+        assert_eq!(bucket.take(1000, 1024), Duration::ZERO);
+        assert!(bucket.take(1, 2048) > Duration::from_millis(1900));
+    }
+}
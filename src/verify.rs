@@ -0,0 +1,177 @@
+use crate::failover;
+use crate::iter;
+use crate::writer::chain::{self, Link};
+use crate::writer::frame;
+use crate::writer::header::read_header;
+use crate::writer::manager::{checksum_reader, open_segment};
+use crate::writer::manifest::SegmentInfo;
+#[cfg(feature = "signing")]
+use crate::writer::signature;
+use crate::WalConfig;
+use std::io::{BufReader, Read};
+
+/// Outcome of verifying the integrity of the log files with [Wal::verify](crate::Wal::verify)
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Number of closed log files that were checked
+    pub checked: usize,
+    /// Files whose content doesn't match the checksum recorded when they were closed
+    pub corrupted: Vec<usize>,
+    /// Files recorded in the manifest, which couldn't be found
+    pub missing: Vec<usize>,
+    /// Files in which a log doesn't carry the hash of the log before it, i.e. logs were
+    /// modified or deleted, when the logs are hash-chained
+    pub broken_chain: Vec<usize>,
+    /// Files without a valid signature, when checked with [verify_signed](crate::verify_signed)
+    pub bad_signature: Vec<usize>,
+}
+
+impl VerifyReport {
+    /// Whether all the checked files are intact
+    pub fn is_ok(&self) -> bool {
+        self.corrupted.is_empty()
+            && self.missing.is_empty()
+            && self.broken_chain.is_empty()
+            && self.bad_signature.is_empty()
+    }
+}
+
+/// Verify every closed log file against the checksum recorded in the manifest
+pub(crate) fn verify(config: &WalConfig) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    for (index, info) in sealed(config) {
+        report.checked += 1;
+        match verify_segment(config, index, info.checksum) {
+            None => report.missing.push(index),
+            Some(false) => report.corrupted.push(index),
+            Some(true) => {}
+        }
+    }
+    if config.hash_chain {
+        report.broken_chain = verify_chain(config);
+    }
+    report
+}
+
+/// Verify the closed log files in a location, along with their signatures
+///
+/// Meant for third parties checking a copy of the files, so the files are only read, without
+/// opening a [Wal](crate::Wal) on them. Every closed file must carry a valid signature, made
+/// with the key set by [WalBuilder::signing_key](crate::WalBuilder::signing_key).
+///
+/// ## Arguments
+/// - `location`: The directory of the files
+/// - `public_key`: The Ed25519 public key of the signing key
+///
+/// ## Returns
+/// An error if the public key is invalid
+#[cfg(feature = "signing")]
+pub fn verify_signed(location: &str, public_key: &[u8; 32]) -> Result<VerifyReport, String> {
+    let key = ed25519_dalek::VerifyingKey::from_bytes(public_key)
+        .map_err(|e| format!("Invalid public key: {}", e))?;
+    let config = WalConfig {
+        location: location.into(),
+        ..Default::default()
+    };
+    let mut report = verify(&config);
+    for (index, info) in sealed(&config) {
+        let reader = match open_segment(&config, index) {
+            Some(reader) => BufReader::new(reader),
+            None => continue,
+        };
+        let valid = info.signature.is_some_and(|signature| {
+            matches!(signature::verify(&key, index, reader, &signature), Ok(true))
+        });
+        if !valid {
+            report.bad_signature.push(index);
+        }
+    }
+    Ok(report)
+}
+
+/// Check that every log carries the link of the log before it, across all the files kept
+///
+/// The first log kept is taken as the anchor of the chain, as the logs before it are gone.
+///
+/// ## Returns
+/// The files in which the chain is broken
+fn verify_chain(config: &WalConfig) -> Vec<usize> {
+    let manifest = iter::manifest(config);
+    let mut broken = Vec::new();
+    let mut last = None;
+    for index in iter::segments(config).unwrap_or_default() {
+        let info = manifest.get(&index);
+        let reader = match open_segment(config, index) {
+            Some(reader) => BufReader::new(reader),
+            // the link recorded for a missing file still chains the files around it
+            None => {
+                last = info.and_then(|info| info.chain).or(last);
+                continue;
+            }
+        };
+        if !matches!(verify_links(reader, info, &mut last), Ok(true)) {
+            broken.push(index);
+        }
+    }
+    broken
+}
+
+/// Check the links of the logs in a file
+///
+/// ## Arguments
+/// - `reader`: The file
+/// - `info`: The manifest entry of the file, if any
+/// - `last`: The link of the last log before the file, moved to the last log of the file
+fn verify_links(
+    mut reader: impl Read,
+    info: Option<&SegmentInfo>,
+    last: &mut Option<Link>,
+) -> std::io::Result<bool> {
+    let (format, prefix) = read_header(&mut reader)?;
+    if !format.chained || prefix.len() < format.header_len {
+        *last = None;
+        return Ok(true);
+    }
+    let skip = info.map_or(0, |info| {
+        info.trim_offset.saturating_sub(format.header_len as u64)
+    });
+    // the first log kept follows logs that can no longer be read
+    if skip > 0 {
+        *last = None;
+    }
+    let mut intact = true;
+    // the chain carries on past a broken link, so that the files after it are still checked
+    chain::walk(
+        frame::records(reader, format),
+        format,
+        skip,
+        |link, record| {
+            intact &= last.is_none_or(|last| last == *link);
+            *last = Some(chain::next(link, record));
+        },
+    )?;
+    // the link recorded at close catches the last logs of the file being cut off
+    let recorded = info.and_then(|info| info.chain);
+    Ok(intact && (recorded.is_none() || recorded == *last))
+}
+
+/// The manifest entries of the closed files in the WAL
+///
+/// The entry of the file before the current one is kept once the file is deleted, and skipped.
+pub(crate) fn sealed(config: &WalConfig) -> Vec<(usize, SegmentInfo)> {
+    let files = iter::segments(config).unwrap_or_default();
+    let mut manifest = failover::manifest(config);
+    files
+        .into_iter()
+        .filter_map(|index| manifest.remove(&index).map(|info| (index, info)))
+        .collect()
+}
+
+/// Verify a closed log file against its recorded checksum
+///
+/// ## Returns
+/// Whether the checksum matches, or `None` if the file is missing
+pub(crate) fn verify_segment(config: &WalConfig, index: usize, checksum: u32) -> Option<bool> {
+    let reader = open_segment(config, index)?;
+    Some(matches!(checksum_reader(reader), Ok(c) if c == checksum))
+}
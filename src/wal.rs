@@ -21,7 +21,7 @@
 //! let wal = Wal::new("/tmp/logz", Some(2000));
 //!
 //! // recovery: Option A
-//! let all_logs = wal.read().unwrap().into_iter().collect::<Vec<Log> > ();
+//! let all_logs = wal.read().unwrap().collect::<Vec<Log> > ();
 //! // recovery: Option B
 //! for log in wal.read().unwrap() {
 //!   // do something with logs
@@ -35,28 +35,43 @@
 //! // Flush to disk early/manually, before the buffer is filled
 //! wal.flush();
 //!```
-use crate::iter::WalIterator;
-use crate::writer::Writer;
-use crate::{WalConfig, DEFAULT_BUFFER_SIZE};
+use crate::builder::ConfigError;
+use crate::consumer::{self, ConsumerGroup};
+use crate::expiry::Expiry;
+use crate::health::{self, Health};
+use crate::iter::{self, Chunks, RecordMeta, WalIterator, READ_RESERVE};
+use crate::merkle::MerkleTree;
+use crate::recovery::RecoveryReport;
+use crate::replay;
+use crate::scrubber::Scrubber;
+use crate::segments::{self, Segment, SegmentBound};
+use crate::snapshot;
+use crate::stats::{LifetimeStats, Monitor, WalError, WalStats};
+use crate::verify::{self, VerifyReport};
+use crate::writer::buffer::Data;
+#[cfg(feature = "compression")]
+use crate::writer::compress;
+use crate::writer::frame::RecordHeader;
+use crate::writer::manager::{open_segment, Meta};
+use crate::writer::state;
+use crate::writer::{FlushHandle, RecordKind, Writer};
+use crate::{Lsn, ReadOptions, Size, WalConfig, WriteLimit, WriteOptions, DEFAULT_BUFFER_SIZE};
 use serde::{Deserialize, Serialize};
-use std::fs::remove_dir_all;
+use std::fs::{remove_dir_all, File};
+use std::io::Write;
 use std::marker::PhantomData;
 use std::path::PathBuf;
-use std::sync::atomic::Ordering::Acquire;
-use std::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use std::sync::Arc;
-
-pub(crate) const MODE_IDLE: u8 = 0;
-const MODE_READ: u8 = 1;
-const MODE_WRITE: u8 = 2;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub(crate) struct WalInner<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
 {
     pub config: WalConfig,
-    pub mode: AtomicU8,
     pub writer: Writer,
+    _scrubber: Option<Scrubber>,
     _phantom: PhantomData<T>,
 }
 
@@ -65,16 +80,36 @@ where
     T: Serialize + for<'a> Deserialize<'a>,
 {
     pub fn new(config: WalConfig) -> Self {
+        let scrubber = config
+            .scrub_interval
+            .map(|interval| Scrubber::start(config.clone(), interval));
         Self {
             writer: Writer::new(config.clone()),
-            mode: AtomicU8::new(MODE_IDLE),
             config,
+            _scrubber: scrubber,
             _phantom: PhantomData,
         }
     }
 }
 
-#[derive(Clone)]
+impl<T> Drop for WalInner<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn drop(&mut self) {
+        if self.config.temporary {
+            let _ = remove_dir_all(self.config.location.as_path());
+        }
+    }
+}
+
+/// Logs serializing to at most this many bytes skip the heap allocation when written
+const SMALL_LOG_SIZE: usize = 256;
+/// Number of logs applied between two progress reports of [Wal::replay]
+const REPLAY_PROGRESS_INTERVAL: u64 = 10_000;
+/// Number of logs applied by a resumable replay between two stores of its progress
+const REPLAY_CHECKPOINT_INTERVAL: u64 = 100_000;
+
 pub struct Wal<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
@@ -82,6 +117,18 @@ where
     pub(crate) inner: Arc<WalInner<T>>,
 }
 
+// the handles share the same instance, so the logs themselves don't need to be cloneable
+impl<T> Clone for Wal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
 impl<T> Wal<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
@@ -97,6 +144,7 @@ where
             fsync: false,
             buffer_size: DEFAULT_BUFFER_SIZE,
             size,
+            ..Default::default()
         };
         let inner = WalInner::new(config);
         Self {
@@ -104,53 +152,872 @@ where
         }
     }
 
+    /// Create a temporary [Wal] in a unique directory under the system's temp directory
+    ///
+    /// The directory and all the logs in it are deleted once the last handle to the [Wal] is dropped.
+    /// Handy for tests and scratch pipelines that don't need the logs to persist.
+    pub fn temp() -> Result<Self, String> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let name = format!(
+            "walcraft-{}-{}-{}",
+            std::process::id(),
+            nanos,
+            COUNTER.fetch_add(1, Relaxed)
+        );
+        let location = std::env::temp_dir().join(name);
+        if let Err(e) = std::fs::create_dir_all(&location) {
+            return Err(format!("Failed to access location: {}", e));
+        }
+        let config = WalConfig {
+            location,
+            temporary: true,
+            ..Default::default()
+        };
+        Ok(Self::with_config(config))
+    }
+
+    /// Open a [Wal] that already exists at the given location
+    ///
+    /// Unlike [Wal::new], this fails instead of silently creating an empty log,
+    /// e.g. when the location has a typo in it.
+    ///
+    /// # Arguments
+    /// - location: Location where the files are stored
+    /// - size: Optional, maximum storage size taken by logs in MBs
+    pub fn open_existing(location: &str, size: Option<u16>) -> Result<Self, String> {
+        if !Meta::new(PathBuf::from(location)).exists() {
+            return Err(format!("No WAL exists at location: {}", location));
+        }
+        Ok(Self::new(location, size))
+    }
+
+    /// Create a new [Wal] at the given location
+    ///
+    /// Unlike [Wal::new], this fails if a WAL already exists at the location,
+    /// instead of appending to it.
+    ///
+    /// # Arguments
+    /// - location: Location where the files shall be stored
+    /// - size: Optional, maximum storage size taken by logs in MBs
+    pub fn create_new(location: &str, size: Option<u16>) -> Result<Self, String> {
+        if Meta::new(PathBuf::from(location)).exists() {
+            return Err(format!("A WAL already exists at location: {}", location));
+        }
+        if let Err(e) = std::fs::create_dir_all(location) {
+            return Err(format!("Failed to access location: {}", e));
+        }
+        Ok(Self::new(location, size))
+    }
+
     pub(crate) fn with_config(config: WalConfig) -> Self {
         let inner = Arc::new(WalInner::new(config));
         Self { inner }
     }
 
     /// Read the logs
-    pub fn read(&self) -> Result<impl Iterator<Item = T>, String> {
-        if let Err(_) = self
-            .inner
-            .mode
-            .compare_exchange(MODE_IDLE, MODE_READ, Relaxed, Relaxed)
-        {
-            return Err("Unable to acquire read lock on WAL".to_string());
-        }
+    ///
+    /// Reading doesn't block writing, and several readers may exist at once. The iterator gives
+    /// a point-in-time view of the logs on disk when this method is called. The logs written
+    /// later are not included, and the files being read are kept from the garbage collection.
+    ///
+    /// The [WalIterator] tells how far it has read with [WalIterator::progress], so that a long
+    /// recovery can drive a progress bar or a readiness probe.
+    pub fn read(&self) -> Result<WalIterator<T>, String> {
         let wal = Wal {
             inner: self.inner.clone(),
         };
-        let t = WalIterator::new(wal);
+        let t = WalIterator::new(wal, ReadOptions::default());
         Ok(t)
     }
 
-    /// Write a new log
-    pub fn write(&self, item: T) {
-        // ensure write mode is either ON
-        // or enable it if it's not ON
-        let mode = self.inner.mode.load(Relaxed);
-        if mode != MODE_WRITE {
-            if let Err(d) = self
-                .inner
-                .mode
-                .compare_exchange(MODE_IDLE, MODE_WRITE, Acquire, Relaxed)
-            {
-                // check if another thread hasn't already set the value
-                if d != MODE_WRITE {
-                    panic!("Walcraft Error: Writing logs while reading data is forbidden");
+    /// Read up to `limit` logs, from the oldest one, onto the end of a vector
+    ///
+    /// Meant for loading the logs into memory in bulk: the room for up to `limit` logs is
+    /// reserved at once, up to 65536 of them, and the logs are decoded straight into the vector.
+    /// To load them in batches, use [WalIterator::read_into] on a single iterator instead.
+    ///
+    /// ## Returns
+    /// The number of logs appended
+    pub fn read_into(&self, out: &mut Vec<T>, limit: usize) -> Result<usize, String> {
+        // the logs aren't counted up front, as that would read them twice
+        out.reserve(limit.min(READ_RESERVE));
+        Ok(self.read()?.read_into(out, limit))
+    }
+
+    /// Read the logs in chunks of up to `chunk_size` logs, e.g. to insert them into a database
+    /// in bulk, see [WalIterator::chunks]
+    ///
+    /// ### Example
+    /// ```no_run
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+    /// for chunk in wal.read_chunks(1000).unwrap() {
+    ///     println!("inserting {} logs", chunk.len());
+    /// }
+    /// ```
+    pub fn read_chunks(&self, chunk_size: usize) -> Result<Chunks<T>, String> {
+        Ok(self.read()?.chunks(chunk_size))
+    }
+
+    /// Read the logs with custom [ReadOptions]
+    pub fn read_with(&self, options: ReadOptions) -> Result<WalIterator<T>, String> {
+        let wal = Wal {
+            inner: self.inner.clone(),
+        };
+        Ok(WalIterator::new(wal, options))
+    }
+
+    /// Read the logs along with their [Lsn]
+    ///
+    /// Consumers can persist the [Lsn] of the last processed log, and later resume
+    /// from the log after it with [Wal::read_from].
+    pub fn read_with_positions(&self) -> Result<impl Iterator<Item = (Lsn, T)>, String> {
+        self.read_from(0)
+    }
+
+    /// Read the logs starting at the given [Lsn], along with their [Lsn]
+    ///
+    /// The files that only have older logs in them are skipped without being read.
+    pub fn read_from(&self, lsn: Lsn) -> Result<impl Iterator<Item = (Lsn, T)>, String> {
+        let wal = Wal {
+            inner: self.inner.clone(),
+        };
+        let iter = WalIterator::new(wal, ReadOptions::default());
+        Ok(iter.start_from(lsn).with_positions())
+    }
+
+    /// Read the undecoded logs along with their [RecordMeta]
+    ///
+    /// This skips deserialization entirely, for building tools such as inspectors, shippers
+    /// and format converters on top of the log files.
+    pub fn read_raw(&self) -> Result<impl Iterator<Item = (RecordMeta, Vec<u8>)>, String> {
+        let wal = Wal {
+            inner: self.inner.clone(),
+        };
+        Ok(WalIterator::new(wal, ReadOptions::default()).raw())
+    }
+
+    /// Count the logs stored on disk
+    ///
+    /// This is much faster than reading all the logs, as the counts recorded for the filled
+    /// log files are summed up, and only the current file is scanned. No log is deserialized.
+    /// The logs still waiting in the buffer aren't counted, while the control records are, see
+    /// [Wal::append_control].
+    pub fn count(&self) -> u64 {
+        iter::count(&self.inner.config)
+    }
+
+    /// List the groups of [Consumer](crate::Consumer) reading the logs, with their offsets
+    /// and how far behind they are
+    pub fn consumer_groups(&self) -> Vec<ConsumerGroup> {
+        consumer::groups(&self.inner.config)
+    }
+
+    /// Delete the stored offset of a group of [Consumer](crate::Consumer), e.g. once retired
+    ///
+    /// ## Returns
+    /// Whether the group existed, or an error if the name is invalid or the offset couldn't
+    /// be deleted
+    pub fn remove_consumer_group(&self, group: &str) -> Result<bool, String> {
+        consumer::remove_group(&self.inner.config, group)
+    }
+
+    /// List all the log files, from the oldest to the newest
+    ///
+    /// This exposes the layout of the WAL on disk, e.g. for tools archiving the filled files
+    /// or monitoring the storage.
+    pub fn list_segments(&self) -> Vec<Segment> {
+        segments::list(&self.inner.config)
+    }
+
+    /// Read the logs that haven't expired yet
+    ///
+    /// Same as [Wal::read], but the logs past their [Expiry] at the time of reading are skipped.
+    /// This gives finer retention than the storage limit, which deletes whole files at once.
+    pub fn read_unexpired(&self) -> Result<impl Iterator<Item = T>, String>
+    where
+        T: Expiry,
+    {
+        let now = SystemTime::now();
+        Ok(self.read()?.filter(move |item| !item.is_expired(now)))
+    }
+
+    /// Rebuild an in-memory state by applying all the logs to it, from the oldest one
+    ///
+    /// This is the usual recovery at startup, before beginning to write. The progress is reported
+    /// to the [WalListener](crate::WalListener) once every 10,000 logs, and once all of them
+    /// have been applied.
+    ///
+    /// ### Example
+    /// ```no_run
+    /// use std::collections::HashMap;
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<(String, u64)> = Wal::new("/tmp/logz", None);
+    /// let balances = wal
+    ///     .replay(HashMap::new(), |mut balances, (account, amount)| {
+    ///         *balances.entry(account).or_insert(0) += amount;
+    ///         balances
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn replay<S, F>(&self, initial: S, mut apply: F) -> Result<S, String>
+    where
+        F: FnMut(S, T) -> S,
+    {
+        let listener = self.inner.config.listener.as_ref();
+        let total = listener.map(|_| self.count()).unwrap_or(0);
+        let mut state = initial;
+        let mut applied = 0;
+        for item in self.read()? {
+            state = apply(state, item);
+            applied += 1;
+            if applied % REPLAY_PROGRESS_INTERVAL == 0 {
+                if let Some(listener) = listener {
+                    listener.on_replay_progress(applied, total);
                 }
             }
         }
+        if let Some(listener) = listener {
+            listener.on_replay_progress(applied, total);
+        }
+        Ok(state)
+    }
+
+    /// Change the cap on the rate of the writes set with
+    /// [WalBuilder::write_limit](crate::WalBuilder::write_limit), `None` lifting it
+    ///
+    /// ## Returns
+    /// An error, leaving the limit as it is, if the rate is zero
+    pub fn set_write_limit(&self, limit: Option<WriteLimit>) -> Result<(), String> {
+        if limit.is_some_and(|limit| limit.rate() == 0) {
+            return Err("The write limit must be above zero".to_string());
+        }
+        self.inner.writer.set_write_limit(limit);
+        Ok(())
+    }
+
+    /// Same as [Wal::replay], storing the progress periodically, so that a replay interrupted
+    /// by a crash resumes where it left off instead of starting over
+    ///
+    /// Every 100,000 logs, and once all of them have been applied, the state is stored along
+    /// with the [Lsn] of the next log to apply, in the location of the logs. The next call with
+    /// the same name starts from the stored state, and only applies the logs after it, which
+    /// makes it cheap to call at every startup. The logs applied after the last store are
+    /// applied again on resume.
+    ///
+    /// ## Arguments
+    /// - `name`: Name of the replay, made of ASCII letters, digits, `-` and `_`
+    /// - `initial`: The state to start from, if there's no stored progress
+    /// - `apply`: Applies a log to the state
+    ///
+    /// ### Example
+    /// ```no_run
+    /// use std::collections::HashMap;
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<(String, u64)> = Wal::new("/tmp/logz", None);
+    /// let balances = wal
+    ///     .replay_resumable("balances", HashMap::new(), |mut balances, (account, amount)| {
+    ///         *balances.entry(account).or_insert(0) += amount;
+    ///         balances
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn replay_resumable<S, F>(&self, name: &str, initial: S, mut apply: F) -> Result<S, String>
+    where
+        S: Serialize + for<'a> Deserialize<'a>,
+        F: FnMut(S, T) -> S,
+    {
+        let path = replay::progress_path(&self.inner.config, name)?;
+        let (mut next, mut state) = replay::load(&path)?.unwrap_or((0, initial));
+        let mut applied = 0;
+        for (lsn, item) in self.read_from(next)? {
+            state = apply(state, item);
+            next = lsn + 1;
+            applied += 1;
+            if applied % REPLAY_CHECKPOINT_INTERVAL == 0 {
+                replay::store(&path, next, &state)?;
+            }
+        }
+        if applied % REPLAY_CHECKPOINT_INTERVAL != 0 {
+            replay::store(&path, next, &state)?;
+        }
+        Ok(state)
+    }
+
+    /// Forget the stored progress of a [Wal::replay_resumable], so that its next call starts
+    /// over from the oldest log, e.g. after changing the type of its state
+    pub fn reset_replay(&self, name: &str) -> Result<(), String> {
+        let path = replay::progress_path(&self.inner.config, name)?;
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to reset replay progress: {}", e)),
+        }
+    }
+
+    /// Write a new log
+    ///
+    /// With [BufferOverflow::Reject](crate::BufferOverflow::Reject), a log the buffer has no
+    /// room for is dropped with a warning, see [Wal::try_write] instead. So is a log that fails
+    /// to serialize, or is too large to be framed, see [Wal::last_error].
+    pub fn write(&self, item: T) {
         // write the data
-        if let Ok(d) = bincode::serialize(&item) {
-            self.inner.writer.log(&d);
+        // a log refused by the writer is reported by it already
+        let header = RecordHeader::default();
+        let refused = self.serialize(&item, header, |d| self.inner.writer.log(d)) == Some(false);
+        if refused && self.inner.writer.refusal().is_none() {
+            let message = "walcraft buffer is full - log dropped".to_string();
+            Monitor::new(&self.inner.config).report(message, None);
+        }
+    }
+
+    /// Write a new log, unless the buffer has no room for it
+    ///
+    /// ## Returns
+    /// The log back if it was refused, which happens with
+    /// [BufferOverflow::Reject](crate::BufferOverflow::Reject) until the buffer is flushed, or
+    /// when the WAL is read-only, see [Wal::is_read_only]
+    pub fn try_write(&self, item: T) -> Result<(), T> {
+        match self.serialize(&item, RecordHeader::default(), |d| self.inner.writer.log(d)) {
+            Some(false) => Err(item),
+            _ => Ok(()),
+        }
+    }
+
+    /// Serialize a log and hand over the bytes to the closure
+    ///
+    /// Small logs are serialized into a buffer on the stack, skipping the heap allocation. The
+    /// size of the log is worked out first, so that every log is serialized only once, and a log
+    /// too large to be framed along with its header is never buffered.
+    ///
+    /// ## Returns
+    /// The outcome of the closure, or `None` if the log couldn't be serialized or is too large,
+    /// which is reported by the [Monitor]
+    fn serialize<R>(
+        &self,
+        item: &T,
+        header: RecordHeader,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Option<R> {
+        let codec = &self.inner.config.codec;
+        let len = self.serialized_size(item, header)?;
+        // too large for the stack buffer
+        let result = if len > SMALL_LOG_SIZE {
+            codec.serialize(item).map(|d| f(&Data::from(d)))
+        } else {
+            let mut scratch = [0; SMALL_LOG_SIZE];
+            let result = codec
+                .serialize_into(&mut scratch[..len], item)
+                .map(|()| f(&scratch[..len]));
+            #[cfg(feature = "zeroize")]
+            zeroize::Zeroize::zeroize(&mut scratch);
+            result
+        };
+        match result {
+            Ok(result) => Some(result),
+            Err(e) => self.dropped(format!("walcraft serialization error - {}", e)),
+        }
+    }
+
+    /// Size of the serialized log, as long as it can be framed along with its header
+    ///
+    /// ## Returns
+    /// The size, or `None` if the log couldn't be serialized or is too large, which is reported
+    /// by the [Monitor]
+    fn serialized_size(&self, item: &T, header: RecordHeader) -> Option<usize> {
+        let len = match self.inner.config.codec.serialized_size(item) {
+            Ok(len) => len,
+            Err(e) => return self.dropped(format!("walcraft serialization error - {}", e)),
+        };
+        // the kind is counted in the largest size already
+        let max = self.inner.config.max_record_size() + 1 - header.len();
+        match usize::try_from(len) {
+            Ok(len) if len <= max => Some(len),
+            _ => {
+                let message = format!("walcraft log of {} bytes is larger than {} bytes", len, max);
+                self.dropped(message)
+            }
+        }
+    }
+
+    /// Report a log that can't be written
+    fn dropped<R>(&self, reason: String) -> Option<R> {
+        let message = format!("{} - log dropped", reason);
+        Monitor::new(&self.inner.config).report(message, Some(std::io::ErrorKind::InvalidInput));
+        None
+    }
+
+    /// Write all the logs from an iterator
+    ///
+    /// This is more efficient than calling [Wal::write] in a loop, as the buffer is locked only
+    /// once and the filled buffers are written to disk together. Useful for bulk-loading logs,
+    /// e.g. when importing a backlog.
+    ///
+    /// As with [Wal::write], the logs that fail to serialize or are too large to be framed are
+    /// dropped, and each one is reported, see [Wal::last_error].
+    pub fn write_iter<I>(&self, items: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let codec = self.inner.config.codec;
+        let data = items.into_iter().filter_map(|item| {
+            self.serialized_size(&item, RecordHeader::default())?;
+            match codec.serialize(&item) {
+                Ok(data) => Some(data),
+                Err(e) => self.dropped(format!("walcraft serialization error - {}", e)),
+            }
+        });
+        self.inner.writer.log_many(data);
+    }
+
+    /// Write a high-priority log straight to the file, bypassing the in-memory buffer
+    ///
+    /// Any logs waiting in the buffer are written along with it to preserve the order of logs.
+    /// This is meant for control records, such as "checkpoint complete", that gate other operations.
+    ///
+    /// ## Arguments
+    /// - `item`: The log to write
+    /// - `fsync`: Whether to sync the file to disk before returning, even if fsync is disabled for the [Wal]
+    pub fn write_priority(&self, item: T, fsync: bool) {
+        let options = WriteOptions {
+            fsync,
+            priority: true,
+            ..Default::default()
+        };
+        self.write_with(item, options);
+    }
+
+    /// Write a new log with custom [WriteOptions]
+    ///
+    /// This allows an individual log to demand an fsync or skip the buffer,
+    /// without changing the behaviour of the whole [Wal].
+    pub fn write_with(&self, item: T, options: WriteOptions) {
+        let header = RecordHeader::log(options.term);
+        self.serialize(&item, header, |d| {
+            if options.fsync || options.priority {
+                self.inner.writer.log_direct(header, d, options.fsync);
+            } else {
+                self.inner.writer.log_record(header, d);
+            }
+        });
+    }
+
+    /// Write logs that are serialized already, straight to the file
+    ///
+    /// Meant for pipelines that receive the logs serialized, e.g. from the network, and don't
+    /// need to decode them before storing them. The logs are framed without being copied, and
+    /// written along with the logs waiting in the buffer in a single vectored write.
+    ///
+    /// The bytes are stored as they are. They can always be read back with [Wal::read_raw],
+    /// while [Wal::read] only decodes them if they were serialized with the same encoding as `T`.
+    ///
+    /// ## Returns
+    /// An error, without writing anything, if a log is empty or longer than 65534 bytes,
+    /// less the bytes taken by the hash chain and the checksum in front of every log, or if the
+    /// WAL is read-only
+    pub fn append_raw_batch<B: AsRef<[u8]>>(&self, records: &[B]) -> Result<(), String> {
+        let records = records.iter().map(|r| r.as_ref()).collect::<Vec<_>>();
+        let max = self.inner.config.max_record_size();
+        if let Some(index) = records.iter().position(|r| r.is_empty() || r.len() > max) {
+            return Err(format!(
+                "Log {} of the batch has an unsupported size of {} bytes",
+                index,
+                records[index].len()
+            ));
+        }
+        if let Some((message, _)) = self.inner.writer.refusal() {
+            return Err(message);
+        }
+        if !records.is_empty() {
+            self.inner.writer.log_vectored(&records);
+        }
+        Ok(())
+    }
+
+    /// Append a control record, e.g. a checkpoint marker, in order with the logs
+    ///
+    /// Control records are meant for the bookkeeping built on top of the WAL. Each one takes up
+    /// an [Lsn] and is counted by [Wal::count], but reading the logs skips it: it only shows up
+    /// through [Wal::read_raw], along with its [RecordKind].
+    ///
+    /// ## Returns
+    /// An error, without writing anything, if the kind is [RecordKind::Log], the payload is
+    /// too large to be framed, the buffer has no room for it, see
+    /// [BufferOverflow::Reject](crate::BufferOverflow::Reject), or the WAL is read-only
+    pub fn append_control(&self, kind: RecordKind, payload: &[u8]) -> Result<(), String> {
+        if !kind.is_control() {
+            return Err("A log can't be appended as a control record".to_string());
+        }
+        if !kind.is_storable() {
+            return Err(format!(
+                "{:?} can't be stored as the kind of a record",
+                kind
+            ));
+        }
+        if payload.len() > self.inner.config.max_record_size() {
+            return Err(format!(
+                "Control record of {} bytes is too large to be framed",
+                payload.len()
+            ));
+        }
+        let header = RecordHeader { kind, term: None };
+        match self.inner.writer.log_record(header, payload) {
+            true => Ok(()),
+            false => Err(match self.inner.writer.refusal() {
+                Some((message, _)) => message,
+                None => "The buffer has no room for the control record".to_string(),
+            }),
         }
     }
 
     /// Sync the in-memory buffer with Disk IO
-    pub fn flush(&self) {
-        self.inner.writer.flush();
+    ///
+    /// The buffered data is written to the log file before this method returns.
+    /// The returned [FlushHandle] resolves once that data has also been synced to disk,
+    /// allowing the caller to do other work in the meantime.
+    pub fn flush(&self) -> FlushHandle {
+        self.inner.writer.flush()
+    }
+
+    /// Change the storage size limit and fsync setting without restarting
+    ///
+    /// The new storage size applies to log files created from now on, while a smaller limit
+    /// is enforced right away by deleting the older logs.
+    ///
+    /// ## Arguments
+    /// - `storage_size`: New storage size limit, `None` for unlimited storage
+    /// - `fsync`: Whether every write shall be synced to disk
+    pub fn reload(&self, storage_size: Option<Size>, fsync: bool) {
+        let size = storage_size
+            .map(|size| size.to_bytes())
+            .unwrap_or(usize::MAX);
+        self.inner.writer.reload(size, fsync);
+    }
+
+    /// Whether this [Wal] has been fenced off by a newer writer
+    ///
+    /// With fencing enabled, every writer opening the WAL moves it to a new epoch. A writer that
+    /// finds the WAL at a newer epoch than its own refuses to append, and stays fenced for good.
+    pub fn is_fenced(&self) -> bool {
+        self.inner.writer.is_fenced()
+    }
+
+    /// Whether this [Wal] refuses to write, as it was opened with
+    /// [FormatPolicy::ReadOnly](crate::FormatPolicy::ReadOnly) on files written by a newer version
+    pub fn is_read_only(&self) -> bool {
+        self.inner.writer.is_read_only()
+    }
+
+    /// The most recent failure inside this [Wal], e.g. a failed write to a file
+    ///
+    /// The failures are reported to [WalListener::on_error](crate::WalListener::on_error) as
+    /// they happen as well, e.g. to alert or trip a circuit breaker.
+    pub fn last_error(&self) -> Option<WalError> {
+        self.inner.config.stats.last_error()
+    }
+
+    /// The most recent failures inside this [Wal], up to 16 of them, from the oldest to the
+    /// newest
+    pub fn recent_errors(&self) -> Vec<WalError> {
+        self.inner.config.stats.errors()
+    }
+
+    /// Check whether the logs are being written normally, e.g. for the readiness endpoint of
+    /// a service
+    ///
+    /// The [Wal] is [Health::Failed] while the writes to the files fail, or once fenced off by a
+    /// newer writer. It's [Health::Degraded] if writes failed since it was opened, if a sync to
+    /// disk failed, if the disk is nearly full, or if the files kept beyond the storage limit
+    /// pile up, e.g. because they're pinned by a long-running reader.
+    pub fn health(&self) -> Health {
+        health::check(&self.inner.config, &self.inner.writer)
+    }
+
+    /// Outcome of checking the log files against the pointers in meta, when the [Wal] was opened
+    ///
+    /// Files deleted manually are detected at open. The pointers are moved past the missing
+    /// oldest files, while the files missing in between are reported as gaps the reader skips.
+    pub fn recovery_report(&self) -> RecoveryReport {
+        let stats = self.stats();
+        RecoveryReport {
+            skipped_records: stats.skipped_records,
+            skipped_bytes: stats.skipped_bytes,
+            ..self.inner.writer.recovery_report()
+        }
+    }
+
+    /// Create the directories and open the log files now, for a [Wal] built with
+    /// [WalBuilder::lazy_init](crate::WalBuilder::lazy_init)
+    ///
+    /// This surfaces a misconfigured location early. It does nothing if the files are open already.
+    pub fn init(&self) -> Result<(), ConfigError> {
+        self.inner.writer.init()
+    }
+
+    /// Delete the older log files, e.g. once the application has archived them
+    ///
+    /// This bypasses the storage limit, and deletes the files from the cold storage too.
+    /// The live file being written to is never deleted.
+    ///
+    /// With an [Lsn] bound falling in the middle of a file, the logs before it are trimmed as well,
+    /// and the readers skip them from then on. In a large filled file, the disk blocks taken by
+    /// them are released on Linux, by punching a hole in the file. The logs after it keep their
+    /// offsets.
+    ///
+    /// ## Returns
+    /// The number of files deleted
+    pub fn delete_segments_before(&self, bound: SegmentBound) -> usize {
+        let end = match bound {
+            SegmentBound::Segment(index) => index,
+            SegmentBound::Lsn(lsn) => match segments::segment_of(&self.inner.config, lsn) {
+                Some(index) => index,
+                None => return 0,
+            },
+        };
+        let deleted = self.inner.writer.delete_before(end);
+        if let SegmentBound::Lsn(lsn) = bound {
+            self.inner.writer.trim_before(end, lsn);
+        }
+        deleted
+    }
+
+    /// Delete the logs from the given [Lsn] onwards, e.g. the conflicting entries at the tail
+    /// of a replicated log
+    ///
+    /// The logs still in the buffer are deleted too. The files after the one holding the log
+    /// are deleted, and that file is cut short right before it. The logs written from now on
+    /// continue from the given [Lsn].
+    ///
+    /// ## Returns
+    /// The number of logs deleted, or an error if the log is before the first log kept, or the
+    /// [Lsn] of the logs is unknown, as they were written by an older version
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+    /// wal.write_iter(["a", "b", "c"].map(String::from));
+    /// assert_eq!(wal.delete_from(1).unwrap(), 2);
+    /// ```
+    pub fn delete_from(&self, lsn: Lsn) -> Result<u64, String> {
+        self.inner
+            .writer
+            .delete_from(lsn)
+            .map_err(|e| format!("Failed to delete logs: {}", e))
+    }
+
+    /// Replace the logs from the given [Lsn] onwards with new ones, as a single operation
+    ///
+    /// This is what a Raft follower does on receiving entries from the leader: the conflicting
+    /// logs at the tail, if any, are deleted as with [Wal::delete_from], and the new logs are
+    /// written straight to the file in their place, each one along with its optional term, see
+    /// [WriteOptions::term]. No other log can be written in between.
+    ///
+    /// ## Returns
+    /// The number of logs deleted, or an error, without changing anything, if a log can't be
+    /// serialized or framed, or the [Lsn] is past the next log, or can't be deleted from
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+    /// wal.write_iter(["a", "b", "c"].map(String::from));
+    /// let deleted = wal.append_at(1, [(Some(2), "d".to_string())]).unwrap();
+    /// assert_eq!(deleted, 2);
+    /// ```
+    pub fn append_at<I>(&self, lsn: Lsn, items: I) -> Result<u64, String>
+    where
+        I: IntoIterator<Item = (Option<u64>, T)>,
+    {
+        let codec = &self.inner.config.codec;
+        let max = self.inner.config.max_record_size();
+        let mut records = Vec::new();
+        for (i, (term, item)) in items.into_iter().enumerate() {
+            let header = RecordHeader::log(term);
+            let data = codec
+                .serialize(&item)
+                .map_err(|e| format!("Failed to serialize log {}: {}", i, e))?;
+            // the kind is counted in the largest size already
+            if data.is_empty() || data.len() + header.len() - 1 > max {
+                return Err(format!(
+                    "Log {} has an unsupported size of {} bytes",
+                    i,
+                    data.len()
+                ));
+            }
+            records.push((header, data));
+        }
+        let result = self.inner.writer.append_at(lsn, &records);
+        #[cfg(feature = "zeroize")]
+        for (_, data) in records.iter_mut() {
+            zeroize::Zeroize::zeroize(data);
+        }
+        result.map_err(|e| format!("Failed to append logs: {}", e))
+    }
+
+    /// Store small key-value pairs along with the logs, e.g. the term and the vote of a Raft
+    /// node, instead of keeping them in a file of their own
+    ///
+    /// The logs written so far, the buffered ones included, are synced to disk first, then all
+    /// the pairs given are updated at once: a crash leaves either all of them or none of them
+    /// updated. An empty value removes its key. Read them back with [Wal::state].
+    ///
+    /// ## Returns
+    /// An error, without storing anything, if a key is empty or has whitespace or `=` in it
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+    /// wal.set_state(&[("term", &7u64.to_le_bytes()), ("vote", b"node-2")])
+    ///     .unwrap();
+    /// assert_eq!(wal.state("vote"), Some(b"node-2".to_vec()));
+    /// ```
+    pub fn set_state(&self, values: &[(&str, &[u8])]) -> Result<(), String> {
+        if let Some((key, _)) = values.iter().find(|(key, _)| !state::is_valid_key(key)) {
+            return Err(format!("Invalid key {:?}", key));
+        }
+        self.inner
+            .writer
+            .set_state(values)
+            .map_err(|e| format!("Failed to store the state: {}", e))
+    }
+
+    /// The value of a key stored with [Wal::set_state]
+    pub fn state(&self, key: &str) -> Option<Vec<u8>> {
+        self.inner.writer.state().remove(key)
+    }
+
+    /// Write a snapshot of the application state, then delete the log files it covers
+    ///
+    /// The buffered logs are written out first, and no log can be added while the closure runs,
+    /// so the snapshot covers every log written so far. The closure must not write to this
+    /// [Wal], as it would block forever.
+    ///
+    /// The snapshot is stored in the location of the logs, and replaces the previous one
+    /// atomically. On restart, load it with [Wal::snapshot] and replay the logs from its [Lsn].
+    ///
+    /// ## Returns
+    /// The [Lsn] of the first log not covered by the snapshot
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<String> = Wal::new("/tmp/logz", None);
+    /// let state = vec!["a", "b"];
+    /// let lsn = wal
+    ///     .snapshot_with(|writer| writer.write_all(state.join("\n").as_bytes()))
+    ///     .unwrap();
+    /// ```
+    pub fn snapshot_with<F>(&self, write: F) -> Result<Lsn, String>
+    where
+        F: FnOnce(&mut dyn Write) -> std::io::Result<()>,
+    {
+        let config = &self.inner.config;
+        let lsn = self.inner.writer.flushed(|| {
+            let lsn = segments::next_lsn(config)
+                .ok_or("The LSN of the logs written by older versions is unknown")?;
+            snapshot::write(config, lsn, write)
+                .map_err(|e| format!("Failed to write snapshot: {}", e))?;
+            Ok::<_, String>(lsn)
+        })?;
+        self.delete_segments_before(SegmentBound::Lsn(lsn));
+        Ok(lsn)
+    }
+
+    /// The latest snapshot written with [Wal::snapshot_with], if any
+    ///
+    /// ## Returns
+    /// The [Lsn] of the first log not covered by the snapshot, and the snapshot data to read
+    pub fn snapshot(&self) -> Result<Option<(Lsn, File)>, String> {
+        snapshot::read(&self.inner.config).map_err(|e| format!("Failed to read snapshot: {}", e))
+    }
+
+    /// Statistics about the operations of this [Wal], such as the latency of writes and syncs
+    ///
+    /// A rising fsync latency is often the first sign of a degrading disk.
+    pub fn stats(&self) -> WalStats {
+        self.inner.config.stats.snapshot()
+    }
+
+    /// Activity of this [Wal] over its whole life, across restarts, unlike [Wal::stats]
+    pub fn lifetime_stats(&self) -> LifetimeStats {
+        self.inner.writer.lifetime_stats()
+    }
+
+    /// Bytes of logs not written to disk yet, and so lost in a crash, see
+    /// [WalBuilder::max_unflushed](crate::WalBuilder::max_unflushed)
+    pub fn unflushed_bytes(&self) -> usize {
+        self.inner.writer.unflushed()
+    }
+
+    /// Verify the integrity of all the filled log files
+    ///
+    /// Every log file is checksummed when it's filled and closed. This method compares the
+    /// content of the files against those checksums, without decoding any of the logs.
+    pub fn verify(&self) -> VerifyReport {
+        verify::verify(&self.inner.config)
+    }
+
+    /// Build the [MerkleTree] over the logs of a log file
+    ///
+    /// The root of the tree of a filled file matches [Segment::merkle_root], unless the file
+    /// changed since it was closed. A replica builds the tree over its copy with
+    /// [MerkleTree::from_file] and compares the two, to find the logs it has to fetch again.
+    ///
+    /// ## Returns
+    /// An error if the file doesn't exist or can't be read
+    pub fn merkle_tree(&self, segment: usize) -> Result<MerkleTree, String> {
+        let reader = open_segment(&self.inner.config, segment)
+            .ok_or_else(|| format!("Log file {} not found", segment))?;
+        MerkleTree::from_reader(reader).map_err(|e| format!("Failed to read log file: {}", e))
+    }
+
+    /// Train a zstd dictionary on the most recent logs, and compress the logs with it from now on
+    ///
+    /// Dictionaries give much better ratios for small and repetitive logs, which have too little
+    /// data of their own to compress well. The dictionary is stored in the log directory, and
+    /// the logs compressed with it go to a new file. The older dictionaries are kept, so that the
+    /// logs compressed with them stay readable, and the latest one is used again once reopened.
+    ///
+    /// ## Arguments
+    /// - `samples`: Number of the most recent logs to train on, a few thousand being typical
+    /// - `max_size`: Maximum size of the dictionary, in bytes, e.g. 16 KB
+    ///
+    /// ## Returns
+    /// The id of the dictionary, or an error if the logs aren't compressed, see
+    /// [WalBuilder::compress_records](crate::WalBuilder::compress_records), or too few to train on
+    #[cfg(feature = "compression")]
+    pub fn train_dictionary(&self, samples: usize, max_size: usize) -> Result<u32, String> {
+        if self.inner.config.compress_records.is_none() {
+            return Err("Compression of the logs isn't enabled".to_string());
+        }
+        let end = segments::next_lsn(&self.inner.config).unwrap_or(0);
+        let wal = Wal {
+            inner: self.inner.clone(),
+        };
+        let samples = WalIterator::new(wal, ReadOptions::default())
+            .start_from(end.saturating_sub(samples as u64))
+            .raw()
+            .map(|(_, record)| record)
+            .collect::<Vec<_>>();
+        let dictionary = compress::train(&samples, max_size)
+            .map_err(|e| format!("Failed to train the dictionary: {}", e))?;
+        self.inner
+            .writer
+            .use_dictionary(dictionary)
+            .map_err(|e| format!("Failed to store the dictionary: {}", e))
     }
 
     /// Delete all the stored logs... Use Carefully!
@@ -162,6 +1029,10 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::writer::chain::LINK_SIZE;
+    use crate::writer::header::HEADER_SIZE;
+    use crate::writer::manifest::Manifest;
+    use crate::{Checksum, FormatPolicy, RecordKind};
 
     #[derive(Serialize, Deserialize, Clone)]
     struct Log {
@@ -230,7 +1101,7 @@ mod tests {
         drop(wal);
         // read data
         let wal = Wal::new(LOCATION, Some(500));
-        let data = wal.read().unwrap().into_iter().collect::<Vec<Log>>();
+        let data = wal.read().unwrap().collect::<Vec<Log>>();
         assert_eq!(data.len(), 20);
         // write more data
         for i in 20..25 {
@@ -243,9 +1114,2062 @@ mod tests {
         drop(wal);
         // read to ensure everything new is also there
         let wal = Wal::new(LOCATION, Some(500));
-        let data = wal.read().unwrap().into_iter().collect::<Vec<Log>>();
+        let data = wal.read().unwrap().collect::<Vec<Log>>();
         assert_eq!(data.len(), 25);
         assert_eq!(data.first().unwrap().id, 1);
         assert_eq!(data.last().unwrap().id, 25);
     }
+
+    #[test]
+    fn write_while_reading() {
+        let location = "./tmp/write_while_reading";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        for id in 0..10 {
+            wal.write(Log {
+                id,
+                name: "before".to_string(),
+            });
+        }
+        wal.flush();
+        let mut logs = wal.read().unwrap();
+        assert_eq!(logs.next().unwrap().id, 0);
+        // writing with an iterator around is fine, and so is a second reader
+        wal.write(Log {
+            id: 10,
+            name: "during".to_string(),
+        });
+        assert!(wal.read().is_ok());
+        assert_eq!(logs.count(), 9);
+        wal.flush();
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..=10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn read_unexpired() {
+        #[derive(Serialize, Deserialize)]
+        struct Token {
+            id: usize,
+            expires: Option<u64>,
+        }
+
+        impl Expiry for Token {
+            fn expires_at(&self) -> Option<SystemTime> {
+                self.expires
+                    .map(|secs| UNIX_EPOCH + std::time::Duration::from_secs(secs))
+            }
+        }
+
+        let location = "./tmp/read_unexpired";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let wal = Wal::new(location, None);
+        wal.write(Token {
+            id: 1,
+            expires: Some(now - 60),
+        });
+        wal.write(Token {
+            id: 2,
+            expires: Some(now + 3600),
+        });
+        wal.write(Token {
+            id: 3,
+            expires: None,
+        });
+        wal.flush();
+        let ids = wal
+            .read_unexpired()
+            .unwrap()
+            .map(|t| t.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 3]);
+        // expired logs are still there for a plain read
+        assert_eq!(wal.read().unwrap().count(), 3);
+    }
+
+    #[test]
+    fn open_existing_and_create_new() {
+        let location = "./tmp/open_existing";
+        let _ = std::fs::remove_dir_all(location);
+        // nothing to open yet
+        assert!(Wal::<Log>::open_existing(location, None).is_err());
+        assert!(!PathBuf::from(location).exists());
+        // create it once
+        let wal = Wal::<Log>::create_new(location, None);
+        assert!(wal.is_ok());
+        drop(wal);
+        assert!(Wal::<Log>::create_new(location, None).is_err());
+        // now it can be opened
+        assert!(Wal::<Log>::open_existing(location, None).is_ok());
+    }
+
+    #[test]
+    fn temp() {
+        let wal = Wal::temp().unwrap();
+        let location = wal.inner.config.location.clone();
+        assert!(location.exists());
+        wal.write(Log {
+            id: 1,
+            name: "scratch".to_string(),
+        });
+        wal.flush();
+        assert!(location.join("log_0.bin").metadata().unwrap().len() > 0);
+        // the directory outlives clones, but not the last handle
+        let clone = wal.clone();
+        drop(wal);
+        assert!(location.exists());
+        drop(clone);
+        assert!(!location.exists());
+    }
+
+    #[test]
+    fn verify() {
+        let location = "./tmp/verify";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, Some(1));
+        wal.write_iter((0..30_000).map(|id| Log {
+            id,
+            name: "Jane Doe".repeat(4),
+        }));
+        wal.flush();
+        let report = wal.verify();
+        assert!(report.checked > 0);
+        assert!(report.is_ok());
+        // flip a byte in one of the filled files
+        let index = *Manifest::new(location.into()).read().keys().next().unwrap();
+        let path = format!("{}/log_{}.bin", location, index);
+        let mut data = std::fs::read(&path).unwrap();
+        data[10] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+        let report = wal.verify();
+        assert_eq!(report.corrupted, vec![index]);
+    }
+
+    #[test]
+    fn merkle_roots() {
+        let location = "./tmp/merkle_roots";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, Some(1));
+        for id in 0..10_000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        let segments = wal.list_segments();
+        let sealed = &segments[0];
+        assert!(sealed.sealed);
+        assert!(segments.last().unwrap().merkle_root.is_none());
+        let tree = wal.merkle_tree(sealed.index).unwrap();
+        assert_eq!(tree.root(), sealed.merkle_root);
+        assert!(tree.root().is_some());
+        // a replica with a damaged copy of the file
+        let mut data = std::fs::read(&sealed.path).unwrap();
+        data[HEADER_SIZE + 2 + 1] ^= 0xff;
+        let copy = format!("{}/replica.bin", location);
+        std::fs::write(&copy, data).unwrap();
+        let replica = MerkleTree::from_file(&copy).unwrap();
+        assert_ne!(replica.root(), sealed.merkle_root);
+        assert_eq!(replica.diff(&tree), vec![0..1]);
+    }
+
+    #[test]
+    fn hash_chain() {
+        let location = "./tmp/hash_chain";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let open = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .storage_size(Size::Mb(1))
+                .hash_chain()
+                .build()
+                .unwrap()
+        };
+        let wal = open();
+        for id in 0..30_000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        drop(wal);
+        // the chain carries on across a restart
+        let wal = open();
+        wal.write(Log {
+            id: 30_000,
+            name: "Jane Doe".to_string(),
+        });
+        wal.flush();
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids.last(), Some(&30_000));
+        assert!(ids.windows(2).all(|w| w[1] == w[0] + 1));
+        let report = wal.verify();
+        assert!(report.checked > 1);
+        assert!(report.is_ok(), "{:?}", report);
+        // modify the first log of the current file
+        let (gc, current) = Meta::new(location.into()).read().unwrap();
+        let path = format!("{}/log_{}.bin", location, current);
+        let original = std::fs::read(&path).unwrap();
+        let mut data = original.clone();
+        data[HEADER_SIZE + 2 + LINK_SIZE] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+        assert_eq!(wal.verify().broken_chain, vec![current]);
+        std::fs::write(&path, original).unwrap();
+        // delete a file in the middle, along with any record of it
+        let middle = gc + 1;
+        assert!(middle < current);
+        std::fs::remove_file(format!("{}/log_{}.bin", location, middle)).unwrap();
+        Manifest::new(location.into()).remove(&[middle]).unwrap();
+        assert_eq!(wal.verify().broken_chain, vec![middle + 1]);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_records() {
+        let location = "./tmp/compressed_records";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = crate::WalBuilder::<Log>::new()
+            .location(location)
+            .compress_records(512)
+            .hash_chain()
+            .checksum(Checksum::Crc32c)
+            .build()
+            .unwrap();
+        let logs = [10, 5000, 20].map(|len| Log {
+            id: len,
+            name: "x".repeat(len),
+        });
+        wal.write_iter(logs.clone());
+        wal.flush();
+        let path = format!("{}/log_0.bin", location);
+        // the large log takes up much less than its size
+        let size = std::fs::metadata(&path).unwrap().len();
+        assert!(size < 1000);
+        let data = wal.read().unwrap().collect::<Vec<_>>();
+        assert_eq!(data.len(), 3);
+        assert!(data.iter().zip(&logs).all(|(a, b)| a.name == b.name));
+        let lens = wal.read_raw().unwrap().map(|(m, _)| m.len);
+        assert_eq!(lens.collect::<Vec<_>>(), [26, 5016, 36]);
+        assert!(wal.verify().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compression_dictionary() {
+        let location = "./tmp/compression_dictionary";
+        let _ = std::fs::remove_dir_all(location);
+        let open = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .compress_records(32)
+                .build()
+                .unwrap()
+        };
+        let log = |id| Log {
+            id,
+            name: format!(
+                "{{\"user\": \"user-{}\", \"action\": \"login\", \"ok\": true}}",
+                id
+            ),
+        };
+        let wal = open();
+        assert!(wal.train_dictionary(1000, 4096).is_err());
+        wal.write_iter((0..1000).map(log));
+        wal.flush();
+        let id = wal.train_dictionary(1000, 4096).unwrap();
+        assert_eq!(id, 1);
+        wal.write_iter((1000..2000).map(log));
+        wal.flush();
+        // the logs of the new file are compressed with the dictionary
+        let segments = wal.list_segments();
+        assert_eq!(segments.len(), 2);
+        let before = segments[0].size;
+        let after = std::fs::metadata(format!("{}/log_1.bin", location))
+            .unwrap()
+            .len();
+        assert!(after * 2 < before);
+        drop(wal);
+        // the dictionary is used again once reopened, without starting a new file
+        let wal = open();
+        wal.write(log(2000));
+        wal.flush();
+        assert_eq!(wal.list_segments().len(), 2);
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..2001).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn record_checksums() {
+        let location = "./tmp/record_checksums";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let open = |checksum| {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .checksum(checksum)
+                .build()
+                .unwrap()
+        };
+        let log = |id| Log {
+            id,
+            name: "Jane Doe".to_string(),
+        };
+        let wal = open(Checksum::Crc32c);
+        (0..3).for_each(|id| wal.write(log(id)));
+        wal.flush();
+        let metas = wal.read_raw().unwrap().map(|(m, _)| m).collect::<Vec<_>>();
+        assert!(metas.iter().all(|m| m.crc_ok == Some(true)));
+        // damage the second log
+        let path = format!("{}/log_0.bin", location);
+        let mut data = std::fs::read(&path).unwrap();
+        let offset = metas[1].offset as usize + 2 + Checksum::Crc32c.size();
+        data[offset + 1] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+        let crc_ok = wal.read_raw().unwrap().map(|(m, _)| m.crc_ok);
+        assert_eq!(
+            crc_ok.collect::<Vec<_>>(),
+            [Some(true), Some(false), Some(true)]
+        );
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 2]);
+        drop(wal);
+        // another algorithm starts a new file, and both of them are read
+        let wal = open(Checksum::XxHash64);
+        wal.write(log(3));
+        wal.flush();
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 2, 3]);
+        assert_eq!(wal.list_segments().len(), 2);
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn signed_segments() {
+        let location = "./tmp/signed_segments";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let secret = [5; 32];
+        let wal = crate::WalBuilder::<Log>::new()
+            .location(location)
+            .storage_size(Size::Mb(1))
+            .signing_key(secret)
+            .build()
+            .unwrap();
+        for id in 0..10_000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        let public = ed25519_dalek::SigningKey::from_bytes(&secret)
+            .verifying_key()
+            .to_bytes();
+        let report = crate::verify_signed(location, &public).unwrap();
+        assert!(report.checked > 0);
+        assert!(report.is_ok(), "{:?}", report);
+        // another key
+        let report = crate::verify_signed(location, &[7; 32]);
+        assert!(report.is_err() || !report.unwrap().is_ok());
+        // a file modified along with its checksum
+        let mut info = Manifest::new(location.into()).read().pop_first().unwrap().1;
+        let path = format!("{}/log_{}.bin", location, info.index);
+        let mut data = std::fs::read(&path).unwrap();
+        data[HEADER_SIZE + 4] ^= 0xff;
+        info.checksum = crc32fast::hash(&data);
+        std::fs::write(&path, data).unwrap();
+        Manifest::new(location.into()).update(info.clone()).unwrap();
+        let report = crate::verify_signed(location, &public).unwrap();
+        assert!(report.corrupted.is_empty());
+        assert_eq!(report.bad_signature, vec![info.index]);
+    }
+
+    #[test]
+    fn missing_files() {
+        let location = "./tmp/missing_files";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, Some(1));
+        for id in 0..30_000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        drop(wal);
+        let (gc, current) = Meta::new(location.into()).read().unwrap();
+        assert!(current > gc + 1);
+        std::fs::remove_file(format!("{}/log_{}.bin", location, gc)).unwrap();
+        let wal: Wal<Log> = Wal::new(location, Some(1));
+        let report = wal.recovery_report();
+        assert!(report.repaired);
+        assert_eq!(report.found, Some((gc, current)));
+        assert_eq!(report.pointers, (gc + 1, current));
+        assert!(report.gaps.is_empty());
+        assert_eq!(Meta::new(location.into()).read(), Some((gc + 1, current)));
+        // the remaining logs are still readable
+        assert!(wal.read().unwrap().count() > 0);
+    }
+
+    #[test]
+    fn list_segments() {
+        let location = "./tmp/list_segments";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, Some(1));
+        for id in 0..10_000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        let segments = wal.list_segments();
+        assert!(segments.len() > 1);
+        let (live, sealed) = segments.split_last().unwrap();
+        assert!(!live.sealed);
+        assert!(sealed.iter().all(|s| s.sealed && s.size > 0));
+        // the lsn ranges are contiguous, up to the last log
+        for pair in segments.windows(2) {
+            let (a, b) = (pair[0].lsns.clone(), pair[1].lsns.clone());
+            assert_eq!(a.unwrap().end, b.unwrap().start);
+        }
+        assert_eq!(live.lsns.clone().unwrap().end, 10_000);
+        assert_eq!(
+            live.path,
+            PathBuf::from(location).join(format!("log_{}.bin", live.index))
+        );
+    }
+
+    #[test]
+    fn segment_io() {
+        let location = "./tmp/segment_io";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        wal.reload(Some(Size::Kb(64)), false);
+        for id in 0..2000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush().wait().unwrap();
+        let segments = wal.list_segments();
+        assert!(segments.len() > 1);
+        for segment in &segments {
+            let io = segment.io.clone().unwrap();
+            assert_eq!(io.bytes_written + HEADER_SIZE as u64, segment.size);
+            assert!(io.flush.count > 0);
+        }
+        assert_eq!(segments.last().unwrap().io.clone().unwrap().fsync.count, 1);
+        // the files written before a restart have no statistics
+        drop(wal);
+        let wal: Wal<Log> = Wal::new(location, None);
+        assert!(wal.list_segments().iter().all(|s| s.io.is_none()));
+    }
+
+    #[test]
+    fn delete_segments_before() {
+        let location = "./tmp/delete_segments_before";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        wal.reload(Some(Size::Kb(64)), false);
+        for id in 0..2000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        let segments = wal.list_segments();
+        // the log 1000 is kept along with the rest of its file
+        let lsns = segments
+            .iter()
+            .find(|s| s.lsns.as_ref().unwrap().contains(&1000));
+        let first = lsns.unwrap().index;
+        let count = first - segments[0].index;
+        assert_eq!(wal.delete_segments_before(SegmentBound::Lsn(1000)), count);
+        let logs = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert!(logs[0] <= 1000);
+        assert_eq!(logs.last(), Some(&1999));
+        assert_eq!(wal.delete_segments_before(SegmentBound::Segment(first)), 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn punch_hole() {
+        use std::os::unix::fs::MetadataExt;
+
+        let location = "./tmp/punch_hole";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, Some(4));
+        for id in 0..60_000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        let segments = wal.list_segments();
+        let second = segments[1].clone();
+        let lsns = second.lsns.clone().unwrap();
+        let lsn = (lsns.start + lsns.end) / 2;
+        let blocks = std::fs::metadata(&second.path).unwrap().blocks();
+        assert_eq!(wal.delete_segments_before(SegmentBound::Lsn(lsn)), 1);
+        // the size stays the same, while the blocks before the lsn are released
+        let metadata = std::fs::metadata(&second.path).unwrap();
+        assert_eq!(metadata.len(), second.size);
+        assert!(metadata.blocks() < blocks * 3 / 4);
+        assert_eq!(wal.list_segments()[0].lsns.clone().unwrap().start, lsn);
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids.len() as u64, 60_000 - lsn);
+        assert_eq!(ids[0] as u64, lsn);
+        assert_eq!(wal.count(), 60_000 - lsn);
+        assert!(wal.verify().is_ok());
+        // punching again before the same lsn is a no-op
+        assert_eq!(wal.delete_segments_before(SegmentBound::Lsn(lsn)), 0);
+        assert_eq!(wal.read().unwrap().count() as u64, 60_000 - lsn);
+    }
+
+    #[test]
+    fn snapshot_with() {
+        let location = "./tmp/snapshot_with";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        assert!(wal.snapshot().unwrap().is_none());
+        wal.reload(Some(Size::Kb(64)), false);
+        for id in 0..2000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        // the buffered logs are covered too
+        let lsn = wal
+            .snapshot_with(|writer| writer.write_all(b"state"))
+            .unwrap();
+        assert_eq!(lsn, 2000);
+        assert_eq!(wal.list_segments().len(), 1);
+        assert!(wal.verify().is_ok());
+        for id in 2000..2003 {
+            wal.write(Log {
+                id,
+                name: String::new(),
+            });
+        }
+        wal.flush();
+        // recover from the snapshot, then the logs after it
+        let (lsn, mut file) = wal.snapshot().unwrap().unwrap();
+        let mut state = String::new();
+        std::io::Read::read_to_string(&mut file, &mut state).unwrap();
+        assert_eq!(state, "state");
+        let logs = wal.read_from(lsn).unwrap().map(|(_, l)| l.id);
+        assert_eq!(logs.collect::<Vec<_>>(), [2000, 2001, 2002]);
+        // a failed snapshot keeps the previous one and the logs
+        let result = wal.snapshot_with(|_| Err(std::io::Error::other("failed")));
+        assert!(result.is_err());
+        assert_eq!(wal.snapshot().unwrap().unwrap().0, 2000);
+        assert_eq!(wal.read_from(2000).unwrap().count(), 3);
+        // the logs left in the live file before the snapshot are skipped
+        assert_eq!(wal.read().unwrap().count(), 3);
+    }
+
+    #[test]
+    fn trim_live_file() {
+        let location = "./tmp/trim_live_file";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        for id in 0..10 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".to_string(),
+            });
+        }
+        wal.flush();
+        assert_eq!(wal.delete_segments_before(SegmentBound::Lsn(4)), 0);
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, (4..10).collect::<Vec<_>>());
+        assert_eq!(wal.count(), 6);
+        assert_eq!(wal.list_segments()[0].lsns, Some(4..10));
+        // a trim never moves backwards
+        wal.delete_segments_before(SegmentBound::Lsn(2));
+        assert_eq!(wal.count(), 6);
+        // the trim survives a restart
+        drop(wal);
+        let wal: Wal<Log> = Wal::new(location, None);
+        let logs = wal.read_from(0).unwrap().map(|(lsn, _)| lsn);
+        assert_eq!(logs.collect::<Vec<_>>(), (4..10).collect::<Vec<_>>());
+        // and moves to the manifest once the file is filled
+        wal.reload(Some(Size::Kb(256)), false);
+        for id in 10..2000 {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".repeat(4),
+            });
+        }
+        wal.flush();
+        assert!(wal.list_segments().len() > 1);
+        assert_eq!(wal.read().unwrap().next().unwrap().id, 4);
+        assert_eq!(wal.count(), 1996);
+        assert!(wal.verify().is_ok());
+    }
+
+    #[test]
+    fn power_loss_safe() {
+        use crate::writer::page::PAGE_SIZE;
+
+        let location = "./tmp/power_loss_safe";
+        let _ = std::fs::remove_dir_all(location);
+        let build = |safe: bool| {
+            let builder = crate::WalBuilder::<Log>::new()
+                .location(location)
+                .storage_size(Size::Mb(4))
+                .disable_buffer();
+            match safe {
+                true => builder.power_loss_safe().build().unwrap(),
+                false => builder.build().unwrap(),
+            }
+        };
+        let log = |id| Log {
+            id,
+            name: "Jane Doe".repeat(id % 5),
+        };
+        let wal = build(true);
+        for id in 0..500 {
+            wal.write(log(id));
+        }
+        wal.write_iter((500..510).map(log));
+        // every write takes up whole pages, and the pointers are kept in two slots
+        let segments = wal.list_segments();
+        assert!(segments.len() > 1);
+        assert!(segments.iter().all(|s| s.size % PAGE_SIZE as u64 == 0));
+        assert!(!std::path::Path::new(location).join("meta").exists());
+        assert!(std::path::Path::new(location).join("meta.1").exists());
+        assert_eq!(
+            wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>(),
+            (0..510).collect::<Vec<_>>()
+        );
+        assert_eq!(wal.count(), 510);
+        assert!(wal.verify().is_ok());
+        // the last write is torn in the middle of its last page
+        let path = segments.last().unwrap().path.clone();
+        let size = std::fs::metadata(&path).unwrap().len();
+        drop(wal);
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(size - 100).unwrap();
+        drop(file);
+        let wal = build(true);
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..500).collect::<Vec<_>>());
+        assert_eq!(wal.recovery_report().truncated, PAGE_SIZE as u64 - 100);
+        wal.write(log(510));
+        drop(wal);
+        // the file in the other layout is left as it is
+        let wal = build(false);
+        wal.write(log(511));
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 502);
+        assert_eq!(ids[500..], [510, 511]);
+        assert_eq!(wal.count(), 502);
+    }
+
+    #[test]
+    fn stats() {
+        let location = "./tmp/stats";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        assert_eq!(wal.stats().flush.count, 0);
+        wal.write_iter((0..500).map(|id| Log {
+            id,
+            name: "Jane Doe".to_string(),
+        }));
+        wal.flush().wait().unwrap();
+        let stats = wal.stats();
+        assert!(stats.flush.count > 0);
+        assert_eq!(stats.fsync.count, 1);
+        assert!(stats.flush.p50 <= stats.flush.max);
+        // 24 bytes per log, after a 3 bytes frame
+        assert_eq!(stats.bytes_written, 500 * 27);
+    }
+
+    #[test]
+    fn replay() {
+        #[derive(Default)]
+        struct Progress(std::sync::Mutex<Vec<(u64, u64)>>);
+
+        impl crate::WalListener for Progress {
+            fn on_replay_progress(&self, applied: u64, total: u64) {
+                self.0.lock().unwrap().push((applied, total));
+            }
+        }
+
+        let location = "./tmp/replay";
+        let _ = std::fs::remove_dir_all(location);
+        let progress = Arc::new(Progress::default());
+        let wal = crate::WalBuilder::new()
+            .location(location)
+            .listener(progress.clone())
+            .build()
+            .unwrap();
+        wal.write_iter((0..25_000).map(|id| Log {
+            id,
+            name: String::new(),
+        }));
+        wal.flush();
+        let sum = wal.replay(0, |sum, log| sum + log.id).unwrap();
+        assert_eq!(sum, (0..25_000).sum::<usize>());
+        let progress = progress.0.lock().unwrap();
+        assert_eq!(
+            *progress,
+            [(10_000, 25_000), (20_000, 25_000), (25_000, 25_000)]
+        );
+    }
+
+    #[test]
+    fn small_and_large_logs() {
+        let location = "./tmp/small_and_large_logs";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        // serialized sizes on either side of the stack buffer
+        let sizes = [0, 200, SMALL_LOG_SIZE - 17, SMALL_LOG_SIZE - 15, 1000];
+        for (id, size) in sizes.iter().enumerate() {
+            wal.write(Log {
+                id,
+                name: "x".repeat(*size),
+            });
+        }
+        wal.flush();
+        let logs = wal.read().unwrap().collect::<Vec<_>>();
+        assert_eq!(logs.len(), sizes.len());
+        for (log, size) in logs.iter().zip(sizes) {
+            assert_eq!(log.name.len(), size);
+        }
+    }
+
+    #[test]
+    fn oversized_log() {
+        let location = "./tmp/oversized_log";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        let log = |id, size| Log {
+            id,
+            name: "x".repeat(size),
+        };
+        wal.write(log(0, 10));
+        // too large to be framed, dropped and reported
+        wal.write(log(1, 70_000));
+        assert!(wal.last_error().unwrap().message.contains("log dropped"));
+        // the largest log left no room for a term, the id and the length taking 16 bytes
+        let largest = wal.inner.config.max_record_size() - 16;
+        let options = WriteOptions {
+            term: Some(3),
+            ..Default::default()
+        };
+        wal.write_with(log(2, largest), options);
+        assert_eq!(wal.recent_errors().len(), 2);
+        wal.write(log(3, largest));
+        wal.write(log(4, 10));
+        wal.flush();
+        assert_eq!(wal.recent_errors().len(), 2);
+        let logs = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(logs, [0, 3, 4]);
+    }
+
+    #[test]
+    fn write_iter() {
+        let location = "./tmp/write_iter";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        wal.write_iter((0..500).map(|id| Log {
+            id,
+            name: format!("log {}", id),
+        }));
+        wal.flush();
+        drop(wal);
+        let wal: Wal<Log> = Wal::new(location, None);
+        let data = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(data, (0..500).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn write_iter_dropped() {
+        let location = "./tmp/write_iter_dropped";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = crate::WalBuilder::new()
+            .location(location)
+            .max_record_size(100)
+            .build()
+            .unwrap();
+        // every other log is over the limit, failing to serialize
+        wal.write_iter((0..6).map(|id| Log {
+            id,
+            name: "x".repeat(id % 2 * 200),
+        }));
+        wal.flush();
+        let errors = wal.recent_errors();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].message.contains("log dropped"));
+        let logs = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(logs, [0, 2, 4]);
+    }
+
+    #[test]
+    fn bincode_options() {
+        let location = "./tmp/bincode_options";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = crate::WalBuilder::new()
+            .location(location)
+            .int_encoding(crate::IntEncoding::Varint)
+            .max_record_size(100)
+            .build()
+            .unwrap();
+        wal.write(Log {
+            id: 1,
+            name: "small".to_string(),
+        });
+        // over the limit, refused
+        wal.write(Log {
+            id: 2,
+            name: "x".repeat(200),
+        });
+        wal.flush();
+        let logs = wal.read().unwrap().collect::<Vec<_>>();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].name, "small");
+        // varint packs the id and the length of the name in a byte each
+        let size = wal.list_segments().iter().map(|s| s.size).sum::<u64>();
+        assert_eq!(size as usize, HEADER_SIZE + 3 + 7);
+    }
+
+    #[test]
+    fn append_raw_batch() {
+        let location = "./tmp/append_raw_batch";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        let log = |id| Log {
+            id,
+            name: "Jane Doe".to_string(),
+        };
+        wal.write(log(0));
+        let batch = (1..4)
+            .map(|id| bincode::serialize(&log(id)).unwrap())
+            .collect::<Vec<_>>();
+        wal.append_raw_batch(&batch).unwrap();
+        // the buffered log went to disk ahead of the batch
+        assert_eq!(wal.count(), 4);
+        wal.write(log(4));
+        wal.flush();
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1, 2, 3, 4]);
+        // nothing is written if any of the logs can't be framed
+        let result = wal.append_raw_batch(&[&b"log"[..], &[]]);
+        assert!(result.is_err());
+        assert!(wal.append_raw_batch(&[vec![1; 70_000]]).is_err());
+        assert_eq!(wal.count(), 5);
+    }
+
+    #[test]
+    fn legacy_files() {
+        let location = "./tmp/legacy_files";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        // a file written by an older version, without a header and in the native byte order
+        let log = bincode::serialize(&Log {
+            id: 1,
+            name: "old".to_string(),
+        })
+        .unwrap();
+        let mut data = (log.len() as u16).to_ne_bytes().to_vec();
+        data.extend(log);
+        std::fs::write(format!("{}/log_0.bin", location), data).unwrap();
+        std::fs::write(format!("{}/meta", location), "0 0").unwrap();
+        let wal = Wal::new(location, None);
+        wal.write(Log {
+            id: 2,
+            name: "new".to_string(),
+        });
+        wal.flush();
+        // new logs went to a new file with a header
+        let segments = wal.list_segments();
+        assert_eq!(segments.len(), 2);
+        let new = std::fs::read(&segments[1].path).unwrap();
+        assert_eq!(new[..4], *b"WALC");
+        let logs = wal.read().unwrap().map(|log| log.name).collect::<Vec<_>>();
+        assert_eq!(logs, ["old", "new"]);
+    }
+
+    #[test]
+    fn priority_write() {
+        let location = "./tmp/priority_write";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        wal.write(Log {
+            id: 1,
+            name: "buffered".to_string(),
+        });
+        wal.write_priority(
+            Log {
+                id: 2,
+                name: "checkpoint".to_string(),
+            },
+            true,
+        );
+        // both logs are on disk without an explicit flush, in the order of writing
+        drop(wal);
+        let wal: Wal<Log> = Wal::new(location, None);
+        let data = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(data, vec![1, 2]);
+    }
+
+    #[test]
+    fn write_with_fsync() {
+        let location = "./tmp/write_with_fsync";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        let options = WriteOptions {
+            fsync: true,
+            ..Default::default()
+        };
+        wal.write_with(
+            Log {
+                id: 7,
+                name: "durable".to_string(),
+            },
+            options,
+        );
+        drop(wal);
+        let wal: Wal<Log> = Wal::new(location, None);
+        let data = wal.read().unwrap().collect::<Vec<_>>();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].id, 7);
+    }
+
+    #[test]
+    fn lazy_init() {
+        let location = "./tmp/lazy_init";
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<Log> = crate::WalBuilder::new()
+            .location(location)
+            .lazy_init()
+            .build()
+            .unwrap();
+        // reading and flushing leave nothing behind
+        assert_eq!(wal.read().unwrap().count(), 0);
+        assert_eq!(wal.count(), 0);
+        wal.flush();
+        assert!(std::fs::metadata(location).is_err());
+        // the files are created along with the first log on disk
+        wal.write(Log {
+            id: 1,
+            name: "first".to_string(),
+        });
+        assert!(std::fs::metadata(location).is_err());
+        wal.flush();
+        assert!(Meta::new(PathBuf::from(location)).exists());
+        assert_eq!(wal.read().unwrap().count(), 1);
+        drop(wal);
+        // or with an explicit init
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<Log> = crate::WalBuilder::new()
+            .location(location)
+            .lazy_init()
+            .build()
+            .unwrap();
+        wal.init().unwrap();
+        assert!(std::fs::metadata(format!("{}/log_0.bin", location)).is_ok());
+        wal.init().unwrap();
+    }
+
+    #[test]
+    fn buffer_overflow() {
+        use crate::{BufferOverflow, Size, WalReader};
+        let log = |id| Log {
+            id,
+            name: "x".repeat(1500),
+        };
+        // logs on disk, read without flushing the buffer
+        let on_disk = |location| {
+            WalReader::<Log>::open(location)
+                .unwrap()
+                .read()
+                .unwrap()
+                .count()
+        };
+        for (policy, written) in [
+            (BufferOverflow::Grow, 3),
+            (BufferOverflow::FlushFirst, 2),
+            (BufferOverflow::WriteThrough, 3),
+            (BufferOverflow::Reject, 0),
+        ] {
+            let location = "./tmp/buffer_overflow";
+            let _ = std::fs::remove_dir_all(location);
+            let wal: Wal<Log> = crate::WalBuilder::new()
+                .location(location)
+                .buffer_size(Size::Kb(4))
+                .buffer_overflow(policy)
+                .build()
+                .unwrap();
+            assert!(wal.try_write(log(0)).is_ok());
+            assert!(wal.try_write(log(1)).is_ok());
+            assert_eq!(on_disk(location), 0);
+            let third = wal.try_write(log(2));
+            assert_eq!(
+                third.is_err(),
+                policy == BufferOverflow::Reject,
+                "{:?}",
+                policy
+            );
+            assert_eq!(on_disk(location), written, "{:?}", policy);
+            // the buffered logs are kept in order
+            wal.flush();
+            let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+            if policy == BufferOverflow::Reject {
+                assert_eq!(ids, [0, 1]);
+                // the refused log fits once the buffer is flushed
+                assert!(wal.try_write(third.unwrap_err()).is_ok());
+            } else {
+                assert_eq!(ids, [0, 1, 2], "{:?}", policy);
+            }
+        }
+        // a policy needs a buffer
+        let err = crate::WalBuilder::<Log>::new()
+            .location("./tmp/buffer_overflow")
+            .disable_buffer()
+            .buffer_overflow(BufferOverflow::Reject)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.field, "buffer_overflow");
+    }
+
+    #[test]
+    fn frame_low_byte_zero() {
+        let location = "./tmp/frame_low_byte_zero";
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<Log> = crate::WalBuilder::new().location(location).build().unwrap();
+        // some of the logs have a size whose low byte is zero, e.g. 256 or 512 bytes
+        let logs = (0..600).map(|id| Log {
+            id,
+            name: "x".repeat(id),
+        });
+        wal.write_iter(logs);
+        wal.flush();
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..600).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn skipped_regions() {
+        use crate::{SkippedRegion, WalListener};
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Skipped(Mutex<Vec<SkippedRegion>>);
+
+        impl WalListener for Skipped {
+            fn on_skipped_region(&self, region: SkippedRegion) {
+                self.0.lock().unwrap().push(region);
+            }
+        }
+
+        let location = "./tmp/skipped_regions";
+        let _ = std::fs::remove_dir_all(location);
+        let listener = Arc::new(Skipped::default());
+        let wal: Wal<Log> = crate::WalBuilder::new()
+            .location(location)
+            .checksum(Checksum::Crc32c)
+            .listener(listener.clone())
+            .build()
+            .unwrap();
+        (0..4).for_each(|id| {
+            wal.write(Log {
+                id,
+                name: "Jane Doe".to_string(),
+            })
+        });
+        wal.flush();
+        let metas = wal.read_raw().unwrap().map(|(m, _)| m).collect::<Vec<_>>();
+        // damage the second log, and the frame of the third one
+        let path = format!("{}/log_0.bin", location);
+        let mut data = std::fs::read(&path).unwrap();
+        let size = data.len() as u64;
+        data[metas[1].offset as usize + 2 + Checksum::Crc32c.size() + 1] ^= 0xff;
+        data[metas[2].offset as usize..][..2].copy_from_slice(&[0xff, 0xff]);
+        std::fs::write(&path, data).unwrap();
+        let ids = wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids, [0]);
+        let skipped = listener.0.lock().unwrap().clone();
+        assert_eq!(
+            skipped,
+            [
+                SkippedRegion {
+                    segment: 0,
+                    offset: metas[1].offset,
+                    bytes: metas[2].offset - metas[1].offset,
+                    records: 1,
+                },
+                SkippedRegion {
+                    segment: 0,
+                    offset: metas[2].offset,
+                    bytes: size - metas[2].offset,
+                    records: 0,
+                }
+            ]
+        );
+        let stats = wal.stats();
+        assert_eq!(stats.skipped_records, 1);
+        assert_eq!(stats.skipped_bytes, size - metas[1].offset);
+        let report = wal.recovery_report();
+        assert_eq!(report.skipped_records, 1);
+        assert_eq!(report.skipped_bytes, size - metas[1].offset);
+    }
+
+    #[test]
+    fn read_progress() {
+        let location = "./tmp/read_progress";
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<Log> = crate::WalBuilder::new().location(location).build().unwrap();
+        let log = |id| Log {
+            id,
+            name: "Jane Doe".to_string(),
+        };
+        (0..100).for_each(|id| wal.write(log(id)));
+        wal.flush();
+        let size = std::fs::metadata(format!("{}/log_0.bin", location))
+            .unwrap()
+            .len();
+        let mut iter = wal.read().unwrap();
+        let progress = iter.progress();
+        assert_eq!((progress.bytes_read, progress.total_bytes), (0, size));
+        assert_eq!(progress.segment, None);
+        iter.next().unwrap();
+        let progress = iter.progress();
+        assert_eq!(progress.segment, Some(0));
+        assert!(progress.bytes_read > 0 && progress.bytes_read < size);
+        assert_eq!(iter.by_ref().count(), 99);
+        let progress = iter.progress();
+        assert_eq!((progress.bytes_read, progress.segment), (size, None));
+        assert_eq!(progress.fraction(), 1.0);
+        // the buffered logs are counted after the files
+        wal.write(log(100));
+        let options = ReadOptions {
+            include_buffered: true,
+            ..Default::default()
+        };
+        let mut iter = wal.read_with(options).unwrap();
+        assert!(iter.progress().total_bytes > size);
+        assert_eq!(iter.by_ref().count(), 101);
+        assert_eq!(iter.progress().fraction(), 1.0);
+    }
+
+    #[test]
+    fn write_limit() {
+        use std::time::{Duration, Instant};
+        let location = "./tmp/write_limit";
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<Log> = crate::WalBuilder::new()
+            .location(location)
+            .write_limit(WriteLimit::Records(100))
+            .build()
+            .unwrap();
+        let log = |id| Log {
+            id,
+            name: "Jane Doe".to_string(),
+        };
+        // a second worth of logs goes through right away, the rest waits
+        let start = Instant::now();
+        (0..150).for_each(|id| wal.write(log(id)));
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        // the limit applies to the batches too
+        wal.set_write_limit(Some(WriteLimit::Records(200))).unwrap();
+        let start = Instant::now();
+        wal.write_iter((0..300).map(log));
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        assert!(wal.set_write_limit(Some(WriteLimit::Records(0))).is_err());
+        wal.set_write_limit(None).unwrap();
+        let start = Instant::now();
+        wal.write_iter((0..10_000).map(log));
+        assert!(start.elapsed() < Duration::from_millis(450));
+        wal.flush();
+        assert_eq!(wal.read().unwrap().count(), 10_450);
+        let err = crate::WalBuilder::<Log>::new()
+            .location(location)
+            .write_limit(WriteLimit::Bytes(Size::Kb(0)))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.field, "write_limit");
+    }
+
+    #[test]
+    fn replay_resumable() {
+        let location = "./tmp/replay_resumable";
+        let _ = std::fs::remove_dir_all(location);
+        let wal: Wal<u64> = crate::WalBuilder::new().location(location).build().unwrap();
+        wal.write_iter(0..250_000);
+        wal.flush();
+        assert!(wal.replay_resumable("sum!", 0, |s, x| s + x).is_err());
+
+        // a crash halfway, after the first checkpoint
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            wal.replay_resumable("sum", 0u64, |s, x| {
+                assert!(x < 150_000, "crash");
+                s + x
+            })
+        }));
+        assert!(crashed.is_err());
+
+        // resumes from the checkpoint
+        let mut applied = 0;
+        let sum = wal
+            .replay_resumable("sum", 0, |s, x| {
+                applied += 1;
+                s + x
+            })
+            .unwrap();
+        assert_eq!(sum, (0..250_000).sum::<u64>());
+        assert_eq!(applied, 150_000);
+
+        // only the new logs get applied
+        wal.write_iter(250_000..250_010);
+        wal.flush();
+        let mut applied = 0;
+        let sum = wal
+            .replay_resumable("sum", 0, |s, x| {
+                applied += 1;
+                s + x
+            })
+            .unwrap();
+        assert_eq!(sum, (0..250_010).sum::<u64>());
+        assert_eq!(applied, 10);
+
+        wal.reset_replay("sum").unwrap();
+        let sum = wal.replay_resumable("sum", 0, |s, x| s + x).unwrap();
+        assert_eq!(sum, (0..250_010).sum::<u64>());
+    }
+
+    #[test]
+    fn last_error() {
+        let location = "./tmp/last_error";
+        let _ = std::fs::remove_dir_all(location);
+        let build = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .disable_buffer()
+                .enable_fencing()
+                .build()
+                .unwrap()
+        };
+        let stale = build();
+        stale.write(Log {
+            id: 1,
+            name: "one".to_string(),
+        });
+        assert!(stale.last_error().is_none());
+        let _current = build();
+        stale.write(Log {
+            id: 2,
+            name: "two".to_string(),
+        });
+        let error = stale.last_error().unwrap();
+        assert!(error.message.starts_with("Refusing to write to WAL"));
+        assert_eq!(error.kind, None);
+        assert_eq!(stale.recent_errors(), [error]);
+        // so is the corrupted data skipped while reading
+        let path = format!("{}/log_0.bin", location);
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        std::io::Write::write_all(&mut file, &[9]).unwrap();
+        assert_eq!(stale.read().unwrap().count(), 1);
+        let error = stale.last_error().unwrap();
+        assert!(error.message.contains("corrupted data"));
+        assert_eq!(error.kind, Some(std::io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn format_versions() {
+        let location = "./tmp/format_versions";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let frame = |name: &str, size: fn(u16) -> [u8; 2]| {
+            let log = bincode::serialize(&Log {
+                id: 1,
+                name: name.to_string(),
+            })
+            .unwrap();
+            let mut data = size(log.len() as u16).to_vec();
+            data.extend(log);
+            data
+        };
+        // written without a header, then with the header of version 1
+        std::fs::write(
+            format!("{}/log_0.bin", location),
+            frame("v0", u16::to_ne_bytes),
+        )
+        .unwrap();
+        let mut v1 = b"WALC\x01\x00\x01\x00".to_vec();
+        v1.resize(16, 0);
+        v1.extend(frame("v1", u16::to_le_bytes));
+        std::fs::write(format!("{}/log_1.bin", location), &v1).unwrap();
+        // and by a newer version, laid out in an unknown way
+        let mut newer = v1.clone();
+        newer[4] = 0xff;
+        std::fs::write(format!("{}/log_2.bin", location), newer).unwrap();
+        std::fs::write(format!("{}/meta", location), "0 2").unwrap();
+        let wal = Wal::new(location, None);
+        wal.write(Log {
+            id: 2,
+            name: "new".to_string(),
+        });
+        wal.flush();
+        // the file of the newer version is left as it is
+        assert_eq!(wal.list_segments().len(), 4);
+        let logs = wal.read().unwrap().map(|log| log.name).collect::<Vec<_>>();
+        assert_eq!(logs, ["v0", "v1", "new"]);
+        let error = wal.last_error().unwrap();
+        assert_eq!(error.kind, Some(std::io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn format_policy() {
+        let location = "./tmp/format_policy";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        wal.write(Log {
+            id: 1,
+            name: "known".to_string(),
+        });
+        wal.flush();
+        drop(wal);
+        // a newer version moved on to a file in its own format
+        let mut newer = std::fs::read(format!("{}/log_0.bin", location)).unwrap();
+        newer[4] = 0xff;
+        std::fs::write(format!("{}/log_1.bin", location), newer).unwrap();
+        std::fs::write(format!("{}/meta", location), "0 1").unwrap();
+        let builder = || crate::WalBuilder::<Log>::new().location(location);
+        let err = builder()
+            .format_policy(FormatPolicy::Strict)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.field, "format_policy");
+        // nothing is written in read-only mode
+        let wal = builder()
+            .format_policy(FormatPolicy::ReadOnly)
+            .build()
+            .unwrap();
+        assert!(wal.is_read_only());
+        wal.write(Log {
+            id: 2,
+            name: "refused".to_string(),
+        });
+        let refused = Log {
+            id: 2,
+            name: "refused".to_string(),
+        };
+        assert!(wal.try_write(refused).is_err());
+        assert!(wal.append_control(RecordKind::Checkpoint, b"1").is_err());
+        assert!(wal.append_raw_batch(&[b"raw"]).is_err());
+        wal.flush().wait().unwrap();
+        assert!(!wal.health().is_ready());
+        let logs = wal.read().unwrap().map(|log| log.name).collect::<Vec<_>>();
+        assert_eq!(logs, ["known"]);
+        // the two log files, meta and the lifetime counters of the first writer
+        assert_eq!(std::fs::read_dir(location).unwrap().count(), 4);
+        drop(wal);
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/meta", location)).unwrap(),
+            "0 1"
+        );
+        // while the default keeps writing, in a new file
+        let wal = builder().build().unwrap();
+        assert!(!wal.is_read_only());
+        wal.write(Log {
+            id: 3,
+            name: "new".to_string(),
+        });
+        wal.flush();
+        let logs = wal.read().unwrap().map(|log| log.name).collect::<Vec<_>>();
+        assert_eq!(logs, ["known", "new"]);
+    }
+
+    #[test]
+    fn sortable_file_names() {
+        let location = "./tmp/sortable_file_names";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let log = |id: usize| Log {
+            id,
+            name: "x".repeat(100),
+        };
+        // files named before switching to the sortable names
+        let wal = Wal::new(location, None);
+        wal.write(log(0));
+        wal.flush();
+        drop(wal);
+        let wal = crate::WalBuilder::<Log>::new()
+            .location(location)
+            .storage_size(crate::Size::Kb(64))
+            .sortable_file_names()
+            .build()
+            .unwrap();
+        assert_eq!(
+            wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>(),
+            [0]
+        );
+        for id in 1..2000 {
+            wal.write(log(id));
+        }
+        wal.flush();
+        let mut names = std::fs::read_dir(location)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("log_"))
+            .collect::<Vec<_>>();
+        names.sort();
+        // the oldest files were collected, the first one along with them
+        assert!(!names.contains(&"log_0.bin".to_string()));
+        assert!(names.iter().all(|name| name.len() == 44));
+        // listing the files in alphabetical order lists them in the order of the logs
+        let segments = wal
+            .list_segments()
+            .into_iter()
+            .map(|segment| {
+                segment
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, segments);
+        let ids = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids.last(), Some(&1999));
+        assert!(ids.windows(2).all(|w| w[1] == w[0] + 1));
+    }
+
+    #[test]
+    fn page_size() {
+        let location = "./tmp/page_size";
+        let _ = std::fs::remove_dir_all(location);
+        let build = |page_size: Size| {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .storage_size(Size::Mb(64))
+                .page_size(page_size)
+                .buffer_size(Size::Kb(16))
+                .power_loss_safe()
+                .build()
+                .unwrap()
+        };
+        let log = |id| Log {
+            id,
+            name: "x".repeat(id),
+        };
+        let wal = build(Size::Kb(64));
+        wal.write_iter((0..50).map(log));
+        wal.flush();
+        let segments = wal.list_segments();
+        assert!(segments.iter().all(|s| s.size % (64 * 1024) == 0));
+        drop(wal);
+        // pages of another size go to a new file, read along with the older ones
+        let wal = build(Size::Mb(1));
+        wal.write_iter((50..100).map(log));
+        wal.flush();
+        let segments = wal.list_segments();
+        assert!(segments.last().unwrap().size % (1024 * 1024) == 0);
+        assert_eq!(
+            wal.read().unwrap().map(|l| l.id).collect::<Vec<_>>(),
+            (0..100).collect::<Vec<_>>()
+        );
+        // a torn page of 1 MB is cut off as a whole, along with the write it was part of
+        let path = segments.last().unwrap().path.clone();
+        let size = std::fs::metadata(&path).unwrap().len();
+        drop(wal);
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(size - 100).unwrap();
+        drop(file);
+        let wal = build(Size::Mb(1));
+        assert_eq!(wal.recovery_report().truncated, 1024 * 1024 - 100);
+        assert_eq!(wal.read().unwrap().last().unwrap().id, 49);
+    }
+
+    #[test]
+    fn read_into() {
+        let location = "./tmp/read_into";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        wal.write_iter((0..100).map(|id| Log {
+            id,
+            name: id.to_string(),
+        }));
+        wal.flush();
+        let mut logs = Vec::new();
+        assert_eq!(wal.read_into(&mut logs, 30).unwrap(), 30);
+        assert!(logs.capacity() >= 30);
+        assert_eq!(wal.read_into(&mut logs, 1000).unwrap(), 100);
+        let ids = logs.iter().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids[..30], (0..30).collect::<Vec<_>>());
+        assert_eq!(ids[30..], (0..100).collect::<Vec<_>>());
+        // in batches from a single iterator
+        let mut iter = wal.read().unwrap();
+        let mut batch = Vec::with_capacity(40);
+        let mut batches = Vec::new();
+        loop {
+            batch.clear();
+            match iter.read_into(&mut batch, 40) {
+                0 => break,
+                n => batches.push((n, batch[0].id)),
+            }
+        }
+        assert_eq!(batches, [(40, 0), (40, 40), (20, 80)]);
+        // in chunks
+        let chunks = wal.read_chunks(40).unwrap().collect::<Vec<_>>();
+        let sizes = chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, [40, 40, 20]);
+        assert_eq!(chunks[1][0].id, 40);
+        assert_eq!(wal.read_chunks(0).unwrap().count(), 100);
+        assert_eq!(wal.read_chunks(usize::MAX).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let location = "./tmp/decode_errors";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal: Wal<u32> = Wal::new(location, None);
+        wal.write_iter(0..10);
+        wal.flush();
+        drop(wal);
+        // the type of the logs changed since
+        let wal: Wal<u64> = Wal::new(location, None);
+        wal.write(10);
+        wal.flush();
+        let failed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = failed.clone();
+        let logs = wal
+            .read()
+            .unwrap()
+            .on_decode_error(move |meta, bytes, _| {
+                seen.lock().unwrap().push(meta.lsn);
+                let old = u32::from_le_bytes(bytes.try_into().ok()?);
+                (old % 2 == 0).then_some(old as u64)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(logs, [0, 2, 4, 6, 8, 10]);
+        assert_eq!(*failed.lock().unwrap(), (0..10).collect::<Vec<_>>());
+        // reported without a handler
+        assert_eq!(wal.read().unwrap().collect::<Vec<_>>(), [10]);
+        let error = wal.last_error().unwrap();
+        assert_eq!(error.kind, Some(std::io::ErrorKind::InvalidData));
+        assert!(error.message.contains("log 9"));
+    }
+
+    #[test]
+    fn control_records() {
+        let location = "./tmp/control_records";
+        let _ = std::fs::remove_dir_all(location);
+        let wal = crate::WalBuilder::<Log>::new()
+            .location(location)
+            .checksum(Checksum::Crc32c)
+            .build()
+            .unwrap();
+        let log = |id| Log {
+            id,
+            name: "control".to_string(),
+        };
+        wal.write(log(0));
+        wal.append_control(RecordKind::Checkpoint, b"state-0")
+            .unwrap();
+        wal.write(log(1));
+        wal.append_control(RecordKind::TxnCommit, &[]).unwrap();
+        assert!(wal.append_control(RecordKind::Log, b"log").is_err());
+        wal.flush();
+        // the logs keep their place, the control records taking up an lsn in between
+        let logs = wal.read_with_positions().unwrap().collect::<Vec<_>>();
+        assert_eq!(
+            logs.iter()
+                .map(|(lsn, log)| (*lsn, log.id))
+                .collect::<Vec<_>>(),
+            [(0, 0), (2, 1)]
+        );
+        let kinds = wal
+            .read_raw()
+            .unwrap()
+            .map(|(meta, bytes)| (meta.lsn, meta.kind, meta.crc_ok, bytes))
+            .collect::<Vec<_>>();
+        assert_eq!(kinds.len(), 4);
+        assert_eq!(kinds[0].1, RecordKind::Log);
+        assert_eq!(
+            kinds[1],
+            (1, RecordKind::Checkpoint, Some(true), b"state-0".to_vec())
+        );
+        assert_eq!(kinds[3], (3, RecordKind::TxnCommit, Some(true), vec![]));
+        assert_eq!(wal.count(), 4);
+    }
+
+    #[test]
+    fn terms() {
+        let location = "./tmp/terms";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        let log = |id| Log {
+            id,
+            name: "term".to_string(),
+        };
+        let term = |term, priority| WriteOptions {
+            term: Some(term),
+            priority,
+            ..Default::default()
+        };
+        wal.write_with(log(0), term(1, false));
+        wal.write(log(1));
+        wal.write_with(log(2), term(u64::MAX, true));
+        wal.write_with(log(3), term(2, false));
+        wal.flush();
+        let logs = wal.read().unwrap().with_terms().collect::<Vec<_>>();
+        assert_eq!(
+            logs.iter()
+                .map(|(lsn, term, log)| (*lsn, *term, log.id))
+                .collect::<Vec<_>>(),
+            [
+                (0, Some(1), 0),
+                (1, None, 1),
+                (2, Some(u64::MAX), 2),
+                (3, Some(2), 3)
+            ]
+        );
+        // the term isn't part of the log
+        let raw = wal.read_raw().unwrap().collect::<Vec<_>>();
+        assert_eq!(raw[0].0.term, Some(1));
+        assert_eq!(raw[0].0.len, raw[1].0.len);
+        assert_eq!(wal.read().unwrap().count(), 4);
+    }
+
+    #[test]
+    fn delete_from() {
+        let log = |id: usize| Log {
+            id,
+            name: "x".repeat(100),
+        };
+        let ids = |wal: &Wal<Log>| wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        for (name, paged) in [("plain", false), ("paged", true)] {
+            let location = format!("./tmp/delete_from/{}", name);
+            let _ = std::fs::remove_dir_all(&location);
+            let open = || {
+                let builder = crate::WalBuilder::<Log>::new()
+                    .location(&location)
+                    .storage_size(crate::Size::Kb(64))
+                    .hash_chain();
+                match paged {
+                    true => builder.power_loss_safe(),
+                    false => builder,
+                }
+                .build()
+                .unwrap()
+            };
+            let wal = open();
+            for id in 0..250 {
+                wal.write(log(id));
+            }
+            wal.flush();
+            assert!(wal.list_segments().len() > 2);
+            // past the last log
+            assert_eq!(wal.delete_from(250).unwrap(), 0);
+            // within the current file, the buffered logs included
+            wal.write(log(250));
+            assert_eq!(wal.delete_from(249).unwrap(), 2);
+            assert_eq!(ids(&wal), (0..249).collect::<Vec<_>>());
+            // within the first file, the later ones being deleted
+            assert_eq!(wal.delete_from(50).unwrap(), 199);
+            assert_eq!(wal.list_segments().len(), 1);
+            assert_eq!(wal.count(), 50);
+            // the new logs continue from it, and survive a restart
+            for id in 1000..1100 {
+                wal.write(log(id));
+            }
+            wal.flush();
+            drop(wal);
+            let wal = open();
+            let expected = (0..50).chain(1000..1100).collect::<Vec<_>>();
+            assert_eq!(ids(&wal), expected);
+            let lsns = wal.read().unwrap().with_positions().map(|(lsn, _)| lsn);
+            assert!(lsns.eq(0..150));
+            assert!(wal.verify().is_ok());
+            // the logs before the first one kept
+            wal.delete_segments_before(SegmentBound::Lsn(120));
+            assert!(wal.delete_from(10).is_err());
+            assert_eq!(wal.delete_from(130).unwrap(), 20);
+        }
+    }
+
+    #[test]
+    fn append_at() {
+        let location = "./tmp/append_at";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        let log = |id| Log {
+            id,
+            name: "append".to_string(),
+        };
+        wal.write_iter((0..5).map(log));
+        // past the next log
+        assert!(wal.append_at(6, [(None, log(6))]).is_err());
+        // at the next log, and over the tail
+        assert_eq!(wal.append_at(5, [(Some(1), log(5))]).unwrap(), 0);
+        let new = [(Some(2), log(10)), (None, log(11))];
+        assert_eq!(wal.append_at(3, new).unwrap(), 3);
+        wal.write(log(12));
+        wal.flush();
+        let logs = wal.read().unwrap().with_terms().collect::<Vec<_>>();
+        assert_eq!(
+            logs.iter()
+                .map(|(lsn, term, log)| (*lsn, *term, log.id))
+                .collect::<Vec<_>>(),
+            [
+                (0, None, 0),
+                (1, None, 1),
+                (2, None, 2),
+                (3, Some(2), 10),
+                (4, None, 11),
+                (5, None, 12)
+            ]
+        );
+    }
+
+    #[test]
+    fn append_at_fenced() {
+        let location = "./tmp/append_at_fenced";
+        let _ = std::fs::remove_dir_all(location);
+        let build = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .disable_buffer()
+                .enable_fencing()
+                .build()
+                .unwrap()
+        };
+        let log = |id| Log {
+            id,
+            name: "fenced".to_string(),
+        };
+        let stale = build();
+        stale.write_iter((0..3).map(log));
+        // a newer writer takes over, the logs of the stale one aren't written
+        let _current = build();
+        assert!(stale.append_at(3, [(Some(1), log(3))]).is_err());
+        assert_eq!(stale.read().unwrap().count(), 3);
+    }
+
+    #[test]
+    fn hard_state() {
+        let location = "./tmp/hard_state";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let wal = Wal::new(location, None);
+        assert_eq!(wal.state("term"), None);
+        wal.write(Log {
+            id: 0,
+            name: "state".to_string(),
+        });
+        wal.set_state(&[("term", &[7]), ("vote", b"node-2")])
+            .unwrap();
+        // the buffered log was written out first
+        assert_eq!(wal.read().unwrap().count(), 1);
+        assert!(wal.set_state(&[("a b", &[1])]).is_err());
+        wal.set_state(&[("vote", &[])]).unwrap();
+        drop(wal);
+        let wal: Wal<Log> = Wal::new(location, None);
+        assert_eq!(wal.state("term"), Some(vec![7]));
+        assert_eq!(wal.state("vote"), None);
+    }
+
+    #[test]
+    fn preallocate() {
+        let location = "./tmp/preallocate";
+        let _ = std::fs::remove_dir_all(location);
+        let builder = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .preallocate()
+        };
+        assert!(builder().build().is_err());
+        let wal = builder().storage_size(crate::Size::Kb(64)).build().unwrap();
+        let files = || {
+            let mut files = std::fs::read_dir(location)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.starts_with("log_"))
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        let before = files();
+        // the current file and the ones after it, as many as kept at most
+        assert_eq!(before.len(), 6);
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let path = format!("{}/log_5.bin", location);
+            let meta = std::fs::metadata(path).unwrap();
+            assert_eq!(meta.len(), 0);
+            assert!(meta.blocks() * 512 >= 16 * 1024);
+        }
+        let log = |id| Log {
+            id,
+            name: "x".repeat(100),
+        };
+        for id in 0..1000 {
+            wal.write(log(id));
+        }
+        wal.flush();
+        // the files collected were replaced
+        assert_eq!(files().len(), 6);
+        assert_ne!(files(), before);
+        let ids = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids.last(), Some(&999));
+        drop(wal);
+        let wal = builder().storage_size(crate::Size::Kb(64)).build().unwrap();
+        assert!(wal.recovery_report().is_consistent());
+        assert_eq!(
+            wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>(),
+            ids
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn disk_reserve() {
+        use crate::LowDiskSpace;
+        let location = "./tmp/disk_reserve";
+        let _ = std::fs::remove_dir_all(location);
+        let builder = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .storage_size(crate::Size::Kb(64))
+        };
+        // larger than any disk
+        let reserve = crate::Size::Gb(1 << 30);
+        assert!(builder()
+            .preallocate()
+            .disk_reserve(reserve, LowDiskSpace::Refuse)
+            .build()
+            .is_err());
+        let log = |id| Log {
+            id,
+            name: "x".repeat(100),
+        };
+        let wal = builder().build().unwrap();
+        for id in 0..200 {
+            wal.write(log(id));
+        }
+        wal.flush();
+        assert!(wal.list_segments().len() > 1);
+        drop(wal);
+
+        // refused before writing anything
+        let wal = builder()
+            .disk_reserve(reserve, LowDiskSpace::Refuse)
+            .build()
+            .unwrap();
+        wal.write(log(200));
+        wal.flush();
+        let error = wal.last_error().unwrap();
+        assert_eq!(error.kind, Some(std::io::ErrorKind::StorageFull));
+        assert_eq!(wal.read().unwrap().count(), 200);
+        drop(wal);
+
+        // the oldest files deleted first, to no avail
+        let wal = builder()
+            .disk_reserve(reserve, LowDiskSpace::DeleteOldest)
+            .build()
+            .unwrap();
+        wal.write(log(200));
+        wal.flush();
+        assert!(wal.last_error().is_some());
+        assert_eq!(wal.list_segments().len(), 1);
+        let ids = wal.read().unwrap().map(|log| log.id).collect::<Vec<_>>();
+        assert_eq!(ids.last(), Some(&199));
+        drop(wal);
+
+        // a reserve the disk has room for
+        let wal = builder()
+            .disk_reserve(crate::Size::Bytes(1), LowDiskSpace::Refuse)
+            .build()
+            .unwrap();
+        wal.write(log(200));
+        wal.flush();
+        assert_eq!(wal.last_error(), None);
+        assert_eq!(wal.read().unwrap().last().map(|log| log.id), Some(200));
+    }
+
+    #[test]
+    fn sync_window() {
+        let location = "./tmp/sync_window";
+        let _ = std::fs::remove_dir_all(location);
+        let builder = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .disable_buffer()
+                .sync_window(std::time::Duration::from_millis(2))
+        };
+        assert!(builder().build().is_err());
+        let wal = builder().enable_fsync().build().unwrap();
+        let threads = (0..8)
+            .map(|thread| {
+                let wal = wal.clone();
+                std::thread::spawn(move || {
+                    for i in 0..20 {
+                        let id = thread * 100 + i;
+                        wal.write(Log {
+                            id,
+                            name: format!("log {}", id),
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert!(wal.flush().wait().is_ok());
+        let stats = wal.stats();
+        // the concurrent writes were synced together
+        assert!(stats.coalesced_syncs > 0);
+        assert!(stats.fsync.count < 160);
+        assert_eq!(stats.fsync.count + stats.coalesced_syncs, 160);
+        assert_eq!(wal.read().unwrap().count(), 160);
+    }
+
+    #[test]
+    fn lifetime_stats() {
+        let location = "./tmp/lifetime_stats";
+        let _ = std::fs::remove_dir_all(location);
+        let builder = || {
+            crate::WalBuilder::<Log>::new()
+                .location(location)
+                .storage_size(crate::Size::Kb(64))
+        };
+        let log = |id| Log {
+            id,
+            name: "x".repeat(100),
+        };
+        let wal = builder().build().unwrap();
+        for id in 0..1000 {
+            wal.write(log(id));
+        }
+        wal.flush();
+        let before = wal.lifetime_stats();
+        assert_eq!(before.records, 1000);
+        assert_eq!(before.bytes, wal.stats().bytes_written);
+        assert!(before.segments_collected > 0);
+        drop(wal);
+
+        // kept across restarts, unlike the stats
+        let wal = builder().build().unwrap();
+        assert_eq!(wal.lifetime_stats(), before);
+        assert_eq!(wal.stats().bytes_written, 0);
+        wal.write(log(1000));
+        wal.flush();
+        assert_eq!(wal.lifetime_stats().records, 1001);
+        drop(wal);
+        let wal = builder().build().unwrap();
+        assert_eq!(wal.lifetime_stats().records, 1001);
+    }
+
+    #[test]
+    fn gc_events() {
+        use crate::{GcEvent, GcRule, SegmentBound, WalListener};
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Collected(Mutex<Vec<GcEvent>>);
+
+        impl WalListener for Collected {
+            fn on_gc(&self, event: GcEvent) {
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        let location = "./tmp/gc_events";
+        let _ = std::fs::remove_dir_all(location);
+        let listener = Arc::new(Collected::default());
+        let wal = crate::WalBuilder::<Log>::new()
+            .location(location)
+            .storage_size(crate::Size::Kb(64))
+            .listener(listener.clone())
+            .build()
+            .unwrap();
+        for id in 0..1000 {
+            wal.write(Log {
+                id,
+                name: "x".repeat(100),
+            });
+        }
+        wal.flush();
+        let events = std::mem::take(&mut *listener.0.lock().unwrap());
+        assert!(!events.is_empty());
+        // the files are deleted oldest first, their logs following each other
+        let segments = events
+            .iter()
+            .inspect(|event| assert_eq!(event.rule, GcRule::StorageLimit))
+            .flat_map(|event| event.segments.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(segments[0].index, 0);
+        assert_eq!(segments[0].lsns.as_ref().unwrap().start, 0);
+        for pair in segments.windows(2) {
+            assert_eq!(pair[1].index, pair[0].index + 1);
+            assert_eq!(
+                pair[1].lsns.as_ref().unwrap().start,
+                pair[0].lsns.as_ref().unwrap().end
+            );
+        }
+        for event in events.iter() {
+            let size = event.segments.iter().map(|s| s.size).sum::<u64>();
+            assert_eq!(event.reclaimed, size);
+        }
+        assert!(segments.iter().all(|s| !s.path.exists() && !s.offloaded));
+        let first = wal.list_segments()[0].clone();
+        assert_eq!(first.index, segments.last().unwrap().index + 1);
+
+        // deleted on demand
+        assert_eq!(
+            wal.delete_segments_before(SegmentBound::Segment(first.index + 1)),
+            1
+        );
+        let events = listener.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].rule, GcRule::OnDemand);
+        assert_eq!(events[0].segments.len(), 1);
+        assert_eq!(events[0].segments[0].index, first.index);
+        assert_eq!(events[0].segments[0].lsns, first.lsns);
+        assert_eq!(events[0].reclaimed, first.size);
+    }
 }
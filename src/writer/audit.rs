@@ -0,0 +1,75 @@
+use super::manager::COMPRESSED_EXT;
+use super::manifest::{Manifest, SegmentInfo};
+use super::naming;
+use crate::listener::{CollectedSegment, GcEvent, GcRule};
+use crate::stats::Monitor;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Collects the files deleted by a run of the garbage collection, to report them to the
+/// listener with [WalListener::on_gc](crate::WalListener::on_gc)
+///
+/// The files are described before being deleted, and only added once deleted. Nothing is
+/// collected without a listener.
+pub(crate) struct GcAudit {
+    /// Manifest of the location, `None` without a listener
+    manifest: Option<BTreeMap<usize, SegmentInfo>>,
+    segments: Vec<CollectedSegment>,
+}
+
+impl GcAudit {
+    pub fn new(location: &Path, monitor: &Monitor) -> Self {
+        let manifest = monitor
+            .listening()
+            .then(|| Manifest::new(location.to_path_buf()).read());
+        Self {
+            manifest,
+            segments: Vec::new(),
+        }
+    }
+
+    /// Describe a file about to be deleted from the directory
+    ///
+    /// ## Returns
+    /// `None` without a listener, or if there's no such file
+    pub fn describe(&self, dir: &Path, index: usize, offloaded: bool) -> Option<CollectedSegment> {
+        let manifest = self.manifest.as_ref()?;
+        let name = naming::find(dir, index)?;
+        let (path, metadata) = [
+            dir.join(format!("{}{}", name, COMPRESSED_EXT)),
+            dir.join(name),
+        ]
+        .into_iter()
+        .find_map(|path| std::fs::metadata(&path).ok().map(|m| (path, m)))?;
+        let lsns = manifest.get(&index).and_then(|info| {
+            // the logs trimmed from the start of the file are gone already
+            let next = info.next_lsn()?;
+            Some(info.first? + info.trimmed..next)
+        });
+        Some(CollectedSegment {
+            index,
+            path,
+            lsns,
+            size: metadata.len(),
+            offloaded,
+        })
+    }
+
+    /// Add a file once deleted, as described by [GcAudit::describe]
+    pub fn push(&mut self, segment: Option<CollectedSegment>) {
+        self.segments.extend(segment);
+    }
+
+    /// Report the files deleted, if any
+    pub fn report(self, monitor: &Monitor, rule: GcRule) {
+        if self.segments.is_empty() {
+            return;
+        }
+        let reclaimed = self.segments.iter().map(|segment| segment.size).sum();
+        monitor.collected(GcEvent {
+            rule,
+            segments: self.segments,
+            reclaimed,
+        });
+    }
+}
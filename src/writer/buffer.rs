@@ -1,9 +1,56 @@
+use super::frame::{RecordHeader, RecordKind};
 use crate::DEFAULT_BUFFER_SIZE;
+use serde::{Deserialize, Serialize};
+
+/// What happens to a log that doesn't fit in the space left in the buffer, set with
+/// [WalBuilder::buffer_overflow](crate::WalBuilder::buffer_overflow)
+///
+/// A log larger than the whole buffer is always accepted by an empty buffer, so that it's
+/// never refused for good.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BufferOverflow {
+    /// Add the log past the size of the buffer, then write the whole buffer to the file
+    #[default]
+    Grow,
+    /// Write the buffer to the file first, then add the log to a new buffer
+    FlushFirst,
+    /// Write the buffer to the file along with the log, leaving a new buffer empty
+    WriteThrough,
+    /// Refuse the log, leaving the buffer as it is until flushed
+    Reject,
+}
+
+/// Data taken out of a [Buffer], or copied from it, zeroed once dropped with the `zeroize`
+/// feature
+#[cfg(feature = "zeroize")]
+pub(crate) type Data = zeroize::Zeroizing<Vec<u8>>;
+#[cfg(not(feature = "zeroize"))]
+pub(crate) type Data = Vec<u8>;
+
+/// Make room for more bytes in the data, which grows without leaving a copy behind in the
+/// memory freed with the `zeroize` feature
+pub(crate) fn reserve(data: &mut Vec<u8>, additional: usize) {
+    #[cfg(feature = "zeroize")]
+    if data.capacity() < data.len() + additional {
+        let capacity = (data.len() + additional).max(data.capacity() * 2);
+        let mut grown = Vec::with_capacity(capacity);
+        grown.extend_from_slice(data);
+        zeroize::Zeroize::zeroize(&mut std::mem::replace(data, grown));
+    }
+    data.reserve(additional);
+}
+
+/// Append bytes to the data, see [reserve]
+pub(crate) fn append(data: &mut Vec<u8>, bytes: &[u8]) {
+    reserve(data, bytes.len());
+    data.extend_from_slice(bytes);
+}
 
 pub(crate) struct Buffer {
     size: usize,
     inner: Vec<u8>,
-    // checksum: u32 <- for future use
+    /// Running checksum of the data as it was added, see [Buffer::verify]
+    checksum: crc32fast::Hasher,
 }
 
 impl Buffer {
@@ -20,19 +67,24 @@ impl Buffer {
         Self {
             inner: Vec::with_capacity(size),
             size,
+            checksum: crc32fast::Hasher::new(),
         }
     }
 
     /// Add data to buffer
     ///
+    /// ## Arguments
+    /// - `header`: What the data holds, a log unless it's a control record, and its term
+    /// - `data`: The data to add
+    ///
     /// ## Returns
     /// Tuple of 2 boolean where
     /// - 0: Whether the new data was accepted to the buffer or not
     /// - 1: Whether the buffer is ready to be flushed or not
     ///
-    pub fn try_add(&mut self, data: &[u8]) -> (bool, bool) {
-        // check for empty addition
-        if data.is_empty() {
+    pub fn try_add(&mut self, header: RecordHeader, data: &[u8]) -> (bool, bool) {
+        // check for empty addition, a control record being never empty along with its kind
+        if data.is_empty() && !header.kind.is_control() {
             return (true, false);
         }
         // check whether the buffer isn't already filled
@@ -49,7 +101,7 @@ impl Buffer {
         // }
 
         // add to buffer & return accepted status
-        self.add(data);
+        self.add(header, data);
         (true, self.inner.len() >= self.size)
     }
 
@@ -57,31 +109,85 @@ impl Buffer {
     ///
     /// If enough space is not available, then this method will
     /// extend the size of the buffer beyond [PAGE_SIZE]
-    fn add(&mut self, data: &[u8]) {
-        // store length
-        let size: [u8; 2] = (data.len() as u16).to_ne_bytes();
-        self.inner.extend(&size);
+    ///
+    /// Data too large to be framed is left out rather than stored with its size cut short, as
+    /// the [Wal](crate::Wal) refuses it beforehand.
+    pub fn add(&mut self, header: RecordHeader, data: &[u8]) {
+        // store length, then kind and term
+        let Ok(size) = u16::try_from(header.len() + data.len()) else {
+            return;
+        };
+        let start = self.inner.len();
+        reserve(&mut self.inner, 2 + header.len() + data.len());
+        self.inner.extend(size.to_le_bytes());
+        header.encode(&mut self.inner);
         // store data
         self.inner.extend(data);
+        // the data is checksummed from the caller's copy, so a copy corrupted in the buffer
+        // doesn't match
+        let framed = self.inner.len() - data.len();
+        self.checksum.update(&self.inner[start..framed]);
+        self.checksum.update(data);
+    }
+
+    /// Whether the data fits in the space left in the buffer, along with its frame and header
+    pub fn fits(&self, header: RecordHeader, data: &[u8]) -> bool {
+        self.inner.len() + 2 + header.len() + data.len() <= self.size
+    }
+
+    /// Whether nothing has been added to the buffer
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// The data added to the buffer so far
+    pub fn data(&self) -> &[u8] {
+        &self.inner
+    }
+
+    /// Whether the data in the buffer still matches the data added to it, i.e. it wasn't
+    /// corrupted in memory meanwhile, e.g. by bad RAM or a stray write
+    pub fn verify(&self) -> bool {
+        crc32fast::hash(&self.inner) == self.checksum.clone().finalize()
     }
 
     /// Consume the buffer to return the inner data for dumping to file
     ///
     /// ## Argument
-    /// - `padding` - Whether the inner data shall be padded to [PAGE_SIZE] or not
+    /// - `padding` - Whether the inner data shall be padded to the size of the buffer or not,
+    ///   with a [padding record](super::frame::PADDING) that may go a few bytes past it
     ///
     /// ## Returns
     /// The internal contents of the buffer
-    pub fn consume(mut self, padding: bool) -> Vec<u8> {
+    pub fn consume(mut self, padding: bool) -> Data {
         if padding && self.inner.len() < self.size {
             let diff = self.size - self.inner.len();
-            let v = (0..diff).map(|_| 0).collect::<Vec<_>>();
-            self.inner.extend(v);
+            append(&mut self.inner, &super::frame::padding(diff));
         }
-        self.inner
+        Data::from(std::mem::take(&mut self.inner))
+    }
+}
+
+/// Zero the logs left in a buffer dropped before being written, with the `zeroize` feature
+#[cfg(feature = "zeroize")]
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.inner);
     }
 }
 
+/// Size of the frame written before a log without a term
+pub(crate) const FRAME_SIZE: usize = 3;
+
+/// The frame written before a log without a term, holding its size then its [RecordKind]
+///
+/// The size covers the kind along with the log, so the files can be scanned without knowing
+/// about the kinds.
+pub(crate) fn frame(data: &[u8]) -> [u8; FRAME_SIZE] {
+    let [low, high] = ((data.len() + 1) as u16).to_le_bytes();
+    [low, high, RecordKind::Log.id()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,16 +202,16 @@ mod tests {
     fn consume() {
         let mut buffer = Buffer::new(None);
         let data = [20; 100];
-        buffer.add(&data);
+        buffer.add(RecordHeader::default(), &data);
         let data = buffer.consume(false);
-        assert_eq!(data.len(), 102); // 2 extra bytes are for representation of length of 1 added item to buffer
+        assert_eq!(data.len(), 103); // 3 extra bytes are for representation of length and kind of 1 added item to buffer
     }
 
     #[test]
     fn consume_padding() {
         let mut buffer = Buffer::new(None);
         let data = [10; 100];
-        buffer.add(&data);
+        buffer.add(RecordHeader::default(), &data);
         let data = buffer.consume(true);
         assert_eq!(data.len(), DEFAULT_BUFFER_SIZE);
     }
@@ -114,23 +220,49 @@ mod tests {
     fn try_add() {
         let mut buffer = Buffer::new(Some(120));
         let data = [10; 100];
-        let d = buffer.try_add(&data);
+        let d = buffer.try_add(RecordHeader::default(), &data);
         assert_eq!(d, (true, false));
         let data = [10; 100];
-        let d = buffer.try_add(&data);
+        let d = buffer.try_add(RecordHeader::default(), &data);
         assert_eq!(d, (true, true));
     }
 
+    #[test]
+    fn fits() {
+        let mut buffer = Buffer::new(Some(120));
+        assert!(buffer.is_empty());
+        assert!(buffer.fits(RecordHeader::default(), &[10; 117]));
+        assert!(!buffer.fits(RecordHeader::default(), &[10; 118]));
+        buffer.try_add(RecordHeader::default(), &[10; 100]);
+        assert!(buffer.fits(RecordHeader::default(), &[10; 14]));
+        assert!(!buffer.fits(RecordHeader::default(), &[10; 15]));
+        // the term takes up 8 more bytes
+        assert!(buffer.fits(RecordHeader::log(Some(1)), &[10; 6]));
+        assert!(!buffer.fits(RecordHeader::log(Some(1)), &[10; 7]));
+    }
+
+    #[test]
+    fn verify() {
+        let mut buffer = Buffer::new(None);
+        assert!(buffer.verify());
+        buffer.add(RecordHeader::default(), &[10; 100]);
+        buffer.add(RecordHeader::log(Some(7)), &[20; 50]);
+        assert!(buffer.verify());
+        // a bit flipped in memory
+        buffer.inner[42] ^= 1;
+        assert!(!buffer.verify());
+    }
+
     #[test]
     fn reject_on_add() {
         let mut buffer = Buffer::new(Some(120));
         // first larger than buffer size payload
         let data = [10; 140];
-        let d = buffer.try_add(&data);
+        let d = buffer.try_add(RecordHeader::default(), &data);
         assert_eq!(d, (true, true));
         // extending the existing buffer will fail now
         let data = [10; 20];
-        let d = buffer.try_add(&data);
+        let d = buffer.try_add(RecordHeader::default(), &data);
         assert_eq!(d, (false, true));
     }
 }
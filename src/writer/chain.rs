@@ -0,0 +1,131 @@
+use super::frame::{skip_padding, PADDING};
+use super::header::{read_header, Format};
+use crate::stats::Monitor;
+use sha2::{Digest, Sha256};
+use std::io::{ErrorKind, Read};
+
+/// Size of the link stored in front of every record of a hash-chained file
+///
+/// In a hash-chained file, the size in the frame of a record covers the link along with the
+/// serialized record, so the files can be scanned without knowing about the links.
+pub(crate) const LINK_SIZE: usize = 32;
+
+/// SHA-256 of a record chained to all the records before it
+pub(crate) type Link = [u8; LINK_SIZE];
+
+/// The link of a record, following the link of the record before it
+pub(crate) fn next(previous: &Link, record: &[u8]) -> Link {
+    let mut hasher = Sha256::new();
+    hasher.update(previous);
+    hasher.update(record);
+    hasher.finalize().into()
+}
+
+/// Reframe the records of the data committed to a file, putting the link of the previous
+/// record in front of each one
+///
+/// ## Arguments
+/// - `data`: Framed records, possibly along with padding
+/// - `last`: Link of the last record written, moved to the last record of the data
+/// - `monitor`: Reports the records dropped, see [reframe](super::frame::reframe)
+pub(crate) fn link(data: &[&[u8]], last: &mut Link, monitor: &Monitor) -> Vec<u8> {
+    super::frame::reframe(data, monitor, |record, out| {
+        out.extend(last.as_slice());
+        out.extend(record);
+        *last = next(last, record);
+    })
+}
+
+/// Read the records of a hash-chained file, along with the links stored in front of them
+///
+/// ## Arguments
+/// - `reader`: The records of the file, read from right after its header
+/// - `format`: Layout of the file
+/// - `skip`: Number of bytes of the records to skip, e.g. the ones trimmed
+/// - `f`: Called with the stored link and the record
+pub(crate) fn walk(
+    mut reader: impl Read,
+    format: Format,
+    skip: u64,
+    mut f: impl FnMut(&Link, &[u8]),
+) -> std::io::Result<()> {
+    // the checksum comes in front of the link
+    let checksum = format.checksum.size();
+    std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink())?;
+    let mut frame = [0; 2];
+    let mut record = Vec::new();
+    loop {
+        match reader.read_exact(&mut frame) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let size = u16::from_le_bytes(frame);
+        if size == PADDING {
+            match skip_padding(&mut reader, format)? {
+                Some(_) => continue,
+                None => return Ok(()),
+            }
+        }
+        let size = size as usize;
+        if size < checksum + LINK_SIZE {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "Record too short for its link",
+            ));
+        }
+        record.resize(size, 0);
+        match reader.read_exact(&mut record) {
+            Ok(()) => {}
+            // a partial record left by a crash
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let (link, data) = record[checksum..].split_at(LINK_SIZE);
+        f(link.try_into().unwrap(), data);
+    }
+}
+
+/// Find the link of the last record of a file
+///
+/// ## Returns
+/// `None` if the file has no records, or isn't hash-chained
+pub(crate) fn last_link(mut reader: impl Read) -> std::io::Result<Option<Link>> {
+    let (format, prefix) = read_header(&mut reader)?;
+    if !format.chained || prefix.len() < format.header_len {
+        return Ok(None);
+    }
+    let mut last = None;
+    walk(
+        super::frame::records(reader, format),
+        format,
+        0,
+        |link, record| {
+            last = Some(next(link, record));
+        },
+    )?;
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_records() {
+        let data = [&[3, 0, 1, 2, 3][..], &[1, 0, 9, 0, 0, 0]].concat();
+        let mut last = [0; LINK_SIZE];
+        let monitor = Monitor::new(&crate::WalConfig::default());
+        let chained = link(&[&data], &mut last, &monitor);
+        // the padding is dropped
+        assert_eq!(chained.len(), 2 * (2 + LINK_SIZE) + 4);
+        let mut links = Vec::new();
+        walk(&chained[..], Format::default(), 0, |link, record| {
+            links.push((*link, record.to_vec()));
+        })
+        .unwrap();
+        assert_eq!(links[0], ([0; LINK_SIZE], vec![1, 2, 3]));
+        assert_eq!(links[1].0, next(&[0; LINK_SIZE], &[1, 2, 3]));
+        assert_eq!(last, next(&links[1].0, &[9]));
+    }
+}
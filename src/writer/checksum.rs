@@ -0,0 +1,125 @@
+use crate::stats::Monitor;
+use serde::{Deserialize, Serialize};
+
+/// Algorithm of the checksum stored in front of every log, set with
+/// [WalBuilder::checksum](crate::WalBuilder::checksum)
+///
+/// The algorithm is recorded in the header of every file, so files written with different
+/// algorithms can be read together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Checksum {
+    /// No checksum per log, the files are still checked as a whole by [Wal::verify](crate::Wal::verify)
+    #[default]
+    None,
+    /// CRC32C, taking 4 bytes per log, which most CPUs compute in hardware
+    Crc32c,
+    /// XXH64, taking 8 bytes per log, fast on any CPU and less likely to miss a corruption
+    XxHash64,
+}
+
+/// When the checksums of the logs are verified while reading, set with
+/// [ReadOptions::checksums](crate::ReadOptions::checksums)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChecksumVerification {
+    /// Every log is verified as it's read, and the ones failing their checksum are skipped
+    #[default]
+    Eager,
+    /// The checksums are left unverified, for a faster read when the content is validated
+    /// further up anyway. They can still be verified on demand by an eager read, and the filled
+    /// files as a whole by [Wal::verify](crate::Wal::verify).
+    ///
+    /// A corrupted log is then decoded as it is, and reported if it fails to deserialize.
+    Lazy,
+}
+
+impl Checksum {
+    /// Identifier of the algorithm in the header of a file
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc32c => 1,
+            Checksum::XxHash64 => 2,
+        }
+    }
+
+    /// The algorithm with the identifier, `None` for the ones unknown to this version
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Checksum::None),
+            1 => Some(Checksum::Crc32c),
+            2 => Some(Checksum::XxHash64),
+            _ => None,
+        }
+    }
+
+    /// Number of bytes the checksum takes in front of a log
+    pub(crate) fn size(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc32c => 4,
+            Checksum::XxHash64 => 8,
+        }
+    }
+
+    fn compute(self, data: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Checksum::None => {}
+            Checksum::Crc32c => out.extend(crc32c::crc32c(data).to_le_bytes()),
+            Checksum::XxHash64 => out.extend(xxhash_rust::xxh64::xxh64(data, 0).to_le_bytes()),
+        }
+    }
+
+    /// Reframe the records of the data committed to a file, putting the checksum of each record
+    /// in front of it
+    pub(crate) fn stamp(self, data: &[&[u8]], monitor: &Monitor) -> Vec<u8> {
+        super::frame::reframe(data, monitor, |record, out| {
+            self.compute(record, out);
+            out.extend(record);
+        })
+    }
+
+    /// Check a record against the checksum in front of it, and remove the checksum
+    ///
+    /// ## Returns
+    /// Whether the record matches its checksum, or `None` without a checksum
+    pub(crate) fn strip(self, record: &mut Vec<u8>) -> Option<bool> {
+        if self == Checksum::None {
+            return None;
+        }
+        let size = self.size().min(record.len());
+        let stored = record.drain(..size).collect::<Vec<_>>();
+        let mut computed = Vec::with_capacity(size);
+        self.compute(record, &mut computed);
+        Some(stored == computed)
+    }
+
+    /// Remove the checksum in front of a record without checking the record against it
+    pub(crate) fn skip(self, record: &mut Vec<u8>) {
+        record.drain(..self.size().min(record.len()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamp_and_strip() {
+        let data = [3, 0, 1, 2, 3, 0, 0];
+        for checksum in [Checksum::None, Checksum::Crc32c, Checksum::XxHash64] {
+            let monitor = Monitor::new(&crate::WalConfig::default());
+            let stamped = checksum.stamp(&[&data], &monitor);
+            assert_eq!(stamped.len(), 5 + checksum.size());
+            let mut record = stamped[2..].to_vec();
+            assert_eq!(
+                checksum.strip(&mut record),
+                Some(true).filter(|_| checksum.size() > 0)
+            );
+            assert_eq!(record, [1, 2, 3]);
+            let mut torn = stamped[2..].to_vec();
+            *torn.last_mut().unwrap() ^= 1;
+            assert_ne!(checksum.strip(&mut torn), Some(true));
+            assert_eq!(Checksum::from_id(checksum.id()), Some(checksum));
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::stats::Monitor;
+use std::path::{Path, PathBuf};
+
+/// Marks a record stored as it is
+///
+/// In a file with [FLAG_COMPRESSED](super::header::FLAG_COMPRESSED) set, every record starts
+/// with a byte telling how the rest of it is stored. The byte comes after the checksum and the
+/// link, if any, so they cover the record as stored.
+const STORED: u8 = 0;
+/// Marks a record compressed with zstd
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+const ZSTD: u8 = 1;
+/// Marks a record compressed with zstd and the dictionary named in the header of the file
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+const ZSTD_DICTIONARY: u8 = 2;
+/// Prefix of the names of the dictionary files, followed by the id of the dictionary
+const DICTIONARY_PREFIX: &str = "dict_";
+/// Extension of the names of the dictionary files
+const DICTIONARY_EXT: &str = ".zdict";
+
+/// A zstd dictionary, stored in the log directory and referred to by its id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Dictionary {
+    /// Identifier of the dictionary, starting at 1 as 0 means no dictionary
+    pub id: u32,
+    pub data: Vec<u8>,
+}
+
+impl Dictionary {
+    fn path(location: &Path, id: u32) -> PathBuf {
+        location.join(format!("{}{}{}", DICTIONARY_PREFIX, id, DICTIONARY_EXT))
+    }
+
+    /// Load the dictionary with the id from the log directory
+    pub fn load(location: &Path, id: u32) -> Option<Self> {
+        let data = std::fs::read(Self::path(location, id)).ok()?;
+        Some(Self { id, data })
+    }
+
+    /// Load the most recent dictionary from the log directory, if any
+    pub fn latest(location: &Path) -> Option<Self> {
+        let id = Self::ids(location).into_iter().max()?;
+        Self::load(location, id)
+    }
+
+    /// Identifiers of all the dictionaries in the log directory
+    fn ids(location: &Path) -> Vec<u32> {
+        let entries = match std::fs::read_dir(location) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                let name = name.to_str()?;
+                name.strip_prefix(DICTIONARY_PREFIX)?
+                    .strip_suffix(DICTIONARY_EXT)?
+                    .parse()
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Store the data as a new dictionary in the log directory, with the next id
+    ///
+    /// The file is written under a temporary name first, so that a crash never leaves
+    /// a partial dictionary behind.
+    #[cfg(feature = "compression")]
+    pub fn create(location: &Path, data: Vec<u8>) -> std::io::Result<Self> {
+        use std::io::Write;
+        let id = Self::ids(location).into_iter().max().unwrap_or(0) + 1;
+        let path = Self::path(location, id);
+        let temp = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, &path)?;
+        Ok(Self { id, data })
+    }
+}
+
+/// Train a dictionary on sampled records
+///
+/// ## Arguments
+/// - `samples`: Records representative of the ones to compress
+/// - `max_size`: Maximum size of the dictionary, in bytes
+#[cfg(feature = "compression")]
+pub(crate) fn train(samples: &[Vec<u8>], max_size: usize) -> std::io::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size)
+}
+
+/// Reframe the records of the data committed to a file, compressing the ones at least
+/// `threshold` bytes long, with the dictionary if any
+///
+/// A record that doesn't shrink is stored as it is, as are all of them without the
+/// `compression` feature.
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+pub(crate) fn compress(
+    data: &[&[u8]],
+    threshold: usize,
+    dictionary: Option<&Dictionary>,
+    monitor: &Monitor,
+) -> Vec<u8> {
+    #[cfg(feature = "compression")]
+    let (mut compressor, kind) = match dictionary {
+        Some(dictionary) => (
+            zstd::bulk::Compressor::with_dictionary(0, &dictionary.data).ok(),
+            ZSTD_DICTIONARY,
+        ),
+        None => (zstd::bulk::Compressor::new(0).ok(), ZSTD),
+    };
+    // the header of the file names the dictionary already
+    #[cfg(feature = "compression")]
+    if let Some(compressor) = compressor.as_mut() {
+        let _ = compressor.set_parameter(zstd::zstd_safe::CParameter::DictIdFlag(false));
+    }
+    super::frame::reframe(data, monitor, |record, out| {
+        #[cfg(feature = "compression")]
+        if let Some(compressor) = compressor.as_mut().filter(|_| record.len() >= threshold) {
+            if let Ok(compressed) = compressor.compress(record) {
+                if compressed.len() < record.len() {
+                    out.push(kind);
+                    out.extend(compressed);
+                    return;
+                }
+            }
+        }
+        out.push(STORED);
+        out.extend(record);
+    })
+}
+
+/// Restore a record as it was written, removing the byte in front of it
+///
+/// ## Arguments
+/// - `record`: The record as stored
+/// - `dictionary`: The dictionary named in the header of the file, if any
+///
+/// ## Returns
+/// `false` if the record can't be decompressed, e.g. when it's corrupted, its dictionary is
+/// missing, or it's compressed while the `compression` feature is disabled
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+pub(crate) fn decompress(record: &mut Vec<u8>, dictionary: Option<&Dictionary>) -> bool {
+    // a record can't be larger than its frame
+    #[cfg(feature = "compression")]
+    let capacity = u16::MAX as usize;
+    let decompressed = match record.first() {
+        Some(&STORED) => {
+            record.remove(0);
+            return true;
+        }
+        #[cfg(feature = "compression")]
+        Some(&ZSTD) => zstd::bulk::decompress(&record[1..], capacity).ok(),
+        #[cfg(feature = "compression")]
+        Some(&ZSTD_DICTIONARY) => dictionary.and_then(|dictionary| {
+            zstd::bulk::Decompressor::with_dictionary(&dictionary.data)
+                .and_then(|mut decompressor| decompressor.decompress(&record[1..], capacity))
+                .ok()
+        }),
+        _ => None,
+    };
+    match decompressed {
+        Some(decompressed) => {
+            *record = decompressed;
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compress_records() {
+        let records = [vec![b'a'; 1000], vec![1, 2, 3]];
+        let mut data = Vec::new();
+        for record in &records {
+            data.extend((record.len() as u16).to_le_bytes());
+            data.extend(record);
+        }
+        let monitor = Monitor::new(&crate::WalConfig::default());
+        let compressed = compress(&[&data], 100, None, &monitor);
+        // only the large record is compressed
+        let first = u16::from_le_bytes([compressed[0], compressed[1]]) as usize;
+        assert!(first < 100);
+        assert_eq!(compressed[2], ZSTD);
+        let mut restored = Vec::new();
+        let mut pos = 0;
+        while pos < compressed.len() {
+            let size = u16::from_le_bytes([compressed[pos], compressed[pos + 1]]) as usize;
+            let mut record = compressed[pos + 2..pos + 2 + size].to_vec();
+            assert!(decompress(&mut record, None));
+            restored.push(record);
+            pos += 2 + size;
+        }
+        assert_eq!(restored, records);
+        // a corrupted record
+        let mut record = compressed[2..2 + first].to_vec();
+        record[5] ^= 0xff;
+        assert!(!decompress(&mut record, None));
+    }
+
+    #[test]
+    fn stored() {
+        let mut record = vec![STORED, 7, 8];
+        assert!(decompress(&mut record, None));
+        assert_eq!(record, [7, 8]);
+        assert!(!decompress(&mut vec![9, 7, 8], None));
+        assert!(!decompress(&mut Vec::new(), None));
+    }
+}
@@ -0,0 +1,82 @@
+use super::manager::{read_slot, write_slot};
+use crate::LifetimeStats;
+use std::path::{Path, PathBuf};
+
+/// Name of the slots of the lifetime counters in the log directory, followed by the slot number
+const COUNTERS_FILE: &str = "counters";
+
+/// The [LifetimeStats] of a WAL, kept along with the logs across restarts
+///
+/// The counters are stored in two slots written alternately, as the hard state is, so a crash
+/// while writing them leaves the previous ones intact. The slot holds the counters on a single
+/// line, as `name=value`, and the names not known are ignored.
+pub(crate) struct CountersFile {
+    slots: [PathBuf; 2],
+}
+
+impl CountersFile {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            slots: [0, 1].map(|slot| dir.join(format!("{}.{}", COUNTERS_FILE, slot))),
+        }
+    }
+
+    /// Read the counters, all zero if they were never written
+    pub fn read(&self) -> LifetimeStats {
+        let mut stats = LifetimeStats::default();
+        let values = read_slot(&self.slots).map(|(_, values)| values);
+        for pair in values.unwrap_or_default().split_whitespace() {
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name, value.parse().unwrap_or(0)),
+                None => continue,
+            };
+            match name {
+                "records" => stats.records = value,
+                "bytes" => stats.bytes = value,
+                "segments_collected" => stats.segments_collected = value,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Add to the counters stored, syncing them to disk
+    pub fn add(&self, delta: &LifetimeStats) -> std::io::Result<()> {
+        let stats = self.read().add(delta);
+        let values = format!(
+            "records={} bytes={} segments_collected={}",
+            stats.records, stats.bytes, stats.segments_collected
+        );
+        write_slot(&self.slots, &values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_file() {
+        let dir = Path::new("./tmp/counters_file");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let file = CountersFile::new(dir);
+        assert_eq!(file.read(), LifetimeStats::default());
+        let delta = LifetimeStats {
+            records: 10,
+            bytes: 1000,
+            segments_collected: 1,
+        };
+        file.add(&delta).unwrap();
+        file.add(&delta).unwrap();
+        let expected = LifetimeStats {
+            records: 20,
+            bytes: 2000,
+            segments_collected: 2,
+        };
+        assert_eq!(file.read(), expected);
+        // a torn write of the other slot leaves the counters intact
+        std::fs::write(dir.join("counters.0"), "records=1").unwrap();
+        assert_eq!(file.read(), expected);
+    }
+}
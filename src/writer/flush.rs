@@ -0,0 +1,186 @@
+use crate::listener::Operation;
+use crate::stats::Monitor;
+use std::fs::File;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+#[cfg(not(target_os = "wasi"))]
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
+#[cfg(not(target_os = "wasi"))]
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Handle to an in-progress flush, returned by [Wal::flush](crate::Wal::flush)
+///
+/// The flushed data is handed over to the OS before the handle is returned, while the sync to
+/// the disk runs in the background. Call [FlushHandle::wait] once the data must be durable,
+/// e.g. before acknowledging a client.
+///
+/// Dropping the handle does not cancel the sync.
+pub struct FlushHandle {
+    rx: Option<Receiver<std::io::Result<()>>>,
+    /// Outcome of the sync, once it has been received
+    result: Option<std::io::Result<()>>,
+}
+
+impl FlushHandle {
+    /// A handle for a flush that is already durable
+    pub(crate) fn done() -> Self {
+        Self::finished(Ok(()))
+    }
+
+    /// A handle for a flush whose sync is over, with its outcome
+    pub(crate) fn finished(result: std::io::Result<()>) -> Self {
+        Self {
+            rx: None,
+            result: Some(result),
+        }
+    }
+
+    /// Check whether the flushed data has been synced to disk, without blocking
+    pub fn is_done(&mut self) -> bool {
+        if self.result.is_some() {
+            return true;
+        }
+        let rx = match self.rx.as_ref() {
+            None => return true,
+            Some(rx) => rx,
+        };
+        match rx.try_recv() {
+            Ok(result) => self.result = Some(result),
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => self.result = Some(Ok(())),
+        }
+        self.rx = None;
+        true
+    }
+
+    /// Block until the flushed data has been synced to disk
+    pub fn wait(self) -> std::io::Result<()> {
+        if let Some(result) = self.result {
+            return result;
+        }
+        match self.rx {
+            None => Ok(()),
+            Some(rx) => rx.recv().unwrap_or(Ok(())),
+        }
+    }
+}
+
+/// Syncs the current file of a writer to disk for its flushes
+///
+/// The syncs run one after the other on a thread of the writer, started by the first flush
+/// syncing in the background, and stopped along with the writer. WASI has no threads, so the
+/// syncs run on the calling thread there.
+pub(crate) struct Syncer {
+    monitor: Monitor,
+    /// Number of the write up to which the data is synced, see [FileManager::writes]
+    ///
+    /// [FileManager::writes]: super::manager::FileManager::writes
+    synced: Arc<AtomicU64>,
+    /// Hands over the syncs to the thread, `None` if it couldn't be started
+    #[cfg(not(target_os = "wasi"))]
+    tx: OnceLock<Option<Sender<SyncJob>>>,
+}
+
+/// A sync handed over to the thread of a [Syncer]
+#[cfg(not(target_os = "wasi"))]
+struct SyncJob {
+    file: File,
+    segment: usize,
+    writes: u64,
+    done: Sender<std::io::Result<()>>,
+}
+
+impl Syncer {
+    pub fn new(monitor: Monitor) -> Self {
+        Self {
+            monitor,
+            synced: Arc::new(AtomicU64::new(0)),
+            #[cfg(not(target_os = "wasi"))]
+            tx: OnceLock::new(),
+        }
+    }
+
+    /// Whether the data is synced up to the given write
+    pub fn is_synced(&self, writes: u64) -> bool {
+        self.synced.load(Relaxed) >= writes
+    }
+
+    /// Sync the file on the calling thread
+    ///
+    /// ## Arguments
+    /// - `file`: The current file
+    /// - `segment`: Index of the file
+    /// - `writes`: Number of the newest write to the file
+    pub fn sync_now(&self, file: File, segment: usize, writes: u64) -> FlushHandle {
+        let result = sync(&file, &self.monitor, &self.synced, segment, writes);
+        FlushHandle::finished(result)
+    }
+
+    /// Sync the file on the thread of the writer, see [Syncer::sync_now] for the arguments
+    #[cfg(not(target_os = "wasi"))]
+    pub fn sync(&self, file: File, segment: usize, writes: u64) -> FlushHandle {
+        let tx = match self.tx.get_or_init(|| self.start()) {
+            Some(tx) => tx,
+            None => return self.sync_now(file, segment, writes),
+        };
+        let (done, rx) = std::sync::mpsc::channel();
+        let job = SyncJob {
+            file,
+            segment,
+            writes,
+            done,
+        };
+        match tx.send(job) {
+            Ok(()) => FlushHandle {
+                rx: Some(rx),
+                result: None,
+            },
+            Err(e) => self.sync_now(e.0.file, segment, writes),
+        }
+    }
+
+    /// Sync the file right away, as WASI has no threads to do it in the background
+    #[cfg(target_os = "wasi")]
+    pub fn sync(&self, file: File, segment: usize, writes: u64) -> FlushHandle {
+        self.sync_now(file, segment, writes)
+    }
+
+    /// Start the thread running the syncs, until the writer drops its end of the channel
+    #[cfg(not(target_os = "wasi"))]
+    fn start(&self) -> Option<Sender<SyncJob>> {
+        let (tx, rx) = std::sync::mpsc::channel::<SyncJob>();
+        let (monitor, synced) = (self.monitor.clone(), self.synced.clone());
+        std::thread::Builder::new()
+            .name("walcraft-sync".to_string())
+            .spawn(move || {
+                for job in rx {
+                    let result = sync(&job.file, &monitor, &synced, job.segment, job.writes);
+                    let _ = job.done.send(result);
+                }
+            })
+            .ok()
+            .map(|_| tx)
+    }
+}
+
+/// Sync a file to disk, and count the writes as synced unless that failed
+fn sync(
+    file: &File,
+    monitor: &Monitor,
+    synced: &AtomicU64,
+    segment: usize,
+    writes: u64,
+) -> std::io::Result<()> {
+    let start = Instant::now();
+    let result = file.sync_data();
+    match result.as_ref() {
+        Ok(()) => {
+            synced.fetch_max(writes, Relaxed);
+        }
+        Err(e) => monitor.sync_failed(e),
+    }
+    monitor.observe(Operation::Fsync, segment, start);
+    result
+}
@@ -0,0 +1,644 @@
+use super::header::{read_header, Format};
+use super::page::PageReader;
+use crate::stats::Monitor;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// Frame marking padding instead of a record, as records are never empty
+///
+/// The marker is followed by the number of padding bytes after it, framed the same way, so the
+/// readers skip the padding without having to guess where it ends. Files written by older
+/// versions pad with zeros, which read as a run of markers followed by no padding.
+pub(crate) const PADDING: u16 = 0;
+
+/// Padding taking up `len` bytes in total, or the 4 bytes of its marker and size if more
+///
+/// More padding than a single frame holds is split into a run of frames.
+pub(crate) fn padding(len: usize) -> Vec<u8> {
+    let mut left = len.max(4);
+    let mut padding = Vec::with_capacity(left);
+    while left > 0 {
+        let mut size = u16::try_from(left - 4).unwrap_or(u16::MAX);
+        // leaving room for the marker and size of the next frame
+        if (1..4).contains(&(left - 4 - size as usize)) {
+            size -= 4;
+        }
+        padding.extend(PADDING.to_le_bytes());
+        padding.extend(size.to_le_bytes());
+        padding.resize(padding.len() + size as usize, 0);
+        left -= 4 + size as usize;
+    }
+    padding
+}
+
+/// What a record holds, told by the byte every record starts with
+///
+/// Apart from the logs, the WAL holds control records for its own bookkeeping, which take up
+/// an [Lsn](crate::Lsn) like the logs do. Reading the logs skips them, while the low-level
+/// readers, such as [Wal::read_raw](crate::Wal::read_raw), yield them along with the logs.
+/// The files written by older versions only hold logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum RecordKind {
+    /// A log written to the WAL
+    #[default]
+    Log,
+    /// Marks a checkpoint, all the logs before it being applied
+    Checkpoint,
+    /// Marks the end of a file, the next records being in the next file
+    Rotation,
+    /// Points at a snapshot of the state built from the logs
+    Snapshot,
+    /// Marks the start of a transaction
+    TxnBegin,
+    /// Marks the commit of a transaction
+    TxnCommit,
+    /// A control record written by a newer version, unknown to this one
+    Other(u8),
+}
+
+impl RecordKind {
+    /// Byte stored in front of the records of this kind
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            RecordKind::Log => 0,
+            RecordKind::Checkpoint => 1,
+            RecordKind::Rotation => 2,
+            RecordKind::Snapshot => 3,
+            RecordKind::TxnBegin => 4,
+            RecordKind::TxnCommit => 5,
+            RecordKind::Other(id) => id,
+        }
+    }
+
+    /// The kind stored as the given byte
+    pub(crate) fn from_id(id: u8) -> Self {
+        match id {
+            0 => RecordKind::Log,
+            1 => RecordKind::Checkpoint,
+            2 => RecordKind::Rotation,
+            3 => RecordKind::Snapshot,
+            4 => RecordKind::TxnBegin,
+            5 => RecordKind::TxnCommit,
+            id => RecordKind::Other(id),
+        }
+    }
+
+    /// Whether the record is a control record rather than a log
+    pub fn is_control(self) -> bool {
+        self.id() != RecordKind::Log.id()
+    }
+
+    /// Whether the kind can be stored, its byte leaving room for [FLAG_TERM]
+    pub(crate) fn is_storable(self) -> bool {
+        self.id() & FLAG_TERM == 0
+    }
+}
+
+/// Set in the byte of the [RecordKind] when the record carries a term, stored right after it
+const FLAG_TERM: u8 = 0x80;
+
+/// What's stored in front of a record, right after its size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct RecordHeader {
+    pub kind: RecordKind,
+    /// Term, or epoch, of the leader that appended the record, see
+    /// [WriteOptions::term](crate::WriteOptions::term)
+    pub term: Option<u64>,
+}
+
+impl RecordHeader {
+    /// The header of a log
+    pub fn log(term: Option<u64>) -> Self {
+        Self {
+            kind: RecordKind::Log,
+            term,
+        }
+    }
+
+    /// Number of bytes taken by the header
+    pub fn len(&self) -> usize {
+        1 + self.term.map_or(0, |_| 8)
+    }
+
+    /// Push the header to the output: the byte of the kind, then the term as a little-endian
+    /// `u64` if any
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self.term {
+            Some(term) => {
+                out.push(self.kind.id() | FLAG_TERM);
+                out.extend(term.to_le_bytes());
+            }
+            None => out.push(self.kind.id()),
+        }
+    }
+
+    /// Remove the header from the front of a record
+    ///
+    /// ## Returns
+    /// `None` if the record is too short for its header, which only happens when it's corrupted
+    pub fn strip(record: &mut Vec<u8>) -> Option<Self> {
+        let id = *record.first()?;
+        let header = match id & FLAG_TERM {
+            0 => Self {
+                kind: RecordKind::from_id(id),
+                term: None,
+            },
+            _ => Self {
+                kind: RecordKind::from_id(id & !FLAG_TERM),
+                term: Some(u64::from_le_bytes(record.get(1..9)?.try_into().unwrap())),
+            },
+        };
+        record.drain(..header.len());
+        Some(header)
+    }
+}
+
+/// Frame a record the way it's stored in the log files, with its size as a little-endian `u16`
+/// in front of it
+///
+/// The record is framed as it is, so in a file written with per-log checksums, a hash chain or
+/// per-log compression, it's expected to start with those, followed by the byte of its
+/// [RecordKind], see [decode_record].
+///
+/// ## Returns
+/// An error if the record is empty, which would read as padding, or longer than 65535 bytes
+pub fn encode_record(record: &[u8]) -> Result<Vec<u8>, String> {
+    match u16::try_from(record.len()) {
+        Ok(0) => Err("An empty record can't be framed".to_string()),
+        Ok(size) => {
+            let mut framed = Vec::with_capacity(2 + record.len());
+            framed.extend(size.to_le_bytes());
+            framed.extend(record);
+            Ok(framed)
+        }
+        Err(_) => Err(format!(
+            "A record of {} bytes is too large to be framed",
+            record.len()
+        )),
+    }
+}
+
+/// Decode the record at the start of framed data, e.g. the data of a log file after its header,
+/// skipping the padding in front of it
+///
+/// This is the reverse of [encode_record], handy for external tools and fuzzers working on the
+/// format without a [Wal](crate::Wal). The record is returned as stored, which in a file
+/// written with per-log checksums, a hash chain or per-log compression starts with the
+/// checksum, then the link of the previous record, then the compression marker. The byte of its
+/// [RecordKind] comes next, compressed along with the rest of the record if at all.
+///
+/// ## Returns
+/// The record along with the number of bytes consumed, padding and frame included, or an error
+/// if the data ends before the end of the record
+pub fn decode_record(data: &[u8]) -> Result<(&[u8], usize), String> {
+    let mut pos = 0;
+    loop {
+        let size = match data.get(pos..pos + 2) {
+            Some(frame) => u16::from_le_bytes([frame[0], frame[1]]),
+            None => {
+                return Err(format!(
+                    "The data ends at byte {} inside a frame",
+                    data.len()
+                ))
+            }
+        };
+        let (start, len) = match size == PADDING {
+            true => match data.get(pos + 2..pos + 4) {
+                Some(len) => (pos + 4, u16::from_le_bytes([len[0], len[1]]) as usize),
+                None => return Err("The data ends inside the size of the padding".to_string()),
+            },
+            false => (pos + 2, size as usize),
+        };
+        if data.len() < start + len {
+            return Err(format!(
+                "The data ends {} bytes short of the end of the record at byte {}",
+                start + len - data.len(),
+                pos
+            ));
+        }
+        if size != PADDING {
+            return Ok((&data[start..start + len], start + len));
+        }
+        pos = start + len;
+    }
+}
+
+/// Skip the padding of a reader positioned right after its marker
+///
+/// ## Returns
+/// The number of bytes skipped, size of the padding included, or `None` if the reader ends
+/// before the end of the padding
+pub(crate) fn skip_padding(mut reader: impl Read, format: Format) -> std::io::Result<Option<u64>> {
+    let mut size = [0; 2];
+    match reader.read_exact(&mut size) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let size = format.frame_size(size) as u64;
+    let skipped = std::io::copy(&mut reader.take(size), &mut std::io::sink())?;
+    Ok((skipped == size).then_some(2 + size))
+}
+
+/// Counts the records in a stream of framed data, which is fed in chunks of any size
+///
+/// Every record is framed with its size as a `u16`, followed by the serialized record.
+/// The data fed excludes the header of the file.
+#[derive(Debug, Default)]
+pub(crate) struct RecordCounter {
+    /// Layout of the file the data comes from
+    format: Format,
+    /// Number of records whose header has been seen
+    count: u64,
+    /// Bytes of the current record still to be skipped
+    skip: usize,
+    /// First byte of a header split across two chunks
+    partial: Option<u8>,
+    /// Whether the next frame holds the size of padding, following its marker
+    padding: bool,
+    /// Number of bytes fed so far
+    seen: u64,
+    /// Number of bytes covered by complete records
+    complete: u64,
+}
+
+impl RecordCounter {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            ..Self::default()
+        }
+    }
+
+    /// Feed the next chunk of data
+    pub fn update(&mut self, data: &[u8]) {
+        let mut pos = 0;
+        while pos < data.len() {
+            if self.skip > 0 {
+                let n = std::cmp::min(self.skip, data.len() - pos);
+                self.skip -= n;
+                pos += n;
+                if self.skip == 0 {
+                    self.complete = self.seen + pos as u64;
+                }
+                continue;
+            }
+            let size = match self.partial.take() {
+                Some(first) => {
+                    pos += 1;
+                    self.format.frame_size([first, data[pos - 1]])
+                }
+                None if pos + 1 == data.len() => {
+                    self.partial = Some(data[pos]);
+                    break;
+                }
+                None => {
+                    pos += 2;
+                    self.format.frame_size([data[pos - 2], data[pos - 1]])
+                }
+            };
+            if self.padding {
+                self.padding = false;
+                self.skip = size as usize;
+                if size == 0 {
+                    self.complete = self.seen + pos as u64;
+                }
+            } else if size == PADDING {
+                self.padding = true;
+            } else {
+                self.count += 1;
+                self.skip = size as usize;
+            }
+        }
+        self.seen += data.len() as u64;
+    }
+
+    /// Number of records seen so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Number of bytes covered by complete records, anything after it is a partial record
+    pub fn complete(&self) -> u64 {
+        self.complete
+    }
+}
+
+/// Read a file from its start, passing every complete record to `f` as stored after its frame
+///
+/// The records of a hash-chained file, or of one with a checksum per record, include the link
+/// and the checksum in front of them.
+pub(crate) fn for_each_record(
+    mut reader: impl Read,
+    mut f: impl FnMut(&[u8]),
+) -> std::io::Result<()> {
+    let (format, prefix) = read_header(&mut reader)?;
+    if prefix.len() < format.header_len {
+        return Ok(());
+    }
+    // a file written by an older version has its first record in the prefix
+    let rest = prefix[format.header_len..].to_vec();
+    let mut reader = std::io::Cursor::new(rest).chain(records(reader, format));
+    let mut frame = [0; 2];
+    let mut record = Vec::new();
+    loop {
+        match reader.read_exact(&mut frame) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let size = format.frame_size(frame);
+        if size == PADDING {
+            match skip_padding(&mut reader, format)? {
+                Some(_) => continue,
+                None => return Ok(()),
+            }
+        }
+        record.resize(size as usize, 0);
+        match reader.read_exact(&mut record) {
+            Ok(()) => f(&record),
+            // a partial record left by a crash
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reframe the records of the data committed to a file, transforming each one
+///
+/// The size in the frame of a record is updated to cover what was pushed for it, e.g. extra
+/// bytes in front of it, and the padding between the records is dropped.
+///
+/// ## Arguments
+/// - `data`: Framed records, possibly along with padding
+/// - `monitor`: Reports the records dropped, as they grew too large to be framed
+/// - `f`: Called with every record, to push the transformed record to the output
+pub(crate) fn reframe(
+    data: &[&[u8]],
+    monitor: &Monitor,
+    mut f: impl FnMut(&[u8], &mut Vec<u8>),
+) -> Vec<u8> {
+    let data = data.concat();
+    let mut framed = Vec::with_capacity(data.len());
+    let mut pos = 0;
+    while pos + 2 <= data.len() {
+        let size = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        if size == PADDING as usize {
+            pos += match data.get(pos + 2..pos + 4) {
+                Some(len) => 4 + u16::from_le_bytes([len[0], len[1]]) as usize,
+                None => 2,
+            };
+            continue;
+        }
+        let record = &data[pos + 2..(pos + 2 + size).min(data.len())];
+        pos += 2 + size;
+        let start = framed.len();
+        framed.extend([0; 2]);
+        f(record, &mut framed);
+        match u16::try_from(framed.len() - start - 2) {
+            Ok(len) => framed[start..start + 2].copy_from_slice(&len.to_le_bytes()),
+            Err(_) => {
+                let message = format!("Dropped a log of {} bytes, too large to be framed", size);
+                monitor.report(message, Some(std::io::ErrorKind::InvalidData));
+                framed.truncate(start);
+            }
+        }
+    }
+    framed
+}
+
+/// Count the records in a file, read from its start
+pub(crate) fn count_records(reader: impl Read) -> std::io::Result<u64> {
+    scan(reader, |_| {}).map(|(count, _)| count)
+}
+
+/// Read a file from its start and count the records in it, passing every chunk read to `f`
+///
+/// ## Returns
+/// A tuple with 2 values:
+/// - 0: the number of records
+/// - 1: the number of bytes covered by the header and the complete records, not counting the
+///   page framing of the files written in the power-loss-safe mode
+pub(crate) fn scan(reader: impl Read, f: impl FnMut(&[u8])) -> std::io::Result<(u64, u64)> {
+    let mut reader = Tap { inner: reader, f };
+    let (format, prefix) = read_header(&mut reader)?;
+    // a header cut short covers nothing
+    if prefix.len() < format.header_len {
+        return Ok((0, 0));
+    }
+    let mut counter = RecordCounter::new(format);
+    counter.update(&prefix[format.header_len..]);
+    let mut reader = records(reader, format);
+    let mut data = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut data)?;
+        if n == 0 {
+            break;
+        }
+        counter.update(&data[..n]);
+    }
+    Ok((
+        counter.count(),
+        format.header_len as u64 + counter.complete(),
+    ))
+}
+
+/// The records of a file, read from right after its header
+///
+/// The records of the files written in the power-loss-safe mode are read out of their pages.
+pub(crate) fn records<'a>(reader: impl Read + 'a, format: Format) -> Box<dyn Read + 'a> {
+    match format.paged {
+        true => Box::new(PageReader::new(reader, format.page_size)),
+        false => Box::new(reader),
+    }
+}
+
+/// Passes everything read from the inner reader to a closure
+struct Tap<R, F> {
+    inner: R,
+    f: F,
+}
+
+impl<R: Read, F: FnMut(&[u8])> Read for Tap<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        (self.f)(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Find where a record starts in a file, read from its start
+///
+/// ## Arguments
+/// - `reader`: The file, best buffered as it's read a frame at a time
+/// - `position`: Number of records before the wanted one
+///
+/// ## Returns
+/// The offset of the record in the file, which is the end of the file if it has exactly
+/// `position` records, or `None` if it has fewer. The page framing of the files written in the
+/// power-loss-safe mode isn't counted.
+pub(crate) fn offset_of(mut reader: impl Read, position: u64) -> std::io::Result<Option<u64>> {
+    let (format, prefix) = read_header(&mut reader)?;
+    if prefix.len() > format.header_len {
+        // a file written by an older version, whose first record is in the prefix
+        return Ok(None);
+    }
+    let mut reader = records(reader, format);
+    let mut offset = prefix.len() as u64;
+    let mut records = 0;
+    let mut frame = [0; 2];
+    loop {
+        if let Err(e) = reader.read_exact(&mut frame) {
+            return match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => Ok((records == position).then_some(offset)),
+                _ => Err(e),
+            };
+        }
+        let size = format.frame_size(frame);
+        if size == PADDING {
+            match skip_padding(&mut reader, format)? {
+                Some(skipped) => offset += 2 + skipped,
+                None => return Ok((records == position).then_some(offset)),
+            }
+            continue;
+        }
+        if records == position {
+            return Ok(Some(offset));
+        }
+        let size = size as u64;
+        let skipped = std::io::copy(&mut (&mut reader).take(size), &mut std::io::sink())?;
+        if skipped < size {
+            return Ok(None);
+        }
+        records += 1;
+        offset += 2 + size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_chunks() {
+        let mut data = Vec::new();
+        for size in [1u16, 300, 2] {
+            data.extend(size.to_le_bytes());
+            data.extend(vec![9; size as usize]);
+        }
+        data.extend([0; 4]);
+        // every possible split point, including inside a header
+        for split in 0..data.len() {
+            let mut counter = RecordCounter::default();
+            counter.update(&data[..split]);
+            counter.update(&data[split..]);
+            assert_eq!(counter.count(), 3);
+            assert_eq!(counter.complete(), data.len() as u64);
+        }
+        // a record cut short
+        let mut counter = RecordCounter::default();
+        counter.update(&data[..100]);
+        assert_eq!(counter.complete(), 3);
+    }
+
+    #[test]
+    fn record_kinds() {
+        for id in 0..=u8::MAX {
+            assert_eq!(RecordKind::from_id(id).id(), id);
+        }
+        assert!(!RecordKind::Log.is_control());
+        assert!(RecordKind::Snapshot.is_control());
+        // unknown to this version, but not a log either
+        assert!(RecordKind::from_id(200).is_control());
+        assert!(!RecordKind::Other(0).is_control());
+    }
+
+    #[test]
+    fn record_headers() {
+        let headers = [
+            RecordHeader::log(None),
+            RecordHeader::log(Some(7)),
+            RecordHeader {
+                kind: RecordKind::TxnBegin,
+                term: Some(u64::MAX),
+            },
+        ];
+        for header in headers {
+            let mut record = Vec::new();
+            header.encode(&mut record);
+            assert_eq!(record.len(), header.len());
+            record.extend([1, 2, 3]);
+            assert_eq!(RecordHeader::strip(&mut record), Some(header));
+            assert_eq!(record, [1, 2, 3]);
+        }
+        // a term cut short
+        assert_eq!(RecordHeader::strip(&mut vec![FLAG_TERM, 1, 2]), None);
+        assert_eq!(RecordHeader::strip(&mut vec![]), None);
+        assert!(!RecordKind::Other(FLAG_TERM).is_storable());
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut data = encode_record(&[1, 2, 3]).unwrap();
+        assert_eq!(data, [3, 0, 1, 2, 3]);
+        data.extend(super::padding(6));
+        data.extend(encode_record(&[4; 300]).unwrap());
+        let (record, consumed) = decode_record(&data).unwrap();
+        assert_eq!((record, consumed), (&[1, 2, 3][..], 5));
+        // the padding is skipped
+        let (record, consumed) = decode_record(&data[5..]).unwrap();
+        assert_eq!((record, consumed), (&[4; 300][..], 6 + 302));
+        // the data cut short at every point before the end of the record
+        for end in 0..data.len() - 5 {
+            assert!(decode_record(&data[5..5 + end]).is_err());
+        }
+        assert!(encode_record(&[]).is_err());
+        assert!(encode_record(&[0; 65535]).is_ok());
+        assert!(encode_record(&[0; 65536]).is_err());
+    }
+
+    #[test]
+    fn padding() {
+        let mut data = super::super::header::header_with(0, Default::default()).to_vec();
+        // more padding than a frame holds, including a few bytes more
+        for (size, pad) in [(256u16, 10), (3, 1), (512, 0), (7, 70_000), (8, 65_540)] {
+            data.extend(size.to_le_bytes());
+            data.extend(vec![9; size as usize]);
+            data.extend(super::padding(pad));
+        }
+        assert_eq!(super::padding(1).len(), 4);
+        assert_eq!(super::padding(70_000).len(), 70_000);
+        assert_eq!(super::padding(65_540).len(), 65_540);
+        let mut records = Vec::new();
+        for_each_record(&data[..], |r| records.push(r.len())).unwrap();
+        assert_eq!(records, [256, 3, 512, 7, 8]);
+        assert_eq!(count_records(&data[..]).unwrap(), 5);
+        let (_, complete) = scan(&data[..], |_| {}).unwrap();
+        assert_eq!(complete, data.len() as u64);
+        assert_eq!(super::offset_of(&data[..], 1).unwrap(), Some(16 + 258 + 10));
+        // the padding is dropped when reframing
+        let monitor = Monitor::new(&crate::WalConfig::default());
+        let reframed = reframe(&[&data[16..]], &monitor, |r, out| out.extend(r));
+        assert_eq!(reframed.len(), 258 + 5 + 514 + 9 + 10);
+    }
+
+    #[test]
+    fn offset_of() {
+        let mut data = super::super::header::header_with(0, Default::default()).to_vec();
+        for size in [1u16, 300] {
+            data.extend(size.to_le_bytes());
+            data.extend(vec![9; size as usize]);
+        }
+        data.extend([0; 4]);
+        data.extend(2u16.to_le_bytes());
+        data.extend([9; 2]);
+        let offset = |position| super::offset_of(&data[..], position).unwrap();
+        assert_eq!(offset(0), Some(16));
+        assert_eq!(offset(1), Some(19));
+        // past the padding
+        assert_eq!(offset(2), Some(325));
+        // the end of the file, where the next record goes
+        assert_eq!(offset(3), Some(329));
+        assert_eq!(offset(4), None);
+    }
+}
@@ -0,0 +1,121 @@
+use super::manager::FileManager;
+use crate::listener::Operation;
+use crate::stats::Monitor;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Coalesces the syncs of concurrent writes into one, see
+/// [WalBuilder::sync_window](crate::WalBuilder::sync_window)
+///
+/// The first write to wait for a sync leads: it waits out the window, then syncs the file once
+/// for all the writes made meanwhile. The writes arriving while it syncs wait for it, and the
+/// ones it doesn't cover are synced by the next leader.
+pub(crate) struct SyncGroup {
+    window: Duration,
+    state: Mutex<State>,
+    synced: Condvar,
+    monitor: Monitor,
+}
+
+#[derive(Default)]
+struct State {
+    /// Number of the newest write, see [FileManager::writes]
+    written: u64,
+    /// Number of the write up to which the data is synced to disk
+    synced: u64,
+    /// Whether a write is waiting out the window, or syncing, for the others
+    leading: bool,
+    /// The current file as of the newest write, along with its index
+    file: Option<(usize, Arc<File>)>,
+    /// Writes covered by the last sync, and its error if it failed
+    last: (u64, u64, Option<ErrorKind>),
+}
+
+impl SyncGroup {
+    pub fn new(window: Duration, monitor: Monitor) -> Self {
+        Self {
+            window,
+            state: Mutex::new(State::default()),
+            synced: Condvar::new(),
+            monitor,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Register the writes made so far, to be called while holding the file manager
+    ///
+    /// ## Returns
+    /// The number of the newest write, to wait for with [SyncGroup::wait]
+    pub fn join(&self, io: &FileManager) -> u64 {
+        let mut state = self.state();
+        state.written = state.written.max(io.writes());
+        // the files are synced once filled, so only the current one needs it
+        let current = io.current();
+        if state
+            .file
+            .as_ref()
+            .is_none_or(|(index, _)| *index != current)
+        {
+            state.file = io.file().map(|file| (current, Arc::new(file)));
+        }
+        io.writes()
+    }
+
+    /// Block until the writes up to the given one are synced to disk
+    ///
+    /// ## Returns
+    /// The error of the sync covering the write, if it failed
+    pub fn wait(&self, write: u64) -> std::io::Result<()> {
+        let mut state = self.state();
+        loop {
+            if state.synced >= write {
+                return match state.last {
+                    (from, to, Some(kind)) if from < write && write <= to => Err(kind.into()),
+                    _ => Ok(()),
+                };
+            }
+            if !state.leading {
+                break;
+            }
+            state = self
+                .synced
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        state.leading = true;
+        drop(state);
+        // the concurrent writes join meanwhile
+        std::thread::sleep(self.window);
+        let (from, to, file) = {
+            let state = self.state();
+            (state.synced, state.written, state.file.clone())
+        };
+        let result = match file {
+            Some((index, file)) => {
+                let start = Instant::now();
+                let result = file.sync_data();
+                if let Err(e) = result.as_ref() {
+                    self.monitor.sync_failed(e);
+                }
+                self.monitor.observe(Operation::Fsync, index, start);
+                result
+            }
+            None => Ok(()),
+        };
+        // every write covered but the one leading had its sync coalesced
+        self.monitor
+            .coalesced(to.saturating_sub(from).saturating_sub(1));
+        let mut state = self.state();
+        state.synced = state.synced.max(to);
+        state.leading = false;
+        state.last = (from, to, result.as_ref().err().map(|e| e.kind()));
+        drop(state);
+        self.synced.notify_all();
+        result
+    }
+}
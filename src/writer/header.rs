@@ -0,0 +1,323 @@
+use super::checksum::Checksum;
+use super::page::PAGE_SIZE;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read};
+
+/// Marks a file that starts with a header
+const MAGIC: [u8; 4] = *b"WALC";
+/// Version of the format of the files written by this version of the crate
+///
+/// Every version released so far stays readable: the files without a header are version 0,
+/// and [Format::detect] decodes each header according to its version.
+///
+/// Changes in version 2: the size of the pages is recorded in the header, it was always
+/// [PAGE_SIZE] before.
+///
+/// Changes in version 3: every record starts with a byte telling its kind, see
+/// [RecordKind](super::frame::RecordKind), followed by the term it was appended in, if any.
+pub(crate) const FORMAT_VERSION: u16 = 3;
+/// Flag set when the sizes of the records are little-endian
+const FLAG_LITTLE_ENDIAN: u16 = 1;
+/// Flag set when the records are stored in checksummed pages, see [PAGE_SIZE]
+pub(crate) const FLAG_PAGED: u16 = 2;
+/// Flag set when every record starts with the hash of the records before it, see [LINK_SIZE](super::chain::LINK_SIZE)
+pub(crate) const FLAG_CHAINED: u16 = 4;
+/// Flag set when every record starts with a byte telling how it's compressed, see [decompress](super::compress::decompress)
+pub(crate) const FLAG_COMPRESSED: u16 = 8;
+/// Size of the header at the start of every file
+///
+/// Layout, with all the integers in little-endian:
+/// - 4 bytes: [MAGIC]
+/// - 2 bytes: format version
+/// - 2 bytes: flags
+/// - 1 byte: algorithm of the checksum in front of every record, see [Checksum::id]
+/// - 4 bytes: id of the zstd dictionary of the compressed records, zero for none
+/// - 1 byte: base-2 logarithm of the size of the pages, since version 2
+/// - 2 bytes: reserved, zeroed
+pub(crate) const HEADER_SIZE: usize = 16;
+
+/// How to treat the log files written by a newer version of the crate, in a format unknown to
+/// this one, set with [WalBuilder::format_policy](crate::WalBuilder::format_policy)
+///
+/// It matters when binaries built with different versions of walcraft share a directory, e.g.
+/// during a rolling upgrade, or when a tool reads the WAL of a newer service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FormatPolicy {
+    /// Read the known files, skipping the unknown ones and reporting them as errors, and keep
+    /// writing new files in the format of this version
+    #[default]
+    Lenient,
+    /// Refuse to open the WAL while it holds unknown files
+    Strict,
+    /// Read the known files, skipping the unknown ones, but leave the files untouched: nothing is
+    /// written, and the logs are refused
+    ReadOnly,
+}
+
+/// The header of a new file, with extra flags set for the optional features of the format
+pub(crate) fn header_with(flags: u16, checksum: Checksum) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header[6..8].copy_from_slice(&(FLAG_LITTLE_ENDIAN | flags).to_le_bytes());
+    header[8] = checksum.id();
+    header[13] = PAGE_SIZE.trailing_zeros() as u8;
+    header
+}
+
+/// How the records are laid out in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Format {
+    /// Version of the format, zero for the files written without a header
+    pub version: u16,
+    /// Size of the header, zero for the files written by older versions
+    pub header_len: usize,
+    /// Whether the sizes of the records are big-endian
+    pub big_endian: bool,
+    /// Whether the records are stored in checksummed pages after the header
+    pub paged: bool,
+    /// Whether every record starts with the hash of the records before it
+    pub chained: bool,
+    /// Whether every record may be compressed on its own
+    pub compressed: bool,
+    /// Id of the dictionary the records are compressed with, zero for none
+    pub dictionary: u32,
+    /// Checksum in front of every record, ahead of the hash if any
+    pub checksum: Checksum,
+    /// Size of the pages, see [PAGE_SIZE]
+    pub page_size: usize,
+    /// Whether every record starts with its kind, see [RecordKind](super::frame::RecordKind)
+    pub kinds: bool,
+}
+
+impl Default for Format {
+    /// The format of the files written by this version
+    fn default() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            header_len: HEADER_SIZE,
+            big_endian: false,
+            paged: false,
+            chained: false,
+            compressed: false,
+            dictionary: 0,
+            checksum: Checksum::None,
+            page_size: PAGE_SIZE,
+            kinds: true,
+        }
+    }
+}
+
+impl Format {
+    /// Detect the format of a file from its first bytes
+    ///
+    /// Files written by older versions have no header, and use the byte order of the machine
+    /// that wrote them. A header cut short by a crash is still recognised as a header of the
+    /// current version. Headers of a version newer than [FORMAT_VERSION] are decoded as the
+    /// latest version known, which may not match how the records are laid out, see
+    /// [Format::is_supported].
+    pub fn detect(prefix: &[u8]) -> Self {
+        let len = prefix.len().min(MAGIC.len());
+        if len == 0 || prefix[..len] != MAGIC[..len] {
+            return Self::legacy();
+        }
+        let version = match prefix.get(4..6) {
+            Some(version) => u16::from_le_bytes([version[0], version[1]]),
+            None => FORMAT_VERSION,
+        };
+        match version {
+            1 => Self::v1(prefix),
+            2 => Self::v2(prefix),
+            // a header zeroed past the magic was torn while being written
+            0 | 3 => Self::v3(prefix),
+            version => Self {
+                version,
+                ..Self::v3(prefix)
+            },
+        }
+    }
+
+    /// The format of the files written without a header
+    fn legacy() -> Self {
+        Self {
+            version: 0,
+            header_len: 0,
+            big_endian: cfg!(target_endian = "big"),
+            paged: false,
+            chained: false,
+            compressed: false,
+            dictionary: 0,
+            checksum: Checksum::None,
+            page_size: PAGE_SIZE,
+            kinds: false,
+        }
+    }
+
+    /// Decode a header of version 3, possibly cut short
+    fn v3(prefix: &[u8]) -> Self {
+        Self {
+            version: 3,
+            kinds: true,
+            ..Self::v2(prefix)
+        }
+    }
+
+    /// Decode a header of version 2, possibly cut short
+    fn v2(prefix: &[u8]) -> Self {
+        // a size out of range can only come from a torn header
+        let page_size = match prefix.get(13) {
+            Some(&shift) if (12..=20).contains(&shift) => 1 << shift,
+            _ => PAGE_SIZE,
+        };
+        Self {
+            version: 2,
+            page_size,
+            ..Self::v1(prefix)
+        }
+    }
+
+    /// Decode a header of version 1, possibly cut short
+    fn v1(prefix: &[u8]) -> Self {
+        let flags = match prefix.get(6..8) {
+            Some(flags) => u16::from_le_bytes([flags[0], flags[1]]),
+            None => FLAG_LITTLE_ENDIAN,
+        };
+        Self {
+            version: 1,
+            header_len: HEADER_SIZE,
+            big_endian: flags & FLAG_LITTLE_ENDIAN == 0,
+            paged: flags & FLAG_PAGED != 0,
+            chained: flags & FLAG_CHAINED != 0,
+            compressed: flags & FLAG_COMPRESSED != 0,
+            dictionary: prefix
+                .get(9..13)
+                .map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+                .unwrap_or(0),
+            // unknown to this version, or a header cut short before it
+            checksum: prefix
+                .get(8)
+                .and_then(|id| Checksum::from_id(*id))
+                .unwrap_or_default(),
+            page_size: PAGE_SIZE,
+            kinds: false,
+        }
+    }
+
+    /// Whether this version of the crate can read the records of the file
+    pub fn is_supported(&self) -> bool {
+        self.version <= FORMAT_VERSION
+    }
+
+    /// The header of a new file in this format
+    pub fn header(&self) -> [u8; HEADER_SIZE] {
+        let mut flags = 0;
+        if self.paged {
+            flags |= FLAG_PAGED;
+        }
+        if self.chained {
+            flags |= FLAG_CHAINED;
+        }
+        if self.compressed {
+            flags |= FLAG_COMPRESSED;
+        }
+        let mut header = header_with(flags, self.checksum);
+        header[9..13].copy_from_slice(&self.dictionary.to_le_bytes());
+        header[13] = self.page_size.trailing_zeros() as u8;
+        header
+    }
+
+    /// Decode the size of a record from its frame
+    pub fn frame_size(&self, bytes: [u8; 2]) -> u16 {
+        match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    }
+}
+
+/// Read the start of a file and detect its format
+///
+/// ## Returns
+/// The format of the file, and all the bytes read, including the header, or an error of kind
+/// [Unsupported](ErrorKind::Unsupported) if the file was written by a newer version
+pub(crate) fn read_header(reader: &mut impl Read) -> std::io::Result<(Format, Vec<u8>)> {
+    let mut prefix = vec![0; HEADER_SIZE];
+    let mut filled = 0;
+    while filled < HEADER_SIZE {
+        match reader.read(&mut prefix[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    prefix.truncate(filled);
+    let format = Format::detect(&prefix);
+    if !format.is_supported() {
+        return Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "Format version {} is newer than the latest supported, {}",
+                format.version, FORMAT_VERSION
+            ),
+        ));
+    }
+    Ok((format, prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        let format = Format::detect(&header_with(0, Checksum::None));
+        assert_eq!(format, Format::default());
+        assert_eq!(format.frame_size([1, 2]), 0x0201);
+        // torn header
+        assert_eq!(Format::detect(b"WA"), Format::default());
+        // written by an older version
+        let legacy = Format::detect(&300u16.to_ne_bytes());
+        assert_eq!(legacy.header_len, 0);
+        assert_eq!(legacy.version, 0);
+        assert_eq!(legacy.frame_size(300u16.to_ne_bytes()), 300);
+        // big-endian sizes
+        let mut big = header_with(0, Checksum::None);
+        big[6] = 0;
+        assert!(Format::detect(&big).big_endian);
+        let crc = Format::detect(&header_with(0, Checksum::Crc32c));
+        assert_eq!(crc.checksum, Checksum::Crc32c);
+        let format = Format {
+            chained: true,
+            compressed: true,
+            dictionary: 7,
+            checksum: Checksum::XxHash64,
+            ..Format::default()
+        };
+        assert_eq!(Format::detect(&format.header()), format);
+        let large = Format {
+            paged: true,
+            page_size: 1 << 20,
+            ..Format::default()
+        };
+        assert_eq!(Format::detect(&large.header()), large);
+        // version 1 had no page size
+        let mut v1 = large.header();
+        v1[4] = 1;
+        let format = Format::detect(&v1);
+        assert_eq!((format.version, format.page_size), (1, PAGE_SIZE));
+        // version 2 had no kinds
+        let mut v2 = large.header();
+        v2[4] = 2;
+        let format = Format::detect(&v2);
+        assert_eq!((format.version, format.page_size), (2, 1 << 20));
+        assert!(!format.kinds);
+        // written by a newer version
+        let mut newer = header_with(0, Checksum::None);
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(!Format::detect(&newer).is_supported());
+        let err = read_header(&mut &newer[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        assert!(read_header(&mut &format.header()[..]).is_ok());
+    }
+}
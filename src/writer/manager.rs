@@ -1,13 +1,40 @@
-use crate::WalConfig;
+use super::audit::GcAudit;
+use super::buffer::Data;
+use super::chain::{self, Link};
+use super::checksum::Checksum;
+use super::compress::{self, Dictionary};
+use super::counters::CountersFile;
+use super::frame::{self, RecordCounter};
+use super::header::{read_header, Format, HEADER_SIZE};
+use super::manifest::{Manifest, SegmentInfo};
+use super::naming;
+use super::page::{self, PAGE_SIZE};
+use super::pins::Pins;
+#[cfg(feature = "signing")]
+use super::signature;
+use super::state::StateFile;
+use crate::failover::{self, FAILOVER_AFTER};
+use crate::health::{self, LowDiskSpace};
+use crate::listener::{GcRule, Operation};
+use crate::merkle;
+use crate::recovery::{self, RecoveryReport};
+use crate::stats::Monitor;
+use crate::{LifetimeStats, Lsn, WalConfig};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{BufReader, ErrorKind, IoSlice, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 const MAX_FILE_SIZE: usize = 10 * 1024 * 1024 * 1024; // 10 GB
 const NUM_FILES_SPLIT: usize = 4;
-
-// Todo: delete me
-const PAGE_SIZE: usize = 4096;
+/// Smallest storage size limit, giving each of the files a page
+pub(crate) const MIN_STORAGE_SIZE: usize = min_storage_size(PAGE_SIZE);
+const LOCK_FILE: &str = "lock";
+/// Extension appended to the name of files compressed at rotation time
+pub(crate) const COMPRESSED_EXT: &str = ".zst";
+/// Smallest range of a filled file worth releasing the disk blocks of
+const MIN_PUNCH_SIZE: u64 = 64 * 1024;
 
 pub(crate) struct Meta {
     location: PathBuf,
@@ -20,28 +47,162 @@ impl Meta {
         Self { location: path }
     }
 
+    /// Whether the meta file exists, i.e. a WAL has been created in this directory
+    pub fn exists(&self) -> bool {
+        self.location.is_file() || self.slotted()
+    }
+
+    /// Paths of the two slots used instead of the meta file, which are written alternately
+    ///
+    /// A crash while writing one of them leaves the other one intact, unlike rewriting the meta
+    /// file in place. Each slot holds the values, followed by a line with a sequence number and
+    /// the CRC32 of the values.
+    fn slots(&self) -> [PathBuf; 2] {
+        [0, 1].map(|slot| {
+            let mut path = self.location.clone().into_os_string();
+            path.push(format!(".{}", slot));
+            PathBuf::from(path)
+        })
+    }
+
+    /// Whether the values are stored in the slots instead of the meta file
+    fn slotted(&self) -> bool {
+        self.slots().iter().any(|slot| slot.is_file())
+    }
+
+    /// Switch to storing the values in two slots, for the power-loss-safe mode
+    pub fn use_slots(&self) -> std::io::Result<()> {
+        if self.slotted() {
+            return Ok(());
+        }
+        let content = std::fs::read_to_string(&self.location).unwrap_or_default();
+        write_slot(&self.slots(), content.trim())?;
+        let _ = std::fs::remove_file(&self.location);
+        Ok(())
+    }
+
+    /// Read the latest intact slot
+    ///
+    /// ## Returns
+    /// The sequence number of the slot and its values
+    fn read_slot(&self) -> Option<(u64, String)> {
+        read_slot(&self.slots())
+    }
+
     pub fn read(&self) -> Option<(usize, usize)> {
-        let content = std::fs::read_to_string(&self.location).ok()?;
-        let d = content
-            .split_whitespace()
-            .filter_map(|v| v.parse::<usize>().ok())
-            .collect::<Vec<usize>>();
-        if d.len() != 2 {
+        let (gc, current) = self.read_u64()?;
+        Some((usize::try_from(gc).ok()?, usize::try_from(current).ok()?))
+    }
+
+    /// Read the pointers as stored, which may not fit in a `usize` if the WAL
+    /// was written on a platform with wider pointers
+    pub fn read_u64(&self) -> Option<(u64, u64)> {
+        let d = self.values()?;
+        // the optional third value is the epoch, followed by the optional [Trim]
+        if d.len() != 2 && d.len() != 3 && d.len() != 6 {
             return None;
         }
         Some((d[0], d[1]))
     }
 
-    pub fn write(&self, v: (usize, usize)) {
-        let content = format!("{} {}", v.0, v.1);
-        let mut file = match File::create(&self.location) {
-            Ok(v) => v,
-            Err(err) => return eprintln!("Failed to write meta info: {:?}", err),
+    /// Read the logs trimmed from the start of the current file
+    pub fn trim(&self) -> Option<Trim> {
+        match self.values()?.as_slice() {
+            [_, _, _, index, offset, records] => Some(Trim {
+                index: usize::try_from(*index).ok()?,
+                offset: *offset,
+                records: *records,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Read the epoch of the WAL, which is incremented every time a writer opens it
+    pub fn epoch(&self) -> u64 {
+        self.values().and_then(|d| d.get(2).copied()).unwrap_or(0)
+    }
+
+    /// All the values are stored as `u64`, regardless of the platform
+    fn values(&self) -> Option<Vec<u64>> {
+        let content = match self.slotted() {
+            true => self.read_slot()?.1,
+            false => std::fs::read_to_string(&self.location).ok()?,
         };
-        if let Err(e) = file.write_all(content.as_bytes()) {
-            eprintln!("Failed to write meta to file: {}", e);
+        content
+            .split_whitespace()
+            .map(|v| v.parse::<u64>().ok())
+            .collect()
+    }
+
+    pub fn write(&self, v: (usize, usize)) -> std::io::Result<()> {
+        let content = format!("{} {}", v.0 as u64, v.1 as u64);
+        self.write_content(content)
+    }
+
+    /// Write the pointers along with the epoch of the writer, and the logs trimmed if any
+    pub fn write_with_epoch(
+        &self,
+        v: (usize, usize),
+        epoch: u64,
+        trim: Option<Trim>,
+    ) -> std::io::Result<()> {
+        let mut content = format!("{} {} {}", v.0 as u64, v.1 as u64, epoch);
+        if let Some(trim) = trim {
+            let index = trim.index as u64;
+            content.push_str(&format!(" {} {} {}", index, trim.offset, trim.records));
         }
+        self.write_content(content)
     }
+
+    fn write_content(&self, content: String) -> std::io::Result<()> {
+        if self.slotted() {
+            return write_slot(&self.slots(), &content);
+        }
+        File::create(&self.location)?.write_all(content.as_bytes())
+    }
+}
+
+/// Read the latest intact one of two slots written alternately
+///
+/// ## Returns
+/// The sequence number of the slot and its values, which are a single line
+pub(crate) fn read_slot(slots: &[PathBuf; 2]) -> Option<(u64, String)> {
+    slots
+        .iter()
+        .filter_map(|slot| {
+            let content = std::fs::read_to_string(slot).ok()?;
+            let (values, trailer) = content.split_once('\n')?;
+            let (seq, checksum) = trailer.trim().split_once(' ')?;
+            let checksum = u32::from_str_radix(checksum, 16).ok()?;
+            if checksum != crc32fast::hash(values.as_bytes()) {
+                return None;
+            }
+            Some((seq.parse().ok()?, values.to_string()))
+        })
+        .max_by_key(|(seq, _)| *seq)
+}
+
+/// Write the values to the slot not holding the latest ones, and sync it to disk
+pub(crate) fn write_slot(slots: &[PathBuf; 2], values: &str) -> std::io::Result<()> {
+    let seq = read_slot(slots).map(|(seq, _)| seq + 1).unwrap_or(0);
+    let checksum = crc32fast::hash(values.as_bytes());
+    let content = format!("{}\n{} {:08x}", values, seq, checksum);
+    let mut file = File::create(&slots[(seq % 2) as usize])?;
+    file.write_all(content.as_bytes())?;
+    file.sync_data()
+}
+
+/// Logs at the start of a file that are no longer part of the WAL, though still on disk
+///
+/// Recorded in meta for the current file, and in the manifest once the file is filled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Trim {
+    /// Index of the file
+    pub index: usize,
+    /// Offset of the first log kept
+    pub offset: u64,
+    /// Number of logs before the offset
+    pub records: u64,
 }
 
 struct FileConfig {
@@ -71,22 +232,27 @@ impl Default for FileConfig {
     }
 }
 
+/// Smallest storage size, splitting it into several files of at least a page each
+pub(crate) const fn min_storage_size(page_size: usize) -> usize {
+    NUM_FILES_SPLIT * page_size
+}
+
 impl FileConfig {
-    pub fn new(size: usize) -> Self {
+    pub fn new(size: usize, page_size: usize) -> Self {
         // calculate how much data to store per file
         let mut capacity = std::cmp::min(size / NUM_FILES_SPLIT, MAX_FILE_SIZE);
-        capacity = std::cmp::max(capacity, PAGE_SIZE);
-        // create a conf object
-        let mut conf = Self::default();
-        conf.size_per_file = capacity;
+        capacity = std::cmp::max(capacity, page_size);
         // set how many maximum files shall be there
-        conf.max_files = if size % capacity == 0 {
+        let max_files = if size.is_multiple_of(capacity) {
             size / capacity + 1
         } else {
             size / capacity + 2
         };
-        // sync with disk
-        conf
+        Self {
+            max_files,
+            size_per_file: capacity,
+            ..Self::default()
+        }
     }
 }
 
@@ -100,104 +266,1488 @@ pub(crate) struct FileManager {
     filled: usize,
     /// Configuration for FileManager on storage of data
     config: FileConfig,
+    /// Handle to the lock file, shared by all the processes appending to the WAL
+    /// This is only present when multiple processes are allowed to append
+    lock: Option<File>,
+    /// Location of the cold tier, where older files are moved to instead of being deleted
+    cold_location: Option<PathBuf>,
+    /// Whether files are compressed once they are filled
+    #[cfg_attr(not(feature = "compression"), allow(dead_code))]
+    compress: bool,
+    /// Running checksum over the data written to the current file
+    hasher: crc32fast::Hasher,
+    /// Number of bytes of the current file covered by the running checksum
+    hashed: usize,
+    /// Running count of the logs written to the current file
+    records: RecordCounter,
+    /// [Lsn] of the first log in the current file, unknown for files written by older versions
+    base: Option<Lsn>,
+    /// Logs trimmed from the start of the current file
+    trim: Option<Trim>,
+    /// Whether the logs are written in checksummed pages, for the power-loss-safe mode
+    paged: bool,
+    /// Link of the last log written, when every log carries the link of the one before it
+    chain: Option<Link>,
+    /// Checksum written in front of every log
+    checksum: Checksum,
+    /// Logs at least this long are compressed on their own, when set
+    compress_records: Option<usize>,
+    /// Dictionary the logs are compressed with
+    dictionary: Option<Dictionary>,
+    /// Key signing the files once they are filled
+    #[cfg(feature = "signing")]
+    signing_key: Option<ed25519_dalek::SigningKey>,
+    /// Manager of the mirror location, which receives a copy of everything written
+    mirror: Option<Box<FileManager>>,
+    /// Epoch of this writer, taken when it opened the WAL
+    epoch: u64,
+    /// Whether to check that no newer writer has opened the WAL before every write
+    fencing: bool,
+    /// Set once a newer writer has been detected, after which all the writes are refused
+    fenced: bool,
+    /// Outcome of checking the files against meta at initialization
+    recovery: RecoveryReport,
+    /// Files pinned by the readers, which are kept until released
+    pins: Pins,
+    /// Measures the durations of the operations
+    monitor: Monitor,
+    /// Location to switch to once the writes keep failing, until switched to it
+    fallback: Option<PathBuf>,
+    /// The primary location, holding the older files, once switched to the fallback one
+    primary: Option<PathBuf>,
+    /// Number of writes that failed in a row
+    failures: usize,
+    /// Whether the new files are named in the sortable scheme, see [naming::new_name]
+    sortable: bool,
+    /// Size of the pages of the files, see [PAGE_SIZE]
+    page_size: usize,
+    /// Whether the disk space of the files up to the storage limit is reserved
+    preallocate: bool,
+    /// Disk space kept free, and what happens to the writes that would take it
+    disk_reserve: Option<(usize, LowDiskSpace)>,
+    /// Whether the syncs are left to the writer, which coalesces them, see [SyncGroup]
+    ///
+    /// [SyncGroup]: super::group::SyncGroup
+    coalesce: bool,
+    /// Number of writes to the files so far
+    writes: u64,
+    /// Activity since the lifetime counters were last stored, see [CountersFile]
+    lifetime: LifetimeStats,
 }
 
 impl FileManager {
-    pub fn new(config: WalConfig) -> Self {
-        let mut file_config = FileConfig::new(config.size);
+    /// Open the files of the WAL, repairing them if needed
+    ///
+    /// ## Returns
+    /// An error if the lock file or the current file can't be opened, or if the pointers in meta
+    /// are out of range for the platform
+    pub fn new(mut config: WalConfig) -> std::io::Result<Self> {
+        let monitor = Monitor::new(&config);
+        // a writer that switched to the fallback location keeps writing there
+        let primary = failover::failed_over(&config)
+            .cloned()
+            .map(|fallback| std::mem::replace(&mut config.location, fallback));
+        // restore a lost location from its copy, before either of them is initialized
+        if let Some(mirror) = config.mirror_location.as_ref() {
+            Self::restore(mirror, &config.location, &monitor);
+            Self::restore(&config.location, mirror, &monitor);
+        }
+        // the mirror keeps its own files and pointers, in sync with the primary location
+        let mirror = config.mirror_location.clone().map(|location| {
+            let mirror_config = WalConfig {
+                location,
+                mirror_location: None,
+                cold_location: None,
+                fallback_location: None,
+                // the mirror isn't waited for, so it syncs every write on its own
+                sync_window: None,
+                ..config.clone()
+            };
+            FileManager::new(mirror_config).map(Box::new)
+        });
+        let mirror = mirror.transpose()?;
+        let mut file_config = FileConfig::new(config.size, config.page_size);
         file_config.sync = config.fsync;
+        // open the lock file and hold the lock during initialization
+        let lock = match config.multi_process {
+            true => {
+                let mut path = config.location.clone();
+                path.push(LOCK_FILE);
+                let file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+                    .map_err(|e| {
+                        std::io::Error::new(
+                            e.kind(),
+                            format!("Failed to open WAL lock file: {}", e),
+                        )
+                    })?;
+                Some(file)
+            }
+            false => None,
+        };
+        if let Some(lock) = lock.as_ref() {
+            let _ = lock.lock();
+        }
         let meta = Meta::new(config.location.clone());
-        if let Some(data) = meta.read() {
-            file_config.gc_pointer = data.0;
-            file_config.current_pointer = data.1;
+        if config.power_loss_safe {
+            if let Err(e) = meta.use_slots() {
+                monitor.error("Failed to write WAL meta", &e);
+            }
+        }
+        // starting afresh would overwrite the logs of a WAL written on a 64-bit platform
+        if let Some((gc, current)) = meta.read_u64() {
+            let fits = usize::try_from(gc).is_ok() && usize::try_from(current).is_ok();
+            if !fits {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "WAL pointers ({}, {}) are out of range on this platform",
+                        gc, current
+                    ),
+                ));
+            }
+        }
+        // the files might have been deleted behind the back of meta
+        let locations = std::iter::once(&config.location)
+            .chain(primary.as_ref())
+            .map(PathBuf::as_path)
+            .collect::<Vec<_>>();
+        let mut recovery = recovery::check(&locations, meta.read());
+        (file_config.gc_pointer, file_config.current_pointer) = recovery.pointers;
+        let base = Self::base_lsn(&config.location, recovery.pointers);
+        // every writer opening the WAL moves it to a new epoch
+        let epoch = meta.epoch() + 1;
+        let trim = meta
+            .trim()
+            .filter(|trim| trim.index == file_config.current_pointer);
+        let result = meta.write_with_epoch(
+            (file_config.gc_pointer, file_config.current_pointer),
+            epoch,
+            trim,
+        );
+        if let Err(e) = result {
+            monitor.error("Failed to write WAL meta", &e);
         }
-        meta.write((file_config.gc_pointer, file_config.current_pointer));
 
-        let current_file = format!("log_{}.bin", file_config.current_pointer);
-        let mut file_path = config.location.clone();
-        file_path.push(current_file);
+        let file_path = naming::new_path(
+            &config.location,
+            file_config.current_pointer,
+            config.sortable_names,
+        );
 
-        let (file, filled) = Self::open_file(file_path).expect("Failed to open WAL file");
-        Self {
+        let (mut file, mut filled) = Self::open_file(file_path.clone())?;
+        // a crash may have left a partial log at the end of the file
+        recovery.truncated = Self::truncate_partial(&file_path, &file, filled, &monitor);
+        filled -= recovery.truncated as usize;
+        if !recovery.is_consistent() {
+            let message = format!("WAL was repaired at open: {:?}", recovery);
+            monitor.report(message, None);
+        }
+        // a file written by an older version has no header, and one written in the other mode
+        // has another layout, so new logs go to the next file
+        let (paged, chained) = (config.power_loss_safe, config.hash_chain);
+        let checksum = config.checksum;
+        // the records are compressed with the latest dictionary, if any
+        let dictionary = config
+            .compress_records
+            .and_then(|_| Dictionary::latest(&config.location));
+        let format = Format {
+            paged,
+            chained,
+            compressed: config.compress_records.is_some(),
+            dictionary: dictionary.as_ref().map_or(0, |d| d.id),
+            checksum,
+            page_size: config.page_size,
+            ..Format::default()
+        };
+        // new logs go to a new file unless the current one has the same format, so the files
+        // written by other versions, older or newer, are left as they are
+        let legacy = filled > 0
+            && !matches!(
+                File::open(&file_path).and_then(|mut f| read_header(&mut f)),
+                Ok((found, _)) if found == format
+            );
+        let header = file_header(format);
+        let filled = Self::init_file(&mut file, filled, &header, &monitor);
+        let chain = chained.then(|| {
+            Self::last_link(&config.location, file_config.current_pointer).unwrap_or_default()
+        });
+        let mut manager = Self {
             location: config.location,
             file,
             filled,
             config: file_config,
+            lock,
+            cold_location: config.cold_location,
+            compress: config.compress_segments,
+            hasher: crc32fast::Hasher::new(),
+            hashed: 0,
+            records: RecordCounter::default(),
+            base,
+            trim,
+            paged,
+            chain,
+            checksum,
+            compress_records: config.compress_records,
+            dictionary,
+            #[cfg(feature = "signing")]
+            signing_key: config.signing_key,
+            mirror,
+            epoch,
+            fencing: config.fencing,
+            fenced: false,
+            recovery,
+            monitor,
+            pins: config.pins,
+            fallback: config.fallback_location.filter(|_| primary.is_none()),
+            primary,
+            failures: 0,
+            sortable: config.sortable_names,
+            page_size: config.page_size,
+            preallocate: config.preallocate,
+            disk_reserve: config.disk_reserve,
+            coalesce: config.sync_window.is_some(),
+            writes: 0,
+            lifetime: LifetimeStats::default(),
+        };
+        if legacy {
+            manager.next_file();
+        }
+        manager.preallocate();
+        if let Some(lock) = manager.lock.as_ref() {
+            let _ = lock.unlock();
+        }
+        Ok(manager)
+    }
+
+    /// Write the header to an empty file
+    ///
+    /// ## Returns
+    /// The size of data in the file
+    fn init_file(file: &mut File, filled: usize, header: &[u8], monitor: &Monitor) -> usize {
+        if filled > 0 {
+            return filled;
+        }
+        match file.write_all(header) {
+            Ok(_) => header.len(),
+            Err(e) => {
+                monitor.error("Failed to write header to WAL file", &e);
+                0
+            }
+        }
+    }
+
+    /// Find the link of the last log written before the new ones, from the current file or
+    /// else from the file before it
+    fn last_link(location: &Path, current: usize) -> Option<Link> {
+        let path = naming::path(location, current);
+        if let Some(link) = File::open(path).and_then(chain::last_link).ok().flatten() {
+            return Some(link);
+        }
+        let previous = current.checked_sub(1)?;
+        Manifest::new(location.to_path_buf())
+            .read()
+            .get(&previous)?
+            .chain
+    }
+
+    /// Cut off a partial log from the end of a file, so that new logs aren't appended after it
+    ///
+    /// ## Returns
+    /// The number of bytes cut off
+    fn truncate_partial(path: &Path, file: &File, filled: usize, monitor: &Monitor) -> u64 {
+        // the files written in the power-loss-safe mode lose whole pages instead
+        let complete = File::open(path).and_then(|mut f| match read_header(&mut f)?.0 {
+            format if format.paged => page::intact_len(File::open(path)?, format.page_size),
+            _ => frame::scan(File::open(path)?, |_| {}).map(|(_, complete)| complete),
+        });
+        let complete = match complete {
+            Ok(complete) if complete < filled as u64 => complete,
+            _ => return 0,
+        };
+        match file.set_len(complete) {
+            Ok(_) => filled as u64 - complete,
+            Err(e) => {
+                monitor.error("Failed to truncate partial log from WAL file", &e);
+                0
+            }
+        }
+    }
+
+    /// Copy all the files from one location to another, if the other one has no WAL in it,
+    /// e.g. after replacing a failed disk
+    fn restore(from: &Path, to: &Path, monitor: &Monitor) {
+        if Meta::new(to.to_path_buf()).exists() || !Meta::new(from.to_path_buf()).exists() {
+            return;
+        }
+        let entries = match std::fs::read_dir(from) {
+            Ok(entries) => entries,
+            Err(e) => {
+                let context = format!("Failed to restore WAL from {}", from.display());
+                return monitor.error(&context, &e);
+            }
+        };
+        for entry in entries.flatten() {
+            if !entry.path().is_file() {
+                continue;
+            }
+            if let Err(e) = std::fs::copy(entry.path(), to.join(entry.file_name())) {
+                monitor.error("Failed to restore WAL file", &e);
+            }
         }
     }
 
     /// Write the change to file
-    pub fn commit(&mut self, data: &[u8]) {
-        let written = match self.file.write(data) {
-            Ok(size) => {
-                if self.config.sync {
-                    let _ = self.file.sync_all();
+    ///
+    /// When multiple processes are allowed to append, the write happens under an exclusive
+    /// lock on the lock file, after catching up with the pointers moved by other processes
+    ///
+    /// ## Returns
+    /// An error if the data wasn't written, which is reported by the [Monitor] already
+    pub fn commit(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.commit_vectored(&[data])
+    }
+
+    /// Write the chunks of data one after another, with a single vectored write, see
+    /// [FileManager::commit]
+    pub fn commit_vectored(&mut self, data: &[&[u8]]) -> std::io::Result<()> {
+        self.fence()?;
+        if let Some(lock) = self.lock.as_ref() {
+            if let Err(e) = lock.lock() {
+                self.monitor.error("Failed to lock WAL for writing", &e);
+                return Err(e);
+            }
+            self.refresh();
+        }
+        let mut result = self.append(data);
+        self.failures = match result {
+            Ok(()) => 0,
+            Err(_) => self.failures + 1,
+        };
+        match result.as_ref() {
+            // the logs of the last failed write are written again in the fallback location
+            Err(e) if self.failures >= FAILOVER_AFTER && self.failover(e) => {
+                result = self.append(data);
+            }
+            _ => {}
+        }
+        if let Some(lock) = self.lock.as_ref() {
+            let _ = lock.unlock();
+        }
+        // the mirror reports its own failures
+        if let Some(mirror) = self.mirror.as_mut() {
+            let _ = mirror.commit_vectored(data);
+        }
+        result
+    }
+
+    /// Switch to the fallback location for good, starting a new file there
+    ///
+    /// ## Returns
+    /// Whether the writer switched, i.e. has a fallback location it wasn't writing to already
+    fn failover(&mut self, error: &std::io::Error) -> bool {
+        let fallback = match self.fallback.take() {
+            Some(fallback) => fallback,
+            None => return false,
+        };
+        let next = self.config.current_pointer.wrapping_add(1);
+        let reason = error.to_string();
+        if let Err(e) = failover::record(&fallback, &self.location, next, &reason) {
+            self.monitor
+                .error("Failed to switch to the fallback location", &e);
+            self.fallback = Some(fallback);
+            return false;
+        }
+        let message = format!(
+            "Switched to the fallback location {} from {}, from file {} on: {}",
+            fallback.display(),
+            self.location.display(),
+            next,
+            reason
+        );
+        self.monitor.report(message, Some(error.kind()));
+        self.primary = Some(std::mem::replace(&mut self.location, fallback));
+        self.next_file();
+        true
+    }
+
+    /// Path of a file, which might have been compressed at rotation time, or be in the primary
+    /// location if it was filled before switching to the fallback one
+    fn segment_file(&self, index: usize) -> PathBuf {
+        std::iter::once(&self.location)
+            .chain(self.primary.as_ref())
+            .find_map(|dir| {
+                let file_name = naming::find(dir, index)?;
+                let compressed = format!("{}{}", file_name, COMPRESSED_EXT);
+                [dir.join(file_name), dir.join(compressed)]
+                    .into_iter()
+                    .find(|path| path.exists())
+            })
+            .unwrap_or_else(|| naming::path(&self.location, index))
+    }
+
+    /// Check that the WAL is still at the epoch of this writer
+    fn check_epoch(&mut self) -> bool {
+        if !self.fenced && Meta::new(self.location.clone()).epoch() != self.epoch {
+            self.fenced = true;
+        }
+        !self.fenced
+    }
+
+    /// Refuse to change the WAL once a newer writer has opened it, with fencing enabled
+    ///
+    /// ## Returns
+    /// An error if the writer is fenced, which is reported by the [Monitor] already
+    fn fence(&mut self) -> std::io::Result<()> {
+        if !self.fencing || self.check_epoch() {
+            return Ok(());
+        }
+        let message = format!(
+            "Refusing to write to WAL, it has been opened by a newer writer since epoch {}",
+            self.epoch
+        );
+        self.monitor.report(message.clone(), None);
+        Err(std::io::Error::other(message))
+    }
+
+    /// Whether a newer writer has opened the WAL, and this one refuses to write
+    pub fn is_fenced(&self) -> bool {
+        self.fenced
+    }
+
+    /// Outcome of checking the files against meta at initialization
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Find the [Lsn] of the first log in the current file, from the file before it
+    fn base_lsn(location: &Path, (gc_pointer, current_pointer): (usize, usize)) -> Option<Lsn> {
+        let previous = current_pointer.wrapping_sub(1);
+        match Manifest::new(location.to_path_buf()).read().get(&previous) {
+            Some(info) => info.next_lsn(),
+            // the current file is the first one
+            None if gc_pointer == current_pointer => Some(0),
+            None => None,
+        }
+    }
+
+    /// Trim the logs before `lsn` from the start of a file
+    ///
+    /// The readers skip the trimmed logs from now on, while their bytes stay on disk. The disk
+    /// blocks taken by them in a filled file are released too, unless the file is pinned by a
+    /// reader or less than [MIN_PUNCH_SIZE] would be released. The released range reads as zeros,
+    /// so the offsets of the logs kept don't change, and the checksum is updated to match.
+    ///
+    /// ## Returns
+    /// The number of bytes released
+    pub fn trim_before(&mut self, index: usize, lsn: Lsn) -> u64 {
+        if self.fence().is_err() {
+            return 0;
+        }
+        if let Some(mirror) = self.mirror.as_mut() {
+            mirror.trim_before(index, lsn);
+        }
+        if index == self.config.current_pointer {
+            self.trim_current(lsn);
+            return 0;
+        }
+        let manifest = Manifest::new(self.location.clone());
+        let mut info = match manifest.read().remove(&index) {
+            Some(info) => info,
+            None => return 0,
+        };
+        let position = match info.first {
+            Some(first) if lsn > first + info.trimmed => lsn - first,
+            _ => return 0,
+        };
+        let gc_pointer = self.config.gc_pointer;
+        let pinned = self
+            .pins
+            .oldest(gc_pointer)
+            .is_some_and(|p| p.wrapping_sub(gc_pointer) <= index.wrapping_sub(gc_pointer));
+        let path = naming::path(&self.location, index);
+        let result = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .and_then(|file| {
+                let offset = match frame::offset_of(BufReader::new(&file), position)? {
+                    Some(offset) => offset,
+                    None => return Ok(None),
+                };
+                let start = info.trim_offset.max(HEADER_SIZE as u64);
+                // the offsets in paged files leave out the page framing
+                let paged = read_header(&mut File::open(&path)?)?.0.paged;
+                // and the signature covers the content as it was written
+                let signed = info.signature.is_some();
+                if pinned || paged || signed || offset < start + MIN_PUNCH_SIZE {
+                    return Ok(Some((offset, 0)));
+                }
+                match punch_hole(&file, start, offset - start) {
+                    Ok(()) => {}
+                    // file systems without support for punching holes
+                    Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(Some((offset, 0))),
+                    Err(e) => return Err(e),
                 }
-                size
+                info.checksum = checksum_reader(File::open(&path)?)?;
+                Ok(Some((offset, offset - start)))
+            });
+        match result {
+            Ok(Some((offset, released))) => {
+                info.trim_offset = offset;
+                info.trimmed = position;
+                if let Err(e) = manifest.update(info) {
+                    self.monitor.error("Failed to write WAL manifest", &e);
+                }
+                released
+            }
+            Ok(None) => 0,
+            // compressed files are left as they are
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => {
+                let context = format!("Failed to trim WAL file {}", index);
+                self.monitor.error(&context, &e);
+                0
+            }
+        }
+    }
+
+    /// Trim the logs before `lsn` from the start of the current file, by recording it in meta
+    fn trim_current(&mut self, lsn: Lsn) {
+        let trimmed = self.trim.map(|trim| trim.records).unwrap_or(0);
+        let position = match self.base {
+            Some(base) if lsn > base + trimmed => lsn - base,
+            _ => return,
+        };
+        let index = self.config.current_pointer;
+        let path = naming::path(&self.location, index);
+        let offset = File::open(path).and_then(|f| frame::offset_of(BufReader::new(f), position));
+        match offset {
+            Ok(Some(offset)) => {
+                self.trim = Some(Trim {
+                    index,
+                    offset,
+                    records: position,
+                });
+                self.write_meta();
             }
+            Ok(None) => {}
             Err(e) => {
-                return println!("Failed to write to file: {}", e);
+                let context = format!("Failed to trim WAL file {}", index);
+                self.monitor.error(&context, &e);
+            }
+        }
+    }
+
+    /// Drop the manifest entries of the deleted files
+    ///
+    /// The entry of the file right before the current one is kept even once the file is deleted,
+    /// as the [Lsn] of the logs in the current file continue from it. It's dropped along with the
+    /// next files deleted.
+    fn forget(&self, deleted: &[usize]) {
+        let previous = self.config.current_pointer.wrapping_sub(1);
+        let stale = deleted.first().map(|first| first.wrapping_sub(1));
+        let indexes = stale
+            .into_iter()
+            .chain(deleted.iter().copied())
+            .filter(|index| *index != previous)
+            .collect::<Vec<_>>();
+        if let Err(e) = Manifest::new(self.location.clone()).remove(&indexes) {
+            self.monitor.error("Failed to write WAL manifest", &e);
+        }
+    }
+
+    /// Write the pointers to meta
+    fn write_meta(&self) {
+        let meta = Meta::new(self.location.clone());
+        let result = meta.write_with_epoch(
+            (self.config.gc_pointer, self.config.current_pointer),
+            self.epoch,
+            self.trim,
+        );
+        if let Err(e) = result {
+            self.monitor.error("Failed to write WAL meta", &e);
+        }
+    }
+
+    /// Catch up with the changes made to the WAL by other processes
+    fn refresh(&mut self) {
+        let meta = Meta::new(self.location.clone());
+        if let Some((gc_pointer, current_pointer)) = meta.read() {
+            self.config.gc_pointer = gc_pointer;
+            self.trim = meta.trim().filter(|trim| trim.index == current_pointer);
+            if current_pointer != self.config.current_pointer {
+                self.config.current_pointer = current_pointer;
+                self.base = Self::base_lsn(&self.location, (gc_pointer, current_pointer));
+                let file_path = naming::new_path(&self.location, current_pointer, self.sortable);
+                match Self::open_file(file_path) {
+                    Ok((file, _)) => self.file = file,
+                    Err(e) => return self.monitor.error("Failed to catch up with the WAL", &e),
+                }
             }
+        }
+        // other processes may have appended to the current file
+        if let Ok(meta_data) = self.file.metadata() {
+            self.filled = meta_data.len() as usize;
+        }
+    }
+
+    /// Append the data to the current file, and rotate the file once it's filled
+    ///
+    /// ## Returns
+    /// The error if the data couldn't be written in full
+    fn append(&mut self, data: &[&[u8]]) -> std::io::Result<()> {
+        if let Err(e) = self.reserve_space(data.iter().map(|d| d.len()).sum()) {
+            self.monitor.write_result(Some(&e));
+            return Err(e);
+        }
+        let current = self.config.current_pointer;
+        let start = Instant::now();
+        let counted = self.records.count();
+        // the large logs are compressed on their own
+        // every copy of the logs made on the way is zeroed once written, see [Data]
+        let compressed = self.compress_records.map(|threshold| {
+            Data::from(compress::compress(
+                data,
+                threshold,
+                self.dictionary.as_ref(),
+                &self.monitor,
+            ))
+        });
+        let data = match compressed.as_ref() {
+            Some(compressed) => vec![compressed.as_slice()],
+            None => data.to_vec(),
+        };
+        // in a hash-chained file, every log carries the link of the log before it
+        let linked = self
+            .chain
+            .as_mut()
+            .map(|last| Data::from(chain::link(&data, last, &self.monitor)));
+        let data = match linked.as_ref() {
+            Some(linked) => vec![linked.as_slice()],
+            None => data,
+        };
+        // and the checksum of both of them
+        let stamped = (self.checksum != Checksum::None)
+            .then(|| Data::from(self.checksum.stamp(&data, &self.monitor)));
+        let data = match stamped.as_ref() {
+            Some(stamped) => vec![stamped.as_slice()],
+            None => data,
+        };
+        // in the power-loss-safe mode, every write covers whole pages
+        let pages = self
+            .paged
+            .then(|| Data::from(page::encode(&data, self.page_size)));
+        let chunks = match pages.as_ref() {
+            Some(pages) => vec![pages.as_slice()],
+            None => data.clone(),
         };
+        let mut slices = chunks.iter().map(|d| IoSlice::new(d)).collect::<Vec<_>>();
+        let mut remaining = &mut slices[..];
+        let mut written = 0;
+        let mut result = Ok(());
+        while !remaining.is_empty() {
+            match self.file.write_vectored(remaining) {
+                Ok(0) => {
+                    result = Err(ErrorKind::WriteZero.into());
+                    break;
+                }
+                Ok(size) => {
+                    written += size;
+                    IoSlice::advance_slices(&mut remaining, size);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if written == 0 => {
+                    self.monitor.write_result(Some(&e));
+                    return Err(e);
+                }
+                Err(e) => {
+                    self.monitor.write_result(Some(&e));
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            self.monitor.write_result(None);
+        }
+        self.monitor.observe(Operation::Flush, current, start);
+        self.monitor.written(current, written);
+        self.writes += 1;
+        // with the syncs coalesced, a file is still synced on its own once filled
+        let filled = self.filled + written >= self.config.size_per_file;
+        if self.config.sync && (!self.coalesce || filled) {
+            let start = Instant::now();
+            if let Err(e) = self.file.sync_all() {
+                self.monitor.sync_failed(&e);
+            }
+            self.monitor.observe(Operation::Fsync, current, start);
+        }
         self.filled += written;
+        self.hashed += written;
+        let mut left = written;
+        for chunk in &chunks {
+            let chunk = &chunk[..left.min(chunk.len())];
+            self.hasher.update(chunk);
+            if !self.paged {
+                self.records.update(chunk);
+            }
+            left -= chunk.len();
+        }
+        // the pages only hold complete records once fully written
+        if pages.is_some_and(|pages| pages.len() == written) {
+            data.iter().for_each(|d| self.records.update(d));
+        }
+        self.lifetime.records += self.records.count() - counted;
+        self.lifetime.bytes += written as u64;
         if self.filled >= self.config.size_per_file {
             self.next_file()
         }
+        result
+    }
+
+    /// Make sure writing `len` bytes leaves the disk reserve free, deleting the oldest files
+    /// first with [LowDiskSpace::DeleteOldest]
+    ///
+    /// ## Returns
+    /// An error if there isn't enough space left, in which case nothing should be written
+    fn reserve_space(&mut self, len: usize) -> std::io::Result<()> {
+        let reserve = match self.disk_reserve {
+            Some((reserve, _)) => reserve,
+            None => return Ok(()),
+        };
+        let needed = reserve.saturating_add(len) as u64;
+        let low = |location: &Path| {
+            health::disk_space(location).is_some_and(|(available, _)| available < needed)
+        };
+        if !low(&self.location) {
+            return Ok(());
+        }
+        if let Some((_, LowDiskSpace::DeleteOldest)) = self.disk_reserve {
+            let start = Instant::now();
+            let first = self.config.gc_pointer;
+            let pinned = self.pins.oldest(first);
+            let mut deleted = Vec::new();
+            let mut audit = GcAudit::new(&self.location, &self.monitor);
+            while low(&self.location)
+                && self.config.gc_pointer != self.config.current_pointer
+                && pinned != Some(self.config.gc_pointer)
+            {
+                let segment = audit.describe(&self.location, self.config.gc_pointer, false);
+                if self.remove_segment(&self.location, self.config.gc_pointer) {
+                    audit.push(segment);
+                }
+                deleted.push(self.config.gc_pointer);
+                self.config.gc_pointer = self.config.gc_pointer.wrapping_add(1);
+            }
+            if !deleted.is_empty() {
+                self.lifetime.segments_collected += deleted.len() as u64;
+                self.forget(&deleted);
+                self.write_meta();
+                self.monitor.observe(Operation::Gc, first, start);
+            }
+            audit.report(&self.monitor, GcRule::DiskReserve);
+            if !low(&self.location) {
+                return Ok(());
+            }
+        }
+        let message = format!(
+            "Less than the {} bytes reserved would be left free on the disk",
+            reserve
+        );
+        Err(std::io::Error::new(ErrorKind::StorageFull, message))
+    }
+
+    /// Whether every commit is synced to disk
+    pub fn syncs(&self) -> bool {
+        self.config.sync
+    }
+
+    /// Whether the syncs of the commits are left to the writer, to coalesce them
+    pub fn coalesces(&self) -> bool {
+        self.config.sync && self.coalesce
+    }
+
+    /// Number of writes to the files so far
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    /// Apply a new storage size limit and fsync setting
+    ///
+    /// The new size applies to files opened from now on. A smaller limit is enforced right away
+    /// by running the garbage collection.
+    pub fn reload(&mut self, size: usize, fsync: bool) {
+        let file_config = FileConfig::new(size, self.page_size);
+        self.config.max_files = file_config.max_files;
+        self.config.size_per_file = file_config.size_per_file;
+        self.config.sync = fsync;
+        if let Some(mirror) = self.mirror.as_mut() {
+            mirror.reload(size, fsync);
+        }
+        self.gc();
+        self.write_meta();
+        self.preallocate();
+    }
+
+    /// Compress the logs with a new dictionary from now on
+    ///
+    /// The dictionary is stored in the log directory, and the logs compressed with it go to the
+    /// next file, whose header names it.
+    ///
+    /// ## Returns
+    /// The id of the dictionary
+    #[cfg(feature = "compression")]
+    pub fn use_dictionary(&mut self, data: Vec<u8>) -> std::io::Result<u32> {
+        if let Some(mirror) = self.mirror.as_mut() {
+            mirror.use_dictionary(data.clone())?;
+        }
+        let dictionary = Dictionary::create(&self.location, data)?;
+        let id = dictionary.id;
+        self.dictionary = Some(dictionary);
+        self.next_file();
+        Ok(id)
+    }
+
+    /// Layout of the files written from now on
+    fn format(&self) -> Format {
+        Format {
+            paged: self.paged,
+            chained: self.chain.is_some(),
+            compressed: self.compress_records.is_some(),
+            dictionary: self.dictionary.as_ref().map_or(0, |d| d.id),
+            checksum: self.checksum,
+            page_size: self.page_size,
+            ..Format::default()
+        }
+    }
+
+    /// Index of the current file
+    pub fn current(&self) -> usize {
+        self.config.current_pointer
+    }
+
+    /// Get a new handle to the current file, used for syncing it outside of the IO lock
+    pub fn file(&self) -> Option<File> {
+        self.file.try_clone().ok()
     }
 
     // Open next file and run garbage collection
     fn next_file(&mut self) {
+        let start = Instant::now();
+        let previous = self.config.current_pointer;
+        let (new_pointer, _) = previous.overflowing_add(1);
+        // open new file
+        // remove the file in case it exists, unless it's preallocated and still empty
+        let stale = naming::path(&self.location, new_pointer);
+        if !self.preallocate || std::fs::metadata(&stale).is_ok_and(|m| m.len() > 0) {
+            let _ = std::fs::remove_file(stale);
+        }
+        // the logs keep going to the current file until the next one can be opened
+        let file_path = naming::new_path(&self.location, new_pointer, self.sortable);
+        let (mut file, filled) = match Self::open_file(file_path) {
+            Ok(opened) => opened,
+            Err(e) => return self.monitor.error("Failed to rotate WAL file", &e),
+        };
+        self.seal();
         // set a new pointer
-        let (new_pointer, _) = self.config.current_pointer.overflowing_add(1);
         self.config.current_pointer = new_pointer;
         // run garbage collection
         self.gc();
-        let meta = Meta::new(self.location.clone());
-        meta.write((self.config.gc_pointer, self.config.current_pointer));
-        // open new file
-        let file_name = format!("log_{}.bin", new_pointer);
-        let mut file_path = self.location.clone();
-        file_path.push(file_name);
-        let _ = std::fs::remove_file(&file_path); // remove the file in case it exists
-        let d = Self::open_file(file_path).expect("Failed to open next WAL file");
-        self.file = d.0;
-        self.filled = d.1;
+        self.write_meta();
+        self.store_counters();
+        let header = file_header(self.format());
+        self.filled = Self::init_file(&mut file, filled, &header, &self.monitor);
+        self.file = file;
+        self.hasher = crc32fast::Hasher::new();
+        self.hashed = 0;
+        if self.filled == header.len() {
+            self.hasher.update(&header);
+            self.hashed = header.len();
+        }
+        self.records = RecordCounter::default();
+        // compress the file that just got filled
+        #[cfg(feature = "compression")]
+        if self.compress {
+            let path = self.segment_file(previous);
+            if let Err(e) = Self::compress(&path) {
+                self.monitor.error("Failed to compress WAL file", &e);
+            }
+        }
+        // the space freed by the garbage collection is taken again right away
+        self.preallocate();
+        self.monitor.observe(Operation::Rotation, previous, start);
+    }
+
+    /// Reserve the disk space of the files up to the storage limit, see
+    /// [WalBuilder::preallocate](crate::WalBuilder::preallocate)
+    ///
+    /// The files after the current one are created empty, and every file is given the disk
+    /// blocks of a full file without changing its size, so the logs are read as before.
+    fn preallocate(&self) {
+        if !self.preallocate || self.config.max_files == usize::MAX {
+            return;
+        }
+        let ahead = self.config.max_files.saturating_sub(self.retained());
+        for index in 0..=ahead {
+            let index = self.config.current_pointer.wrapping_add(index);
+            let path = naming::new_path(&self.location, index, self.sortable);
+            let result = std::fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .and_then(|file| allocate(&file, self.config.size_per_file as u64));
+            match result {
+                Ok(()) => {}
+                // the files are still created, filling up as usual
+                Err(e) if e.kind() == ErrorKind::Unsupported => {}
+                Err(e) => return self.monitor.error("Failed to preallocate WAL file", &e),
+            }
+        }
+    }
+
+    /// Record the checksum of the current file in the manifest, as it's about to be closed
+    fn seal(&mut self) {
+        let index = self.config.current_pointer;
+        let hasher = std::mem::take(&mut self.hasher);
+        let first = self.base.take();
+        // the file was (partly) written by a previous run or another process,
+        // so the running checksum and count don't cover all of it
+        let (checksum, records) = if self.hashed == self.filled {
+            (hasher.finalize(), self.records.count())
+        } else {
+            match scan_file(self.segment_file(index)) {
+                Ok(v) => v,
+                Err(e) => return self.monitor.error("Failed to checksum WAL file", &e),
+            }
+        };
+        self.base = first.map(|first| first + records);
+        let mut info = SegmentInfo {
+            index,
+            size: self.filled as u64,
+            checksum,
+            first,
+            records: Some(records),
+            chain: self.chain,
+            ..Default::default()
+        };
+        // the logs trimmed from the file move from meta to the manifest
+        if let Some(trim) = self.trim.take().filter(|trim| trim.index == index) {
+            info.trim_offset = trim.offset;
+            info.trimmed = trim.records;
+        }
+        let path = self.segment_file(index);
+        match File::open(&path).and_then(merkle::root) {
+            Ok(root) => info.merkle = root,
+            Err(e) => {
+                let context = format!("Failed to build Merkle tree of WAL file {}", index);
+                self.monitor.error(&context, &e);
+            }
+        }
+        #[cfg(feature = "signing")]
+        if let Some(key) = self.signing_key.as_ref() {
+            let reader = File::open(path).map(BufReader::new);
+            match reader.and_then(|reader| signature::sign(key, index, reader)) {
+                Ok(signature) => info.signature = Some(signature),
+                Err(e) => {
+                    let context = format!("Failed to sign WAL file {}", index);
+                    self.monitor.error(&context, &e);
+                }
+            }
+        }
+        if let Err(e) = Manifest::new(self.location.clone()).append(&info) {
+            self.monitor.error("Failed to write WAL manifest", &e);
+        }
+    }
+
+    /// Compress a file with zstd, replacing it with a file with [COMPRESSED_EXT] suffix
+    #[cfg(feature = "compression")]
+    fn compress(path: &Path) -> std::io::Result<()> {
+        let mut target = path.as_os_str().to_owned();
+        target.push(COMPRESSED_EXT);
+        let mut temp = target.clone();
+        temp.push(".tmp");
+        // compress to a temp file first, so that a crash never leaves a partial file behind
+        let mut input = File::open(path)?;
+        let mut encoder = zstd::Encoder::new(File::create(&temp)?, 0)?;
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        std::fs::rename(&temp, &target)?;
+        std::fs::remove_file(path)
     }
 
     // Run garbage collection on files
     // i.e. delete files beyond max_files limit
     fn gc(&mut self) {
-        let current = self.config.current_pointer;
         let mut gc_pointer = self.config.gc_pointer;
-        // check files between the two pointers
-        let mut diff = 0;
-        if current > gc_pointer {
-            diff = current - gc_pointer;
-        } else if gc_pointer > current {
-            diff = usize::MAX - (gc_pointer - current) + 1;
-        }
+        let diff = self.retained();
         // no GC needed
         if diff <= self.config.max_files {
             return;
         }
 
         // GC is needed
+        let start = Instant::now();
         let del_count = diff - self.config.max_files;
         let mut counter = 0;
+        let mut deleted = Vec::new();
+        // files from the oldest pinned one onwards are still being read,
+        // and are collected once released
+        let pinned = self.pins.oldest(gc_pointer);
+        let mut audit = GcAudit::new(&self.location, &self.monitor);
         // delete files upto `del_count`
-        while counter <= del_count {
-            let file_name = format!("log_{}.bin", gc_pointer);
-            let mut file_path = self.location.clone();
-            file_path.push(&file_name);
-            let _ = std::fs::remove_file(file_path).unwrap();
+        // or move them to the cold tier, if there is one
+        while counter <= del_count && pinned != Some(gc_pointer) {
+            // the file might have been compressed at rotation time
+            let file_path = self.segment_file(gc_pointer);
+            let file_name = file_path.file_name().unwrap_or_default().to_os_string();
+            let cold = self.cold_location.as_ref();
+            let segment = audit.describe(&self.location, gc_pointer, cold.is_some());
+            match cold {
+                None => match std::fs::remove_file(file_path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        self.monitor.error("Failed to delete WAL file", &e)
+                    }
+                    _ => {
+                        deleted.push(gc_pointer);
+                        audit.push(segment);
+                    }
+                },
+                Some(cold) => {
+                    if self.offload(file_path, cold.join(&file_name)) {
+                        audit.push(segment);
+                    }
+                }
+            }
             // increment counter
             gc_pointer = gc_pointer.overflowing_add(1).0;
             counter += 1;
         }
+        self.forget(&deleted);
+        // keep track of the range of files in the cold tier
+        if let Some(cold) = self.cold_location.as_ref() {
+            let meta = Meta::new(cold.clone());
+            let start = meta.read().map(|v| v.0).unwrap_or(self.config.gc_pointer);
+            if let Err(e) = meta.write((start, gc_pointer)) {
+                self.monitor
+                    .error("Failed to write meta of the cold tier", &e);
+            }
+        }
+        self.monitor
+            .observe(Operation::Gc, self.config.gc_pointer, start);
+        audit.report(&self.monitor, GcRule::StorageLimit);
+        self.lifetime.segments_collected += counter as u64;
         // set a new garbage pointer
         self.config.gc_pointer = gc_pointer;
     }
 
+    /// Add the activity since the lifetime counters were last stored to them
+    fn store_counters(&mut self) {
+        let delta = std::mem::take(&mut self.lifetime);
+        if delta == LifetimeStats::default() {
+            return;
+        }
+        if let Err(e) = CountersFile::new(&self.location).add(&delta) {
+            self.monitor
+                .error("Failed to store the lifetime counters of WAL", &e);
+        }
+    }
+
+    /// The lifetime counters, along with the activity not stored yet
+    pub fn lifetime_stats(&self) -> LifetimeStats {
+        CountersFile::new(&self.location).read().add(&self.lifetime)
+    }
+
+    /// Number of files between the two pointers
+    fn retained(&self) -> usize {
+        let (current, gc_pointer) = (self.config.current_pointer, self.config.gc_pointer);
+        if current >= gc_pointer {
+            current - gc_pointer
+        } else {
+            usize::MAX - (gc_pointer - current) + 1
+        }
+    }
+
+    /// Number of files kept beyond the storage limit, because they're pinned by readers or
+    /// failed to be deleted
+    pub fn gc_backlog(&self) -> usize {
+        self.retained().saturating_sub(self.config.max_files)
+    }
+
+    /// Delete all the files before the given one, along with those in the cold tier
+    ///
+    /// The current file is never deleted. Files that are already gone are skipped. A fenced
+    /// writer deletes nothing, see [FileManager::is_fenced].
+    ///
+    /// ## Returns
+    /// The number of files deleted
+    pub fn delete_before(&mut self, end: usize) -> usize {
+        if self.fence().is_err() {
+            return 0;
+        }
+        if let Some(lock) = self.lock.as_ref() {
+            if let Err(e) = lock.lock() {
+                self.monitor
+                    .error("Failed to lock WAL for deleting files", &e);
+                return 0;
+            }
+            self.refresh();
+        }
+        let mut deleted = Vec::new();
+        let mut audit = GcAudit::new(&self.location, &self.monitor);
+        // the older files in the cold tier
+        if let Some(cold) = self.cold_location.as_ref() {
+            let meta = Meta::new(cold.clone());
+            if let Some((mut start, cold_end)) = meta.read() {
+                let last = match end.wrapping_sub(start) <= cold_end.wrapping_sub(start) {
+                    true => end,
+                    false => cold_end,
+                };
+                while start != last {
+                    let segment = audit.describe(cold, start, false);
+                    if self.remove_segment(cold, start) {
+                        audit.push(segment);
+                    }
+                    deleted.push(start);
+                    start = start.wrapping_add(1);
+                }
+                if let Err(e) = meta.write((start, cold_end)) {
+                    self.monitor
+                        .error("Failed to write meta of the cold tier", &e);
+                }
+            }
+        }
+        // the files at the location, up to the current one
+        let (gc_pointer, current) = (self.config.gc_pointer, self.config.current_pointer);
+        let pinned = self.pins.oldest(gc_pointer);
+        if end.wrapping_sub(gc_pointer) <= current.wrapping_sub(gc_pointer) {
+            while self.config.gc_pointer != end && pinned != Some(self.config.gc_pointer) {
+                let segment = audit.describe(&self.location, self.config.gc_pointer, false);
+                if self.remove_segment(&self.location, self.config.gc_pointer) {
+                    audit.push(segment);
+                }
+                if let Some(primary) = self.primary.as_ref() {
+                    self.remove_segment(primary, self.config.gc_pointer);
+                }
+                deleted.push(self.config.gc_pointer);
+                self.config.gc_pointer = self.config.gc_pointer.wrapping_add(1);
+                // the files of the cold tier were counted once moved there
+                self.lifetime.segments_collected += 1;
+            }
+        }
+        self.forget(&deleted);
+        self.write_meta();
+        if let Some(lock) = self.lock.as_ref() {
+            let _ = lock.unlock();
+        }
+        audit.report(&self.monitor, GcRule::OnDemand);
+        if let Some(mirror) = self.mirror.as_mut() {
+            mirror.delete_before(end);
+        }
+        deleted.len()
+    }
+
+    /// Delete the logs from `lsn` onwards, after which new logs continue from it
+    ///
+    /// The files after the one holding the log are deleted, and that file is cut short right
+    /// before the log, becoming the current file again. A compressed file is decompressed first.
+    ///
+    /// ## Returns
+    /// The number of logs deleted, or an error if the log is before the first one kept, or the
+    /// [Lsn] of the logs is unknown
+    pub fn delete_from(&mut self, lsn: Lsn) -> std::io::Result<u64> {
+        self.fence()?;
+        if let Some(lock) = self.lock.as_ref() {
+            lock.lock()?;
+            self.refresh();
+        }
+        let result = self.truncate(lsn);
+        if let Some(lock) = self.lock.as_ref() {
+            let _ = lock.unlock();
+        }
+        if let Some(mirror) = self.mirror.as_mut() {
+            if let Err(e) = mirror.delete_from(lsn) {
+                self.monitor
+                    .error("Failed to delete logs from the mirror", &e);
+            }
+        }
+        result
+    }
+
+    /// Replace the logs from `lsn` onwards with new ones, as a Raft follower does with the
+    /// entries conflicting with the leader's
+    ///
+    /// ## Arguments
+    /// - `lsn`: [Lsn] of the first new log, at most the [Lsn] of the next log
+    /// - `data`: The new logs, framed
+    ///
+    /// ## Returns
+    /// The number of logs deleted, or an error, without changing anything, if `lsn` would leave
+    /// a gap or can't be deleted from, see [FileManager::delete_from]
+    pub fn append_at(&mut self, lsn: Lsn, data: &[u8]) -> std::io::Result<u64> {
+        self.fence()?;
+        let next = self.next_lsn()?;
+        if lsn > next {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Log {} doesn't follow the last log, {}", lsn, next),
+            ));
+        }
+        let deleted = self.delete_from(lsn)?;
+        if !data.is_empty() {
+            self.commit(data)?;
+        }
+        Ok(deleted)
+    }
+
+    /// [Lsn] of the next log written
+    fn next_lsn(&self) -> std::io::Result<Lsn> {
+        let base = self.base.ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::Unsupported,
+                "The LSN of the logs written by older versions is unknown",
+            )
+        })?;
+        let path = naming::path(&self.location, self.config.current_pointer);
+        let (records, _) = frame::scan(File::open(path)?, |_| {})?;
+        Ok(base + records)
+    }
+
+    /// Store key-value pairs along with the logs, such as the term and the vote of a Raft node
+    ///
+    /// The current file is synced to disk first, so that the pairs never refer to logs lost in a
+    /// crash, then all the pairs are replaced at once. An empty value removes its key.
+    pub fn set_state(&mut self, values: &[(&str, &[u8])]) -> std::io::Result<()> {
+        self.fence()?;
+        if let Some(lock) = self.lock.as_ref() {
+            lock.lock()?;
+        }
+        let result = self.file.sync_data().and_then(|_| {
+            let file = StateFile::new(&self.location);
+            let mut state = file.read();
+            for (key, value) in values {
+                match value.is_empty() {
+                    true => state.remove(*key),
+                    false => state.insert(key.to_string(), value.to_vec()),
+                };
+            }
+            file.write(&state)
+        });
+        if let Some(lock) = self.lock.as_ref() {
+            let _ = lock.unlock();
+        }
+        if let Some(mirror) = self.mirror.as_mut() {
+            if let Err(e) = mirror.set_state(values) {
+                self.monitor
+                    .error("Failed to store the state in the mirror", &e);
+            }
+        }
+        result
+    }
+
+    /// The key-value pairs stored along with the logs, see [FileManager::set_state]
+    pub fn state(&self) -> BTreeMap<String, Vec<u8>> {
+        StateFile::new(&self.location).read()
+    }
+
+    /// Cut the logs from `lsn` onwards, see [FileManager::delete_from]
+    fn truncate(&mut self, lsn: Lsn) -> std::io::Result<u64> {
+        let unknown = || {
+            std::io::Error::new(
+                ErrorKind::Unsupported,
+                "The LSN of the logs written by older versions is unknown",
+            )
+        };
+        let current = self.config.current_pointer;
+        let base = self.base.ok_or_else(unknown)?;
+        let next = self.next_lsn()?;
+        if lsn >= next {
+            return Ok(0);
+        }
+        // the file holding the log, searched from the current one backwards
+        let manifest = Manifest::new(self.location.clone());
+        let infos = manifest.read();
+        let (mut index, mut first) = (current, base);
+        let mut trim = self.trim;
+        while lsn < first {
+            if index == self.config.gc_pointer {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Log {} is before the first log kept", lsn),
+                ));
+            }
+            index = index.wrapping_sub(1);
+            let info = infos.get(&index).ok_or_else(unknown)?;
+            first = info.first.ok_or_else(unknown)?;
+            trim = (info.trimmed > 0).then_some(Trim {
+                index,
+                offset: info.trim_offset,
+                records: info.trimmed,
+            });
+        }
+        if lsn < first + trim.map_or(0, |trim| trim.records) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Log {} was trimmed already", lsn),
+            ));
+        }
+        let path = self.segment_file(index);
+        if !path.starts_with(&self.location) {
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!("WAL file {} is no longer in the location written to", index),
+            ));
+        }
+        // the files after it
+        let mut deleted = Vec::new();
+        let mut later = index;
+        while later != current {
+            later = later.wrapping_add(1);
+            self.remove_segment(&self.location, later);
+            deleted.push(later);
+        }
+        // the file is no longer filled, and gets sealed again once it is
+        manifest.remove(&[&[index][..], &deleted].concat())?;
+        let path = match path.to_string_lossy().ends_with(COMPRESSED_EXT) {
+            true => Self::decompress(&path)?,
+            false => path,
+        };
+        let format = read_header(&mut File::open(&path)?)?.0;
+        let offset = File::open(&path)
+            .and_then(|file| frame::offset_of(BufReader::new(file), lsn - first))?
+            .ok_or_else(unknown)?;
+        match format.paged {
+            true => {
+                // the pages are rewritten to end right before the log
+                let mut reader = File::open(&path)?;
+                let (_, prefix) = read_header(&mut reader)?;
+                let mut records = Vec::new();
+                frame::records(reader, format)
+                    .take(offset - prefix.len() as u64)
+                    .read_to_end(&mut records)?;
+                let mut content = file_header(format);
+                content.extend(page::encode(&[&records], format.page_size));
+                // to a temp file first, so that a crash leaves either file whole
+                let mut temp = path.as_os_str().to_owned();
+                temp.push(".tmp");
+                let mut file = File::create(&temp)?;
+                file.write_all(&content)?;
+                file.sync_all()?;
+                std::fs::rename(&temp, &path)?;
+                // persist the rename too, where directories can be synced
+                if let Ok(dir) = File::open(&self.location) {
+                    let _ = dir.sync_all();
+                }
+            }
+            false => std::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(offset)?,
+        }
+        // the file becomes the current one again
+        let (file, filled) = Self::open_file(path)?;
+        self.file = file;
+        self.filled = filled;
+        self.config.current_pointer = index;
+        self.hasher = crc32fast::Hasher::new();
+        self.hashed = 0;
+        self.records = RecordCounter::default();
+        self.base = Some(first);
+        self.trim = trim;
+        if self.chain.is_some() {
+            self.chain = Some(Self::last_link(&self.location, index).unwrap_or_default());
+        }
+        self.write_meta();
+        // the logs appended from now on go to a file of the current format
+        if format != self.format() {
+            self.next_file();
+        }
+        self.preallocate();
+        Ok(next - lsn)
+    }
+
+    /// Decompress a file compressed at rotation time, replacing it with the plain file
+    ///
+    /// ## Returns
+    /// The path of the plain file
+    fn decompress(path: &Path) -> std::io::Result<PathBuf> {
+        let target = PathBuf::from(
+            path.to_string_lossy()
+                .trim_end_matches(COMPRESSED_EXT)
+                .to_string(),
+        );
+        #[cfg(feature = "compression")]
+        {
+            let mut temp = target.as_os_str().to_owned();
+            temp.push(".tmp");
+            let mut decoder = zstd::Decoder::new(File::open(path)?)?;
+            let mut output = File::create(&temp)?;
+            std::io::copy(&mut decoder, &mut output)?;
+            output.sync_all()?;
+            std::fs::rename(&temp, &target)?;
+            std::fs::remove_file(path)?;
+            Ok(target)
+        }
+        #[cfg(not(feature = "compression"))]
+        Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "Enable the `compression` feature to truncate {}",
+                target.display()
+            ),
+        ))
+    }
+
+    /// Delete a file from the directory, whether it's compressed or not
+    ///
+    /// ## Returns
+    /// Whether the file was there, and was deleted
+    fn remove_segment(&self, dir: &Path, index: usize) -> bool {
+        let file_name = match naming::find(dir, index) {
+            Some(file_name) => file_name,
+            None => return false,
+        };
+        let mut removed = true;
+        for path in [
+            dir.join(&file_name),
+            dir.join(format!("{}{}", file_name, COMPRESSED_EXT)),
+        ] {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    self.monitor.error("Failed to delete WAL file", &e);
+                    removed = false;
+                }
+                _ => {}
+            }
+        }
+        removed
+    }
+
+    /// Move a file to the cold tier
+    ///
+    /// ## Returns
+    /// Whether the file was moved
+    fn offload(&self, from: PathBuf, to: PathBuf) -> bool {
+        if std::fs::rename(&from, &to).is_ok() {
+            return true;
+        }
+        // the cold tier may be on a different device
+        match std::fs::copy(&from, &to) {
+            Ok(_) => {
+                let _ = std::fs::remove_file(from);
+                true
+            }
+            Err(e) => {
+                self.monitor
+                    .error("Failed to move WAL file to cold storage", &e);
+                false
+            }
+        }
+    }
+
     /// Create or open the current file to write logs to
     ///
     /// ## Returns
@@ -205,25 +1755,183 @@ impl FileManager {
     /// - 0: the handle to opened file
     /// - 1: size of data in the current file
     ///
-    fn open_file(path: PathBuf) -> Result<(File, usize), ()> {
+    fn open_file(path: PathBuf) -> std::io::Result<(File, usize)> {
+        let context = |e: std::io::Error| {
+            let message = format!("Failed to open WAL file {}: {}", path.display(), e);
+            std::io::Error::new(e.kind(), message)
+        };
         // open the current file in append mode
         let file = std::fs::OpenOptions::new()
             .append(true)
             .create(true)
             .open(&path)
-            .map_err(|_| ())?;
+            .map_err(context)?;
 
         // read size of current file
-        let meta_data = file.metadata().map_err(|_| ())?;
+        let meta_data = file.metadata().map_err(context)?;
         let filled = meta_data.len() as usize;
         Ok((file, filled))
     }
 }
 
+impl Drop for FileManager {
+    /// Store the lifetime counters of a WAL being closed
+    fn drop(&mut self) {
+        // the directory of a temporary WAL is gone already
+        if !self.location.is_dir() {
+            return;
+        }
+        if self.lock.as_ref().is_some_and(|lock| lock.lock().is_err()) {
+            return;
+        }
+        self.store_counters();
+        if let Some(lock) = self.lock.as_ref() {
+            let _ = lock.unlock();
+        }
+    }
+}
+
+/// The header written at the start of every new file
+///
+/// In the power-loss-safe mode, the header takes up the whole first page.
+fn file_header(format: Format) -> Vec<u8> {
+    let mut header = format.header().to_vec();
+    if format.paged {
+        header.resize(format.page_size, 0);
+    }
+    header
+}
+
+/// Find the path of a file, looking into the location first, then the mirror and then the cold storage
+pub(crate) fn segment_path(config: &WalConfig, index: usize) -> Option<PathBuf> {
+    let dirs = std::iter::once(&config.location)
+        .chain(config.fallback_location.as_ref())
+        .chain(config.mirror_location.as_ref())
+        .chain(config.cold_location.as_ref());
+    for dir in dirs {
+        let file_name = match naming::find(dir, index) {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        let path = dir.join(&file_name);
+        if path.exists() {
+            return Some(path);
+        }
+        let compressed = dir.join(format!("{}{}", file_name, COMPRESSED_EXT));
+        if compressed.exists() {
+            return Some(compressed);
+        }
+    }
+    None
+}
+
+/// Open a file for reading, looking into the location first, then the fallback, the mirror and the
+/// cold storage
+/// Files compressed at rotation time are decompressed on the fly
+pub(crate) fn open_segment(config: &WalConfig, index: usize) -> Option<Box<dyn Read>> {
+    let dirs = std::iter::once(&config.location)
+        .chain(config.fallback_location.as_ref())
+        .chain(config.mirror_location.as_ref())
+        .chain(config.cold_location.as_ref());
+    for dir in dirs {
+        let file_name = match naming::find(dir, index) {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        if let Ok(file) = File::open(dir.join(&file_name)) {
+            return Some(Box::new(file));
+        }
+        let compressed = dir.join(format!("{}{}", file_name, COMPRESSED_EXT));
+        if !compressed.exists() {
+            continue;
+        }
+        #[cfg(feature = "compression")]
+        match File::open(compressed).and_then(zstd::Decoder::new) {
+            Ok(decoder) => return Some(Box::new(decoder)),
+            Err(e) => Monitor::new(config).error("Failed to open compressed WAL file", &e),
+        }
+        #[cfg(not(feature = "compression"))]
+        Monitor::new(config).report(
+            format!(
+                "Skipping compressed WAL file {}, enable the `compression` feature to read it",
+                compressed.display()
+            ),
+            Some(ErrorKind::Unsupported),
+        );
+    }
+    None
+}
+
+/// Calculate the CRC32 checksum and count the logs over the full content of a file
+fn scan_file(path: PathBuf) -> std::io::Result<(u32, u64)> {
+    let mut hasher = crc32fast::Hasher::new();
+    let (records, _) = frame::scan(File::open(path)?, |data| hasher.update(data))?;
+    Ok((hasher.finalize(), records))
+}
+
+/// Deallocate a range of a file, which then reads as zeros, without changing the size of the file
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+    // SAFETY: the descriptor stays open for the duration of the call
+    let result = unsafe { libc::fallocate(file.as_raw_fd(), mode, offset as i64, len as i64) };
+    match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Punching holes is only supported on Linux
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _len: u64) -> std::io::Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// Allocate the disk blocks of the start of a file, without changing the size of the file
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: the descriptor stays open for the duration of the call
+    let result =
+        unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len as i64) };
+    match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Allocating blocks past the end of a file is only supported on Linux
+#[cfg(not(target_os = "linux"))]
+fn allocate(_file: &File, _len: u64) -> std::io::Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// Calculate the CRC32 checksum over everything that can be read from the reader
+pub(crate) fn checksum_reader(mut reader: impl Read) -> std::io::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut data = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut data)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&data[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A framed log taking up the given number of bytes, header included
+    fn record(byte: u8, len: usize) -> Vec<u8> {
+        let mut data = ((len - 2) as u16).to_le_bytes().to_vec();
+        data.extend(vec![byte; len - 2]);
+        data
+    }
+
     #[test]
     fn garbage_collection() {
         let location = "./tmp/testing";
@@ -236,7 +1944,7 @@ mod tests {
         }
         // set a pointer
         let meta = Meta::new(PathBuf::from(location));
-        meta.write((0, 9));
+        meta.write((0, 9)).unwrap();
 
         // write to manager to test that the GC ran
         let config = WalConfig {
@@ -244,12 +1952,13 @@ mod tests {
             size: PAGE_SIZE * NUM_FILES_SPLIT,
             fsync: false,
             buffer_size: 4 * 1024,
+            ..Default::default()
         };
-        let mut manager = FileManager::new(config); // 1MB
+        let mut manager = FileManager::new(config).unwrap(); // 1MB
         assert_eq!(manager.config.max_files, 5);
         for _ in 0..2 {
             let data = [101; PAGE_SIZE];
-            manager.commit(&data);
+            manager.commit(&data).unwrap();
         }
 
         // run tests
@@ -257,11 +1966,11 @@ mod tests {
         let (gc, cp) = meta.read().unwrap();
         assert_eq!(gc, 6);
         assert_eq!(cp, 11);
-        assert_eq!(PathBuf::from("./tmp/testing/log_1.bin").exists(), false);
-        assert_eq!(PathBuf::from("./tmp/testing/log_5.bin").exists(), false);
-        assert_eq!(PathBuf::from("./tmp/testing/log_6.bin").exists(), true);
-        assert_eq!(PathBuf::from("./tmp/testing/log_10.bin").exists(), true);
-        assert_eq!(PathBuf::from("./tmp/testing/log_11.bin").exists(), true);
+        assert!(!PathBuf::from("./tmp/testing/log_1.bin").exists());
+        assert!(!PathBuf::from("./tmp/testing/log_5.bin").exists());
+        assert!(PathBuf::from("./tmp/testing/log_6.bin").exists());
+        assert!(PathBuf::from("./tmp/testing/log_10.bin").exists());
+        assert!(PathBuf::from("./tmp/testing/log_11.bin").exists());
     }
 
     // Test garbage collection when logs until
@@ -282,7 +1991,7 @@ mod tests {
         }
         // set a pointer
         let meta = Meta::new(PathBuf::from(location));
-        meta.write((usize::MAX - 9, 1));
+        meta.write((usize::MAX - 9, 1)).unwrap();
 
         // write to manager to test that the GC ran
         let config = WalConfig {
@@ -290,12 +1999,13 @@ mod tests {
             size: PAGE_SIZE * NUM_FILES_SPLIT,
             fsync: false,
             buffer_size: 4 * 1024,
+            ..Default::default()
         };
-        let mut manager = FileManager::new(config);
+        let mut manager = FileManager::new(config).unwrap();
         assert_eq!(manager.config.max_files, 5);
         for _ in 0..2 {
             let data = [101; PAGE_SIZE];
-            manager.commit(&data);
+            manager.commit(&data).unwrap();
         }
 
         // run tests
@@ -303,20 +2013,368 @@ mod tests {
         let (gc, cp) = meta.read().unwrap();
         assert_eq!(gc, usize::MAX - 1);
         assert_eq!(cp, 3);
-        assert_eq!(PathBuf::from("./tmp/testing/log_1.bin").exists(), true);
-        assert_eq!(PathBuf::from("./tmp/testing/log_3.bin").exists(), true);
-        assert_eq!(
-            PathBuf::from(format!("./tmp/testing/log_{}.bin", usize::MAX)).exists(),
-            true
-        );
-        assert_eq!(
-            PathBuf::from(format!("./tmp/testing/log_{}.bin", usize::MAX - 1)).exists(),
-            true
-        );
+        assert!(PathBuf::from("./tmp/testing/log_1.bin").exists());
+        assert!(PathBuf::from("./tmp/testing/log_3.bin").exists());
+        assert!(PathBuf::from(format!("./tmp/testing/log_{}.bin", usize::MAX)).exists());
+        assert!(PathBuf::from(format!("./tmp/testing/log_{}.bin", usize::MAX - 1)).exists());
+        assert!(!PathBuf::from(format!("./tmp/testing/log_{}.bin", usize::MAX - 3)).exists());
+    }
+
+    #[test]
+    fn multi_process() {
+        let location = "./tmp/multi_process";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            multi_process: true,
+            ..Default::default()
+        };
+        // two managers stand in for two processes sharing the directory
+        let mut first = FileManager::new(config.clone()).unwrap();
+        let mut second = FileManager::new(config).unwrap();
+        first.commit(&record(1, PAGE_SIZE / 2)).unwrap();
+        second.commit(&record(2, PAGE_SIZE / 2)).unwrap();
+        // the second append caught up with the first one and rotated the file
+        let (_, cp) = Meta::new(PathBuf::from(location)).read().unwrap();
+        assert_eq!(cp, 1);
+        first.commit(&record(3, 10)).unwrap();
+        assert_eq!(first.config.current_pointer, 1);
+        let size = std::fs::metadata(format!("{}/log_0.bin", location))
+            .unwrap()
+            .len();
+        assert_eq!(size as usize, HEADER_SIZE + PAGE_SIZE);
+        let size = std::fs::metadata(format!("{}/log_1.bin", location))
+            .unwrap()
+            .len();
+        assert_eq!(size as usize, HEADER_SIZE + 10);
+    }
+
+    #[test]
+    fn cold_storage() {
+        let location = "./tmp/cold_storage";
+        let cold_location = "./tmp/cold_storage_tier";
+        let _ = std::fs::remove_dir_all(location);
+        let _ = std::fs::remove_dir_all(cold_location);
+        std::fs::create_dir_all(location).unwrap();
+        std::fs::create_dir_all(cold_location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            cold_location: Some(cold_location.into()),
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config).unwrap();
+        for _ in 0..8 {
+            manager.commit(&record(101, PAGE_SIZE)).unwrap();
+        }
+        // the oldest files were moved, not deleted
+        let (gc, cp) = Meta::new(PathBuf::from(location)).read().unwrap();
+        assert_eq!((gc, cp), (4, 8));
+        let (start, end) = Meta::new(PathBuf::from(cold_location)).read().unwrap();
+        assert_eq!((start, end), (0, 4));
+        assert!(!PathBuf::from(format!("{}/log_1.bin", location)).exists());
+        assert!(PathBuf::from(format!("{}/log_0.bin", cold_location)).exists());
+        assert!(PathBuf::from(format!("{}/log_3.bin", cold_location)).exists());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compress_at_rotation() {
+        let location = "./tmp/compress_at_rotation";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            compress_segments: true,
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config).unwrap();
+        for _ in 0..8 {
+            manager.commit(&record(101, PAGE_SIZE)).unwrap();
+        }
+        // filled files are compressed, the current one is not
+        assert!(!PathBuf::from(format!("{}/log_6.bin", location)).exists());
+        let compressed = PathBuf::from(format!("{}/log_6.bin.zst", location));
+        assert!(compressed.metadata().unwrap().len() < PAGE_SIZE as u64);
+        assert!(PathBuf::from(format!("{}/log_8.bin", location)).exists());
+        // and still get garbage collected
+        assert!(!PathBuf::from(format!("{}/log_0.bin.zst", location)).exists());
+    }
+
+    #[test]
+    fn checksum_at_close() {
+        let location = "./tmp/checksum_at_close";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config.clone()).unwrap();
+        manager.commit(&record(1, PAGE_SIZE / 2)).unwrap();
+        // the second half is written after a restart
+        drop(manager);
+        let mut manager = FileManager::new(config).unwrap();
+        manager.commit(&record(2, PAGE_SIZE / 2)).unwrap();
+        for _ in 0..6 {
+            manager.commit(&record(3, PAGE_SIZE)).unwrap();
+        }
+        let segments = Manifest::new(location.into()).read();
+        // the GC'd files are dropped from the manifest
         assert_eq!(
-            PathBuf::from(format!("./tmp/testing/log_{}.bin", usize::MAX - 3)).exists(),
-            false
+            segments.keys().copied().collect::<Vec<_>>(),
+            vec![2, 3, 4, 5, 6]
         );
+        for (index, info) in segments {
+            let path = PathBuf::from(format!("{}/log_{}.bin", location, index));
+            assert_eq!(info.checksum, scan_file(path).unwrap().0);
+        }
+    }
+
+    #[test]
+    fn reload() {
+        let location = "./tmp/reload";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config).unwrap();
+        manager.reload(PAGE_SIZE * 2 * NUM_FILES_SPLIT, true);
+        for _ in 0..10 {
+            manager.commit(&record(101, PAGE_SIZE * 2)).unwrap();
+        }
+        assert!(manager.syncs());
+        assert_eq!(manager.config.current_pointer, 10);
+        // shrinking the storage size collects the garbage right away
+        manager.reload(PAGE_SIZE * NUM_FILES_SPLIT, false);
+        assert!(!manager.syncs());
+        assert_eq!(manager.config.max_files, 5);
+        let (gc, cp) = Meta::new(PathBuf::from(location)).read().unwrap();
+        assert_eq!((gc, cp), (6, 10));
+    }
+
+    #[test]
+    fn mirror() {
+        let location = "./tmp/mirror_primary";
+        let mirror_location = "./tmp/mirror_secondary";
+        let _ = std::fs::remove_dir_all(location);
+        let _ = std::fs::remove_dir_all(mirror_location);
+        std::fs::create_dir_all(location).unwrap();
+        std::fs::create_dir_all(mirror_location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            mirror_location: Some(mirror_location.into()),
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config).unwrap();
+        for i in 0..8 {
+            manager.commit(&record(i, PAGE_SIZE)).unwrap();
+        }
+        manager.commit(&record(9, 100)).unwrap();
+        // both locations hold the same files and pointers
+        let primary = Meta::new(PathBuf::from(location)).read();
+        let mirror = Meta::new(PathBuf::from(mirror_location)).read();
+        assert_eq!(primary, mirror);
+        for index in 4..=8 {
+            let primary = std::fs::read(format!("{}/log_{}.bin", location, index)).unwrap();
+            let mirror = std::fs::read(format!("{}/log_{}.bin", mirror_location, index)).unwrap();
+            assert_eq!(primary, mirror);
+        }
+        drop(manager);
+        // a replaced disk is restored from the mirror
+        std::fs::remove_dir_all(location).unwrap();
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            mirror_location: Some(mirror_location.into()),
+            ..Default::default()
+        };
+        let manager = FileManager::new(config).unwrap();
+        assert_eq!(manager.config.current_pointer, 8);
+        assert_eq!(manager.filled, HEADER_SIZE + 100);
+    }
+
+    #[test]
+    fn meta_values() {
+        let location = "./tmp/meta_values";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let meta = Meta::new(location.into());
+        meta.write_with_epoch((3, 7), 2, None).unwrap();
+        assert_eq!(meta.read(), Some((3, 7)));
+        assert_eq!(meta.epoch(), 2);
+        assert_eq!(meta.trim(), None);
+        let trim = Trim {
+            index: 7,
+            offset: 120,
+            records: 4,
+        };
+        meta.write_with_epoch((3, 7), 2, Some(trim)).unwrap();
+        assert_eq!(meta.read(), Some((3, 7)));
+        assert_eq!(meta.trim(), Some(trim));
+        // the full u64 range is accepted, and anything else is rejected
+        std::fs::write(format!("{}/meta", location), "0 18446744073709551615 1").unwrap();
+        assert_eq!(meta.read_u64(), Some((0, u64::MAX)));
+        std::fs::write(format!("{}/meta", location), "0 18446744073709551616 1").unwrap();
+        assert_eq!(meta.read_u64(), None);
+        std::fs::write(format!("{}/meta", location), "0 -1 1").unwrap();
+        assert_eq!(meta.read_u64(), None);
+    }
+
+    #[test]
+    fn meta_slots() {
+        let location = "./tmp/meta_slots";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let meta = Meta::new(location.into());
+        meta.write((1, 2)).unwrap();
+        meta.use_slots().unwrap();
+        assert!(meta.exists());
+        assert_eq!(meta.read(), Some((1, 2)));
+        meta.write_with_epoch((1, 3), 4, None).unwrap();
+        assert_eq!(meta.read(), Some((1, 3)));
+        assert_eq!(meta.epoch(), 4);
+        // a torn slot leaves the previous values in place
+        std::fs::write(format!("{}/meta.1", location), "1 4 5\n1 0").unwrap();
+        assert_eq!(meta.read(), Some((1, 2)));
+        meta.write((2, 5)).unwrap();
+        assert_eq!(meta.read(), Some((2, 5)));
+    }
+
+    #[test]
+    fn meta_errors() {
+        let location = "./tmp/meta_errors";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config.clone()).unwrap();
+        assert!(config.stats.last_error().is_none());
+        // meta can no longer be written
+        std::fs::remove_file(format!("{}/meta", location)).unwrap();
+        std::fs::create_dir(format!("{}/meta", location)).unwrap();
+        manager.next_file();
+        let error = config.stats.last_error().unwrap();
+        assert!(error.message.starts_with("Failed to write WAL meta"));
+    }
+
+    #[test]
+    fn fencing() {
+        let location = "./tmp/fencing";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            fencing: true,
+            ..Default::default()
+        };
+        let mut stale = FileManager::new(config.clone()).unwrap();
+        stale.commit(&record(1, 10)).unwrap();
+        assert!(!stale.is_fenced());
+        // a new writer takes over, e.g. after a failover
+        let mut current = FileManager::new(config).unwrap();
+        assert_eq!(current.epoch, stale.epoch + 1);
+        assert!(stale.commit(&record(2, 10)).is_err());
+        current.commit(&record(3, 10)).unwrap();
+        assert!(stale.is_fenced());
+        assert!(!current.is_fenced());
+        let data = std::fs::read(format!("{}/log_0.bin", location)).unwrap();
+        assert_eq!(data[HEADER_SIZE..], [record(1, 10), record(3, 10)].concat());
+    }
+
+    #[test]
+    fn fencing_deletes() {
+        let location = "./tmp/fencing_deletes";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            fencing: true,
+            ..Default::default()
+        };
+        let mut stale = FileManager::new(config.clone()).unwrap();
+        stale.commit(&record(1, 10)).unwrap();
+        let mut current = FileManager::new(config.clone()).unwrap();
+        for i in 2..6 {
+            current.commit(&record(i, 10)).unwrap();
+        }
+        current.next_file();
+        current.commit(&record(6, 10)).unwrap();
+        // the stale writer changes nothing written by the current one
+        assert!(stale.delete_from(2).is_err());
+        assert!(stale.append_at(2, &record(7, 10)).is_err());
+        assert!(stale.set_state(&[("term", b"1")]).is_err());
+        assert_eq!(stale.delete_before(1), 0);
+        assert!(stale.is_fenced());
+        assert!(stale.state().is_empty());
+        drop(current);
+        assert_eq!(crate::iter::count(&config), 6);
+    }
+
+    #[test]
+    fn delete_before() {
+        let location = "./tmp/delete_before";
+        let cold_location = "./tmp/delete_before_cold";
+        let _ = std::fs::remove_dir_all(location);
+        let _ = std::fs::remove_dir_all(cold_location);
+        std::fs::create_dir_all(location).unwrap();
+        std::fs::create_dir_all(cold_location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            cold_location: Some(cold_location.into()),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config).unwrap();
+        for _ in 0..8 {
+            manager.commit(&record(1, PAGE_SIZE)).unwrap();
+        }
+        let cold = Meta::new(cold_location.into());
+        assert_eq!(cold.read(), Some((0, 4)));
+        assert_eq!(manager.delete_before(6), 6);
+        assert_eq!(cold.read(), Some((4, 4)));
+        assert_eq!(Meta::new(location.into()).read(), Some((6, 8)));
+        assert!(!PathBuf::from(format!("{}/log_5.bin", location)).exists());
+        assert!(PathBuf::from(format!("{}/log_6.bin", location)).exists());
+        // the current file is never deleted
+        assert_eq!(manager.delete_before(100), 0);
+        assert_eq!(manager.delete_before(8), 2);
+        assert_eq!(Meta::new(location.into()).read(), Some((8, 8)));
+    }
+
+    #[test]
+    fn pinned_files() {
+        let location = "./tmp/pinned_files";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            size: PAGE_SIZE * NUM_FILES_SPLIT,
+            ..Default::default()
+        };
+        let pin = config.pins.pin(1);
+        let mut manager = FileManager::new(config).unwrap();
+        for _ in 0..10 {
+            manager.commit(&record(1, PAGE_SIZE)).unwrap();
+        }
+        // a reader is still on file 1, so it's kept along with everything after it
+        assert_eq!(Meta::new(location.into()).read(), Some((1, 10)));
+        assert!(PathBuf::from(format!("{}/log_1.bin", location)).exists());
+        assert!(!PathBuf::from(format!("{}/log_0.bin", location)).exists());
+        // the files are collected once released
+        drop(pin);
+        manager.commit(&record(1, PAGE_SIZE)).unwrap();
+        assert_eq!(Meta::new(location.into()).read(), Some((7, 11)));
     }
 
     #[test]
@@ -324,10 +2382,52 @@ mod tests {
         let v = usize::MAX - 1;
         let (new_v, of) = v.overflowing_add(1);
         assert_eq!(new_v, usize::MAX);
-        assert_eq!(of, false);
+        assert!(!of);
 
         let (new_v, of) = v.overflowing_add(5);
         assert_eq!(new_v, 3);
-        assert_eq!(of, true);
+        assert!(of);
+    }
+
+    #[test]
+    fn failover() {
+        let location = "./tmp/failover_primary";
+        let fallback = "./tmp/failover_fallback";
+        let _ = std::fs::remove_dir_all(location);
+        let _ = std::fs::remove_dir_all(fallback);
+        std::fs::create_dir_all(location).unwrap();
+        let config = WalConfig {
+            location: location.into(),
+            fallback_location: Some(fallback.into()),
+            ..Default::default()
+        };
+        let mut manager = FileManager::new(config.clone()).unwrap();
+        manager.commit(&record(1, 100)).unwrap();
+        // the disk fails, as a read-only handle refuses the writes
+        manager.file = File::open(format!("{}/log_0.bin", location)).unwrap();
+        for i in 2..FAILOVER_AFTER as u8 + 1 {
+            assert!(manager.commit(&record(i, 100)).is_err());
+        }
+        assert!(failover::failed_over(&config).is_none());
+        // the last failed write is written again in the fallback location
+        manager.commit(&record(4, 100)).unwrap();
+        assert_eq!(
+            failover::failed_over(&config),
+            Some(&PathBuf::from(fallback))
+        );
+        assert_eq!(manager.location, PathBuf::from(fallback));
+        manager.commit(&record(5, 100)).unwrap();
+        drop(manager);
+        assert!(PathBuf::from(format!("{}/log_1.bin", fallback)).exists());
+        assert_eq!(crate::iter::count(&config), 3);
+
+        // a writer opening the WAL keeps writing to the fallback location
+        let mut manager = FileManager::new(config.clone()).unwrap();
+        assert_eq!(manager.location, PathBuf::from(fallback));
+        assert_eq!(manager.primary, Some(PathBuf::from(location)));
+        assert!(manager.recovery_report().is_consistent());
+        manager.commit(&record(6, 100)).unwrap();
+        drop(manager);
+        assert_eq!(crate::iter::count(&config), 4);
     }
 }
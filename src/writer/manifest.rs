@@ -0,0 +1,227 @@
+use super::chain::Link;
+use crate::Lsn;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Information about a log file, recorded once the file is filled and closed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SegmentInfo {
+    /// Index of the file, i.e. the postfix in its name
+    pub index: usize,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// CRC32 checksum over the full content of the file
+    pub checksum: u32,
+    /// [Lsn] of the first log in the file, unknown for files written by older versions
+    pub first: Option<Lsn>,
+    /// Number of logs in the file
+    pub records: Option<u64>,
+    /// Offset of the first log kept, once the disk blocks of the logs before it are released
+    pub trim_offset: u64,
+    /// Number of logs before [SegmentInfo::trim_offset], which can no longer be read
+    pub trimmed: u64,
+    /// Link of the last log in the file, when the logs are hash-chained
+    pub chain: Option<Link>,
+    /// Ed25519 signature of the file, when the files are signed
+    pub signature: Option<[u8; 64]>,
+    /// Root of the [MerkleTree](crate::MerkleTree) over the logs of the file, `None` without logs
+    pub merkle: Option<[u8; 32]>,
+}
+
+impl SegmentInfo {
+    fn parse(line: &str) -> Option<Self> {
+        let mut info = Self::default();
+        let mut has_index = false;
+        for pair in line.split_whitespace() {
+            let (key, value) = pair.split_once('=')?;
+            match key {
+                "index" => {
+                    // stored as u64 regardless of the platform
+                    info.index = usize::try_from(value.parse::<u64>().ok()?).ok()?;
+                    has_index = true;
+                }
+                "size" => info.size = value.parse().ok()?,
+                "crc32" => info.checksum = u32::from_str_radix(value, 16).ok()?,
+                "first" => info.first = Some(value.parse().ok()?),
+                "records" => info.records = Some(value.parse().ok()?),
+                "trim_offset" => info.trim_offset = value.parse().ok()?,
+                "trimmed" => info.trimmed = value.parse().ok()?,
+                "chain" => info.chain = Some(from_hex(value)?),
+                "signature" => info.signature = Some(from_hex(value)?),
+                "merkle" => info.merkle = Some(from_hex(value)?),
+                // ignore the keys written by newer versions
+                _ => {}
+            }
+        }
+        has_index.then_some(info)
+    }
+
+    /// [Lsn] of the first log after this file, if known
+    pub fn next_lsn(&self) -> Option<Lsn> {
+        Some(self.first? + self.records?)
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = format!(
+            "index={} size={} crc32={:08x}",
+            self.index, self.size, self.checksum
+        );
+        if let Some(first) = self.first {
+            line.push_str(&format!(" first={}", first));
+        }
+        if let Some(records) = self.records {
+            line.push_str(&format!(" records={}", records));
+        }
+        if self.trim_offset > 0 {
+            line.push_str(&format!(
+                " trim_offset={} trimmed={}",
+                self.trim_offset, self.trimmed
+            ));
+        }
+        if let Some(link) = self.chain.as_ref() {
+            line.push_str(&format!(" chain={}", to_hex(link)));
+        }
+        if let Some(root) = self.merkle.as_ref() {
+            line.push_str(&format!(" merkle={}", to_hex(root)));
+        }
+        if let Some(signature) = self.signature.as_ref() {
+            line.push_str(&format!(" signature={}", to_hex(signature)));
+        }
+        line.push('\n');
+        line
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+    if value.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(value.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// The manifest keeps a record of every closed log file
+///
+/// It's stored as a text file with one line of `key=value` pairs per log file,
+/// so that new keys can be added without breaking older readers.
+pub(crate) struct Manifest {
+    location: PathBuf,
+}
+
+impl Manifest {
+    pub fn new(dir_path: PathBuf) -> Self {
+        let mut path = dir_path;
+        path.push("manifest");
+        Self { location: path }
+    }
+
+    /// Read information of all the closed files, keyed by the file index
+    pub fn read(&self) -> BTreeMap<usize, SegmentInfo> {
+        let content = std::fs::read_to_string(&self.location).unwrap_or_default();
+        content
+            .lines()
+            .filter_map(SegmentInfo::parse)
+            .map(|info| (info.index, info))
+            .collect()
+    }
+
+    /// Record information of a newly closed file
+    pub fn append(&self, info: &SegmentInfo) -> std::io::Result<()> {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.location)?
+            .write_all(info.to_line().as_bytes())
+    }
+
+    /// Replace the information of a file
+    pub fn update(&self, info: SegmentInfo) -> std::io::Result<()> {
+        let mut segments = self.read();
+        segments.insert(info.index, info);
+        self.write(segments.values())
+    }
+
+    /// Drop the information of files that no longer exist
+    pub fn remove(&self, indexes: &[usize]) -> std::io::Result<()> {
+        if indexes.is_empty() {
+            return Ok(());
+        }
+        let mut segments = self.read();
+        for index in indexes {
+            segments.remove(index);
+        }
+        self.write(segments.values())
+    }
+
+    /// Replace the manifest atomically, by writing to a temp file and renaming it
+    fn write<'a>(&self, segments: impl Iterator<Item = &'a SegmentInfo>) -> std::io::Result<()> {
+        let content = segments.map(|s| s.to_line()).collect::<String>();
+        let mut temp = self.location.clone();
+        temp.set_extension("tmp");
+        File::create(&temp)?.write_all(content.as_bytes())?;
+        std::fs::rename(&temp, &self.location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_remove() {
+        let location = "./tmp/manifest";
+        let _ = std::fs::remove_dir_all(location);
+        std::fs::create_dir_all(location).unwrap();
+        let manifest = Manifest::new(location.into());
+        for index in 0..3 {
+            manifest
+                .append(&SegmentInfo {
+                    index,
+                    size: 4096,
+                    checksum: 0xdead_beef,
+                    first: Some(index as u64 * 10),
+                    records: Some(10),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        manifest.remove(&[0, 1]).unwrap();
+        let segments = manifest.read();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[&2].checksum, 0xdead_beef);
+        assert_eq!(segments[&2].next_lsn(), Some(30));
+        // unknown keys from newer versions are ignored
+        assert!(SegmentInfo::parse("index=4 size=1 crc32=00000001 future=abc").is_some());
+        // and the ones missing from older versions are unknown
+        let info = SegmentInfo::parse("index=4 size=1 crc32=00000001").unwrap();
+        assert_eq!(info.first, None);
+        // a trimmed file
+        manifest
+            .update(SegmentInfo {
+                trim_offset: 2048,
+                trimmed: 4,
+                ..segments[&2].clone()
+            })
+            .unwrap();
+        let segments = manifest.read();
+        assert_eq!((segments[&2].trim_offset, segments[&2].trimmed), (2048, 4));
+        assert_eq!(segments[&2].next_lsn(), Some(30));
+        // the link of the last log of a hash-chained file, and the signature of a signed one
+        let info = SegmentInfo {
+            chain: Some([7; 32]),
+            signature: Some([9; 64]),
+            merkle: Some([3; 32]),
+            ..segments[&2].clone()
+        };
+        assert_eq!(SegmentInfo::parse(&info.to_line()), Some(info));
+    }
+}
@@ -1,16 +1,78 @@
-mod buffer;
+mod audit;
+pub(crate) mod buffer;
+pub(crate) mod chain;
+mod checksum;
+pub(crate) mod compress;
+pub(crate) mod counters;
+mod flush;
+pub(crate) mod frame;
+mod group;
+pub(crate) mod header;
 pub(crate) mod manager;
+pub(crate) mod manifest;
+pub(crate) mod naming;
+pub(crate) mod page;
+pub(crate) mod pins;
+#[cfg(feature = "signing")]
+pub(crate) mod signature;
+pub(crate) mod state;
 
-use self::buffer::Buffer;
+pub use self::buffer::BufferOverflow;
+pub use self::checksum::{Checksum, ChecksumVerification};
+pub use self::flush::FlushHandle;
+pub use self::frame::{decode_record, encode_record, RecordKind};
+pub use self::header::FormatPolicy;
+
+use self::buffer::{frame, Buffer, Data, FRAME_SIZE};
+use self::counters::CountersFile;
+use self::flush::Syncer;
+use self::frame::RecordHeader;
+use self::group::SyncGroup;
 use self::manager::FileManager;
-use crate::WalConfig;
-use std::sync::Mutex;
+use self::state::StateFile;
+use crate::builder::ConfigError;
+use crate::listener::Operation;
+use crate::recovery::RecoveryReport;
+use crate::stats::Monitor;
+use crate::throttle::{TokenBucket, WriteLimit};
+use crate::{LifetimeStats, Lsn, WalConfig};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::Instant;
 
 /// Log Writer responsible for writing the information to the buffer as well as on disk
 pub(crate) struct Writer {
     buffer: Mutex<Buffer>,
-    io: Mutex<FileManager>,
+    /// Opened on the first write to disk when the initialization is lazy, or the reason the
+    /// files couldn't be opened, after which the writer refuses to write
+    io: OnceLock<Result<Mutex<FileManager>, String>>,
     config: WalConfig,
+    monitor: Monitor,
+    /// Caps the rate of the writes, see [WriteLimit]
+    limiter: Mutex<Option<TokenBucket>>,
+    /// Set when the WAL holds files written by a newer version, see [FormatPolicy::ReadOnly]
+    read_only: bool,
+    /// Bytes taken out of the buffer, or written around it, waiting for the file manager
+    queued: AtomicUsize,
+    /// Coalesces the syncs of concurrent writes, when the sync window is set
+    group: Option<SyncGroup>,
+    /// Size of the new buffers, the configured one unless capped by [Writer::set_memory_budget]
+    buffer_size: AtomicUsize,
+    /// Limit on the data not written to disk yet, `usize::MAX` without one
+    max_unflushed: AtomicUsize,
+    /// Syncs the current file for the flushes
+    syncer: Syncer,
+}
+
+/// Bytes counted as waiting for the file manager until dropped, see [Writer::unflushed]
+struct Queued<'a>(&'a AtomicUsize, usize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(self.1, Ordering::Relaxed);
+    }
 }
 
 impl Writer {
@@ -20,10 +82,44 @@ impl Writer {
     /// - `location`: Location where the log files shall be stored
     /// - `size`: Maximum amount of data that can be stored, in bytes
     pub fn new(config: WalConfig) -> Self {
-        Self {
+        let read_only = config.format_policy == FormatPolicy::ReadOnly
+            && !crate::iter::newer_segments(&config).is_empty();
+        let writer = Self {
             buffer: Mutex::new(Buffer::new(Some(config.buffer_size))),
-            io: Mutex::new(FileManager::new(config.clone())),
+            io: OnceLock::new(),
+            monitor: Monitor::new(&config),
+            limiter: Mutex::new(config.write_limit.and_then(TokenBucket::new)),
+            group: config
+                .sync_window
+                .map(|window| SyncGroup::new(window, Monitor::new(&config))),
+            buffer_size: AtomicUsize::new(config.buffer_size),
+            max_unflushed: AtomicUsize::new(config.max_unflushed.unwrap_or(usize::MAX)),
+            syncer: Syncer::new(Monitor::new(&config)),
             config,
+            read_only,
+            queued: AtomicUsize::new(0),
+        };
+        if !writer.config.lazy_init && !read_only {
+            // a failure is reported, and kept for the writer to refuse the writes
+            let _ = writer.open();
+        }
+        writer
+    }
+
+    /// Create the directories and open the files, if not done yet
+    ///
+    /// ## Returns
+    /// An error if the directories can't be created, or the files can't be opened
+    pub fn init(&self) -> Result<(), ConfigError> {
+        if self.read_only {
+            return Ok(());
+        }
+        if self.io.get().is_none() {
+            crate::builder::create_dirs(&self.config)?;
+        }
+        match self.open() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ConfigError::new("location", e.to_string())),
         }
     }
 
@@ -34,71 +130,700 @@ impl Writer {
     /// ## Arguments
     /// - `msg`: The log data to be written
     ///
-    pub fn log(&self, msg: &[u8]) {
+    /// ## Returns
+    /// `false` if the log was refused, as the buffer had no room for it, see [BufferOverflow]
+    pub fn log(&self, msg: &[u8]) -> bool {
+        self.log_record(RecordHeader::default(), msg)
+    }
+
+    /// Add a new record of any kind, a log or a control record, in order with the logs
+    ///
+    /// ## Returns
+    /// `false` if the record was refused, as the buffer had no room for it, see [BufferOverflow],
+    /// or as the writer refuses to write, see [Writer::refusal]
+    pub fn log_record(&self, header: RecordHeader, msg: &[u8]) -> bool {
+        if self.refuse() {
+            return false;
+        }
+        self.limit(1, msg.len());
         // if buffer is disabled, write directly to file and exit
         if self.config.buffer_size == 0 {
-            let mut buffer = Buffer::new(Some(msg.len() + 2));
-            buffer.try_add(msg);
+            let mut buffer = Buffer::new(Some(msg.len() + 2 + header.len()));
+            buffer.try_add(header, msg);
             let data = buffer.consume(true);
             self.write(&data);
-            return;
+            return true;
         }
 
         // Buffer is enabled
         // acquire lock on buffer
-        let mut lock = self.buffer.lock().unwrap();
+        let mut lock = self.buffer();
+        if !lock.fits(header, msg) && !lock.is_empty() {
+            match self.config.buffer_overflow {
+                BufferOverflow::Grow => {}
+                BufferOverflow::FlushFirst => {
+                    let full = std::mem::replace(&mut *lock, self.new_buffer());
+                    let mut data = self.take(full, false);
+                    // a log larger than the whole buffer goes along with it
+                    if lock.try_add(header, msg).1 {
+                        let buffer = std::mem::replace(&mut *lock, self.new_buffer());
+                        buffer::append(&mut data, &self.take(buffer, false));
+                    }
+                    let _queued = self.queue(data.len());
+                    let Ok(io) = self.io() else {
+                        return false;
+                    };
+                    drop(lock);
+                    let _ = self.synced(io, |io| io.commit(&data));
+                    return true;
+                }
+                BufferOverflow::WriteThrough => {
+                    self.write_through(lock, header, msg, false);
+                    return true;
+                }
+                BufferOverflow::Reject => return false,
+            }
+        }
         // add data to buffer
-        let (added, flush) = lock.try_add(msg);
+        let (added, full) = lock.try_add(header, msg);
+        // past the limit on the unflushed data, the buffer is written before it's full
+        let flush = full || self.over_limit(lock.data().len());
         if added && !flush {
-            return;
+            return true;
         }
         // buffer not able to accept more data, due to being filled
         // create a new buffer
-        let mut new_buffer = Buffer::new(None);
+        let mut new_buffer = self.new_buffer();
         if !added {
-            new_buffer.try_add(msg);
+            new_buffer.try_add(header, msg);
         }
         // swap the buffers
         let buffer = std::mem::replace(&mut *lock, new_buffer);
-        // drop lock
+        if !flush {
+            return true;
+        }
+        // acquire lock on io to add the buffer to file
+        // hold on to the buffer lock until IO is acquired, so that newer logs can't overtake these
+        let data = self.take(buffer, full);
+        let _queued = self.queue(data.len());
+        let Ok(io) = self.io() else {
+            return false;
+        };
         drop(lock);
+        let _ = self.synced(io, |io| io.commit(&data));
+        true
+    }
 
-        // acquire lock on io to add the buffer to file
-        if flush {
-            let data = buffer.consume(true);
-            self.write(&data);
+    /// Add several logs at once
+    ///
+    /// The buffer lock is acquired only once for all the logs. Buffers filled along the way are
+    /// chained together and written to the file in a single go at the end.
+    ///
+    /// ## Arguments
+    /// - `msgs`: The logs data to be written
+    ///
+    pub fn log_many<I>(&self, msgs: I)
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        if self.refuse() {
+            return;
+        }
+        // the logs past the write limit wait as they come, holding up the others
+        // the conversion is a no-op without the `zeroize` feature
+        #[allow(clippy::useless_conversion)]
+        let msgs = msgs
+            .into_iter()
+            .map(Data::from)
+            .inspect(|msg| self.limit(1, msg.len()));
+        // if buffer is disabled, write everything directly to file
+        if self.config.buffer_size == 0 {
+            let mut data = Data::default();
+            for msg in msgs {
+                let mut buffer = Buffer::new(Some(msg.len() + FRAME_SIZE));
+                buffer.try_add(RecordHeader::default(), &msg);
+                buffer::append(&mut data, &buffer.consume(true));
+            }
+            if !data.is_empty() {
+                self.write(&data);
+            }
+            return;
+        }
+
+        let mut lock = self.buffer();
+        let mut filled = Data::default();
+        for msg in msgs {
+            let (added, full) = lock.try_add(RecordHeader::default(), &msg);
+            if added && !full && !self.over_limit(lock.data().len() + filled.len()) {
+                continue;
+            }
+            let mut new_buffer = self.new_buffer();
+            if !added {
+                new_buffer.try_add(RecordHeader::default(), &msg);
+            }
+            let buffer = std::mem::replace(&mut *lock, new_buffer);
+            buffer::append(&mut filled, &self.take(buffer, full));
+            // past the limit, the filled buffers are written before adding more logs
+            if self.over_limit(filled.len()) {
+                let _queued = self.queue(filled.len());
+                if let Ok(io) = self.io() {
+                    let _ = self.synced(io, |io| io.commit(&filled));
+                }
+                filled.clear();
+            }
+        }
+        if filled.is_empty() {
+            return;
+        }
+        // hold on to the buffer lock until IO is acquired, so that newer logs can't overtake these
+        let _queued = self.queue(filled.len());
+        let Ok(io) = self.io() else {
+            return;
+        };
+        drop(lock);
+        let _ = self.synced(io, |io| io.commit(&filled));
+    }
+
+    /// Write a log straight to the file, along with any data waiting in the buffer
+    ///
+    /// ## Arguments
+    /// - `header`: The kind and the term of the log
+    /// - `msg`: The log data to be written
+    /// - `fsync`: Whether to sync the file to disk after writing
+    ///
+    pub fn log_direct(&self, header: RecordHeader, msg: &[u8], fsync: bool) {
+        if self.refuse() {
+            return;
         }
+        self.limit(1, msg.len());
+        self.write_through(self.buffer(), header, msg, fsync);
+    }
+
+    /// Write a record straight to the file along with the data in the locked buffer
+    fn write_through(
+        &self,
+        mut lock: MutexGuard<'_, Buffer>,
+        header: RecordHeader,
+        msg: &[u8],
+        fsync: bool,
+    ) {
+        let buffer = std::mem::replace(&mut *lock, self.new_buffer());
+        let mut data = self.take(buffer, false);
+        let mut record = Buffer::new(Some(msg.len() + 2 + header.len()));
+        record.try_add(header, msg);
+        buffer::append(&mut data, &record.consume(false));
+        // hold on to the buffer lock until IO is acquired, so that newer logs can't overtake this one
+        let _queued = self.queue(data.len());
+        let Ok(io) = self.io() else {
+            return;
+        };
+        drop(lock);
+        let file = io.file();
+        let current = io.current();
+        let syncs = io.syncs();
+        let _ = self.synced(io, |io| io.commit(&data));
+        if fsync && !syncs {
+            if let Some(file) = file {
+                let start = Instant::now();
+                if let Err(e) = file.sync_data() {
+                    self.monitor.sync_failed(&e);
+                }
+                self.monitor.observe(Operation::Fsync, current, start);
+            }
+        }
+    }
+
+    /// Write several logs straight to the file, along with any data waiting in the buffer
+    ///
+    /// The logs are framed without being copied, and written with a single vectored write.
+    ///
+    /// ## Arguments
+    /// - `msgs`: The logs data to be written
+    ///
+    pub fn log_vectored(&self, msgs: &[&[u8]]) {
+        if self.refuse() {
+            return;
+        }
+        self.limit(msgs.len(), msgs.iter().map(|msg| msg.len()).sum());
+        let frames = msgs.iter().map(|msg| frame(msg)).collect::<Vec<_>>();
+        let mut lock = self.buffer();
+        let buffer = std::mem::replace(&mut *lock, self.new_buffer());
+        let pending = self.take(buffer, false);
+        let mut data = Vec::with_capacity(msgs.len() * 2 + 1);
+        data.push(&pending[..]);
+        for (frame, msg) in frames.iter().zip(msgs) {
+            data.push(&frame[..]);
+            data.push(msg);
+        }
+        // hold on to the buffer lock until IO is acquired, so that newer logs can't overtake these
+        let _queued = self.queue(data.iter().map(|d| d.len()).sum());
+        let Ok(io) = self.io() else {
+            return;
+        };
+        drop(lock);
+        let _ = self.synced(io, |io| io.commit_vectored(&data));
+    }
+
+    /// Change the storage size limit and fsync setting of a running writer
+    ///
+    /// ## Arguments
+    /// - `size`: Maximum amount of data that can be stored, in bytes
+    /// - `fsync`: Whether every write shall be synced to disk
+    ///
+    /// This opens the files of a lazily initialized writer.
+    pub fn reload(&self, size: usize, fsync: bool) {
+        if self.read_only {
+            return;
+        }
+        if let Ok(mut lock) = self.io() {
+            lock.reload(size, fsync);
+        }
+    }
+
+    /// Change the storage size limit of a running writer, keeping its fsync setting
+    pub fn set_storage_size(&self, size: usize) {
+        if self.read_only {
+            return;
+        }
+        if let Ok(mut lock) = self.io() {
+            let fsync = lock.syncs();
+            lock.reload(size, fsync);
+        }
+    }
+
+    /// Delete all the files before the given one
+    ///
+    /// ## Returns
+    /// The number of files deleted
+    pub fn delete_before(&self, end: usize) -> usize {
+        self.opened().map_or(0, |mut io| io.delete_before(end))
+    }
+
+    /// Trim the logs before `lsn` from the start of a file
+    ///
+    /// ## Returns
+    /// The number of bytes released from the disk
+    pub fn trim_before(&self, index: usize, lsn: Lsn) -> u64 {
+        self.opened().map_or(0, |mut io| io.trim_before(index, lsn))
+    }
+
+    /// Delete the logs from `lsn` onwards, see [FileManager::delete_from]
+    ///
+    /// The buffered logs are written out first, as they're counted among the logs deleted.
+    pub fn delete_from(&self, lsn: Lsn) -> std::io::Result<u64> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "The WAL is read-only",
+            ));
+        }
+        let mut buffer = self.buffer();
+        let data = self.take(std::mem::replace(&mut *buffer, self.new_buffer()), false);
+        self.synced(self.io()?, |io| {
+            if !data.is_empty() {
+                io.commit(&data)?;
+            }
+            io.delete_from(lsn)
+        })
+    }
+
+    /// Replace the logs from `lsn` onwards with new ones, see [FileManager::append_at]
+    ///
+    /// Nothing can be logged meanwhile, so no other log ends up among the new ones. The buffered
+    /// logs are written out first, as they're counted among the logs deleted.
+    pub fn append_at(&self, lsn: Lsn, msgs: &[(RecordHeader, Vec<u8>)]) -> std::io::Result<u64> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "The WAL is read-only",
+            ));
+        }
+        let mut batch = Buffer::new(Some(0));
+        for (header, msg) in msgs {
+            batch.add(*header, msg);
+        }
+        let mut buffer = self.buffer();
+        let data = self.take(std::mem::replace(&mut *buffer, self.new_buffer()), false);
+        self.synced(self.io()?, |io| {
+            if !data.is_empty() {
+                io.commit(&data)?;
+            }
+            io.append_at(lsn, &batch.consume(false))
+        })
+    }
+
+    /// Store key-value pairs along with the logs, see [FileManager::set_state]
+    ///
+    /// The buffered logs are written out first, so the pairs never get ahead of them.
+    pub fn set_state(&self, values: &[(&str, &[u8])]) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "The WAL is read-only",
+            ));
+        }
+        let mut buffer = self.buffer();
+        let data = self.take(std::mem::replace(&mut *buffer, self.new_buffer()), false);
+        let mut io = self.io()?;
+        if !data.is_empty() {
+            io.commit(&data)?;
+        }
+        io.set_state(values)
+    }
+
+    /// The key-value pairs stored along with the logs
+    pub fn state(&self) -> BTreeMap<String, Vec<u8>> {
+        match self.opened() {
+            Some(io) => io.state(),
+            None => StateFile::new(&self.config.location).read(),
+        }
+    }
+
+    /// Run a closure while no data is written to disk or added to the buffer
+    ///
+    /// The closure receives the data waiting in the buffer
+    pub fn paused<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        let buffer = self.buffer();
+        let _io = self.opened();
+        f(buffer.data())
+    }
+
+    /// Write the buffered data to the file, then run the closure while nothing can be logged
+    pub fn flushed<R>(&self, f: impl FnOnce() -> R) -> R {
+        let mut buffer = self.buffer();
+        let data = self.take(std::mem::replace(&mut *buffer, self.new_buffer()), false);
+        if data.is_empty() {
+            let _io = self.opened();
+            return f();
+        }
+        let _queued = self.queue(data.len());
+        let Ok(mut io) = self.io() else {
+            return f();
+        };
+        // a failed write is reported by the file manager
+        let _ = io.commit(&data);
+        // nothing else can be written meanwhile, so the sync is waited for with the lock held
+        if let Some(group) = self.group.as_ref().filter(|_| io.coalesces()) {
+            let _ = group.wait(group.join(&io));
+        }
+        f()
+    }
+
+    /// Compress the logs with a new dictionary from now on, see [FileManager::use_dictionary]
+    #[cfg(feature = "compression")]
+    pub fn use_dictionary(&self, data: Vec<u8>) -> std::io::Result<u32> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "The WAL is read-only",
+            ));
+        }
+        self.io()?.use_dictionary(data)
+    }
+
+    /// Whether the writer has been fenced off by a newer writer of the same WAL
+    pub fn is_fenced(&self) -> bool {
+        self.opened().is_some_and(|io| io.is_fenced())
+    }
+
+    /// Whether the writer refuses to write, as the WAL holds files written by a newer version
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Why the writer refuses to write, if it does: it's read-only, or its files couldn't be
+    /// opened
+    pub fn refusal(&self) -> Option<(String, Option<std::io::ErrorKind>)> {
+        if self.read_only {
+            let message = "Refusing to write to WAL, it holds files written by a newer version \
+                of walcraft"
+                .to_string();
+            return Some((message, Some(std::io::ErrorKind::Unsupported)));
+        }
+        match self.io.get() {
+            Some(Err(e)) => Some((
+                format!(
+                    "Refusing to write to WAL, its files couldn't be opened: {}",
+                    e
+                ),
+                None,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Report a log refused by the writer, see [Writer::refusal]
+    ///
+    /// ## Returns
+    /// Whether the log is refused
+    fn refuse(&self) -> bool {
+        match self.refusal() {
+            Some((message, kind)) => {
+                self.monitor.report(message, kind);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The lifetime counters, see [FileManager::lifetime_stats]
+    pub fn lifetime_stats(&self) -> LifetimeStats {
+        match self.opened() {
+            Some(io) => io.lifetime_stats(),
+            None => CountersFile::new(&self.config.location).read(),
+        }
+    }
+
+    /// Number of files kept beyond the storage limit, see [FileManager::gc_backlog]
+    pub fn gc_backlog(&self) -> usize {
+        self.opened().map_or(0, |io| io.gc_backlog())
+    }
+
+    /// Outcome of checking the files against meta when the writer was created
+    ///
+    /// The report is empty until a lazily initialized writer opens the files.
+    pub fn recovery_report(&self) -> RecoveryReport {
+        self.opened()
+            .map(|io| io.recovery_report().clone())
+            .unwrap_or_default()
+    }
+
+    /// Change the cap on the rate of the writes, `None` lifting it
+    pub fn set_write_limit(&self, limit: Option<WriteLimit>) {
+        *self.limiter.lock().unwrap_or_else(PoisonError::into_inner) =
+            limit.and_then(TokenBucket::new);
+    }
+
+    /// Wait for the turn of some logs to be written, if the rate of the writes is capped
+    fn limit(&self, records: usize, bytes: usize) {
+        // the lock is released before waiting, letting the others take their turn meanwhile
+        let wait = self
+            .limiter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .map(|bucket| bucket.take(records, bytes));
+        if let Some(wait) = wait.filter(|wait| !wait.is_zero()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Acquire the lock on the buffer
+    ///
+    /// A panic in another thread holding the lock doesn't brick the writer,
+    /// as the buffer is just bytes and stays usable.
+    fn buffer(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Open the files, if that wasn't done yet
+    ///
+    /// ## Returns
+    /// An error if the files couldn't be opened, which is reported once and kept, so that the
+    /// writer refuses to write from then on
+    fn open(&self) -> std::io::Result<&Mutex<FileManager>> {
+        let io = self.io.get_or_init(|| {
+            if self.config.lazy_init {
+                if let Err(e) = crate::builder::create_dirs(&self.config) {
+                    self.monitor.report(e.to_string(), None);
+                }
+            }
+            FileManager::new(self.config.clone())
+                .map(Mutex::new)
+                .inspect_err(|e| self.monitor.error("Failed to open WAL", e))
+                .map_err(|e| e.to_string())
+        });
+        io.as_ref().map_err(|e| std::io::Error::other(e.clone()))
+    }
+
+    /// Acquire the lock on the file manager, recovering it from a panic in another thread
+    ///
+    /// The files are opened first if that wasn't done yet.
+    fn io(&self) -> std::io::Result<MutexGuard<'_, FileManager>> {
+        Ok(self.open()?.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Acquire the lock on the file manager, only if the files have been opened
+    fn opened(&self) -> Option<MutexGuard<'_, FileManager>> {
+        self.io
+            .get()
+            .and_then(|io| io.as_ref().ok())
+            .map(|io| io.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Take the data out of a buffer to write it to disk, once checked against the checksum of
+    /// the data added to it
+    ///
+    /// ## Returns
+    /// The data, or nothing if it was corrupted in memory, which is reported instead of being
+    /// persisted as durable
+    fn take(&self, buffer: Buffer, padding: bool) -> Data {
+        if !buffer.verify() {
+            let message = format!(
+                "Dropped {} bytes of logs corrupted in memory before being written to disk",
+                buffer.data().len()
+            );
+            self.monitor
+                .report(message, Some(std::io::ErrorKind::InvalidData));
+            return Data::default();
+        }
+        buffer.consume(padding)
+    }
+
+    /// Create a new empty buffer of the configured size
+    fn new_buffer(&self) -> Buffer {
+        Buffer::new(Some(self.buffer_size.load(Ordering::Relaxed)))
+    }
+
+    /// Cap the memory taken by the logs not written to disk yet, on top of the configured
+    /// buffer size and limit on the unflushed data, or lift the cap with `None`
+    ///
+    /// The limit applies right away, while the buffer size applies from the next buffer on.
+    pub fn set_memory_budget(&self, budget: Option<usize>) {
+        let budget = budget.unwrap_or(usize::MAX).max(1);
+        let buffer_size = self.config.buffer_size.min(budget);
+        let max_unflushed = self.config.max_unflushed.unwrap_or(usize::MAX).min(budget);
+        self.buffer_size.store(buffer_size, Ordering::Relaxed);
+        self.max_unflushed.store(max_unflushed, Ordering::Relaxed);
+    }
+
+    /// Count bytes as waiting for the file manager, until the returned guard is dropped
+    fn queue(&self, bytes: usize) -> Queued<'_> {
+        self.queued.fetch_add(bytes, Ordering::Relaxed);
+        Queued(&self.queued, bytes)
+    }
+
+    /// Whether the data not written to disk yet reaches the limit set with
+    /// [WalBuilder::max_unflushed](crate::WalBuilder::max_unflushed)
+    ///
+    /// ## Arguments
+    /// - `buffered`: Bytes waiting in the buffer, or about to be written along with it
+    fn over_limit(&self, buffered: usize) -> bool {
+        match self.max_unflushed.load(Ordering::Relaxed) {
+            usize::MAX => false,
+            limit => buffered + self.queued.load(Ordering::Relaxed) >= limit,
+        }
+    }
+
+    /// Bytes not written to disk yet, waiting in the buffer or for their turn to be written
+    pub fn unflushed(&self) -> usize {
+        let buffered = self.buffer().data().len();
+        buffered + self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Write to the file while holding the lock, then release it, and wait for the write to be
+    /// synced to disk when the syncs are coalesced, see [SyncGroup]
+    ///
+    /// A failure to sync is reported by the [Monitor], as is a failure to write, which the
+    /// writes that don't return their outcome leave at that.
+    fn synced<R>(
+        &self,
+        mut io: MutexGuard<'_, FileManager>,
+        write: impl FnOnce(&mut FileManager) -> R,
+    ) -> R {
+        let result = write(&mut io);
+        let group = self.group.as_ref().filter(|_| io.coalesces());
+        let pending = group.map(|group| (group, group.join(&io)));
+        drop(io);
+        if let Some((group, write)) = pending {
+            let _ = group.wait(write);
+        }
+        result
     }
 
     /// Write the data to the file
     fn write(&self, msg: &[u8]) {
-        let mut lock = self.io.lock().unwrap();
-        lock.commit(msg);
+        let _queued = self.queue(msg.len());
+        if let Ok(io) = self.io() {
+            let _ = self.synced(io, |io| io.commit(msg));
+        }
     }
 
     /// Flush the in-memory buffer to Disk, if any data exists in the buffer
-    pub fn flush(&self) {
+    ///
+    /// ## Returns
+    /// A [FlushHandle] that resolves once the flushed data has been synced to disk
+    ///
+    /// Nothing is synced when no data was written since the last sync.
+    pub fn flush(&self) -> FlushHandle {
+        self.flush_to(|file, segment, writes| self.syncer.sync(file, segment, writes))
+    }
+
+    /// Flush the in-memory buffer, and sync the data on the calling thread instead of in the
+    /// background
+    ///
+    /// Nothing is synced when no data was written since the last sync, so that a task flushing
+    /// many writers, such as the one of a [WalSet](crate::WalSet), skips the idle ones.
+    pub fn flush_and_sync(&self) -> std::io::Result<()> {
+        self.flush_to(|file, segment, writes| self.syncer.sync_now(file, segment, writes))
+            .wait()
+    }
+
+    /// Flush the in-memory buffer, syncing the current file with the given function unless
+    /// the data is synced already
+    ///
+    /// The function receives the file, its index and the number of the newest write to it.
+    fn flush_to(&self, sync: impl FnOnce(File, usize, u64) -> FlushHandle) -> FlushHandle {
         // get buffer
-        let mut lock = self.buffer.lock().unwrap();
-        let buffer = std::mem::replace(&mut *lock, Buffer::new(None));
+        let mut lock = self.buffer();
+        let buffer = std::mem::replace(&mut *lock, self.new_buffer());
+        let queued = self.queue(buffer.data().len());
         drop(lock);
         // acquire lock on io to add the buffer to file
-        let data = buffer.consume(false);
+        let data = self.take(buffer, false);
+        if data.is_empty() && self.io.get().is_none() {
+            return FlushHandle::done();
+        }
+        let mut lock = match self.io() {
+            Ok(lock) => lock,
+            Err(e) => return FlushHandle::finished(Err(e)),
+        };
+        if let Some(group) = self.group.as_ref().filter(|_| lock.coalesces()) {
+            if !data.is_empty() {
+                if let Err(e) = lock.commit(&data) {
+                    return FlushHandle::finished(Err(e));
+                }
+            }
+            let write = group.join(&lock);
+            drop(lock);
+            drop(queued);
+            return FlushHandle::finished(group.wait(write));
+        }
+        // nothing was written since the last sync
+        if data.is_empty() && self.syncer.is_synced(lock.writes()) {
+            return FlushHandle::done();
+        }
+        // grab the file before committing, as the commit may rotate to the next file
+        let file = lock.file();
+        let current = lock.current();
         if !data.is_empty() {
-            let mut lock = self.io.lock().unwrap();
-            lock.commit(&data);
+            if let Err(e) = lock.commit(&data) {
+                return FlushHandle::finished(Err(e));
+            }
+        }
+        let writes = lock.writes();
+        drop(queued);
+        let synced = lock.syncs();
+        drop(lock);
+        // data is synced on every commit when fsync is enabled
+        match file {
+            Some(file) if !synced => sync(file, current, writes),
+            _ => FlushHandle::done(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::header::HEADER_SIZE;
     use super::*;
 
     #[test]
     fn it_works() {
-        let mut config = WalConfig::default();
-        config.location = "./tmp/".into();
+        let config = WalConfig {
+            location: "./tmp/".into(),
+            ..Default::default()
+        };
         let writer = Writer::new(config);
         let data = String::from("This is sparta");
         let data = data.as_bytes();
@@ -108,4 +833,147 @@ mod tests {
             writer.log(&data);
         }
     }
+
+    #[test]
+    fn log_many() {
+        let config = WalConfig {
+            location: "./tmp/log_many".into(),
+            buffer_size: 256,
+            ..Default::default()
+        };
+        let _ = std::fs::remove_dir_all(&config.location);
+        std::fs::create_dir_all(&config.location).unwrap();
+        let writer = Writer::new(config.clone());
+        writer.log_many((0..10).map(|_| vec![7; 100]));
+        // full buffers went to disk, the remainder is still buffered
+        let size = std::fs::metadata(config.location.join("log_0.bin"))
+            .unwrap()
+            .len();
+        assert_eq!(size as usize, HEADER_SIZE + 3 * 309);
+        writer.flush();
+        let size = std::fs::metadata(config.location.join("log_0.bin"))
+            .unwrap()
+            .len();
+        assert_eq!(size as usize, HEADER_SIZE + 3 * 309 + 103);
+    }
+
+    #[test]
+    fn flush_handle() {
+        let config = WalConfig {
+            location: "./tmp/flush_handle".into(),
+            ..Default::default()
+        };
+        let _ = std::fs::remove_dir_all(&config.location);
+        std::fs::create_dir_all(&config.location).unwrap();
+        let writer = Writer::new(config);
+        writer.log(&[42; 100]);
+        let handle = writer.flush();
+        assert!(handle.wait().is_ok());
+        // flushing an empty buffer still yields a usable handle
+        let mut handle = writer.flush();
+        while !handle.is_done() {
+            std::thread::yield_now();
+        }
+        assert!(handle.wait().is_ok());
+    }
+
+    #[test]
+    fn idle_flush() {
+        let config = WalConfig {
+            location: "./tmp/writer_idle_flush".into(),
+            ..Default::default()
+        };
+        let _ = std::fs::remove_dir_all(&config.location);
+        std::fs::create_dir_all(&config.location).unwrap();
+        let writer = Writer::new(config.clone());
+        let syncs = || config.stats.snapshot().fsync.count;
+        writer.log(&[42; 100]);
+        writer.flush().wait().unwrap();
+        assert_eq!(syncs(), 1);
+        // nothing was written since, so nothing is synced
+        for _ in 0..10 {
+            assert!(writer.flush().is_done());
+        }
+        writer.flush_and_sync().unwrap();
+        assert_eq!(syncs(), 1);
+        // the writes straight to the file are synced by the next flush
+        writer.log_direct(RecordHeader::default(), &[7; 10], false);
+        writer.flush().wait().unwrap();
+        assert_eq!(syncs(), 2);
+    }
+
+    #[test]
+    fn unopenable() {
+        let config = WalConfig {
+            location: "./tmp/writer_unopenable".into(),
+            ..Default::default()
+        };
+        let _ = std::fs::remove_dir_all(&config.location);
+        // a directory stands in the way of the first file
+        std::fs::create_dir_all(config.location.join("log_0.bin")).unwrap();
+        let writer = Writer::new(config);
+        assert!(writer.init().is_err());
+        writer.log(&[42; 100]);
+        assert!(writer.flush().wait().is_err());
+        assert!(writer.config.stats.errors().len() >= 2);
+    }
+
+    #[test]
+    fn poisoned_locks() {
+        let config = WalConfig {
+            location: "./tmp/poisoned_locks".into(),
+            buffer_size: 0,
+            ..Default::default()
+        };
+        let _ = std::fs::remove_dir_all(&config.location);
+        std::fs::create_dir_all(&config.location).unwrap();
+        let writer = std::sync::Arc::new(Writer::new(config.clone()));
+        // panic while holding both the locks
+        let clone = writer.clone();
+        let _ = std::thread::spawn(move || {
+            let _buffer = clone.buffer.lock().unwrap();
+            let _io = clone.opened().unwrap();
+            panic!("writer thread crashed");
+        })
+        .join();
+        assert!(writer.buffer.is_poisoned());
+        writer.log(&[1; 10]);
+        writer.flush().wait().unwrap();
+        let size = std::fs::metadata(config.location.join("log_0.bin"))
+            .unwrap()
+            .len();
+        assert_eq!(size as usize, HEADER_SIZE + 13);
+    }
+
+    #[test]
+    fn max_unflushed() {
+        let config = WalConfig {
+            location: "./tmp/max_unflushed".into(),
+            buffer_size: 4096,
+            max_unflushed: Some(250),
+            ..Default::default()
+        };
+        let _ = std::fs::remove_dir_all(&config.location);
+        std::fs::create_dir_all(&config.location).unwrap();
+        let writer = Writer::new(config.clone());
+        let size = || {
+            std::fs::metadata(config.location.join("log_0.bin"))
+                .unwrap()
+                .len() as usize
+        };
+        writer.log(&[7; 100]);
+        writer.log(&[7; 100]);
+        assert_eq!(writer.unflushed(), 206);
+        assert_eq!(size(), HEADER_SIZE);
+        // the third log crosses the limit, and the buffer is written without padding
+        writer.log(&[7; 100]);
+        assert_eq!(writer.unflushed(), 0);
+        assert_eq!(size(), HEADER_SIZE + 309);
+        writer.log_many((0..5).map(|_| vec![7; 100]));
+        assert_eq!(size(), HEADER_SIZE + 2 * 309);
+        assert_eq!(writer.unflushed(), 206);
+        writer.flush();
+        assert_eq!(writer.unflushed(), 0);
+        assert_eq!(size(), HEADER_SIZE + 8 * 103);
+    }
 }
@@ -0,0 +1,164 @@
+use super::manager::COMPRESSED_EXT;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Start of the name of every log file
+const PREFIX: &str = "log_";
+/// End of the name of every log file, before [COMPRESSED_EXT] once compressed
+const EXTENSION: &str = ".bin";
+/// Number of digits of the zero-padded index, enough for the largest index
+const INDEX_WIDTH: usize = 20;
+
+/// Name of a new log file
+///
+/// Two schemes are in use:
+/// - `log_42.bin`, the default
+/// - `log_00000000000000000042_20240501T120000.bin` with
+///   [WalBuilder::sortable_file_names](crate::WalBuilder::sortable_file_names), zero-padded so
+///   that sorting the names sorts the files, and followed by the UTC time the file was created at
+pub(crate) fn new_name(index: usize, sortable: bool) -> String {
+    match sortable {
+        true => format!(
+            "{}{:0width$}_{}{}",
+            PREFIX,
+            index,
+            timestamp(SystemTime::now()),
+            EXTENSION,
+            width = INDEX_WIDTH
+        ),
+        false => format!("{}{}{}", PREFIX, index, EXTENSION),
+    }
+}
+
+/// Index of a log file from its name, in either scheme, compressed or not
+pub(crate) fn parse(name: &str) -> Option<usize> {
+    let name = name.strip_suffix(COMPRESSED_EXT).unwrap_or(name);
+    let stem = name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?;
+    let index = match stem.split_once('_') {
+        Some((index, time)) if index.len() == INDEX_WIDTH && time.len() == 15 => index,
+        Some(_) => return None,
+        None => stem,
+    };
+    // parsing accepts a sign, which no file name has
+    match index.bytes().all(|b| b.is_ascii_digit()) {
+        true => index.parse().ok(),
+        false => None,
+    }
+}
+
+/// Name of the file of a log in a directory, without [COMPRESSED_EXT] if it's compressed
+///
+/// ## Returns
+/// `None` if there's no such file in the directory
+pub(crate) fn find(dir: &Path, index: usize) -> Option<String> {
+    let plain = new_name(index, false);
+    let compressed = format!("{}{}", plain, COMPRESSED_EXT);
+    if dir.join(&plain).exists() || dir.join(compressed).exists() {
+        return Some(plain);
+    }
+    // the sortable names carry the time, so they can't be told from the index alone
+    let prefix = format!("{}{:0width$}_", PREFIX, index, width = INDEX_WIDTH);
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(&prefix) && parse(name) == Some(index))
+        .map(|name| match name.strip_suffix(COMPRESSED_EXT) {
+            Some(name) => name.to_string(),
+            None => name,
+        })
+        .min()
+}
+
+/// Path of the file of a log in a directory, the default name if there's no such file
+pub(crate) fn path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(find(dir, index).unwrap_or_else(|| new_name(index, false)))
+}
+
+/// Path of the file of a log in a directory, a new name in the given scheme if there's no
+/// such file
+pub(crate) fn new_path(dir: &Path, index: usize, sortable: bool) -> PathBuf {
+    dir.join(find(dir, index).unwrap_or_else(|| new_name(index, sortable)))
+}
+
+/// A UTC time as `20240501T120000`
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // the civil date of a number of days since 1970-01-01, in eras of 400 years from 0000-03-01
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn names() {
+        assert_eq!(new_name(42, false), "log_42.bin");
+        let name = new_name(42, true);
+        assert!(name.starts_with("log_00000000000000000042_"));
+        assert_eq!(
+            name.len(),
+            "log_00000000000000000042_20240501T120000.bin".len()
+        );
+        assert_eq!(parse(&name), Some(42));
+        assert_eq!(parse("log_42.bin"), Some(42));
+        assert_eq!(parse("log_42.bin.zst"), Some(42));
+        let max = format!("log_{}_20240501T120000.bin", usize::MAX);
+        assert_eq!(parse(&max), Some(usize::MAX));
+        assert_eq!(parse("log_+42.bin"), None);
+        assert_eq!(parse("log_42_x.bin"), None);
+        assert_eq!(parse("meta"), None);
+        // sorting the names sorts the files
+        let mut names = [100, 9, 10].map(|index| new_name(index, true));
+        names.sort();
+        assert_eq!(names.map(|name| parse(&name).unwrap()), [9, 10, 100]);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101T000000");
+        let time = UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+        assert_eq!(timestamp(time), "20240501T120000");
+        // a leap day
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400 + 86_399);
+        assert_eq!(timestamp(time), "20000229T235959");
+    }
+
+    #[test]
+    fn find_files() {
+        let dir = Path::new("./tmp/file_names");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let sortable = new_name(7, true);
+        std::fs::write(dir.join(&sortable), []).unwrap();
+        std::fs::write(dir.join("log_8.bin.zst"), []).unwrap();
+        assert_eq!(find(dir, 7), Some(sortable.clone()));
+        assert_eq!(find(dir, 8), Some("log_8.bin".to_string()));
+        assert_eq!(find(dir, 9), None);
+        assert_eq!(path(dir, 9), dir.join("log_9.bin"));
+        assert_eq!(new_path(dir, 7, false), dir.join(sortable));
+        let new = new_path(dir, 9, true);
+        assert_eq!(parse(new.file_name().unwrap().to_str().unwrap()), Some(9));
+    }
+}
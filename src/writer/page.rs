@@ -0,0 +1,228 @@
+use super::header::HEADER_SIZE;
+use std::io::{ErrorKind, Read};
+
+/// Default size of a page, set with [WalBuilder::page_size](crate::WalBuilder::page_size)
+///
+/// The pages are the unit of the files written in the power-loss-safe mode, and the smallest
+/// size of a file in any mode. Layout of a page, with all the integers in little-endian:
+/// - 2 bytes, or 4 in pages larger than 64 KB: number of bytes of data in the page
+/// - the data, followed by zeros up to the checksum
+/// - 4 bytes: CRC32 of everything before it in the page
+///
+/// The header of the file takes up the whole first page, without a checksum.
+pub(crate) const PAGE_SIZE: usize = 4096;
+/// Largest size of a page
+pub(crate) const MAX_PAGE_SIZE: usize = 1024 * 1024;
+
+/// Size of the number of bytes of data in front of a page
+fn len_size(page_size: usize) -> usize {
+    match page_size > 1 << 16 {
+        true => 4,
+        false => 2,
+    }
+}
+
+/// Bytes of data that fit in a page
+fn page_data(page_size: usize) -> usize {
+    page_size - len_size(page_size) - 4
+}
+
+/// Split the data into whole pages, the last one padded with zeros
+pub(crate) fn encode(data: &[&[u8]], page_size: usize) -> Vec<u8> {
+    let data = data.concat();
+    let len_size = len_size(page_size);
+    let capacity = page_data(page_size);
+    let mut pages = Vec::with_capacity(data.len().div_ceil(capacity) * page_size);
+    for chunk in data.chunks(capacity) {
+        let start = pages.len();
+        pages.extend(&(chunk.len() as u32).to_le_bytes()[..len_size]);
+        pages.extend(chunk);
+        pages.resize(start + page_size - 4, 0);
+        let checksum = crc32fast::hash(&pages[start..]);
+        pages.extend(checksum.to_le_bytes());
+    }
+    pages
+}
+
+/// The data in a page, the size of the page being the length of the slice
+///
+/// ## Returns
+/// `None` if the page was torn by a crash, or never written
+pub(crate) fn decode(page: &[u8]) -> Option<&[u8]> {
+    let len_size = len_size(page.len());
+    let (body, checksum) = page.split_at(page.len() - 4);
+    if crc32fast::hash(body) != u32::from_le_bytes(checksum.try_into().ok()?) {
+        return None;
+    }
+    let mut len = [0; 4];
+    len[..len_size].copy_from_slice(&body[..len_size]);
+    let len = u32::from_le_bytes(len) as usize;
+    body.get(len_size..len_size + len)
+        .filter(|_| len <= page_data(page.len()))
+}
+
+/// Find where the intact data of a file ends, skipping past its header
+///
+/// Every write covers whole pages and ends with a complete record, so a crash while writing
+/// leaves a run of pages ending in a partial record, or a page failing its checksum.
+/// All of that run is dropped, along with anything after it.
+///
+/// ## Returns
+/// The size to truncate the file to
+pub(crate) fn intact_len(mut reader: impl Read, page_size: usize) -> std::io::Result<u64> {
+    let mut page = vec![0; page_size];
+    if !read_page(&mut reader, &mut page)? {
+        return Ok(0);
+    }
+    let mut len = page_size as u64;
+    let mut pages = 0;
+    let mut counter = super::frame::RecordCounter::default();
+    let mut fed = 0;
+    while read_page(&mut reader, &mut page)? {
+        let data = match decode(&page) {
+            Some(data) => data,
+            None => break,
+        };
+        counter.update(data);
+        fed += data.len() as u64;
+        pages += 1;
+        if counter.complete() == fed {
+            len += pages * page_size as u64;
+            pages = 0;
+        }
+    }
+    Ok(len)
+}
+
+/// Fill the page from the reader
+///
+/// ## Returns
+/// `false` if the reader ends before the page is filled
+fn read_page(reader: &mut impl Read, page: &mut [u8]) -> std::io::Result<bool> {
+    let mut filled = 0;
+    while filled < page.len() {
+        match reader.read(&mut page[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Reads the data out of the pages of a file, positioned right after its header
+///
+/// The rest of the first page is skipped, and the reading stops at the first page that
+/// fails its checksum.
+pub(crate) struct PageReader<R> {
+    inner: R,
+    page: Vec<u8>,
+    /// Range of the data of the current page still to be read
+    start: usize,
+    end: usize,
+    started: bool,
+    ended: bool,
+}
+
+impl<R: Read> PageReader<R> {
+    pub fn new(inner: R, page_size: usize) -> Self {
+        Self {
+            inner,
+            page: vec![0; page_size],
+            start: 0,
+            end: 0,
+            started: false,
+            ended: false,
+        }
+    }
+
+    /// Load the next page
+    ///
+    /// ## Returns
+    /// `false` once there are no more intact pages
+    fn next_page(&mut self) -> std::io::Result<bool> {
+        if !self.started {
+            self.started = true;
+            let mut rest = vec![0; self.page.len() - HEADER_SIZE];
+            if !read_page(&mut self.inner, &mut rest)? {
+                return Ok(false);
+            }
+        }
+        if !read_page(&mut self.inner, &mut self.page)? {
+            return Ok(false);
+        }
+        match decode(&self.page) {
+            Some(data) => {
+                self.start = len_size(self.page.len());
+                self.end = self.start + data.len();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<R: Read> Read for PageReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.start == self.end && !self.ended {
+            self.ended = !self.next_page()?;
+        }
+        let n = buf.len().min(self.end - self.start);
+        buf[..n].copy_from_slice(&self.page[self.start..self.start + n]);
+        self.start += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::header::{header_with, read_header, Format, FLAG_PAGED};
+
+    #[test]
+    fn pages() {
+        let records = [vec![3; 5000], vec![4; 10]].map(|r| {
+            let mut frame = (r.len() as u16).to_le_bytes().to_vec();
+            frame.extend(r);
+            frame
+        });
+        let mut file = header_with(FLAG_PAGED, Default::default()).to_vec();
+        file.resize(PAGE_SIZE, 0);
+        file.extend(encode(&[&records[0], &records[1]], PAGE_SIZE));
+        assert_eq!(file.len(), PAGE_SIZE * 3);
+        let mut reader = &file[..];
+        let (format, prefix) = read_header(&mut reader).unwrap();
+        assert_eq!(prefix.len(), HEADER_SIZE);
+        assert!(format.paged);
+        assert_ne!(format, Format::default());
+        let mut data = Vec::new();
+        PageReader::new(reader, PAGE_SIZE)
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, records.concat());
+        assert_eq!(intact_len(&file[..], PAGE_SIZE).unwrap(), file.len() as u64);
+        // a torn page drops the whole write it was part of
+        let mut torn = file.clone();
+        torn.extend(encode(&[&records[0]], PAGE_SIZE));
+        torn[PAGE_SIZE * 4 + 100] ^= 1;
+        assert_eq!(intact_len(&torn[..], PAGE_SIZE).unwrap(), file.len() as u64);
+        // and a page cut short
+        assert_eq!(
+            intact_len(&file[..PAGE_SIZE * 3 - 1], PAGE_SIZE).unwrap(),
+            PAGE_SIZE as u64
+        );
+        assert_eq!(intact_len(&file[..10], PAGE_SIZE).unwrap(), 0);
+    }
+
+    #[test]
+    fn large_pages() {
+        let page_size = MAX_PAGE_SIZE;
+        let data = vec![7; page_size];
+        let pages = encode(&[&data], page_size);
+        // the data overflows the first page, past the range of a 2-byte size
+        assert_eq!(pages.len(), page_size * 2);
+        assert_eq!(decode(&pages[..page_size]).unwrap().len(), page_size - 8);
+        assert_eq!(decode(&pages[page_size..]).unwrap().len(), 8);
+    }
+}
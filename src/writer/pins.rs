@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Files pinned by the readers, which the garbage collection must not delete
+///
+/// The pins are shared by all the handles to a [Wal](crate::Wal) within the process.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Pins(Arc<Mutex<BTreeMap<usize, usize>>>);
+
+impl Pins {
+    /// Pin a file until the returned [Pin] is dropped
+    pub fn pin(&self, index: usize) -> Pin {
+        let mut pins = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        *pins.entry(index).or_default() += 1;
+        Pin {
+            pins: self.clone(),
+            index,
+        }
+    }
+
+    /// The oldest pinned file, counting from the given file onwards
+    pub fn oldest(&self, from: usize) -> Option<usize> {
+        let pins = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        pins.keys()
+            .copied()
+            .min_by_key(|index| index.wrapping_sub(from))
+    }
+}
+
+/// A file pinned by a reader, which is released once dropped
+#[derive(Debug)]
+pub(crate) struct Pin {
+    pins: Pins,
+    index: usize,
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        let mut pins = self.pins.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = pins.get_mut(&self.index) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&self.index);
+            }
+        }
+    }
+}
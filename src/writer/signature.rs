@@ -0,0 +1,54 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+/// The message signed for a file: its index, followed by the SHA-256 of its full content
+///
+/// The index is part of the message, so that a signed file can't pass for another one.
+fn message(index: usize, mut reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    let mut data = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut data)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&data[..n]);
+    }
+    let mut message = (index as u64).to_le_bytes().to_vec();
+    message.extend(hasher.finalize());
+    Ok(message)
+}
+
+/// Sign a closed file
+pub(crate) fn sign(key: &SigningKey, index: usize, reader: impl Read) -> std::io::Result<[u8; 64]> {
+    Ok(key.sign(&message(index, reader)?).to_bytes())
+}
+
+/// Check the signature of a closed file
+pub(crate) fn verify(
+    key: &VerifyingKey,
+    index: usize,
+    reader: impl Read,
+    signature: &[u8; 64],
+) -> std::io::Result<bool> {
+    let signature = Signature::from_bytes(signature);
+    Ok(key.verify(&message(index, reader)?, &signature).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let public = key.verifying_key();
+        let signature = sign(&key, 3, &b"logs"[..]).unwrap();
+        assert!(verify(&public, 3, &b"logs"[..], &signature).unwrap());
+        assert!(!verify(&public, 3, &b"Logs"[..], &signature).unwrap());
+        assert!(!verify(&public, 4, &b"logs"[..], &signature).unwrap());
+        let other = SigningKey::from_bytes(&[2; 32]).verifying_key();
+        assert!(!verify(&other, 3, &b"logs"[..], &signature).unwrap());
+    }
+}
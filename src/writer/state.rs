@@ -0,0 +1,90 @@
+use super::manager::{read_slot, write_slot};
+use super::manifest::to_hex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the slots of the hard state in the log directory, followed by the slot number
+const STATE_FILE: &str = "state";
+
+/// Small key-value pairs kept along with the logs, such as the term and the vote of a Raft node
+///
+/// The pairs are stored in two slots written alternately, as meta is in the power-loss-safe
+/// mode, so a crash while writing them leaves the previous ones intact. The slot holds the pairs
+/// on a single line, as `key=value` with the value in hex.
+pub(crate) struct StateFile {
+    slots: [PathBuf; 2],
+}
+
+impl StateFile {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            slots: [0, 1].map(|slot| dir.join(format!("{}.{}", STATE_FILE, slot))),
+        }
+    }
+
+    /// Read all the pairs, none if they were never written
+    pub fn read(&self) -> BTreeMap<String, Vec<u8>> {
+        let values = read_slot(&self.slots).map(|(_, values)| values);
+        values
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                Some((key.to_string(), from_hex(value)?))
+            })
+            .collect()
+    }
+
+    /// Replace all the pairs, syncing them to disk
+    pub fn write(&self, state: &BTreeMap<String, Vec<u8>>) -> std::io::Result<()> {
+        let values = state
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, to_hex(value)))
+            .collect::<Vec<_>>();
+        write_slot(&self.slots, &values.join(" "))
+    }
+}
+
+/// Whether a key can be stored, i.e. it's made of visible ASCII characters other than `=`
+pub(crate) fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.bytes().all(|b| b.is_ascii_graphic() && b != b'=')
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_file() {
+        let dir = Path::new("./tmp/state_file");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let file = StateFile::new(dir);
+        assert!(file.read().is_empty());
+        let mut state = BTreeMap::new();
+        state.insert("term".to_string(), 7u64.to_le_bytes().to_vec());
+        state.insert("empty".to_string(), vec![]);
+        file.write(&state).unwrap();
+        assert_eq!(file.read(), state);
+        // a torn write of the other slot leaves the pairs intact
+        state.insert("vote".to_string(), vec![1, 2]);
+        file.write(&state).unwrap();
+        std::fs::write(dir.join("state.1"), "vote=0").unwrap();
+        state.remove("vote");
+        assert_eq!(file.read(), state);
+        assert!(is_valid_key("raft.term"));
+        assert!(!is_valid_key("a=b"));
+        assert!(!is_valid_key("a b"));
+        assert!(!is_valid_key(""));
+    }
+}
@@ -0,0 +1,42 @@
+/* C bindings for walcraft, see walcraft-ffi/src/lib.rs for the details of every function */
+
+#ifndef WALCRAFT_H
+#define WALCRAFT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct WalcraftWal WalcraftWal;
+typedef struct WalcraftIter WalcraftIter;
+
+/* Open a WAL at the location, storage_mb of zero means unlimited. Returns NULL on failure. */
+WalcraftWal *walcraft_open(const char *location, size_t storage_mb);
+
+/* Write a log. Returns 0 on success, -1 on invalid arguments. */
+int walcraft_write(WalcraftWal *wal, const uint8_t *data, size_t len);
+
+/* Flush the buffered logs and wait for them to be synced. Returns 0 on success, -1 on failure. */
+int walcraft_flush(WalcraftWal *wal);
+
+/* Start reading all the logs. Returns NULL on failure. */
+WalcraftIter *walcraft_read(WalcraftWal *wal);
+
+/* Move to the next log, which stays valid until the next call or until the iterator is freed.
+ * Returns 1 if a log was returned, 0 at the end, -1 on invalid arguments. */
+int walcraft_iter_next(WalcraftIter *iter, const uint8_t **data, size_t *len);
+
+/* Free an iterator. */
+void walcraft_iter_free(WalcraftIter *iter);
+
+/* Flush the buffered logs and close the WAL. */
+void walcraft_close(WalcraftWal *wal);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* WALCRAFT_H */
@@ -0,0 +1,223 @@
+//! C bindings for [walcraft](https://docs.rs/walcraft)
+//!
+//! The WAL is exposed through opaque handles, and every log is a slice of bytes.
+//! The logs are stored as `Vec<u8>`, so the same files can be used from Rust with `Wal<Vec<u8>>`.
+//!
+//! The declarations are in `include/walcraft.h`.
+//!
+//! With the `pyo3` feature, the library is a Python module as well.
+
+#[cfg(feature = "pyo3")]
+mod python;
+
+use std::ffi::{c_char, c_int, CStr};
+use walcraft::{Size, Wal, WalBuilder};
+
+/// Opaque handle to a WAL
+pub struct WalcraftWal(Wal<Vec<u8>>);
+
+/// Opaque handle to an iterator over the logs
+pub struct WalcraftIter {
+    logs: Box<dyn Iterator<Item = Vec<u8>>>,
+    /// The last log returned, kept alive until the next call
+    current: Option<Vec<u8>>,
+}
+
+/// Open a WAL at the location, creating it if needed
+///
+/// `storage_mb` limits the storage taken by the logs, zero means unlimited.
+///
+/// ## Returns
+/// A handle to the WAL, or null if it can't be opened
+///
+/// # Safety
+/// `location` must be a valid nul-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn walcraft_open(
+    location: *const c_char,
+    storage_mb: usize,
+) -> *mut WalcraftWal {
+    if location.is_null() {
+        return std::ptr::null_mut();
+    }
+    let location = match CStr::from_ptr(location).to_str() {
+        Ok(location) => location,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let mut builder = WalBuilder::new().location(location);
+    if storage_mb > 0 {
+        builder = builder.storage_size(Size::Mb(storage_mb));
+    }
+    match builder.build() {
+        Ok(wal) => Box::into_raw(Box::new(WalcraftWal(wal))),
+        Err(e) => {
+            eprintln!("Failed to open WAL: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Write a log
+///
+/// ## Returns
+/// 0 on success, -1 if the arguments are invalid
+///
+/// # Safety
+/// `wal` must be a handle returned by [walcraft_open], and `data` must point to `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn walcraft_write(
+    wal: *mut WalcraftWal,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let wal = match wal.as_ref() {
+        Some(wal) => wal,
+        None => return -1,
+    };
+    if data.is_null() && len > 0 {
+        return -1;
+    }
+    let log = match len {
+        0 => Vec::new(),
+        _ => std::slice::from_raw_parts(data, len).to_vec(),
+    };
+    wal.0.write(log);
+    0
+}
+
+/// Flush the buffered logs to disk, and wait for them to be synced
+///
+/// ## Returns
+/// 0 on success, -1 if the handle is invalid or the sync failed
+///
+/// # Safety
+/// `wal` must be a handle returned by [walcraft_open]
+#[no_mangle]
+pub unsafe extern "C" fn walcraft_flush(wal: *mut WalcraftWal) -> c_int {
+    match wal.as_ref().map(|wal| wal.0.flush().wait()) {
+        Some(Ok(_)) => 0,
+        _ => -1,
+    }
+}
+
+/// Start reading all the logs, from the oldest one
+///
+/// ## Returns
+/// A handle to the iterator, or null if the logs can't be read
+///
+/// # Safety
+/// `wal` must be a handle returned by [walcraft_open]
+#[no_mangle]
+pub unsafe extern "C" fn walcraft_read(wal: *mut WalcraftWal) -> *mut WalcraftIter {
+    let wal = match wal.as_ref() {
+        Some(wal) => wal,
+        None => return std::ptr::null_mut(),
+    };
+    match wal.0.read() {
+        Ok(logs) => Box::into_raw(Box::new(WalcraftIter {
+            logs: Box::new(logs),
+            current: None,
+        })),
+        Err(e) => {
+            eprintln!("Failed to read WAL: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Move to the next log
+///
+/// The log is borrowed from the iterator, and stays valid until the next call
+/// or until the iterator is freed.
+///
+/// ## Returns
+/// 1 if a log was returned, 0 once all the logs have been read, -1 if the arguments are invalid
+///
+/// # Safety
+/// `iter` must be a handle returned by [walcraft_read], `data` and `len` must be valid pointers
+#[no_mangle]
+pub unsafe extern "C" fn walcraft_iter_next(
+    iter: *mut WalcraftIter,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> c_int {
+    let iter = match iter.as_mut() {
+        Some(iter) => iter,
+        None => return -1,
+    };
+    if data.is_null() || len.is_null() {
+        return -1;
+    }
+    iter.current = iter.logs.next();
+    match iter.current.as_ref() {
+        Some(log) => {
+            *data = log.as_ptr();
+            *len = log.len();
+            1
+        }
+        None => {
+            *data = std::ptr::null();
+            *len = 0;
+            0
+        }
+    }
+}
+
+/// Free an iterator
+///
+/// # Safety
+/// `iter` must be a handle returned by [walcraft_read], or null
+#[no_mangle]
+pub unsafe extern "C" fn walcraft_iter_free(iter: *mut WalcraftIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+/// Close a WAL, flushing the buffered logs
+///
+/// # Safety
+/// `wal` must be a handle returned by [walcraft_open], or null
+#[no_mangle]
+pub unsafe extern "C" fn walcraft_close(wal: *mut WalcraftWal) {
+    if wal.is_null() {
+        return;
+    }
+    let wal = Box::from_raw(wal);
+    let _ = wal.0.flush().wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn write_and_read() {
+        let dir = std::env::temp_dir().join("walcraft-ffi-write-and-read");
+        let _ = std::fs::remove_dir_all(&dir);
+        let location = CString::new(dir.to_str().unwrap()).unwrap();
+        unsafe {
+            let wal = walcraft_open(location.as_ptr(), 0);
+            assert!(!wal.is_null());
+            for log in [&b"first"[..], b"", b"third"] {
+                assert_eq!(walcraft_write(wal, log.as_ptr(), log.len()), 0);
+            }
+            assert_eq!(walcraft_flush(wal), 0);
+            let iter = walcraft_read(wal);
+            assert!(!iter.is_null());
+            let mut logs = Vec::new();
+            let (mut data, mut len) = (std::ptr::null(), 0);
+            while walcraft_iter_next(iter, &mut data, &mut len) == 1 {
+                logs.push(std::slice::from_raw_parts(data, len).to_vec());
+            }
+            walcraft_iter_free(iter);
+            walcraft_close(wal);
+            assert_eq!(logs, [&b"first"[..], b"", b"third"]);
+        }
+        // the same files are readable from Rust
+        let wal: Wal<Vec<u8>> = Wal::new(dir.to_str().unwrap(), None);
+        assert_eq!(wal.read().unwrap().count(), 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,171 @@
+//! Python module, enabled with the `pyo3` feature
+//!
+//! ```python
+//! import walcraft
+//!
+//! wal = walcraft.Wal("/tmp/logz")
+//! wal.append(b"raw bytes")
+//! wal.append_json({"id": 1})
+//! wal.flush()
+//! for record in wal:
+//!     print(record)
+//! print(wal.stats())
+//! ```
+
+use pyo3::exceptions::PyIOError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use walcraft::{Latency, Size, Wal, WalBuilder};
+
+/// A WAL storing every log as bytes
+#[pyclass(name = "Wal")]
+struct PyWal(Wal<Vec<u8>>);
+
+#[pymethods]
+impl PyWal {
+    /// Open a WAL at the location, creating it if needed
+    ///
+    /// `storage_mb` limits the storage taken by the logs, zero means unlimited.
+    #[new]
+    #[pyo3(signature = (location, storage_mb = 0))]
+    fn new(location: &str, storage_mb: usize) -> PyResult<Self> {
+        let mut builder = WalBuilder::new().location(location);
+        if storage_mb > 0 {
+            builder = builder.storage_size(Size::Mb(storage_mb));
+        }
+        builder
+            .build()
+            .map(PyWal)
+            .map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// Append a log of raw bytes
+    fn append(&self, data: &[u8]) {
+        self.0.write(data.to_vec());
+    }
+
+    /// Append any object serializable with the `json` module, stored as UTF-8 text
+    fn append_json(&self, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let json = value.py().import("json")?;
+        let text = json.call_method1("dumps", (value,))?;
+        self.0.write(text.extract::<String>()?.into_bytes());
+        Ok(())
+    }
+
+    /// Flush the buffered logs to disk, and wait for them to be synced
+    fn flush(&self) -> PyResult<()> {
+        self.0
+            .flush()
+            .wait()
+            .map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// Iterate over all the logs as bytes, from the oldest one
+    fn __iter__(&self) -> PyResult<Records> {
+        self.records(false)
+    }
+
+    /// Iterate over all the logs appended with `append_json`, decoded back
+    fn read_json(&self) -> PyResult<Records> {
+        self.records(true)
+    }
+
+    /// Number of logs, along with the latency of flushes and fsyncs
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = self.0.stats();
+        let dict = PyDict::new(py);
+        dict.set_item("count", self.0.count())?;
+        dict.set_item("segments", self.0.list_segments().len())?;
+        dict.set_item("flush", latency(py, &stats.flush)?)?;
+        dict.set_item("fsync", latency(py, &stats.fsync)?)?;
+        Ok(dict)
+    }
+}
+
+impl PyWal {
+    fn records(&self, json: bool) -> PyResult<Records> {
+        let logs = self.0.read().map_err(PyIOError::new_err)?;
+        Ok(Records {
+            logs: Box::new(logs),
+            json,
+        })
+    }
+}
+
+/// Durations of an operation, in seconds
+fn latency<'py>(py: Python<'py>, latency: &Latency) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("count", latency.count)?;
+    dict.set_item("p50", latency.p50.as_secs_f64())?;
+    dict.set_item("p99", latency.p99.as_secs_f64())?;
+    dict.set_item("max", latency.max.as_secs_f64())?;
+    Ok(dict)
+}
+
+/// Iterator over the logs
+#[pyclass(unsendable)]
+struct Records {
+    logs: Box<dyn Iterator<Item = Vec<u8>>>,
+    /// Whether to decode the logs as JSON
+    json: bool,
+}
+
+#[pymethods]
+impl Records {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let log = match self.logs.next() {
+            Some(log) => log,
+            None => return Ok(None),
+        };
+        let bytes = PyBytes::new(py, &log).into_any();
+        match self.json {
+            true => py.import("json")?.call_method1("loads", (bytes,)).map(Some),
+            false => Ok(Some(bytes)),
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "walcraft")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyWal>()?;
+    m.add_class::<Records>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_iterate() {
+        let dir = std::env::temp_dir().join("walcraft-python-append-and-iterate");
+        let _ = std::fs::remove_dir_all(&dir);
+        Python::initialize();
+        Python::attach(|py| {
+            let wal = Bound::new(py, PyWal::new(dir.to_str().unwrap(), 0).unwrap()).unwrap();
+            wal.call_method1("append", (PyBytes::new(py, b"raw"),))
+                .unwrap();
+            let value = PyDict::new(py);
+            value.set_item("id", 1).unwrap();
+            wal.call_method1("append_json", (value,)).unwrap();
+            wal.call_method0("flush").unwrap();
+            let records = wal
+                .try_iter()
+                .unwrap()
+                .map(|r| r.unwrap().extract::<Vec<u8>>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(records, [&b"raw"[..], br#"{"id": 1}"#]);
+            let stats = wal.call_method0("stats").unwrap();
+            assert_eq!(
+                stats.get_item("count").unwrap().extract::<u64>().unwrap(),
+                2
+            );
+        });
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,237 @@
+//! A gRPC service fronting a walcraft directory, turning it into a single-node durable log
+//!
+//! The service is defined in `proto/walcraft.proto`. Every record is stored as `Vec<u8>`,
+//! so the directory can be read from Rust with `Wal<Vec<u8>>` as well.
+//!
+//! ```text
+//! walcraft-server <location> [--addr 127.0.0.1:50051] [--storage-mb 0]
+//! ```
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use walcraft::{Lsn, SegmentBound, Size, Wal, WalBuilder, WriteOptions};
+
+mod proto {
+    tonic::include_proto!("walcraft.v1");
+}
+
+use proto::wal_server::WalServer;
+use proto::{
+    AppendBatchRequest, AppendRequest, AppendResponse, ReadFromRequest, Record, TruncateRequest,
+    TruncateResponse,
+};
+
+/// How often a tail checks for new records once it has caught up
+const TAIL_INTERVAL: Duration = Duration::from_millis(100);
+/// Number of records buffered for a slow client of a stream
+const STREAM_BUFFER: usize = 256;
+
+struct Service {
+    wal: Wal<Vec<u8>>,
+}
+
+impl Service {
+    /// Stream the records from `lsn` on a dedicated thread, as the readers block on the disk
+    ///
+    /// With `follow`, the stream keeps polling for new records until the client goes away.
+    fn stream(&self, lsn: Lsn, follow: bool) -> ReceiverStream<Result<Record, Status>> {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let wal = self.wal.clone();
+        std::thread::spawn(move || {
+            let mut next = lsn;
+            loop {
+                let records = match wal.read_from(next) {
+                    Ok(records) => records,
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(Status::internal(e)));
+                        return;
+                    }
+                };
+                for (lsn, data) in records {
+                    next = lsn + 1;
+                    if tx.blocking_send(Ok(Record { lsn, data })).is_err() {
+                        return;
+                    }
+                }
+                if !follow || tx.is_closed() {
+                    return;
+                }
+                std::thread::sleep(TAIL_INTERVAL);
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::wal_server::Wal for Service {
+    async fn append(
+        &self,
+        request: Request<AppendRequest>,
+    ) -> Result<Response<AppendResponse>, Status> {
+        let request = request.into_inner();
+        let options = WriteOptions {
+            fsync: request.fsync,
+            ..Default::default()
+        };
+        let wal = self.wal.clone();
+        tokio::task::spawn_blocking(move || wal.write_with(request.data, options))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(AppendResponse {}))
+    }
+
+    async fn append_batch(
+        &self,
+        request: Request<AppendBatchRequest>,
+    ) -> Result<Response<AppendResponse>, Status> {
+        let request = request.into_inner();
+        let wal = self.wal.clone();
+        tokio::task::spawn_blocking(move || {
+            wal.write_iter(request.data);
+            match request.fsync {
+                true => wal.flush().wait(),
+                false => Ok(()),
+            }
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(AppendResponse {}))
+    }
+
+    type ReadFromStream = ReceiverStream<Result<Record, Status>>;
+
+    async fn read_from(
+        &self,
+        request: Request<ReadFromRequest>,
+    ) -> Result<Response<Self::ReadFromStream>, Status> {
+        let lsn = request.into_inner().lsn;
+        Ok(Response::new(self.stream(lsn, false)))
+    }
+
+    type TailStream = ReceiverStream<Result<Record, Status>>;
+
+    async fn tail(
+        &self,
+        request: Request<ReadFromRequest>,
+    ) -> Result<Response<Self::TailStream>, Status> {
+        let lsn = request.into_inner().lsn;
+        Ok(Response::new(self.stream(lsn, true)))
+    }
+
+    async fn truncate(
+        &self,
+        request: Request<TruncateRequest>,
+    ) -> Result<Response<TruncateResponse>, Status> {
+        let lsn = request.into_inner().lsn;
+        let wal = self.wal.clone();
+        let deleted =
+            tokio::task::spawn_blocking(move || wal.delete_segments_before(SegmentBound::Lsn(lsn)))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(TruncateResponse {
+            deleted: deleted as u64,
+        }))
+    }
+}
+
+/// Parse the command line arguments
+///
+/// ## Returns
+/// A tuple with the location, the address to listen on and the storage size in MBs
+fn args() -> Result<(String, String, usize), String> {
+    let mut location = None;
+    let mut addr = "127.0.0.1:50051".to_string();
+    let mut storage_mb = 0;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().ok_or("Missing value for --addr")?,
+            "--storage-mb" => {
+                let value = args.next().ok_or("Missing value for --storage-mb")?;
+                storage_mb = value.parse().map_err(|_| "Invalid --storage-mb")?;
+            }
+            _ if location.is_none() => location = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let location =
+        location.ok_or("Usage: walcraft-server <location> [--addr host:port] [--storage-mb n]")?;
+    Ok((location, addr, storage_mb))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (location, addr, storage_mb) = args()?;
+    let mut builder = WalBuilder::new().location(&location);
+    if storage_mb > 0 {
+        builder = builder.storage_size(Size::Mb(storage_mb));
+    }
+    let wal = builder.build()?;
+    let service = Service { wal: wal.clone() };
+    println!("walcraft-server listening on {}", addr);
+    Server::builder()
+        .add_service(WalServer::new(service))
+        .serve_with_shutdown(addr.parse()?, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    // don't lose the buffered records on shutdown
+    wal.flush().wait()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::wal_server::Wal as _;
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn append_and_read() {
+        let location = std::env::temp_dir().join("walcraft-server-append-and-read");
+        let _ = std::fs::remove_dir_all(&location);
+        let wal = WalBuilder::new()
+            .location(location.to_str().unwrap())
+            .build()
+            .unwrap();
+        let service = Service { wal };
+        let request = AppendRequest {
+            data: b"first".to_vec(),
+            fsync: true,
+        };
+        service.append(Request::new(request)).await.unwrap();
+        let request = AppendBatchRequest {
+            data: vec![b"second".to_vec(), b"third".to_vec()],
+            fsync: true,
+        };
+        service.append_batch(Request::new(request)).await.unwrap();
+        let stream = service
+            .read_from(Request::new(ReadFromRequest { lsn: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        let records = stream.map(|r| r.unwrap()).collect::<Vec<_>>().await;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].lsn, 1);
+        assert_eq!(records[1].data, b"third");
+        // a tail keeps following the new records
+        let mut tail = service
+            .tail(Request::new(ReadFromRequest { lsn: 3 }))
+            .await
+            .unwrap()
+            .into_inner();
+        let request = AppendRequest {
+            data: b"fourth".to_vec(),
+            fsync: true,
+        };
+        service.append(Request::new(request)).await.unwrap();
+        let record = tail.next().await.unwrap().unwrap();
+        assert_eq!((record.lsn, record.data), (3, b"fourth".to_vec()));
+        let _ = std::fs::remove_dir_all(&location);
+    }
+}